@@ -0,0 +1,27 @@
+use std::{env, io, path::PathBuf, process};
+
+/// Replays a page access trace that was recorded with the page cache's
+/// `access_trace` option against every replacement policy, and prints their
+/// hit ratios:
+///
+/// ```sh
+/// cargo run --release -p beedb_hive --example replay_trace -- trace.txt [cache size in pages]
+/// ```
+///
+/// The cache holds 1024 pages by default.
+fn main() -> io::Result<()> {
+	let mut args = env::args().skip(1);
+	let Some(path) = args.next().map(PathBuf::from) else {
+		eprintln!("Usage: replay_trace <trace file> [cache size in pages]");
+		process::exit(2);
+	};
+	let cache_size = match args.next().map(|size| size.parse()) {
+		None => 1024,
+		Some(Ok(size)) => size,
+		Some(Err(err)) => {
+			eprintln!("Invalid cache size: {err}");
+			process::exit(2);
+		}
+	};
+	beedb_hive::replay_trace_file(&path, cache_size, io::stdout().lock())
+}
@@ -1,12 +1,30 @@
 use std::time::Duration;
 
-use crate::utils::units::{GIB, KIB};
+use crate::utils::units::{GIB, KIB, MIB};
 
-pub(crate) const PAGE_SIZE: usize = 32 * KIB;
+pub(crate) const DEFAULT_PAGE_SIZE: usize = 32 * KIB;
+pub(crate) const MIN_PAGE_SIZE: usize = 4 * KIB;
+pub(crate) const MAX_PAGE_SIZE: usize = 64 * KIB;
+pub(crate) const DIRECT_IO_ALIGNMENT: usize = 4 * KIB;
 pub(crate) const DEFAULT_MAX_NUM_OPEN_SEGMENTS: usize = 512;
+pub(crate) const DEFAULT_SEGMENT_EXTENT_SIZE: usize = 2 * MIB;
+pub(crate) const DEFAULT_USE_DOUBLE_WRITE: bool = false;
+pub(crate) const DEFAULT_USE_DIRECT_IO: bool = false;
+pub(crate) const DEFAULT_IO_QUEUE_DEPTH: u32 = 64;
+pub(crate) const DEFAULT_NUM_IO_RINGS: usize = 4;
+pub(crate) const MAX_COALESCED_PAGES: usize = 64;
 pub(crate) const DEFAULT_MAX_WAL_GENERATION_SIZE: usize = 4 * GIB;
 pub(crate) const DEFAULT_PAGE_CACHE_SIZE: usize = 2 * GIB;
 pub(crate) const DEFAULT_MAX_DIRTY_PAGES: f32 = 0.2;
+pub(crate) const DEFAULT_DIRTY_PAGES_CEILING: f32 = 0.5;
+pub(crate) const DEFAULT_MAX_WRITE_STALL: Duration = Duration::from_millis(200);
+pub(crate) const DEFAULT_NUM_PAGE_CACHE_SHARDS: usize = 16;
+pub(crate) const MIN_PAGES_PER_CACHE_SHARD: usize = 1024;
+pub(crate) const DEFAULT_READ_AHEAD_PAGES: usize = 32;
+pub(crate) const DEFAULT_MAX_SCAN_PAGES: f32 = 0.05;
+pub(crate) const DEFAULT_PERSIST_WORKING_SET: bool = true;
+pub(crate) const DEFAULT_REGISTER_IO_BUFFERS: bool = false;
+pub(crate) const PREWARM_BATCH_PAGES: usize = 64;
 pub(crate) const DEFAULT_NUM_WORKERS: usize = 2;
 pub(crate) const DEFAULT_CHECKPOINT_PERIOD: Duration = Duration::from_secs(60);
 pub(crate) const DEFAULT_FLUSH_PERIOD: Duration = Duration::from_secs(3 * 60);
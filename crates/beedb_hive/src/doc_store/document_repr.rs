@@ -1,6 +1,9 @@
 use std::{convert::Infallible, mem, num::NonZero};
 
-use zerocopy::{FromBytes, FromZeros, Immutable, IntoBytes};
+use zerocopy::{
+	little_endian::{U16, U32},
+	FromBytes, FromZeros, Immutable, IntoBytes,
+};
 
 use crate::{page_store::PageAddress, repr::Repr};
 
@@ -9,9 +12,9 @@ use super::{DatabaseError, DbPointer};
 #[derive(Debug, Immutable, IntoBytes, FromBytes)]
 #[repr(C)]
 struct DbPointerRepr {
-	segment_num: u32,
-	page_num: u16,
-	index: u16,
+	segment_num: U32,
+	page_num: U16,
+	index: U16,
 }
 
 impl From<Option<DbPointer>> for DbPointerRepr {
@@ -20,19 +23,19 @@ impl From<Option<DbPointer>> for DbPointerRepr {
 			return Self::new_zeroed();
 		};
 		Self {
-			segment_num: value.segment_num,
-			page_num: value.page_num.get(),
-			index: value.index,
+			segment_num: value.segment_num.into(),
+			page_num: value.page_num.get().into(),
+			index: value.index.into(),
 		}
 	}
 }
 
 impl From<DbPointerRepr> for Option<DbPointer> {
 	fn from(value: DbPointerRepr) -> Self {
-		let page_num = NonZero::new(value.page_num)?;
+		let page_num = NonZero::new(value.page_num.get())?;
 		Some(DbPointer::new(
-			PageAddress::new(value.segment_num, page_num),
-			value.index,
+			PageAddress::new(value.segment_num.get(), page_num),
+			value.index.get(),
 		))
 	}
 }
@@ -11,6 +11,8 @@ mod document_repr;
 mod page_alloc;
 mod pages;
 
+pub(crate) use pages::swap_page_bytes;
+
 #[derive(Debug, Error)]
 pub(crate) enum DatabaseError {
 	#[error("Page format error: {0}")]
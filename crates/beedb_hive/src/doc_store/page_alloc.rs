@@ -1,12 +1,63 @@
-use std::{mem, num::NonZero};
+use std::{
+	collections::{BTreeSet, HashMap},
+	mem,
+	num::NonZero,
+};
+
+use log::warn;
 
-use crate::page_store::{PageAddress, TransactionApi};
+use crate::page_store::{PageAddress, PageStorageApi, ReadPage, TransactionApi, WritePage};
 
 use super::{
 	pages::{FreelistPage, MetaPage},
-	DatabaseError,
+	DatabaseError, DbPointer,
 };
 
+/// Maps the previous addresses of pages that were moved by a compaction to
+/// their new addresses.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct RelocationMap(HashMap<PageAddress, PageAddress>);
+
+impl RelocationMap {
+	/// Returns the current address of the page that was previously stored at
+	/// `page_address`.
+	pub fn get(&self, page_address: PageAddress) -> PageAddress {
+		self.0.get(&page_address).copied().unwrap_or(page_address)
+	}
+
+	pub fn relocate_pointer(&self, pointer: DbPointer) -> DbPointer {
+		DbPointer::new(self.get(pointer.page_address()), pointer.index())
+	}
+
+	pub fn len(&self) -> usize {
+		self.0.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.0.is_empty()
+	}
+
+	pub fn iter(&self) -> impl Iterator<Item = (PageAddress, PageAddress)> + '_ {
+		self.0.iter().map(|(from, to)| (*from, *to))
+	}
+
+	fn insert(&mut self, from: PageAddress, to: PageAddress) {
+		self.0.insert(from, to);
+	}
+
+	/// Appends relocations that happened after the ones in this map.
+	fn merge(&mut self, later: RelocationMap) {
+		for to in self.0.values_mut() {
+			if let Some(moved_to) = later.0.get(to) {
+				*to = *moved_to;
+			}
+		}
+		for (from, to) in later.0 {
+			self.0.entry(from).or_insert(to);
+		}
+	}
+}
+
 struct PageAllocator;
 
 impl PageAllocator {
@@ -14,10 +65,15 @@ impl PageAllocator {
 
 	pub fn init(t: &mut impl TransactionApi) -> Result<(), DatabaseError> {
 		let mut meta_page = MetaPage::new_unchecked(t.get_page_mut(Self::META_PAGE_ADDRESS)?);
-		meta_page.init(Self::page_address_after(Self::META_PAGE_ADDRESS))?;
+		meta_page.init(Self::META_PAGE_ADDRESS.next())?;
 		Ok(())
 	}
 
+	/// Keeps the meta page cached, since every allocation touches it.
+	pub fn pin_meta_page<S: PageStorageApi>(storage: &S) -> Result<bool, DatabaseError> {
+		Ok(storage.pin(Self::META_PAGE_ADDRESS)?)
+	}
+
 	pub fn alloc(t: &mut impl TransactionApi) -> Result<PageAddress, DatabaseError> {
 		if let Some(free_page) = Self::next_free_page(t)? {
 			return Ok(free_page);
@@ -61,6 +117,205 @@ impl PageAllocator {
 		Ok(())
 	}
 
+	/// Compacts the database while no other transactions are running, and
+	/// returns the freed space to the file system.
+	///
+	/// See [`PageAllocator::compact`] for the semantics of `relocate`.
+	pub fn compact_offline<'s, S: PageStorageApi>(
+		storage: &'s S,
+		mut relocate: impl FnMut(&mut S::Transaction<'s>, &RelocationMap) -> Result<(), DatabaseError>,
+	) -> Result<RelocationMap, DatabaseError> {
+		let mut t = storage.transaction()?;
+		let mut free_pages = BTreeSet::new();
+		let relocations = Self::compact(&mut t, None, &mut free_pages, &mut relocate)?;
+		Self::free_all(&mut t, &free_pages)?;
+		t.commit()?;
+		storage.flush_sync()?;
+		Self::truncate(storage)?;
+		Ok(relocations)
+	}
+
+	/// Compacts the database in steps that move at most `max_moves` pages each.
+	/// Every step runs in its own transaction, so concurrent transactions are
+	/// only ever blocked briefly.
+	///
+	/// The freelist is only read once; the free pages that are left over are
+	/// kept aside between the steps, and put back on the freelist by the last
+	/// one. If the database crashes in between, they are lost until the
+	/// next compaction.
+	///
+	/// See [`PageAllocator::compact`] for the semantics of `relocate`.
+	pub fn compact_online<'s, S: PageStorageApi>(
+		storage: &'s S,
+		max_moves: usize,
+		mut relocate: impl FnMut(&mut S::Transaction<'s>, &RelocationMap) -> Result<(), DatabaseError>,
+	) -> Result<RelocationMap, DatabaseError> {
+		let mut relocations = RelocationMap::default();
+		let mut free_pages = BTreeSet::new();
+		loop {
+			// A failed step doesn't change the free pages that were kept aside.
+			let mut step_free_pages = free_pages.clone();
+			let result =
+				Self::compact_step(storage, max_moves, &mut step_free_pages, &mut relocate);
+			let step = match result {
+				Ok(step) => step,
+				Err(err) => {
+					Self::restore_free_pages(storage, &free_pages);
+					return Err(err);
+				}
+			};
+			free_pages = step_free_pages;
+
+			let done = step.len() < max_moves;
+			relocations.merge(step);
+			if done {
+				break;
+			}
+		}
+		// The moved pages have to be on disk before the pages they were moved
+		// from are cut off.
+		storage.flush_sync()?;
+		Self::truncate(storage)?;
+		Ok(relocations)
+	}
+
+	/// Runs a single step of [`PageAllocator::compact_online`]. The last step
+	/// puts the remaining free pages back on the freelist.
+	fn compact_step<'s, S: PageStorageApi>(
+		storage: &'s S,
+		max_moves: usize,
+		free_pages: &mut BTreeSet<PageAddress>,
+		relocate: &mut impl FnMut(&mut S::Transaction<'s>, &RelocationMap) -> Result<(), DatabaseError>,
+	) -> Result<RelocationMap, DatabaseError> {
+		let mut t = storage.transaction()?;
+		let step = Self::compact(&mut t, Some(max_moves), free_pages, relocate)?;
+		if step.len() < max_moves {
+			Self::free_all(&mut t, free_pages)?;
+		}
+		t.commit()?;
+		Ok(step)
+	}
+
+	/// Puts the free pages that were kept aside by a failed compaction back
+	/// on the freelist. Errors are only logged, since the compaction failed
+	/// already.
+	fn restore_free_pages<S: PageStorageApi>(storage: &S, free_pages: &BTreeSet<PageAddress>) {
+		if free_pages.is_empty() {
+			return;
+		}
+		let result = storage
+			.transaction()
+			.map_err(DatabaseError::from)
+			.and_then(|mut t| {
+				Self::free_all(&mut t, free_pages)?;
+				t.commit()?;
+				Ok(())
+			});
+		if let Err(err) = result {
+			warn!(
+				"{} free pages were lost after a failed compaction: {err}",
+				free_pages.len()
+			);
+		}
+	}
+
+	/// Moves live pages from the end of the allocated range into free slots,
+	/// and shrinks the allocated range accordingly. If `max_moves` is given, at
+	/// most that many pages are moved.
+	///
+	/// The pages on the freelist are added to `free_pages`, which are used as
+	/// the free slots. The ones that are left over stay in `free_pages`, and
+	/// have to be freed again with [`PageAllocator::free_all`] eventually.
+	///
+	/// The moved pages are passed to `relocate` as part of the same
+	/// transaction, which has to update all references to them.
+	pub fn compact<T: TransactionApi>(
+		t: &mut T,
+		max_moves: Option<usize>,
+		free_pages: &mut BTreeSet<PageAddress>,
+		relocate: &mut impl FnMut(&mut T, &RelocationMap) -> Result<(), DatabaseError>,
+	) -> Result<RelocationMap, DatabaseError> {
+		free_pages.append(&mut Self::drain_freelist(t)?);
+		let mut end = Self::meta_page(t)?.get_next_page_address()?;
+		let mut relocations = RelocationMap::default();
+
+		while let Some(last) = Self::page_address_before(end) {
+			if free_pages.remove(&last) {
+				end = last;
+				continue;
+			}
+			if max_moves.is_some_and(|max_moves| relocations.len() >= max_moves) {
+				break;
+			}
+			// All free pages are below `end`, so this is always before `last`.
+			let Some(slot) = free_pages.pop_first() else {
+				break;
+			};
+			Self::move_page(t, last, slot)?;
+			relocations.insert(last, slot);
+			end = last;
+		}
+
+		relocate(t, &relocations)?;
+
+		Self::meta_page_mut(t)?.set_next_page_address(end)?;
+		Ok(relocations)
+	}
+
+	fn free_all(
+		t: &mut impl TransactionApi,
+		free_pages: &BTreeSet<PageAddress>,
+	) -> Result<(), DatabaseError> {
+		// Freeing in reverse order means that the lowest addresses are
+		// allocated first.
+		for page_address in free_pages.iter().rev() {
+			Self::free(t, *page_address)?;
+		}
+		Ok(())
+	}
+
+	fn truncate<S: PageStorageApi>(storage: &S) -> Result<(), DatabaseError> {
+		// Holding the lock on the meta page keeps other transactions from
+		// allocating new pages while the storage is truncated.
+		let mut t = storage.transaction()?;
+		let end = Self::meta_page_mut(&mut t)?.get_next_page_address()?;
+		storage.truncate(end)?;
+		t.commit()?;
+		Ok(())
+	}
+
+	/// Removes all pages from the freelist, including the freelist pages
+	/// themselves.
+	fn drain_freelist(t: &mut impl TransactionApi) -> Result<BTreeSet<PageAddress>, DatabaseError> {
+		let mut free_pages = BTreeSet::new();
+		let mut next = Self::meta_page(t)?.get_freelist_head()?;
+		while let Some(freelist_page_address) = next {
+			let freelist_page = FreelistPage::new(t.get_page(freelist_page_address)?)?;
+			for i in 0..freelist_page.get_length()? {
+				if let Some(page_address) = freelist_page.get_item(i)? {
+					free_pages.insert(page_address);
+				}
+			}
+			next = freelist_page.get_next_page_address()?;
+			free_pages.insert(freelist_page_address);
+		}
+		Self::meta_page_mut(t)?.set_freelist_head(None)?;
+		Ok(free_pages)
+	}
+
+	fn move_page(
+		t: &mut impl TransactionApi,
+		from: PageAddress,
+		to: PageAddress,
+	) -> Result<(), DatabaseError> {
+		let page = t.get_page(from)?;
+		let mut buf = vec![0; page.size()];
+		page.read(0, &mut buf)?;
+		drop(page);
+		t.get_page_mut(to)?.write(0, &buf)?;
+		Ok(())
+	}
+
 	fn next_free_page(t: &mut impl TransactionApi) -> Result<Option<PageAddress>, DatabaseError> {
 		let Some(freelist_head_id) = Self::meta_page(t)?.get_freelist_head()? else {
 			return Ok(None);
@@ -80,25 +335,20 @@ impl PageAllocator {
 	fn next_uninit_page(t: &mut impl TransactionApi) -> Result<PageAddress, DatabaseError> {
 		let mut meta_page = Self::meta_page_mut(t)?;
 		let page_address = meta_page.get_next_page_address()?;
-		meta_page.set_next_page_address(Self::page_address_after(page_address))?;
+		meta_page.set_next_page_address(page_address.next())?;
 		Ok(page_address)
 	}
 
-	fn page_address_after(page_address: PageAddress) -> PageAddress {
-		if page_address.page_num.get() == u16::MAX {
-			PageAddress::new(
-				page_address
-					.segment_num
-					.checked_add(1)
-					.expect("You've somehow managed to exhaust the space of page IDs ¯\\_(ツ)_/¯"),
-				NonZero::new(1).unwrap(),
-			)
-		} else {
-			PageAddress::new(
-				page_address.segment_num,
-				page_address.page_num.checked_add(1).unwrap(),
-			)
-		}
+	/// Returns the page before the given one, unless that is the meta page.
+	fn page_address_before(page_address: PageAddress) -> Option<PageAddress> {
+		let previous = match NonZero::new(page_address.page_num.get() - 1) {
+			Some(page_num) => PageAddress::new(page_address.segment_num, page_num),
+			None => PageAddress::new(
+				page_address.segment_num.checked_sub(1)?,
+				NonZero::new(u16::MAX).unwrap(),
+			),
+		};
+		(previous != Self::META_PAGE_ADDRESS).then_some(previous)
 	}
 
 	fn meta_page<T: TransactionApi>(t: &mut T) -> Result<MetaPage<T::Page<'_>>, DatabaseError> {
@@ -114,11 +364,20 @@ impl PageAllocator {
 
 #[cfg(test)]
 mod tests {
+	use std::{fs, path::Path, sync::Arc};
+
 	use crate::{
+		consts::DEFAULT_PAGE_SIZE as PAGE_SIZE,
 		doc_store::pages::PageKind,
-		page_store::{test_helpers::page_address, MockPage, MockPageMut, MockTransactionApi},
+		files::{segment::PAGE_BODY_SIZE, DatabaseFolder},
+		page_store::{
+			test_helpers::page_address, MockPage, MockPageMut, MockTransactionApi, PageStorage,
+		},
+		tasks::Runtime,
 	};
+	use futures::executor::ThreadPool;
 	use mockall::{predicate::*, Sequence};
+	use tempfile::tempdir;
 
 	use super::*;
 
@@ -131,6 +390,7 @@ mod tests {
 			.with(eq(page_address!(0, 1)))
 			.returning(|_| {
 				let mut page = MockPageMut::new();
+				page.expect_size().return_const(PAGE_BODY_SIZE);
 				page.expect_write()
 					.with(eq(0), eq([PageKind::FreelistMeta as u8]))
 					.once()
@@ -144,8 +404,8 @@ mod tests {
 					.with(
 						eq(7),
 						eq([
-							0_u32.to_ne_bytes().as_slice(),
-							2_u16.to_ne_bytes().as_slice(),
+							0_u32.to_le_bytes().as_slice(),
+							2_u16.to_le_bytes().as_slice(),
 						]
 						.concat()),
 					)
@@ -170,6 +430,7 @@ mod tests {
 			.with(eq(page_address!(0, 1)))
 			.returning(|_| {
 				let mut page = MockPage::new();
+				page.expect_size().return_const(PAGE_BODY_SIZE);
 				// - check the page type
 				page.expect_read()
 					.once()
@@ -185,8 +446,8 @@ mod tests {
 					.returning(|_, buf| {
 						buf.copy_from_slice(
 							&[
-								0x24_u32.to_ne_bytes().as_slice(),
-								0x25_u16.to_ne_bytes().as_slice(),
+								0x24_u32.to_le_bytes().as_slice(),
+								0x25_u16.to_le_bytes().as_slice(),
 							]
 							.concat(),
 						);
@@ -202,6 +463,7 @@ mod tests {
 			.with(eq(page_address!(0x24, 0x25)))
 			.returning(|_| {
 				let mut page = MockPageMut::new();
+				page.expect_size().return_const(PAGE_BODY_SIZE);
 				// - check the page type
 				page.expect_read()
 					.once()
@@ -215,7 +477,7 @@ mod tests {
 					.once()
 					.with(eq(7), always())
 					.returning(|_, buf| {
-						buf.copy_from_slice(&60_u16.to_ne_bytes());
+						buf.copy_from_slice(&60_u16.to_le_bytes());
 						Ok(())
 					});
 				// - read the last item of the freelist head page (None)
@@ -233,8 +495,8 @@ mod tests {
 					.returning(|_, buf| {
 						buf.copy_from_slice(
 							&[
-								0x69_u32.to_ne_bytes().as_slice(),
-								0x420_u16.to_ne_bytes().as_slice(),
+								0x69_u32.to_le_bytes().as_slice(),
+								0x420_u16.to_le_bytes().as_slice(),
 							]
 							.concat(),
 						);
@@ -244,7 +506,7 @@ mod tests {
 				//   skipped)
 				page.expect_write()
 					.once()
-					.with(eq(7), eq(58_u16.to_ne_bytes()))
+					.with(eq(7), eq(58_u16.to_le_bytes()))
 					.returning(|_, _| Ok(()));
 				Ok(page)
 			});
@@ -269,6 +531,7 @@ mod tests {
 			.with(eq(page_address!(0, 1)))
 			.returning(|_| {
 				let mut page = MockPage::new();
+				page.expect_size().return_const(PAGE_BODY_SIZE);
 				// - check the page type
 				page.expect_read()
 					.once()
@@ -284,8 +547,8 @@ mod tests {
 					.returning(|_, buf| {
 						buf.copy_from_slice(
 							&[
-								0x24_u32.to_ne_bytes().as_slice(),
-								0x25_u16.to_ne_bytes().as_slice(),
+								0x24_u32.to_le_bytes().as_slice(),
+								0x25_u16.to_le_bytes().as_slice(),
 							]
 							.concat(),
 						);
@@ -301,6 +564,7 @@ mod tests {
 			.with(eq(page_address!(0x24, 0x25)))
 			.returning(|_| {
 				let mut page = MockPageMut::new();
+				page.expect_size().return_const(PAGE_BODY_SIZE);
 				// - check the page type
 				page.expect_read()
 					.once()
@@ -314,7 +578,7 @@ mod tests {
 					.once()
 					.with(eq(7), always())
 					.returning(|_, buf| {
-						buf.copy_from_slice(&0_u16.to_ne_bytes());
+						buf.copy_from_slice(&0_u16.to_le_bytes());
 						Ok(())
 					});
 				// - read the next page ID in the freelist (60:70)
@@ -324,8 +588,8 @@ mod tests {
 					.returning(|_, buf| {
 						buf.copy_from_slice(
 							&[
-								0x60_u32.to_ne_bytes().as_slice(),
-								0x70_u16.to_ne_bytes().as_slice(),
+								0x60_u32.to_le_bytes().as_slice(),
+								0x70_u16.to_le_bytes().as_slice(),
 							]
 							.concat(),
 						);
@@ -341,6 +605,7 @@ mod tests {
 			.with(eq(page_address!(0, 1)))
 			.returning(|_| {
 				let mut page = MockPageMut::new();
+				page.expect_size().return_const(PAGE_BODY_SIZE);
 				// - check the page type
 				page.expect_read()
 					.once()
@@ -355,8 +620,8 @@ mod tests {
 					.with(
 						eq(1),
 						eq([
-							0x60_u32.to_ne_bytes().as_slice(),
-							0x70_u16.to_ne_bytes().as_slice(),
+							0x60_u32.to_le_bytes().as_slice(),
+							0x70_u16.to_le_bytes().as_slice(),
 						]
 						.concat()),
 					)
@@ -384,6 +649,7 @@ mod tests {
 			.with(eq(page_address!(0, 1)))
 			.returning(|_| {
 				let mut page = MockPage::new();
+				page.expect_size().return_const(PAGE_BODY_SIZE);
 				// - check the page type
 				page.expect_read()
 					.once()
@@ -410,6 +676,7 @@ mod tests {
 			.with(eq(page_address!(0, 1)))
 			.returning(|_| {
 				let mut page = MockPageMut::new();
+				page.expect_size().return_const(PAGE_BODY_SIZE);
 				// - check the page type
 				page.expect_read()
 					.once()
@@ -425,8 +692,8 @@ mod tests {
 					.returning(|_, buf| {
 						buf.copy_from_slice(
 							&[
-								0x2000_u32.to_ne_bytes().as_slice(),
-								0x3_u16.to_ne_bytes().as_slice(),
+								0x2000_u32.to_le_bytes().as_slice(),
+								0x3_u16.to_le_bytes().as_slice(),
 							]
 							.concat(),
 						);
@@ -438,8 +705,8 @@ mod tests {
 					.with(
 						eq(7),
 						eq([
-							0x2000_u32.to_ne_bytes().as_slice(),
-							0x4_u16.to_ne_bytes().as_slice(),
+							0x2000_u32.to_le_bytes().as_slice(),
+							0x4_u16.to_le_bytes().as_slice(),
 						]
 						.concat()),
 					)
@@ -467,6 +734,7 @@ mod tests {
 			.with(eq(page_address!(0, 1)))
 			.returning(|_| {
 				let mut page = MockPage::new();
+				page.expect_size().return_const(PAGE_BODY_SIZE);
 				// - check the page type
 				page.expect_read()
 					.once()
@@ -493,6 +761,7 @@ mod tests {
 			.with(eq(page_address!(0, 1)))
 			.returning(|_| {
 				let mut page = MockPageMut::new();
+				page.expect_size().return_const(PAGE_BODY_SIZE);
 				// - check the page type
 				page.expect_read()
 					.once()
@@ -508,8 +777,8 @@ mod tests {
 					.returning(|_, buf| {
 						buf.copy_from_slice(
 							&[
-								0x2000_u32.to_ne_bytes().as_slice(),
-								0xffff_u16.to_ne_bytes().as_slice(),
+								0x2000_u32.to_le_bytes().as_slice(),
+								0xffff_u16.to_le_bytes().as_slice(),
 							]
 							.concat(),
 						);
@@ -521,8 +790,8 @@ mod tests {
 					.with(
 						eq(7),
 						eq([
-							0x2001_u32.to_ne_bytes().as_slice(),
-							0x1_u16.to_ne_bytes().as_slice(),
+							0x2001_u32.to_le_bytes().as_slice(),
+							0x1_u16.to_le_bytes().as_slice(),
 						]
 						.concat()),
 					)
@@ -550,6 +819,7 @@ mod tests {
 			.with(eq(page_address!(0, 1)))
 			.returning(|_| {
 				let mut page = MockPage::new();
+				page.expect_size().return_const(PAGE_BODY_SIZE);
 				// - check the page type
 				page.expect_read()
 					.once()
@@ -565,8 +835,8 @@ mod tests {
 					.returning(|_, buf| {
 						buf.copy_from_slice(
 							&[
-								0x2000_u32.to_ne_bytes().as_slice(),
-								0x1_u16.to_ne_bytes().as_slice(),
+								0x2000_u32.to_le_bytes().as_slice(),
+								0x1_u16.to_le_bytes().as_slice(),
 							]
 							.concat(),
 						);
@@ -582,6 +852,7 @@ mod tests {
 			.with(eq(page_address!(0x2000, 0x1)))
 			.returning(|_| {
 				let mut page = MockPageMut::new();
+				page.expect_size().return_const(PAGE_BODY_SIZE);
 				// - check the page type
 				page.expect_read()
 					.once()
@@ -594,7 +865,7 @@ mod tests {
 				page.expect_read()
 					.with(eq(7), always())
 					.returning(|_, buf| {
-						buf.copy_from_slice(&2_u16.to_ne_bytes());
+						buf.copy_from_slice(&2_u16.to_le_bytes());
 						Ok(())
 					});
 
@@ -604,8 +875,8 @@ mod tests {
 					.with(
 						eq(21),
 						eq([
-							0x69_u32.to_ne_bytes().as_slice(),
-							0x420_u16.to_ne_bytes().as_slice(),
+							0x69_u32.to_le_bytes().as_slice(),
+							0x420_u16.to_le_bytes().as_slice(),
 						]
 						.concat()),
 					)
@@ -614,7 +885,7 @@ mod tests {
 				// - increment the page length
 				page.expect_write()
 					.once()
-					.with(eq(7), eq(3_u16.to_ne_bytes()))
+					.with(eq(7), eq(3_u16.to_le_bytes()))
 					.returning(|_, _| Ok(()));
 				Ok(page)
 			});
@@ -636,6 +907,7 @@ mod tests {
 			.with(eq(page_address!(0, 1)))
 			.returning(|_| {
 				let mut page = MockPage::new();
+				page.expect_size().return_const(PAGE_BODY_SIZE);
 				// - check the page type
 				page.expect_read()
 					.once()
@@ -662,6 +934,7 @@ mod tests {
 			.with(eq(page_address!(0x69, 0x420)))
 			.returning(|_| {
 				let mut page = MockPageMut::new();
+				page.expect_size().return_const(PAGE_BODY_SIZE);
 				// - set the page type
 				page.expect_write()
 					.once()
@@ -688,6 +961,7 @@ mod tests {
 			.with(eq(page_address!(0, 1)))
 			.returning(|_| {
 				let mut page = MockPageMut::new();
+				page.expect_size().return_const(PAGE_BODY_SIZE);
 				// - check the page type
 				page.expect_read()
 					.once()
@@ -702,8 +976,8 @@ mod tests {
 					.with(
 						eq(1),
 						eq([
-							0x69_u32.to_ne_bytes().as_slice(),
-							0x420_u16.to_ne_bytes().as_slice(),
+							0x69_u32.to_le_bytes().as_slice(),
+							0x420_u16.to_le_bytes().as_slice(),
 						]
 						.concat()),
 					)
@@ -728,6 +1002,7 @@ mod tests {
 			.with(eq(page_address!(0, 1)))
 			.returning(|_| {
 				let mut page = MockPage::new();
+				page.expect_size().return_const(PAGE_BODY_SIZE);
 				// - check the page type
 				page.expect_read()
 					.once()
@@ -743,8 +1018,8 @@ mod tests {
 					.returning(|_, buf| {
 						buf.copy_from_slice(
 							&[
-								0x2000_u32.to_ne_bytes().as_slice(),
-								0x1_u16.to_ne_bytes().as_slice(),
+								0x2000_u32.to_le_bytes().as_slice(),
+								0x1_u16.to_le_bytes().as_slice(),
 							]
 							.concat(),
 						);
@@ -760,6 +1035,7 @@ mod tests {
 			.with(eq(page_address!(0x2000, 0x1)))
 			.returning(|_| {
 				let mut page = MockPageMut::new();
+				page.expect_size().return_const(PAGE_BODY_SIZE);
 				// - set the page type
 				page.expect_read()
 					.once()
@@ -773,7 +1049,10 @@ mod tests {
 					.once()
 					.with(eq(7), always())
 					.returning(|_, buf| {
-						buf.copy_from_slice(&(FreelistPage::<()>::NUM_SLOTS as u16).to_ne_bytes());
+						buf.copy_from_slice(
+							&(FreelistPage::<()>::num_slots_for(PAGE_BODY_SIZE) as u16)
+								.to_le_bytes(),
+						);
 						Ok(())
 					});
 				Ok(page)
@@ -786,6 +1065,7 @@ mod tests {
 			.with(eq(page_address!(0x69, 0x420)))
 			.returning(|_| {
 				let mut page = MockPageMut::new();
+				page.expect_size().return_const(PAGE_BODY_SIZE);
 				// - set the page type
 				page.expect_write()
 					.once()
@@ -802,8 +1082,8 @@ mod tests {
 					.with(
 						eq(1),
 						eq([
-							0x2000_u32.to_ne_bytes().as_slice(),
-							0x1_u16.to_ne_bytes().as_slice(),
+							0x2000_u32.to_le_bytes().as_slice(),
+							0x1_u16.to_le_bytes().as_slice(),
 						]
 						.concat()),
 					)
@@ -824,6 +1104,7 @@ mod tests {
 			.with(eq(page_address!(0, 1)))
 			.returning(|_| {
 				let mut page = MockPageMut::new();
+				page.expect_size().return_const(PAGE_BODY_SIZE);
 				// - check the page type
 				page.expect_read()
 					.once()
@@ -838,8 +1119,8 @@ mod tests {
 					.with(
 						eq(1),
 						eq([
-							0x69_u32.to_ne_bytes().as_slice(),
-							0x420_u16.to_ne_bytes().as_slice(),
+							0x69_u32.to_le_bytes().as_slice(),
+							0x420_u16.to_le_bytes().as_slice(),
 						]
 						.concat()),
 					)
@@ -850,4 +1131,104 @@ mod tests {
 		// when
 		PageAllocator::free(&mut t, page_address!(0x69, 0x420)).unwrap();
 	}
+
+	fn create_fragmented_storage(path: &Path) -> PageStorage {
+		let folder = Arc::new(DatabaseFolder::open(path.to_path_buf()).unwrap());
+		let runtime = Runtime::from(Arc::new(ThreadPool::new().unwrap()));
+		let storage = PageStorage::create(folder, runtime, &Default::default()).unwrap();
+
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		for _ in 0..8 {
+			let page_address = PageAllocator::alloc(&mut t).unwrap();
+			t.get_page_mut(page_address)
+				.unwrap()
+				.write(100, &page_address.page_num.get().to_le_bytes())
+				.unwrap();
+		}
+		for page_num in [3, 5, 6] {
+			PageAllocator::free(&mut t, page_address!(0, page_num)).unwrap();
+		}
+		t.commit().unwrap();
+		storage
+	}
+
+	fn read_marker(storage: &PageStorage, page_address: PageAddress) -> u16 {
+		let mut buf = [0; 2];
+		storage
+			.get_page(page_address)
+			.unwrap()
+			.read(100, &mut buf)
+			.unwrap();
+		u16::from_ne_bytes(buf)
+	}
+
+	fn assert_compacted(storage: &PageStorage, path: &Path, relocations: &RelocationMap) {
+		assert_eq!(
+			relocations.iter().collect::<HashMap<_, _>>(),
+			HashMap::from([
+				(page_address!(0, 9), page_address!(0, 3)),
+				(page_address!(0, 8), page_address!(0, 5)),
+				(page_address!(0, 7), page_address!(0, 6)),
+			])
+		);
+		assert_eq!(read_marker(storage, page_address!(0, 3)), 9);
+		assert_eq!(read_marker(storage, page_address!(0, 5)), 8);
+		assert_eq!(read_marker(storage, page_address!(0, 6)), 7);
+
+		let mut t = storage.transaction().unwrap();
+		assert_eq!(
+			PageAllocator::meta_page(&mut t)
+				.unwrap()
+				.get_next_page_address()
+				.unwrap(),
+			page_address!(0, 7)
+		);
+		t.commit().unwrap();
+
+		// Pages past the end aren't written back anymore once they are cut off.
+		storage.flush_sync().unwrap();
+		let segment_len = fs::metadata(path.join("segments/0")).unwrap().len();
+		assert_eq!(segment_len, 7 * PAGE_SIZE as u64);
+	}
+
+	#[test]
+	fn compact_offline() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let storage = create_fragmented_storage(tempdir.path());
+		storage.flush_sync().unwrap();
+
+		// when
+		let mut relocated = RelocationMap::default();
+		let relocations = PageAllocator::compact_offline(&storage, |_, step| {
+			relocated.merge(step.clone());
+			Ok(())
+		})
+		.unwrap();
+
+		// then
+		assert_eq!(relocated, relocations);
+		assert_compacted(&storage, tempdir.path(), &relocations);
+	}
+
+	#[test]
+	fn compact_online() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let storage = create_fragmented_storage(tempdir.path());
+
+		// when
+		let mut num_steps = 0;
+		let relocations = PageAllocator::compact_online(&storage, 1, |_, step| {
+			assert!(step.len() <= 1);
+			num_steps += 1;
+			Ok(())
+		})
+		.unwrap();
+
+		// then
+		assert_eq!(num_steps, 4);
+		assert_compacted(&storage, tempdir.path(), &relocations);
+	}
 }
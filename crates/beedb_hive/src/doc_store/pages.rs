@@ -3,12 +3,14 @@ use std::{
 	num::NonZeroU16,
 };
 
-use zerocopy::{FromBytes, FromZeros, Immutable, IntoBytes};
+use zerocopy::{
+	little_endian::{U16, U32},
+	FromBytes, FromZeros, Immutable, IntoBytes,
+};
 
 use crate::{
-	files::segment::PAGE_BODY_SIZE,
 	page_store::{PageAddress, ReadPage, WritePage},
-	repr::Repr,
+	repr::{impl_swap_bytes, Repr, SwapBytes},
 };
 
 use super::DatabaseError;
@@ -68,37 +70,42 @@ impl Repr<PageHeader> for PageHeaderRepr {
 #[derive(Debug, Immutable, IntoBytes, FromBytes)]
 #[repr(C, packed)]
 struct PageAddressRepr {
-	segment_num: u32,
-	page_num: u16,
+	segment_num: U32,
+	page_num: U16,
 }
 
 impl TryFrom<PageAddressRepr> for PageAddress {
 	type Error = DatabaseError;
 
 	fn try_from(value: PageAddressRepr) -> Result<Self, DatabaseError> {
-		let Some(page_num) = NonZeroU16::new(value.page_num) else {
+		let Some(page_num) = NonZeroU16::new(value.page_num.get()) else {
 			return Err(DatabaseError::PageFormat(
 				"Found invalid page number '0'!".to_string(),
 			));
 		};
-		Ok(PageAddress::new(value.segment_num, page_num))
+		Ok(PageAddress::new(value.segment_num.get(), page_num))
 	}
 }
 
 impl From<PageAddress> for PageAddressRepr {
 	fn from(value: PageAddress) -> Self {
 		Self {
-			segment_num: value.segment_num,
-			page_num: value.page_num.get(),
+			segment_num: value.segment_num.into(),
+			page_num: value.page_num.get().into(),
 		}
 	}
 }
 
+impl_swap_bytes!(PageAddressRepr {
+	segment_num,
+	page_num
+});
+
 impl From<PageAddressRepr> for Option<PageAddress> {
 	fn from(value: PageAddressRepr) -> Self {
 		Some(PageAddress::new(
-			value.segment_num,
-			NonZeroU16::new(value.page_num)?,
+			value.segment_num.get(),
+			NonZeroU16::new(value.page_num.get())?,
 		))
 	}
 }
@@ -141,7 +148,7 @@ macro_rules! read_array_section {
 	($page:expr, $page_repr:ident.$field:ident, $field_repr:ident, $index:expr) => {{
 		let mut repr = <$field_repr as FromZeros>::new_zeroed();
 		let index = mem::offset_of!($page_repr, $field) + mem::size_of::<$field_repr>() * $index;
-		if index + mem::size_of::<$field_repr>() > PAGE_BODY_SIZE {
+		if index + mem::size_of::<$field_repr>() > ReadPage::size(&$page) {
 			Err(DatabaseError::PageOutOfBounds)
 		} else {
 			ReadPage::read(
@@ -159,7 +166,7 @@ macro_rules! write_array_section {
 	($page:expr, $page_repr:ident.$field:ident, $field_repr:ident, $index:expr, $value:expr) => {{
 		let repr: $field_repr = $value.into();
 		let index = mem::offset_of!($page_repr, $field) + mem::size_of::<$field_repr>() * $index;
-		if index + mem::size_of::<$field_repr>() > PAGE_BODY_SIZE {
+		if index + mem::size_of::<$field_repr>() > ReadPage::size(&$page) {
 			Ok(())
 		} else {
 			WritePage::write(
@@ -172,6 +179,59 @@ macro_rules! write_array_section {
 	}};
 }
 
+/// Converts the integers in a page body from big-endian to little-endian byte
+/// order, for segments that were written on big-endian machines. The
+/// contents of records are stored as they were given, so they stay as they
+/// are.
+pub(crate) fn swap_page_bytes(body: &mut [u8]) -> Result<(), DatabaseError> {
+	let header = PageHeader::try_from(PageHeaderRepr { kind: body[0] })?;
+	match header.kind {
+		PageKind::FreelistMeta => {
+			swap_section::<PageAddressRepr>(body, offset_of!(MetaPageFormat, freelist_head))?;
+			swap_section::<PageAddressRepr>(body, offset_of!(MetaPageFormat, next_page_address))?;
+		}
+		PageKind::FreelistBlock => {
+			swap_section::<PageAddressRepr>(
+				body,
+				offset_of!(FreelistPageFormat, next_page_address),
+			)?;
+			let length = swap_section::<U16>(body, offset_of!(FreelistPageFormat, length))?;
+			for index in 0..usize::from(length.get()) {
+				swap_section::<PageAddressRepr>(
+					body,
+					offset_of!(FreelistPageFormat, items) + size_of::<PageAddressRepr>() * index,
+				)?;
+			}
+		}
+		PageKind::Records => {
+			swap_section::<U16>(body, offset_of!(RecordsPageFormat, record_length))?;
+			let num_records =
+				swap_section::<U16>(body, offset_of!(RecordsPageFormat, num_records))?;
+			for index in 0..usize::from(num_records.get()) {
+				swap_section::<U16>(
+					body,
+					offset_of!(RecordsPageFormat, offsets) + size_of::<U16>() * index,
+				)?;
+			}
+		}
+	}
+	Ok(())
+}
+
+/// Swaps the bytes of the section at `offset`, and returns its new value.
+fn swap_section<R>(body: &mut [u8], offset: usize) -> Result<R, DatabaseError>
+where
+	R: FromBytes + IntoBytes + Immutable + SwapBytes,
+{
+	let Some(bytes) = body.get_mut(offset..offset + size_of::<R>()) else {
+		return Err(DatabaseError::PageOutOfBounds);
+	};
+	let mut repr = R::read_from_bytes(bytes).unwrap();
+	repr.swap_bytes();
+	bytes.copy_from_slice(repr.as_bytes());
+	Ok(repr)
+}
+
 #[repr(C, packed)]
 struct MetaPageFormat {
 	header: PageHeaderRepr,
@@ -241,23 +301,22 @@ impl<P: WritePage> MetaPage<P> {
 struct FreelistPageFormat {
 	header: PageHeaderRepr,
 	next_page_address: PageAddressRepr,
-	length: u16,
+	length: U16,
 	items: [PageAddressRepr; 0],
 }
 
-impl FreelistPageFormat {
-	const NUM_SLOTS: usize =
-		(PAGE_BODY_SIZE - offset_of!(FreelistPageFormat, items)) / size_of::<PageAddressRepr>();
-}
-
 pub(super) struct FreelistPage<P>(P);
 
 impl<P> FreelistPage<P> {
-	pub const NUM_SLOTS: usize = FreelistPageFormat::NUM_SLOTS;
-
 	pub fn new_unchecked(page: P) -> Self {
 		Self(page)
 	}
+
+	/// The number of page addresses that fit into a page body of the given
+	/// size.
+	pub const fn num_slots_for(page_size: usize) -> usize {
+		(page_size - offset_of!(FreelistPageFormat, items)) / size_of::<PageAddressRepr>()
+	}
 }
 
 impl<P: ReadPage> FreelistPage<P> {
@@ -281,11 +340,16 @@ impl<P: ReadPage> FreelistPage<P> {
 	}
 
 	pub fn get_length(&self) -> Result<usize, DatabaseError> {
-		read_section!(self.0, FreelistPageFormat.length, u16)
+		read_section!(self.0, FreelistPageFormat.length, U16)
+	}
+
+	/// The number of page addresses that fit into the page.
+	pub fn num_slots(&self) -> usize {
+		Self::num_slots_for(self.0.size())
 	}
 
 	pub fn is_full(&self) -> Result<bool, DatabaseError> {
-		Ok(self.get_length()? >= FreelistPageFormat::NUM_SLOTS)
+		Ok(self.get_length()? >= self.num_slots())
 	}
 
 	pub fn get_item(&self, index: usize) -> Result<Option<PageAddress>, DatabaseError> {
@@ -322,9 +386,11 @@ impl<P: WritePage> FreelistPage<P> {
 
 	fn set_length(&mut self, value: usize) -> Result<(), DatabaseError> {
 		let repr = u16::try_from(value).expect("Freelist page length must be 16-bit!");
-		write_section!(self.0, FreelistPageFormat.length, u16, repr)
+		write_section!(self.0, FreelistPageFormat.length, U16, repr)
 	}
+}
 
+impl<P: ReadPage + WritePage> FreelistPage<P> {
 	fn set_item(&mut self, index: usize, value: Option<PageAddress>) -> Result<(), DatabaseError> {
 		write_array_section!(
 			self.0,
@@ -334,9 +400,7 @@ impl<P: WritePage> FreelistPage<P> {
 			value
 		)
 	}
-}
 
-impl<P: ReadPage + WritePage> FreelistPage<P> {
 	pub fn push_item(&mut self, value: PageAddress) -> Result<(), DatabaseError> {
 		let index = self.get_length()?;
 		self.set_item(index, Some(value))?;
@@ -362,9 +426,9 @@ impl<P: ReadPage + WritePage> FreelistPage<P> {
 #[repr(C, packed)]
 struct RecordsPageFormat {
 	header: PageHeaderRepr,
-	record_length: u16,
-	num_records: u16,
-	offsets: [u16; 0],
+	record_length: U16,
+	num_records: U16,
+	offsets: [U16; 0],
 	// records themselves stacked end-to-front afterwards
 }
 
@@ -389,17 +453,17 @@ impl<P: ReadPage> RecordsPage<P> {
 	}
 
 	pub fn get_record_length(&self) -> Result<usize, DatabaseError> {
-		read_section!(self.0, RecordsPageFormat.record_length, u16)
+		read_section!(self.0, RecordsPageFormat.record_length, U16)
 	}
 
 	pub fn get_num_records(&self) -> Result<usize, DatabaseError> {
-		read_section!(self.0, RecordsPageFormat.num_records, u16)
+		read_section!(self.0, RecordsPageFormat.num_records, U16)
 	}
 
 	pub fn get_record(&self, index: usize, buf: &mut [u8]) -> Result<(), DatabaseError> {
 		let len = self.get_record_length()?;
 		let offset = self.get_offset_at(index)?;
-		if offset + len > PAGE_BODY_SIZE {
+		if offset + len > self.0.size() {
 			return Err(DatabaseError::PageOutOfBounds);
 		}
 		self.0.read(offset, &mut buf[0..len])?;
@@ -410,12 +474,12 @@ impl<P: ReadPage> RecordsPage<P> {
 		if index >= self.get_num_records()? {
 			return Err(DatabaseError::InvalidRecordIndex);
 		}
-		read_array_section!(self.0, RecordsPageFormat.offsets, u16, index)
+		read_array_section!(self.0, RecordsPageFormat.offsets, U16, index)
 	}
 
 	fn get_first_record_offset(&self) -> Result<usize, DatabaseError> {
 		let offset =
-			offset_of!(RecordsPageFormat, offsets) + self.get_num_records()? * size_of::<u16>();
+			offset_of!(RecordsPageFormat, offsets) + self.get_num_records()? * size_of::<U16>();
 		Ok(offset)
 	}
 }
@@ -423,7 +487,7 @@ impl<P: ReadPage + WritePage> RecordsPage<P> {
 	pub fn set_record(&mut self, index: usize, buf: &[u8]) -> Result<(), DatabaseError> {
 		let len = self.get_record_length()?;
 		let offset = self.get_offset_at(index)?;
-		if offset + len > PAGE_BODY_SIZE {
+		if offset + len > self.0.size() {
 			return Err(DatabaseError::PageOutOfBounds);
 		}
 		self.0.write(offset, &buf[0..len])?;
@@ -432,7 +496,7 @@ impl<P: ReadPage + WritePage> RecordsPage<P> {
 
 	fn set_record_length(&mut self, length: usize) -> Result<(), DatabaseError> {
 		let repr: u16 = length.try_into().expect("Record length must be 16-bit!");
-		write_section!(self.0, RecordsPageFormat.record_length, u16, repr)
+		write_section!(self.0, RecordsPageFormat.record_length, U16, repr)
 	}
 
 	fn set_offset_at(&mut self, index: usize, offset: usize) -> Result<(), DatabaseError> {
@@ -440,6 +504,6 @@ impl<P: ReadPage + WritePage> RecordsPage<P> {
 			return Err(DatabaseError::InvalidRecordIndex);
 		}
 		let repr: u16 = offset.try_into().expect("Record offset must be 16-bit!");
-		write_array_section!(self.0, RecordsPageFormat.offsets, u16, index, repr)
+		write_array_section!(self.0, RecordsPageFormat.offsets, U16, index, repr)
 	}
 }
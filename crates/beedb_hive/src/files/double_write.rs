@@ -0,0 +1,397 @@
+use std::{
+	borrow::Cow,
+	fs::{File, OpenOptions},
+	io::{BufReader, Read, Seek, SeekFrom, Write},
+	num::{NonZeroU16, NonZeroU64},
+	path::Path,
+};
+
+#[cfg(test)]
+use mockall::automock;
+use static_assertions::assert_impl_all;
+use zerocopy::{
+	little_endian::{U16, U32, U64},
+	FromBytes, Immutable, IntoBytes,
+};
+
+use crate::repr::{impl_swap_bytes, ByteOrder, IoRepr, Repr};
+
+use super::{
+	generic::{FileType, GenericHeader, GenericHeaderRepr},
+	segment::PageSize,
+	utils::CRC32,
+	FileError, PageAddress, WalIndex,
+};
+
+pub(super) const FORMAT_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Immutable, FromBytes, IntoBytes)]
+#[repr(C)]
+struct BatchHeaderRepr {
+	num_pages: U64,
+}
+impl_swap_bytes!(BatchHeaderRepr { num_pages });
+
+#[derive(Debug, Clone, Immutable, FromBytes, IntoBytes)]
+#[repr(C, packed)]
+struct PageEntryHeaderRepr {
+	segment_num: U32,
+	page_num: U16,
+	wal_generation: U64,
+	wal_offset: U64,
+	crc: U32,
+}
+impl_swap_bytes!(PageEntryHeaderRepr {
+	segment_num,
+	page_num,
+	wal_generation,
+	wal_offset,
+	crc
+});
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PageEntryHeader {
+	page_address: PageAddress,
+	wal_index: WalIndex,
+	crc: u32,
+}
+
+impl From<PageEntryHeader> for PageEntryHeaderRepr {
+	fn from(value: PageEntryHeader) -> Self {
+		Self {
+			segment_num: value.page_address.segment_num.into(),
+			page_num: value.page_address.page_num.get().into(),
+			wal_generation: value.wal_index.generation.into(),
+			wal_offset: value.wal_index.offset.get().into(),
+			crc: value.crc.into(),
+		}
+	}
+}
+
+impl TryFrom<PageEntryHeaderRepr> for PageEntryHeader {
+	type Error = FileError;
+
+	fn try_from(value: PageEntryHeaderRepr) -> Result<Self, Self::Error> {
+		let Some(page_num) = NonZeroU16::new(value.page_num.get()) else {
+			return Err(FileError::Corrupted(
+				"Found invalid page number 0".to_string(),
+			));
+		};
+		let Some(wal_offset) = NonZeroU64::new(value.wal_offset.get()) else {
+			return Err(FileError::Corrupted(
+				"Found invalid WAL offset '0'".to_string(),
+			));
+		};
+		Ok(Self {
+			page_address: PageAddress::new(value.segment_num.get(), page_num),
+			wal_index: WalIndex::new(value.wal_generation.get(), wal_offset),
+			crc: value.crc.get(),
+		})
+	}
+}
+
+impl Repr<PageEntryHeader> for PageEntryHeaderRepr {
+	type Error = FileError;
+}
+
+struct BatchHeader {
+	num_pages: u64,
+}
+
+impl From<BatchHeader> for BatchHeaderRepr {
+	fn from(value: BatchHeader) -> Self {
+		Self {
+			num_pages: value.num_pages.into(),
+		}
+	}
+}
+
+impl From<BatchHeaderRepr> for BatchHeader {
+	fn from(value: BatchHeaderRepr) -> Self {
+		Self {
+			num_pages: value.num_pages.get(),
+		}
+	}
+}
+
+impl Repr<BatchHeader> for BatchHeaderRepr {
+	type Error = FileError;
+}
+
+/// A copy of a page that is about to be written to its segment file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DoubleWritePage<'a> {
+	pub page_address: PageAddress,
+	pub wal_index: WalIndex,
+	pub buf: Cow<'a, [u8]>,
+}
+
+/// The double-write buffer holds a copy of the most recent batch of page
+/// writes. Since page writes are not atomic, a crash during a batch can leave
+/// torn pages in the segment files; these can then be restored from the copy
+/// here, which is synced to disk before the segment files are touched.
+pub(crate) struct DoubleWriteFile {
+	body_start: u64,
+	page_size: PageSize,
+	byte_order: ByteOrder,
+	file: File,
+}
+assert_impl_all!(DoubleWriteFile: Send, Sync);
+
+impl DoubleWriteFile {
+	pub fn create_file(path: impl AsRef<Path>, page_size: PageSize) -> Result<Self, FileError> {
+		Self::create(
+			OpenOptions::new()
+				.create(true)
+				.truncate(true)
+				.read(true)
+				.write(true)
+				.open(path)?,
+			page_size,
+		)
+	}
+
+	pub fn open_file(path: impl AsRef<Path>, page_size: PageSize) -> Result<Self, FileError> {
+		Self::open(
+			OpenOptions::new().read(true).write(true).open(path)?,
+			page_size,
+		)
+	}
+
+	fn create(mut file: File, page_size: PageSize) -> Result<Self, FileError> {
+		file.seek(SeekFrom::Start(0))?;
+		let content_offset = u16::try_from(GenericHeaderRepr::SIZE).unwrap();
+		let header = GenericHeader {
+			byte_order: ByteOrder::Little,
+			file_type: FileType::DoubleWrite,
+			content_offset,
+			version: FORMAT_VERSION,
+		};
+		GenericHeaderRepr::serialize(header, &mut file)?;
+		BatchHeaderRepr::serialize(BatchHeader { num_pages: 0 }, &mut file)?;
+		Ok(Self {
+			body_start: content_offset.into(),
+			page_size,
+			byte_order: ByteOrder::Little,
+			file,
+		})
+	}
+
+	fn open(mut file: File, page_size: PageSize) -> Result<Self, FileError> {
+		file.seek(SeekFrom::Start(0))?;
+		let header = GenericHeaderRepr::deserialize(&mut file)?;
+		if header.file_type != FileType::DoubleWrite {
+			return Err(FileError::WrongFileType(header.file_type));
+		}
+		if header.version != FORMAT_VERSION {
+			return Err(FileError::IncompatibleVersion(
+				header.file_type,
+				header.version,
+			));
+		}
+		Ok(Self {
+			body_start: header.content_offset.into(),
+			page_size,
+			byte_order: header.byte_order,
+			file,
+		})
+	}
+
+	fn write_page(
+		mut writer: impl Write,
+		page: &DoubleWritePage,
+		byte_order: ByteOrder,
+	) -> Result<(), FileError> {
+		let header = PageEntryHeader {
+			page_address: page.page_address,
+			wal_index: page.wal_index,
+			crc: CRC32.checksum(&page.buf),
+		};
+		PageEntryHeaderRepr::serialize_in(header, &mut writer, byte_order)?;
+		writer.write_all(&page.buf)?;
+		Ok(())
+	}
+
+	fn read_page(
+		mut reader: impl Read,
+		page_size: PageSize,
+		byte_order: ByteOrder,
+	) -> Result<Option<DoubleWritePage<'static>>, FileError> {
+		let header = PageEntryHeaderRepr::deserialize_in(&mut reader, byte_order)?;
+		let mut buf = vec![0; page_size.body_size()];
+		reader.read_exact(&mut buf)?;
+
+		if CRC32.checksum(&buf) != header.crc {
+			return Ok(None);
+		}
+
+		Ok(Some(DoubleWritePage {
+			page_address: header.page_address,
+			wal_index: header.wal_index,
+			buf: Cow::Owned(buf),
+		}))
+	}
+}
+
+#[cfg_attr(test, automock)]
+#[allow(clippy::needless_lifetimes)]
+pub(crate) trait DoubleWriteFileApi {
+	/// Replaces the contents of the buffer with the given pages, and makes
+	/// sure they are persisted before returning.
+	fn write_pages<'a>(&mut self, pages: &[DoubleWritePage<'a>]) -> Result<(), FileError>;
+
+	/// Reads all intact pages from the buffer. Pages that were only partially
+	/// written are skipped.
+	fn read_pages(&mut self) -> Result<Vec<DoubleWritePage<'static>>, FileError>;
+
+	/// Removes all pages from the buffer, once they are persisted in their
+	/// segments. This isn't synced, because copies that survive a crash only
+	/// replace pages that are torn, which they can't be anymore.
+	fn clear(&mut self) -> Result<(), FileError>;
+}
+
+impl DoubleWriteFileApi for DoubleWriteFile {
+	fn write_pages(&mut self, pages: &[DoubleWritePage]) -> Result<(), FileError> {
+		let mut buf: Vec<u8> = Vec::new();
+		BatchHeaderRepr::serialize_in(
+			BatchHeader {
+				num_pages: pages.len() as u64,
+			},
+			&mut buf,
+			self.byte_order,
+		)?;
+		for page in pages {
+			debug_assert_eq!(page.buf.len(), self.page_size.body_size());
+			Self::write_page(&mut buf, page, self.byte_order)?;
+		}
+
+		self.file.seek(SeekFrom::Start(self.body_start))?;
+		self.file.write_all(&buf)?;
+		self.file.set_len(self.body_start + buf.len() as u64)?;
+		self.file.sync_data()?;
+		Ok(())
+	}
+
+	fn read_pages(&mut self) -> Result<Vec<DoubleWritePage<'static>>, FileError> {
+		self.file.seek(SeekFrom::Start(self.body_start))?;
+		let mut reader = BufReader::new(&mut self.file);
+
+		let batch_header = match BatchHeaderRepr::deserialize_in(&mut reader, self.byte_order) {
+			Ok(header) => header,
+			Err(FileError::UnexpectedEof) => return Ok(Vec::new()),
+			Err(err) => return Err(err),
+		};
+
+		let mut pages: Vec<DoubleWritePage> = Vec::new();
+		for _ in 0..batch_header.num_pages {
+			match Self::read_page(&mut reader, self.page_size, self.byte_order) {
+				Ok(Some(page)) => pages.push(page),
+				Ok(None) => continue,
+				Err(FileError::UnexpectedEof) => break,
+				Err(err) => return Err(err),
+			}
+		}
+		Ok(pages)
+	}
+
+	fn clear(&mut self) -> Result<(), FileError> {
+		self.file.set_len(self.body_start)?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::files::{
+		segment::PAGE_BODY_SIZE,
+		test_helpers::{page_address, wal_index},
+	};
+
+	use super::*;
+
+	#[test]
+	fn write_and_read_pages() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let mut file =
+			DoubleWriteFile::create_file(tempdir.path().join("double_write"), PageSize::DEFAULT)
+				.unwrap();
+		let pages = [
+			DoubleWritePage {
+				page_address: page_address!(1, 2),
+				wal_index: wal_index!(3, 4),
+				buf: Cow::Owned(vec![1; PAGE_BODY_SIZE]),
+			},
+			DoubleWritePage {
+				page_address: page_address!(5, 6),
+				wal_index: wal_index!(7, 8),
+				buf: Cow::Owned(vec![2; PAGE_BODY_SIZE]),
+			},
+		];
+
+		// when
+		file.write_pages(&pages).unwrap();
+		let mut file =
+			DoubleWriteFile::open_file(tempdir.path().join("double_write"), PageSize::DEFAULT)
+				.unwrap();
+
+		// then
+		assert_eq!(file.read_pages().unwrap(), pages);
+	}
+
+	#[test]
+	fn clear_pages() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let path = tempdir.path().join("double_write");
+		let mut file = DoubleWriteFile::create_file(&path, PageSize::DEFAULT).unwrap();
+		file.write_pages(&[DoubleWritePage {
+			page_address: page_address!(1, 2),
+			wal_index: wal_index!(3, 4),
+			buf: Cow::Owned(vec![1; PAGE_BODY_SIZE]),
+		}])
+		.unwrap();
+
+		// when
+		file.clear().unwrap();
+		let mut file = DoubleWriteFile::open_file(&path, PageSize::DEFAULT).unwrap();
+
+		// then
+		assert_eq!(file.read_pages().unwrap(), vec![]);
+	}
+
+	#[test]
+	fn skip_torn_pages() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let path = tempdir.path().join("double_write");
+		let mut file = DoubleWriteFile::create_file(&path, PageSize::DEFAULT).unwrap();
+		let torn_page = DoubleWritePage {
+			page_address: page_address!(1, 2),
+			wal_index: wal_index!(3, 4),
+			buf: Cow::Owned(vec![1; PAGE_BODY_SIZE]),
+		};
+		let intact_page = DoubleWritePage {
+			page_address: page_address!(5, 6),
+			wal_index: wal_index!(7, 8),
+			buf: Cow::Owned(vec![2; PAGE_BODY_SIZE]),
+		};
+		file.write_pages(&[torn_page, intact_page.clone()]).unwrap();
+
+		// when
+		let mut raw_file = OpenOptions::new().write(true).open(&path).unwrap();
+		let torn_end = GenericHeaderRepr::SIZE
+			+ BatchHeaderRepr::SIZE
+			+ PageEntryHeaderRepr::SIZE
+			+ PAGE_BODY_SIZE;
+		raw_file
+			.seek(SeekFrom::Start((torn_end - 100) as u64))
+			.unwrap();
+		raw_file.write_all(&[0; 100]).unwrap();
+
+		let mut file = DoubleWriteFile::open_file(&path, PageSize::DEFAULT).unwrap();
+
+		// then
+		assert_eq!(file.read_pages().unwrap(), vec![intact_page]);
+	}
+}
@@ -0,0 +1,926 @@
+use std::{
+	collections::BTreeMap,
+	io, mem,
+	num::{NonZeroU16, NonZeroU64},
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Arc,
+	},
+};
+
+use parking_lot::Mutex;
+
+use super::{
+	double_write::{DoubleWriteFileApi, DoubleWritePage},
+	manifest::{DatabaseId, Manifest},
+	segment::{
+		decode_page, encode_page, PageSize, ReadBuf, SegmentConfig, SegmentFileApi, SegmentOp,
+		SegmentReadOp, SegmentWriteOp,
+	},
+	wal::{Item, WalFileApi},
+	working_set::WorkingSet,
+	DatabaseFolderApi, FileError, WalIndex,
+};
+
+/// A database folder that wraps another folder and injects failures into
+/// the files opened from it, for crash testing.
+///
+/// The following faults can be injected:
+/// - Failing a write, after a given number of page or log item writes have
+///   succeeded. If that write is part of a batch, the writes before it are
+///   still applied, so the batch is only partially written.
+/// - Flipping bits in pages that are read from segment files.
+/// - Cutting the power, which makes all files and folders that were opened
+///   before the power cut unusable. Segment pages that were written since their
+///   file was last synced are either kept, lost, or torn, so that only some of
+///   their sectors were written. Log items that were pushed since their file
+///   was last flushed are lost from some point on.
+pub(crate) struct FaultyFolder<DF: DatabaseFolderApi> {
+	inner: Arc<DF>,
+	handle: FaultHandle,
+}
+
+impl<DF: DatabaseFolderApi> FaultyFolder<DF> {
+	pub fn new(inner: DF, seed: u64) -> Self {
+		let faults = Arc::new(Faults {
+			epoch: AtomicU64::new(0),
+			state: Mutex::new(FaultState {
+				rng: fastrand::Rng::with_seed(seed),
+				writes_until_failure: None,
+				bit_flip_probability: 0.0,
+				torn_page_probability: 0.0,
+				unsynced_pages: BTreeMap::new(),
+				torn_pages: BTreeMap::new(),
+				unflushed_items: BTreeMap::new(),
+			}),
+		});
+		Self {
+			inner: Arc::new(inner),
+			handle: FaultHandle { faults, epoch: 0 },
+		}
+	}
+
+	/// Makes the write after the next `num_writes` writes fail.
+	pub fn fail_after_writes(&self, num_writes: u64) {
+		self.handle.faults.state.lock().writes_until_failure = Some(num_writes);
+	}
+
+	/// Sets the probability with which a bit is flipped in a page that is
+	/// read from a segment file.
+	pub fn set_bit_flip_probability(&self, probability: f64) {
+		self.handle.faults.state.lock().bit_flip_probability = probability;
+	}
+
+	/// Sets the probability with which a page that wasn't synced yet is torn
+	/// in a power cut.
+	pub fn set_torn_page_probability(&self, probability: f64) {
+		self.handle.faults.state.lock().torn_page_probability = probability;
+	}
+
+	/// Simulates a power cut, and returns the folder as it is seen after the
+	/// machine comes back up.
+	///
+	/// Each page that was written since its segment was last synced is torn
+	/// with the configured probability, and otherwise reverted to its
+	/// previous contents with a probability of one half. In each log file,
+	/// the items that were pushed since it was last flushed are lost from a
+	/// random item on. Pending write failures and bit flips are cleared.
+	pub fn power_cut(&self) -> Result<Self, FileError> {
+		let faults = &self.handle.faults;
+		let mut state = faults.state.lock();
+		let epoch = faults.epoch.fetch_add(1, Ordering::AcqRel) + 1;
+
+		let mut lost_pages: Vec<LostPage> = Vec::new();
+		for ((segment_num, page_num), saved_page) in mem::take(&mut state.unsynced_pages) {
+			let num_torn_sectors = if state.rng.f64() < state.torn_page_probability {
+				let num_sectors = saved_page.page_size.get() / SECTOR_SIZE;
+				Some(state.rng.usize(1..num_sectors))
+			} else if state.rng.bool() {
+				None
+			} else {
+				continue;
+			};
+			lost_pages.push(LostPage {
+				segment_num,
+				page_num,
+				saved_page,
+				num_torn_sectors,
+			});
+		}
+
+		let mut lost_items: Vec<(u64, NonZeroU64)> = Vec::new();
+		for (generation, offsets) in mem::take(&mut state.unflushed_items) {
+			let num_kept = state.rng.usize(0..=offsets.len());
+			if let Some(first_lost) = offsets.get(num_kept) {
+				lost_items.push((generation, *first_lost));
+			}
+		}
+
+		state.writes_until_failure = None;
+		state.bit_flip_probability = 0.0;
+		mem::drop(state);
+
+		// Nothing that was opened before the power cut can access the files
+		// anymore, so they can be changed without holding the state.
+		let mut torn_pages = Vec::new();
+		for lost_page in lost_pages {
+			if let Some(torn_page) = self.lose_page(&lost_page)? {
+				torn_pages.push(((lost_page.segment_num, lost_page.page_num), torn_page));
+			}
+		}
+		for (generation, first_lost) in lost_items {
+			self.lose_items(generation, first_lost)?;
+		}
+		faults.state.lock().torn_pages.extend(torn_pages);
+
+		Ok(Self {
+			inner: Arc::clone(&self.inner),
+			handle: FaultHandle {
+				faults: Arc::clone(faults),
+				epoch,
+			},
+		})
+	}
+
+	/// Reverts a page to the contents it had when its segment was last
+	/// synced. If the page is torn, returns what a read of it sees instead:
+	/// its first sectors as they were written last, and the rest as they
+	/// were before.
+	fn lose_page(&self, lost_page: &LostPage) -> Result<Option<Box<[u8]>>, FileError> {
+		let LostPage {
+			page_num,
+			saved_page,
+			..
+		} = lost_page;
+		let config = SegmentConfig {
+			page_size: saved_page.page_size,
+			..Default::default()
+		};
+		let segment = self
+			.inner
+			.open_segment_file(lost_page.segment_num, &config)?;
+
+		let torn_page = match lost_page.num_torn_sectors {
+			Some(num_torn_sectors) => {
+				let mut wal_index = None;
+				let mut body = vec![0; saved_page.page_size.body_size()];
+				segment.read(SegmentReadOp {
+					page_num: *page_num,
+					wal_index: &mut wal_index,
+					buf: ReadBuf::Body(&mut body),
+				})?;
+				let mut torn_page = saved_page.encode(*page_num);
+				if let Some(wal_index) = wal_index {
+					let torn_len = num_torn_sectors * SECTOR_SIZE;
+					let mut page = vec![0; saved_page.page_size.get()];
+					encode_page(
+						&SegmentWriteOp {
+							page_num: *page_num,
+							wal_index,
+							buf: &body,
+						},
+						&mut page,
+					);
+					torn_page[..torn_len].copy_from_slice(&page[..torn_len]);
+				}
+				Some(torn_page)
+			}
+			None => None,
+		};
+
+		// Uninitialized pages can't be written, so they are reverted to a
+		// zeroed page instead.
+		segment.write(SegmentWriteOp {
+			page_num: *page_num,
+			wal_index: saved_page.wal_index.unwrap_or(saved_page.written_index),
+			buf: &saved_page.body,
+		})?;
+		Ok(torn_page)
+	}
+
+	/// Removes the items starting at `first_lost` from a log file, by
+	/// writing the file again with only the items before.
+	fn lose_items(&self, generation: u64, first_lost: NonZeroU64) -> Result<(), FileError> {
+		let mut file = self.inner.open_wal_file(generation)?;
+		let items: Vec<Item<'static>> = file
+			.iter_items()?
+			.take_while(|result| {
+				result
+					.as_ref()
+					.map_or(true, |(offset, _)| *offset < first_lost)
+			})
+			.map(|result| result.map(|(_, item)| item))
+			.collect::<Result<_, _>>()?;
+		mem::drop(file);
+
+		self.inner.delete_wal_file(generation)?;
+		let mut file = self.inner.open_wal_file(generation)?;
+		for item in items {
+			file.push_item(item)?;
+		}
+		file.flush()
+	}
+
+	fn wal_file(&self, generation: u64, file: DF::WalFile) -> FaultyWalFile<DF::WalFile> {
+		FaultyWalFile {
+			inner: file,
+			generation,
+			handle: self.handle.clone(),
+		}
+	}
+}
+
+impl<DF: DatabaseFolderApi> Clone for FaultyFolder<DF> {
+	fn clone(&self) -> Self {
+		Self {
+			inner: Arc::clone(&self.inner),
+			handle: self.handle.clone(),
+		}
+	}
+}
+
+impl<DF: DatabaseFolderApi> DatabaseFolderApi for FaultyFolder<DF> {
+	type SegmentFile = FaultySegmentFile<DF::SegmentFile>;
+	type WalFile = FaultyWalFile<DF::WalFile>;
+	type DoubleWriteFile = FaultyDoubleWriteFile<DF::DoubleWriteFile>;
+	type IterWalFiles = std::vec::IntoIter<Result<(u64, Self::WalFile), FileError>>;
+
+	fn open_segment_file(
+		&self,
+		segment_num: u32,
+		config: &SegmentConfig,
+	) -> Result<Self::SegmentFile, FileError> {
+		self.handle.check()?;
+		Ok(FaultySegmentFile {
+			inner: self.inner.open_segment_file(segment_num, config)?,
+			segment_num,
+			page_size: config.page_size,
+			handle: self.handle.clone(),
+		})
+	}
+
+	fn delete_segment_file(&self, segment_num: u32) -> Result<(), FileError> {
+		self.handle.check()?;
+		self.inner.delete_segment_file(segment_num)
+	}
+
+	fn segment_nums(&self) -> Result<Vec<u32>, FileError> {
+		self.handle.check()?;
+		self.inner.segment_nums()
+	}
+
+	fn open_wal_file(&self, generation: u64) -> Result<Self::WalFile, FileError> {
+		self.handle.check()?;
+		Ok(self.wal_file(generation, self.inner.open_wal_file(generation)?))
+	}
+
+	fn delete_wal_file(&self, generation: u64) -> Result<(), FileError> {
+		self.handle.check()?;
+		self.inner.delete_wal_file(generation)?;
+		self.handle
+			.faults
+			.state
+			.lock()
+			.unflushed_items
+			.remove(&generation);
+		Ok(())
+	}
+
+	fn iter_wal_files(&self) -> Result<Self::IterWalFiles, FileError> {
+		self.handle.check()?;
+		let files: Vec<Result<(u64, Self::WalFile), FileError>> = self
+			.inner
+			.iter_wal_files()?
+			.map(|result| {
+				result.map(|(generation, file)| (generation, self.wal_file(generation, file)))
+			})
+			.collect();
+		Ok(files.into_iter())
+	}
+
+	fn clear_wal_files(&self) -> Result<(), FileError> {
+		self.handle.check()?;
+		self.inner.clear_wal_files()?;
+		self.handle.faults.state.lock().unflushed_items.clear();
+		Ok(())
+	}
+
+	fn open_double_write_file(
+		&self,
+		page_size: PageSize,
+	) -> Result<Self::DoubleWriteFile, FileError> {
+		self.handle.check()?;
+		Ok(FaultyDoubleWriteFile {
+			inner: self.inner.open_double_write_file(page_size)?,
+			handle: self.handle.clone(),
+		})
+	}
+
+	fn read_manifest(&self) -> Result<Option<Manifest>, FileError> {
+		self.handle.check()?;
+		self.inner.read_manifest()
+	}
+
+	fn write_manifest(&self, manifest: &Manifest) -> Result<(), FileError> {
+		self.handle.check()?;
+		self.inner.write_manifest(manifest)
+	}
+
+	fn read_working_set(&self) -> Result<Option<WorkingSet>, FileError> {
+		self.handle.check()?;
+		self.inner.read_working_set()
+	}
+
+	fn write_working_set(&self, working_set: &WorkingSet) -> Result<(), FileError> {
+		self.handle.check()?;
+		self.inner.write_working_set(working_set)
+	}
+}
+
+pub(crate) struct FaultySegmentFile<S: SegmentFileApi> {
+	inner: S,
+	segment_num: u32,
+	page_size: PageSize,
+	handle: FaultHandle,
+}
+
+impl<S: SegmentFileApi> FaultySegmentFile<S> {
+	/// Remembers the current contents of a page before it is first written
+	/// after a sync, so that the write can be lost in a power cut. A page
+	/// that was torn is whole again once it is written.
+	fn save_page(&self, op: &SegmentWriteOp) {
+		let key = (self.segment_num, op.page_num);
+		let mut state = self.handle.faults.state.lock();
+		state.torn_pages.remove(&key);
+		if state.unsynced_pages.contains_key(&key) {
+			return;
+		}
+		mem::drop(state);
+
+		let mut wal_index = None;
+		let mut body: Box<[u8]> = vec![0; self.page_size.body_size()].into();
+		let read_result = self.inner.read(SegmentReadOp {
+			page_num: op.page_num,
+			wal_index: &mut wal_index,
+			buf: ReadBuf::Body(&mut body),
+		});
+		if read_result.is_err() {
+			return;
+		}
+
+		let saved_page = SavedPage {
+			wal_index,
+			written_index: op.wal_index,
+			page_size: self.page_size,
+			body,
+		};
+		self.handle
+			.faults
+			.state
+			.lock()
+			.unsynced_pages
+			.entry(key)
+			.or_insert(saved_page);
+	}
+
+	/// Applies the faults of a read: torn pages are read as they were left
+	/// by the power cut, and bits may be flipped.
+	fn apply_read_faults(&self, op: &mut SegmentReadOp) -> Result<(), FileError> {
+		let state = self.handle.faults.state.lock();
+		if let Some(torn_page) = state.torn_pages.get(&(self.segment_num, op.page_num)) {
+			decode_page(torn_page, op)?;
+		}
+		mem::drop(state);
+		self.flip_bits(op)
+	}
+
+	fn flip_bits(&self, op: &mut SegmentReadOp) -> Result<(), FileError> {
+		let Some(wal_index) = *op.wal_index else {
+			return Ok(());
+		};
+		let Some(bit) = self.handle.bit_to_flip(self.page_size) else {
+			return Ok(());
+		};
+
+		let mut page = vec![0; self.page_size.get()];
+		encode_page(
+			&SegmentWriteOp {
+				page_num: op.page_num,
+				wal_index,
+				buf: &op.buf,
+			},
+			&mut page,
+		);
+		page[bit / 8] ^= 1 << (bit % 8);
+		decode_page(&page, op)
+	}
+}
+
+impl<S: SegmentFileApi> SegmentFileApi for FaultySegmentFile<S> {
+	fn read(&self, mut op: SegmentReadOp) -> Result<(), FileError> {
+		self.handle.check()?;
+		self.inner.read(SegmentReadOp {
+			page_num: op.page_num,
+			wal_index: &mut *op.wal_index,
+			buf: op.buf.reborrow(),
+		})?;
+		self.apply_read_faults(&mut op)
+	}
+
+	fn write(&self, op: SegmentWriteOp) -> Result<(), FileError> {
+		if self.handle.take_writes(1)? == 0 {
+			return Err(injected_failure());
+		}
+		self.save_page(&op);
+		self.inner.write(op)
+	}
+
+	fn batch(&self, ops: &mut [SegmentOp]) -> Result<(), FileError> {
+		let num_writes = ops
+			.iter()
+			.filter(|op| matches!(op, SegmentOp::Write(..)))
+			.count();
+		let allowed_writes = self.handle.take_writes(num_writes)?;
+
+		let mut num_ops = ops.len();
+		if allowed_writes < num_writes {
+			num_ops = ops
+				.iter()
+				.enumerate()
+				.filter(|(_, op)| matches!(op, SegmentOp::Write(..)))
+				.nth(allowed_writes)
+				.map_or(ops.len(), |(index, _)| index);
+		}
+
+		for op in ops[..num_ops].iter() {
+			if let SegmentOp::Write(write_op) = op {
+				self.save_page(write_op);
+			}
+		}
+		self.inner.batch(&mut ops[..num_ops])?;
+		for op in ops[..num_ops].iter_mut() {
+			if let SegmentOp::Read(read_op) = op {
+				self.apply_read_faults(read_op)?;
+			}
+		}
+
+		if num_ops < ops.len() {
+			return Err(injected_failure());
+		}
+		Ok(())
+	}
+
+	fn sync(&self) -> Result<(), FileError> {
+		self.handle.check()?;
+		self.inner.sync()?;
+		self.handle
+			.faults
+			.state
+			.lock()
+			.unsynced_pages
+			.retain(|(segment_num, _), _| *segment_num != self.segment_num);
+		Ok(())
+	}
+
+	fn read_mapped(&self, mut op: SegmentReadOp) -> Result<(), FileError> {
+		self.handle.check()?;
+		self.inner.read_mapped(SegmentReadOp {
+			page_num: op.page_num,
+			wal_index: &mut *op.wal_index,
+			buf: op.buf.reborrow(),
+		})?;
+		self.apply_read_faults(&mut op)
+	}
+
+	fn truncate(&self, high_water_mark: u16) -> Result<(), FileError> {
+		self.handle.check()?;
+		self.inner.truncate(high_water_mark)?;
+		let is_kept = |(segment_num, page_num): &(u32, NonZeroU16)| {
+			*segment_num != self.segment_num || page_num.get() <= high_water_mark
+		};
+		let mut state = self.handle.faults.state.lock();
+		state.unsynced_pages.retain(|key, _| is_kept(key));
+		state.torn_pages.retain(|key, _| is_kept(key));
+		Ok(())
+	}
+
+	fn high_water_mark(&self) -> u16 {
+		self.inner.high_water_mark()
+	}
+
+	fn database_id(&self) -> Option<DatabaseId> {
+		self.inner.database_id()
+	}
+}
+
+pub(crate) struct FaultyWalFile<W: WalFileApi> {
+	inner: W,
+	generation: u64,
+	handle: FaultHandle,
+}
+
+impl<W: WalFileApi> WalFileApi for FaultyWalFile<W> {
+	type IterItems<'a> = W::IterItems<'a> where W: 'a;
+	type IterItemsReverse<'a> = W::IterItemsReverse<'a> where W: 'a;
+
+	fn push_item(&mut self, item: Item<'_>) -> Result<NonZeroU64, FileError> {
+		if self.handle.take_writes(1)? == 0 {
+			return Err(injected_failure());
+		}
+		let offset = self.inner.push_item(item)?;
+		self.handle
+			.faults
+			.state
+			.lock()
+			.unflushed_items
+			.entry(self.generation)
+			.or_default()
+			.push(offset);
+		Ok(offset)
+	}
+
+	fn flush(&mut self) -> Result<(), FileError> {
+		self.handle.check()?;
+		self.inner.flush()?;
+		self.handle
+			.faults
+			.state
+			.lock()
+			.unflushed_items
+			.remove(&self.generation);
+		Ok(())
+	}
+
+	fn read_item_at(&mut self, offset: NonZeroU64) -> Result<Item<'static>, FileError> {
+		self.handle.check()?;
+		self.inner.read_item_at(offset)
+	}
+
+	fn iter_items(&mut self) -> Result<Self::IterItems<'_>, FileError> {
+		self.handle.check()?;
+		self.inner.iter_items()
+	}
+
+	fn iter_items_reverse(&mut self) -> Result<Self::IterItemsReverse<'_>, FileError> {
+		self.handle.check()?;
+		self.inner.iter_items_reverse()
+	}
+
+	fn next_offset(&self) -> NonZeroU64 {
+		self.inner.next_offset()
+	}
+
+	fn size(&self) -> usize {
+		self.inner.size()
+	}
+
+	fn database_id(&self) -> Option<DatabaseId> {
+		self.inner.database_id()
+	}
+}
+
+pub(crate) struct FaultyDoubleWriteFile<D: DoubleWriteFileApi> {
+	inner: D,
+	handle: FaultHandle,
+}
+
+impl<D: DoubleWriteFileApi> DoubleWriteFileApi for FaultyDoubleWriteFile<D> {
+	fn write_pages(&mut self, pages: &[DoubleWritePage]) -> Result<(), FileError> {
+		let allowed_writes = self.handle.take_writes(pages.len())?;
+		self.inner.write_pages(&pages[..allowed_writes])?;
+		if allowed_writes < pages.len() {
+			return Err(injected_failure());
+		}
+		Ok(())
+	}
+
+	fn read_pages(&mut self) -> Result<Vec<DoubleWritePage<'static>>, FileError> {
+		self.handle.check()?;
+		self.inner.read_pages()
+	}
+
+	fn clear(&mut self) -> Result<(), FileError> {
+		self.handle.check()?;
+		self.inner.clear()
+	}
+}
+
+struct Faults {
+	epoch: AtomicU64,
+	state: Mutex<FaultState>,
+}
+
+/// Torn pages are written in parts of this size.
+const SECTOR_SIZE: usize = 512;
+
+struct FaultState {
+	rng: fastrand::Rng,
+	writes_until_failure: Option<u64>,
+	bit_flip_probability: f64,
+	torn_page_probability: f64,
+	unsynced_pages: BTreeMap<(u32, NonZeroU16), SavedPage>,
+	/// The pages that were torn by a power cut, as they are read.
+	torn_pages: BTreeMap<(u32, NonZeroU16), Box<[u8]>>,
+	/// The offsets of the log items that were pushed since their file was
+	/// last flushed, by generation.
+	unflushed_items: BTreeMap<u64, Vec<NonZeroU64>>,
+}
+
+struct SavedPage {
+	/// `None` if the page wasn't initialized.
+	wal_index: Option<WalIndex>,
+	/// The WAL index of the write that follows the saved contents.
+	written_index: WalIndex,
+	page_size: PageSize,
+	body: Box<[u8]>,
+}
+
+impl SavedPage {
+	/// Returns the saved page as it is stored in its segment file.
+	fn encode(&self, page_num: NonZeroU16) -> Box<[u8]> {
+		let mut page: Box<[u8]> = vec![0; self.page_size.get()].into();
+		if let Some(wal_index) = self.wal_index {
+			encode_page(
+				&SegmentWriteOp {
+					page_num,
+					wal_index,
+					buf: &self.body,
+				},
+				&mut page,
+			);
+		}
+		page
+	}
+}
+
+struct LostPage {
+	segment_num: u32,
+	page_num: NonZeroU16,
+	saved_page: SavedPage,
+	/// The number of sectors at the start of the page that keep their new
+	/// contents, if the page is torn.
+	num_torn_sectors: Option<usize>,
+}
+
+/// The shared fault state, as seen from a file or folder that was opened in
+/// a specific epoch. Every power cut starts a new epoch.
+#[derive(Clone)]
+struct FaultHandle {
+	faults: Arc<Faults>,
+	epoch: u64,
+}
+
+impl FaultHandle {
+	fn check(&self) -> Result<(), FileError> {
+		if self.faults.epoch.load(Ordering::Acquire) != self.epoch {
+			return Err(io::Error::other("The power was cut").into());
+		}
+		Ok(())
+	}
+
+	/// Returns how many of the next `num_writes` writes may succeed.
+	fn take_writes(&self, num_writes: usize) -> Result<usize, FileError> {
+		self.check()?;
+		let mut state = self.faults.state.lock();
+		let Some(writes_until_failure) = state.writes_until_failure else {
+			return Ok(num_writes);
+		};
+		let num_writes_u64 = u64::try_from(num_writes).unwrap();
+		if writes_until_failure >= num_writes_u64 {
+			state.writes_until_failure = Some(writes_until_failure - num_writes_u64);
+			return Ok(num_writes);
+		}
+		state.writes_until_failure = None;
+		Ok(usize::try_from(writes_until_failure).unwrap())
+	}
+
+	fn bit_to_flip(&self, page_size: PageSize) -> Option<usize> {
+		let mut state = self.faults.state.lock();
+		if state.rng.f64() >= state.bit_flip_probability {
+			return None;
+		}
+		Some(state.rng.usize(0..page_size.get() * 8))
+	}
+}
+
+fn injected_failure() -> FileError {
+	io::Error::other("Injected write failure").into()
+}
+
+#[cfg(test)]
+mod tests {
+	use std::borrow::Cow;
+
+	use crate::{
+		files::{
+			memory::MemoryFolder,
+			segment::PAGE_BODY_SIZE,
+			test_helpers::{page_address, wal_index},
+			wal::{TransactionData, WriteData},
+		},
+		utils::test_helpers::non_zero,
+	};
+
+	use super::*;
+
+	fn write_page(
+		segment: &impl SegmentFileApi,
+		page_num: u16,
+		value: u8,
+	) -> Result<(), FileError> {
+		segment.write(SegmentWriteOp {
+			page_num: NonZeroU16::new(page_num).unwrap(),
+			wal_index: wal_index!(0, u64::from(value) + 1),
+			buf: &[value; PAGE_BODY_SIZE],
+		})
+	}
+
+	fn read_page(segment: &impl SegmentFileApi, page_num: u16) -> Result<Vec<u8>, FileError> {
+		let mut buf = vec![0; PAGE_BODY_SIZE];
+		segment.read(SegmentReadOp {
+			page_num: NonZeroU16::new(page_num).unwrap(),
+			wal_index: &mut None,
+			buf: ReadBuf::Body(&mut buf),
+		})?;
+		Ok(buf)
+	}
+
+	fn commit_item(transaction_id: u64) -> Item<'static> {
+		Item::Commit(TransactionData {
+			transaction_id,
+			prev_transaction_item: None,
+		})
+	}
+
+	#[test]
+	fn fail_nth_write() {
+		// given
+		let folder = FaultyFolder::new(MemoryFolder::new(), 69);
+		let segment = folder.open_segment_file(1, &Default::default()).unwrap();
+		folder.fail_after_writes(2);
+
+		// when
+		let results: Vec<bool> = (1..=4)
+			.map(|page_num| write_page(&segment, page_num, 25).is_ok())
+			.collect();
+
+		// then
+		assert_eq!(results, vec![true, true, false, true]);
+		assert_eq!(read_page(&segment, 3).unwrap(), vec![0; PAGE_BODY_SIZE]);
+	}
+
+	#[test]
+	fn short_batch() {
+		// given
+		let folder = FaultyFolder::new(MemoryFolder::new(), 69);
+		let segment = folder.open_segment_file(1, &Default::default()).unwrap();
+		folder.fail_after_writes(1);
+
+		// when
+		let body = [25; PAGE_BODY_SIZE];
+		let result = segment.batch(&mut [
+			SegmentOp::Write(SegmentWriteOp {
+				page_num: non_zero!(1),
+				wal_index: wal_index!(0, 1),
+				buf: &body,
+			}),
+			SegmentOp::Write(SegmentWriteOp {
+				page_num: non_zero!(2),
+				wal_index: wal_index!(0, 1),
+				buf: &body,
+			}),
+		]);
+
+		// then
+		assert!(result.is_err());
+		assert_eq!(read_page(&segment, 1).unwrap(), vec![25; PAGE_BODY_SIZE]);
+		assert_eq!(read_page(&segment, 2).unwrap(), vec![0; PAGE_BODY_SIZE]);
+	}
+
+	#[test]
+	fn power_cut_keeps_synced_pages() {
+		// given
+		let folder = FaultyFolder::new(MemoryFolder::new(), 69);
+		let segment = folder.open_segment_file(1, &Default::default()).unwrap();
+		write_page(&segment, 1, 25).unwrap();
+		segment.sync().unwrap();
+		for page_num in 1..=64 {
+			write_page(&segment, page_num, 42).unwrap();
+		}
+
+		// when
+		let folder = folder.power_cut().unwrap();
+
+		// then
+		assert!(read_page(&segment, 1).is_err());
+		let segment = folder.open_segment_file(1, &Default::default()).unwrap();
+		let num_lost = (1..=64)
+			.filter(|page_num| read_page(&segment, *page_num).unwrap() != [42; PAGE_BODY_SIZE])
+			.count();
+		assert!(num_lost > 0 && num_lost < 64);
+		assert!([[25; PAGE_BODY_SIZE], [42; PAGE_BODY_SIZE]]
+			.contains(&read_page(&segment, 1).unwrap().try_into().unwrap()));
+	}
+
+	#[test]
+	fn power_cut_tears_pages() {
+		// given
+		let folder = FaultyFolder::new(MemoryFolder::new(), 69);
+		folder.set_torn_page_probability(1.0);
+		let segment = folder.open_segment_file(1, &Default::default()).unwrap();
+		write_page(&segment, 1, 25).unwrap();
+		segment.sync().unwrap();
+		write_page(&segment, 1, 42).unwrap();
+		write_page(&segment, 2, 42).unwrap();
+
+		// when
+		let folder = folder.power_cut().unwrap();
+
+		// then
+		let segment = folder.open_segment_file(1, &Default::default()).unwrap();
+		assert!(matches!(
+			read_page(&segment, 1),
+			Err(FileError::ChecksumMismatch)
+		));
+		assert!(matches!(
+			read_page(&segment, 2),
+			Err(FileError::ChecksumMismatch)
+		));
+		write_page(&segment, 1, 69).unwrap();
+		assert_eq!(read_page(&segment, 1).unwrap(), vec![69; PAGE_BODY_SIZE]);
+	}
+
+	#[test]
+	fn power_cut_loses_unflushed_wal_items() {
+		// given
+		let folder = FaultyFolder::new(MemoryFolder::new(), 69);
+		let mut wal_file = folder.open_wal_file(0).unwrap();
+		for transaction_id in 0..4 {
+			wal_file.push_item(commit_item(transaction_id)).unwrap();
+		}
+		wal_file.flush().unwrap();
+		for transaction_id in 4..20 {
+			wal_file.push_item(commit_item(transaction_id)).unwrap();
+		}
+
+		// when
+		let folder = folder.power_cut().unwrap();
+
+		// then
+		let mut wal_file = folder.open_wal_file(0).unwrap();
+		let items: Vec<Item> = wal_file
+			.iter_items()
+			.unwrap()
+			.map(|result| result.unwrap().1)
+			.collect();
+		assert!(items.len() >= 4 && items.len() < 20);
+		for (transaction_id, item) in items.into_iter().enumerate() {
+			assert_eq!(item, commit_item(transaction_id as u64));
+		}
+	}
+
+	#[test]
+	fn power_cut_closes_wal_files() {
+		// given
+		let folder = FaultyFolder::new(MemoryFolder::new(), 69);
+		let mut wal_file = folder.open_wal_file(0).unwrap();
+		let item = Item::Write(WriteData {
+			transaction_data: TransactionData {
+				transaction_id: 0,
+				prev_transaction_item: None,
+			},
+			page_address: page_address!(1, 2),
+			offset: 0,
+			from: Some(Cow::Owned(vec![0])),
+			to: Cow::Owned(vec![1]),
+		});
+		wal_file.push_item(item.clone()).unwrap();
+		wal_file.flush().unwrap();
+
+		// when
+		let folder = folder.power_cut().unwrap();
+
+		// then
+		assert!(wal_file.push_item(item).is_err());
+		assert!(wal_file.flush().is_err());
+		let mut wal_files: Vec<(u64, FaultyWalFile<_>)> =
+			Result::from_iter(folder.iter_wal_files().unwrap()).unwrap();
+		assert_eq!(wal_files.len(), 1);
+		assert_eq!(wal_files[0].1.iter_items().unwrap().count(), 1);
+	}
+
+	#[test]
+	fn flip_bits_in_reads() {
+		// given
+		let folder = FaultyFolder::new(MemoryFolder::new(), 69);
+		let segment = folder.open_segment_file(1, &Default::default()).unwrap();
+		write_page(&segment, 1, 25).unwrap();
+
+		// when
+		folder.set_bit_flip_probability(1.0);
+		let result = read_page(&segment, 1);
+
+		// then
+		assert!(result.is_err());
+	}
+}
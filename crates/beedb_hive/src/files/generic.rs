@@ -1,6 +1,6 @@
-use zerocopy::{FromBytes, Immutable, IntoBytes};
+use zerocopy::{little_endian::U16, FromBytes, Immutable, IntoBytes};
 
-use crate::repr::Repr;
+use crate::repr::{ByteOrder, Repr};
 
 use super::FileError;
 
@@ -10,7 +10,7 @@ pub(super) struct GenericHeaderRepr {
 	magic: [u8; 4],
 	byte_order: u8,
 	file_type: u8,
-	content_offset: u16,
+	content_offset: U16,
 	version: u8,
 }
 
@@ -19,6 +19,9 @@ pub(super) struct GenericHeaderRepr {
 pub(crate) enum FileType {
 	Wal = 0,
 	Segment = 1,
+	DoubleWrite = 2,
+	Manifest = 3,
+	WorkingSet = 4,
 }
 
 impl TryFrom<u8> for FileType {
@@ -28,21 +31,33 @@ impl TryFrom<u8> for FileType {
 		match value {
 			0 => Ok(Self::Wal),
 			1 => Ok(Self::Segment),
+			2 => Ok(Self::DoubleWrite),
+			3 => Ok(Self::Manifest),
+			4 => Ok(Self::WorkingSet),
 			_ => Err(FileError::Corrupted(format!("Unknown file type {value}"))),
 		}
 	}
 }
 
-#[cfg(target_endian = "big")]
-const NATIVE_BYTE_ORDER: u8 = 0;
+impl TryFrom<u8> for ByteOrder {
+	type Error = FileError;
 
-#[cfg(target_endian = "little")]
-const NATIVE_BYTE_ORDER: u8 = 1;
+	fn try_from(value: u8) -> Result<Self, Self::Error> {
+		match value {
+			0 => Ok(Self::Big),
+			1 => Ok(Self::Little),
+			_ => Err(FileError::Corrupted(format!("Unknown byte order {value}"))),
+		}
+	}
+}
 
 const MAGIC: [u8; 4] = *b"ACRN";
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(super) struct GenericHeader {
+	/// The byte order of the rest of the file. New files are always
+	/// little-endian.
+	pub byte_order: ByteOrder,
 	pub file_type: FileType,
 	pub content_offset: u16,
 	pub version: u8,
@@ -50,11 +65,15 @@ pub(super) struct GenericHeader {
 
 impl From<GenericHeader> for GenericHeaderRepr {
 	fn from(value: GenericHeader) -> Self {
+		let content_offset = match value.byte_order {
+			ByteOrder::Little => value.content_offset,
+			ByteOrder::Big => value.content_offset.swap_bytes(),
+		};
 		Self {
 			magic: MAGIC,
-			byte_order: NATIVE_BYTE_ORDER,
+			byte_order: value.byte_order as u8,
 			file_type: value.file_type as u8,
-			content_offset: value.content_offset,
+			content_offset: content_offset.into(),
 			version: value.version,
 		}
 	}
@@ -67,12 +86,15 @@ impl TryFrom<GenericHeaderRepr> for GenericHeader {
 		if value.magic != MAGIC {
 			return Err(FileError::MissingMagic);
 		}
-		if value.byte_order != NATIVE_BYTE_ORDER {
-			return Err(FileError::ByteOrderMismatch);
-		}
+		let byte_order = ByteOrder::try_from(value.byte_order)?;
+		let content_offset = match byte_order {
+			ByteOrder::Little => value.content_offset.get(),
+			ByteOrder::Big => value.content_offset.get().swap_bytes(),
+		};
 		Ok(Self {
+			byte_order,
 			file_type: value.file_type.try_into()?,
-			content_offset: value.content_offset,
+			content_offset,
 			version: value.version,
 		})
 	}
@@ -90,14 +112,15 @@ mod tests {
 	fn verify_header() {
 		let header_repr = GenericHeaderRepr {
 			magic: *b"ACRN",
-			byte_order: NATIVE_BYTE_ORDER,
+			byte_order: 1,
 			file_type: FileType::Wal as u8,
-			content_offset: 69,
+			content_offset: 69.into(),
 			version: 1,
 		};
 		assert_eq!(
 			GenericHeader::try_from(header_repr).unwrap(),
 			GenericHeader {
+				byte_order: ByteOrder::Little,
 				file_type: FileType::Wal,
 				content_offset: 69,
 				version: 1
@@ -109,9 +132,9 @@ mod tests {
 	fn try_verify_header_with_missing_magic() {
 		let header_repr = GenericHeaderRepr {
 			magic: *b"KEKW",
-			byte_order: NATIVE_BYTE_ORDER,
+			byte_order: 1,
 			file_type: FileType::Wal as u8,
-			content_offset: 69,
+			content_offset: 69.into(),
 			version: 1,
 		};
 		let err = GenericHeader::try_from(header_repr).unwrap_err();
@@ -119,18 +142,27 @@ mod tests {
 	}
 
 	#[test]
-	fn try_verify_header_with_byte_order_mismatch() {
-		let header_repr = GenericHeaderRepr {
-			magic: *b"ACRN",
-			byte_order: !NATIVE_BYTE_ORDER,
-			file_type: FileType::Wal as u8,
-			content_offset: 69,
-			version: 1,
-		};
-		let err = GenericHeader::try_from(header_repr).unwrap_err();
+	fn verify_big_endian_header() {
+		let header_repr = GenericHeaderRepr::read_from_bytes(b"ACRN\x00\x00\x00\x45\x01").unwrap();
 		assert_eq!(
-			err.to_string(),
-			"The file was created on a platform with a different byte order and cannot be opened"
+			GenericHeader::try_from(header_repr).unwrap(),
+			GenericHeader {
+				byte_order: ByteOrder::Big,
+				file_type: FileType::Wal,
+				content_offset: 69,
+				version: 1
+			}
 		);
 	}
+
+	#[test]
+	fn write_little_endian_header() {
+		let header_repr = GenericHeaderRepr::from(GenericHeader {
+			byte_order: ByteOrder::Little,
+			file_type: FileType::Segment,
+			content_offset: 0x0102,
+			version: 4,
+		});
+		assert_eq!(header_repr.as_bytes(), b"ACRN\x01\x01\x02\x01\x04");
+	}
 }
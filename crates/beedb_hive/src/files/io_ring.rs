@@ -0,0 +1,834 @@
+use std::{
+	fmt,
+	fs::File,
+	io,
+	ops::Range,
+	os::fd::{AsRawFd, RawFd},
+	ptr::{self, NonNull},
+	sync::atomic::{AtomicUsize, Ordering},
+};
+
+use io_uring::{opcode, squeue, types, IoUring};
+use log::warn;
+use parking_lot::{Mutex, MutexGuard};
+use static_assertions::assert_impl_all;
+
+use super::{utils::AlignedPage, FileError};
+
+/// The number of fixed buffers each ring has room for. Besides the staging
+/// buffers, they hold the memory that is registered via
+/// [`IoRing::register_memory`].
+const MAX_FIXED_BUFFERS: u32 = 1024;
+/// The kernel doesn't accept fixed buffers that are larger than this.
+const MAX_FIXED_BUFFER_SIZE: usize = 1 << 30;
+
+/// A pool of long-lived io_uring instances that is shared by all segment
+/// files of a storage.
+///
+/// Every batch takes one of the rings for itself, so that batches of
+/// different threads are executed concurrently. Files are registered with
+/// all rings to avoid the cost of looking up their descriptors for every
+/// operation. Each ring also owns a set of page-sized staging buffers, which
+/// are registered as fixed buffers if possible, just like any memory that is
+/// registered via [`IoRing::register_memory`].
+///
+/// The staging buffers of a ring follow each other in memory, so that
+/// consecutive pages of a coalesced write are transferred from them as a
+/// single fixed buffer. io_uring only supports fixed buffers for vectored
+/// operations on recent kernels, so buffers that are scattered in memory are
+/// transferred with a regular vectored operation instead.
+pub(crate) struct IoRing {
+	rings: Box<[Mutex<IoRingInner>]>,
+	next_ring: AtomicUsize,
+	queue_depth: usize,
+	free_file_slots: Mutex<Vec<u32>>,
+}
+assert_impl_all!(IoRing: Send, Sync);
+
+pub(crate) struct IoRingInner {
+	// Must be dropped before `staging`, since the kernel may still reference
+	// it until then.
+	queue: RingQueue,
+	staging: AlignedPage,
+	page_size: usize,
+}
+
+/// The queues of a ring, and the memory that is registered with it.
+pub(crate) struct RingQueue {
+	ring: IoUring,
+	/// The address ranges of the fixed buffers, by their index. Empty ranges
+	/// are free slots.
+	fixed_buffers: Vec<Range<usize>>,
+	/// The number of fixed buffers the ring has room for.
+	max_fixed_buffers: usize,
+}
+
+impl fmt::Debug for IoRing {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("IoRing")
+			.field("num_rings", &self.rings.len())
+			.field("queue_depth", &self.queue_depth)
+			.finish_non_exhaustive()
+	}
+}
+
+/// A file as it's referred to by operations on the ring.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RingFile {
+	Fixed(u32),
+	Fd(RawFd),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RingOpKind {
+	Read,
+	Write,
+}
+
+/// A read or write of whole buffers, which is issued as a vectored read or
+/// write if there is more than one. Buffers can be the ring's staging buffers,
+/// or any other memory.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct RingOp<'a> {
+	pub kind: RingOpKind,
+	pub offset: u64,
+	pub bufs: Vec<&'a mut [u8]>,
+}
+
+impl IoRing {
+	pub fn new(
+		num_rings: usize,
+		queue_depth: u32,
+		max_num_files: u32,
+		page_size: usize,
+	) -> Result<Self, FileError> {
+		let queue_depth = queue_depth.max(1);
+		let rings = (0..num_rings.max(1))
+			.map(|_| IoRingInner::new(queue_depth, page_size).map(Mutex::new))
+			.collect::<Result<Box<[_]>, _>>()?;
+
+		let mut free_file_slots: Vec<u32> = Vec::new();
+		let result = rings.iter().try_for_each(|ring| {
+			ring.lock()
+				.queue
+				.ring
+				.submitter()
+				.register_files_sparse(max_num_files)
+		});
+		match result {
+			Ok(()) => free_file_slots.extend((0..max_num_files).rev()),
+			Err(err) => warn!("Failed to register fixed files with io_uring: {err}"),
+		}
+
+		Ok(Self {
+			rings,
+			next_ring: AtomicUsize::new(0),
+			queue_depth: queue_depth as usize,
+			free_file_slots: Mutex::new(free_file_slots),
+		})
+	}
+
+	/// The maximum number of operations that can be in flight at once on a
+	/// ring, which is also the number of its staging buffers.
+	#[inline]
+	pub fn queue_depth(&self) -> usize {
+		self.queue_depth
+	}
+
+	/// Registers a file with the rings, if there are free slots. The returned
+	/// handle should be released via [`IoRing::release_file`] once the file is
+	/// closed.
+	pub fn register_file(&self, file: &File) -> RingFile {
+		let fd = file.as_raw_fd();
+		let Some(slot) = self.free_file_slots.lock().pop() else {
+			return RingFile::Fd(fd);
+		};
+		for (ring_num, ring) in self.rings.iter().enumerate() {
+			let result = ring
+				.lock()
+				.queue
+				.ring
+				.submitter()
+				.register_files_update(slot, &[fd]);
+			if let Err(err) = result {
+				warn!("Failed to register file with io_uring: {err}");
+				self.release_slot(slot, &self.rings[..ring_num]);
+				return RingFile::Fd(fd);
+			}
+		}
+		RingFile::Fixed(slot)
+	}
+
+	pub fn release_file(&self, file: RingFile) {
+		if let RingFile::Fixed(slot) = file {
+			self.release_slot(slot, &self.rings);
+		}
+	}
+
+	fn release_slot(&self, slot: u32, rings: &[Mutex<IoRingInner>]) {
+		for ring in rings {
+			let result = ring
+				.lock()
+				.queue
+				.ring
+				.submitter()
+				.register_files_update(slot, &[-1]);
+			if let Err(err) = result {
+				// Leaking the slot is the safe option here.
+				warn!("Failed to release io_uring file slot {slot}: {err}");
+				return;
+			}
+		}
+		self.free_file_slots.lock().push(slot);
+	}
+
+	/// Registers memory as fixed buffers with all rings, so that operations on
+	/// it skip mapping its pages for every operation. Returns whether that
+	/// succeeded.
+	///
+	/// # Safety
+	///
+	/// The kernel pins the pages of the memory, so it has to stay allocated
+	/// and mapped to the same pages until it is unregistered via
+	/// [`IoRing::unregister_memory`]. In particular, it must not be released
+	/// via `madvise(MADV_DONTNEED)` in the meantime.
+	pub unsafe fn register_memory(&self, memory: NonNull<u8>, len: usize) -> bool {
+		let start = memory.as_ptr() as usize;
+		let range = start..start + len;
+		let pieces = fixed_buffer_pieces(&range);
+		for (ring_num, ring) in self.rings.iter().enumerate() {
+			// Safety: upheld by the caller.
+			if let Err(err) = unsafe { ring.lock().queue.register(&pieces) } {
+				warn!("Failed to register memory with io_uring: {err}");
+				for ring in &self.rings[..=ring_num] {
+					ring.lock().queue.unregister(&range);
+				}
+				return false;
+			}
+		}
+		true
+	}
+
+	/// Unregisters memory that was registered via [`IoRing::register_memory`].
+	pub fn unregister_memory(&self, memory: NonNull<u8>, len: usize) {
+		let start = memory.as_ptr() as usize;
+		for ring in &self.rings[..] {
+			ring.lock().queue.unregister(&(start..start + len));
+		}
+	}
+
+	/// Gives exclusive access to one of the rings and its staging buffers.
+	/// Rings that are in use by other threads are skipped, if possible.
+	pub fn lock(&self) -> MutexGuard<'_, IoRingInner> {
+		let start = self.next_ring.fetch_add(1, Ordering::Relaxed);
+		(0..self.rings.len())
+			.find_map(|i| self.rings[(start + i) % self.rings.len()].try_lock())
+			.unwrap_or_else(|| self.rings[start % self.rings.len()].lock())
+	}
+}
+
+impl IoRingInner {
+	fn new(queue_depth: u32, page_size: usize) -> Result<Self, FileError> {
+		let ring = IoUring::new(queue_depth.next_power_of_two())?;
+
+		let staging = AlignedPage::zeroed(queue_depth as usize * page_size);
+		let staging_range = staging.as_ptr_range();
+		let pieces =
+			fixed_buffer_pieces(&(staging_range.start as usize..staging_range.end as usize));
+		let iovecs: Vec<libc::iovec> = pieces
+			.iter()
+			.map(|piece| libc::iovec {
+				iov_base: piece.start as *mut libc::c_void,
+				iov_len: piece.len(),
+			})
+			.collect();
+		// A sparse table leaves room for memory that is registered later on,
+		// but older kernels only support registering a fixed set of buffers.
+		// Safety: the buffers live in the same struct as the ring, and are
+		// only dropped after it.
+		let (result, max_fixed_buffers) =
+			match ring.submitter().register_buffers_sparse(MAX_FIXED_BUFFERS) {
+				Ok(()) => (
+					unsafe { ring.submitter().register_buffers_update(0, &iovecs, None) },
+					MAX_FIXED_BUFFERS as usize,
+				),
+				Err(_) => (
+					unsafe { ring.submitter().register_buffers(&iovecs) },
+					iovecs.len(),
+				),
+			};
+		let fixed_buffers = match result {
+			Ok(()) => pieces,
+			Err(err) => {
+				warn!("Failed to register fixed buffers with io_uring: {err}");
+				Vec::new()
+			}
+		};
+
+		Ok(Self {
+			queue: RingQueue {
+				ring,
+				fixed_buffers,
+				max_fixed_buffers,
+			},
+			staging,
+			page_size,
+		})
+	}
+
+	/// Splits the ring from its staging buffers, so that operations on the
+	/// ring can use them. The buffers are in the order they follow each other
+	/// in memory.
+	#[inline]
+	pub fn split(&mut self) -> (&mut RingQueue, Vec<&mut [u8]>) {
+		let buffers = self.staging.chunks_exact_mut(self.page_size).collect();
+		(&mut self.queue, buffers)
+	}
+}
+
+impl RingQueue {
+	/// Executes the given operations.
+	///
+	/// Operations are resubmitted until they are complete if the kernel only
+	/// transfers part of their buffers, or asks for a retry. If an operation
+	/// fails, nothing else is submitted, but the call only returns once all
+	/// operations in flight are complete, since the kernel may still access
+	/// their buffers until then.
+	pub fn exec(&mut self, file: RingFile, ops: &mut [RingOp]) -> Result<(), FileError> {
+		let lengths: Vec<usize> = ops
+			.iter()
+			.map(|op| op.bufs.iter().map(|buf| buf.len()).sum())
+			.collect();
+
+		// The vectors of vectored operations have to stay in place until the
+		// operations complete.
+		let mut iovecs: Vec<Vec<libc::iovec>> = vec![Vec::new(); ops.len()];
+		let mut progress = vec![0_usize; ops.len()];
+		let mut pending: Vec<usize> = (0..ops.len()).rev().collect();
+		let mut num_in_flight = 0;
+		let mut error: Option<FileError> = None;
+
+		while num_in_flight != 0 || (error.is_none() && !pending.is_empty()) {
+			if error.is_none() {
+				let mut submission = self.ring.submission();
+				while let Some(&index) = pending.last() {
+					let op = &mut ops[index];
+					let offset = op.offset + progress[index] as u64;
+					let entry = if let Some((ptr, len)) =
+						Self::contiguous_rest(&mut op.bufs, progress[index])
+					{
+						let range = ptr as usize..ptr as usize + len;
+						let buf_index = Self::fixed_buffer(&self.fixed_buffers, &range);
+						Self::entry(file, op.kind, buf_index, offset, ptr, len)
+					} else {
+						iovecs[index] = Self::iovecs(&mut op.bufs, progress[index]);
+						Self::vectored_entry(file, op.kind, offset, &iovecs[index])
+					}
+					.user_data(index as u64);
+					// Safety: the buffers outlive the operation, since we wait for all
+					// operations in flight before returning.
+					if unsafe { submission.push(&entry) }.is_err() {
+						break;
+					}
+					pending.pop();
+					num_in_flight += 1;
+				}
+			}
+
+			if let Err(err) = self.ring.submit_and_wait(1) {
+				match err.raw_os_error() {
+					// A full completion queue is drained below, and everything
+					// else is simply tried again.
+					Some(libc::EINTR | libc::EAGAIN | libc::EBUSY) => (),
+					_ if num_in_flight == 0 => return Err(err.into()),
+					// Returning would let the kernel write to buffers that are
+					// freed or reused.
+					_ => panic!("Failed to wait for io_uring operations in flight: {err}"),
+				}
+			}
+
+			for cqe in self.ring.completion() {
+				num_in_flight -= 1;
+				let index = usize::try_from(cqe.user_data()).unwrap();
+				let kind = ops[index].kind;
+				let result = cqe.result();
+
+				if result == -libc::EAGAIN || result == -libc::EINTR {
+					pending.push(index);
+					continue;
+				}
+				if result <= 0 {
+					error.get_or_insert(match (kind, result) {
+						(RingOpKind::Read, 0) => FileError::UnexpectedEof,
+						(RingOpKind::Write, 0) => io::Error::from(io::ErrorKind::WriteZero).into(),
+						(RingOpKind::Read, _) => FileError::ConcurrentReadFail(result),
+						(RingOpKind::Write, _) => FileError::ConcurrentWriteFail(result),
+					});
+					continue;
+				}
+
+				progress[index] += result.unsigned_abs() as usize;
+				if progress[index] < lengths[index] {
+					pending.push(index);
+				}
+			}
+		}
+
+		match error {
+			Some(error) => Err(error),
+			None => Ok(()),
+		}
+	}
+
+	/// Registers each of the address ranges as a fixed buffer in a free slot.
+	///
+	/// # Safety
+	///
+	/// See [`IoRing::register_memory`].
+	unsafe fn register(&mut self, pieces: &[Range<usize>]) -> io::Result<()> {
+		for piece in pieces {
+			let index = self
+				.fixed_buffers
+				.iter()
+				.position(Range::is_empty)
+				.unwrap_or(self.fixed_buffers.len());
+			if index >= self.max_fixed_buffers {
+				return Err(io::Error::other("No free io_uring fixed buffer slots"));
+			}
+			let iovec = libc::iovec {
+				iov_base: piece.start as *mut libc::c_void,
+				iov_len: piece.len(),
+			};
+			// Safety: upheld by the caller.
+			unsafe {
+				self.ring.submitter().register_buffers_update(
+					u32::try_from(index).unwrap(),
+					&[iovec],
+					None,
+				)?;
+			}
+			if index == self.fixed_buffers.len() {
+				self.fixed_buffers.push(piece.clone());
+			} else {
+				self.fixed_buffers[index] = piece.clone();
+			}
+		}
+		Ok(())
+	}
+
+	/// Clears the slots of all fixed buffers within `range`.
+	fn unregister(&mut self, range: &Range<usize>) {
+		for (index, fixed_buffer) in self.fixed_buffers.iter_mut().enumerate() {
+			if Range::is_empty(fixed_buffer)
+				|| fixed_buffer.start < range.start
+				|| range.end < fixed_buffer.end
+			{
+				continue;
+			}
+			let iovec = libc::iovec {
+				iov_base: ptr::null_mut(),
+				iov_len: 0,
+			};
+			// Safety: clearing a slot doesn't register any memory.
+			let result = unsafe {
+				self.ring.submitter().register_buffers_update(
+					u32::try_from(index).unwrap(),
+					&[iovec],
+					None,
+				)
+			};
+			if let Err(err) = result {
+				// The kernel keeps the pages pinned, but the slot must not be
+				// used for whatever is allocated at the same address next.
+				warn!("Failed to unregister io_uring fixed buffer {index}: {err}");
+			}
+			*fixed_buffer = 0..0;
+		}
+	}
+
+	/// The index of the fixed buffer that contains the address range, if
+	/// there is one.
+	fn fixed_buffer(fixed_buffers: &[Range<usize>], range: &Range<usize>) -> Option<u16> {
+		let index = fixed_buffers.iter().position(|fixed_buffer| {
+			!Range::is_empty(fixed_buffer)
+				&& fixed_buffer.start <= range.start
+				&& range.end <= fixed_buffer.end
+		})?;
+		Some(u16::try_from(index).expect("io_uring fixed buffer indices are 16-bit!"))
+	}
+
+	/// Builds the entry for the rest of an operation, which still has to
+	/// transfer `len` bytes at `ptr` from or to `offset`.
+	fn entry(
+		file: RingFile,
+		kind: RingOpKind,
+		buf_index: Option<u16>,
+		offset: u64,
+		ptr: *mut u8,
+		len: usize,
+	) -> squeue::Entry {
+		let len = u32::try_from(len).unwrap();
+
+		match (file, kind, buf_index) {
+			(RingFile::Fixed(slot), RingOpKind::Read, Some(buf_index)) => {
+				opcode::ReadFixed::new(types::Fixed(slot), ptr, len, buf_index)
+					.offset(offset)
+					.build()
+			}
+			(RingFile::Fixed(slot), RingOpKind::Write, Some(buf_index)) => {
+				opcode::WriteFixed::new(types::Fixed(slot), ptr, len, buf_index)
+					.offset(offset)
+					.build()
+			}
+			(RingFile::Fd(fd), RingOpKind::Read, Some(buf_index)) => {
+				opcode::ReadFixed::new(types::Fd(fd), ptr, len, buf_index)
+					.offset(offset)
+					.build()
+			}
+			(RingFile::Fd(fd), RingOpKind::Write, Some(buf_index)) => {
+				opcode::WriteFixed::new(types::Fd(fd), ptr, len, buf_index)
+					.offset(offset)
+					.build()
+			}
+			(RingFile::Fixed(slot), RingOpKind::Read, None) => {
+				opcode::Read::new(types::Fixed(slot), ptr, len)
+					.offset(offset)
+					.build()
+			}
+			(RingFile::Fixed(slot), RingOpKind::Write, None) => {
+				opcode::Write::new(types::Fixed(slot), ptr, len)
+					.offset(offset)
+					.build()
+			}
+			(RingFile::Fd(fd), RingOpKind::Read, None) => {
+				opcode::Read::new(types::Fd(fd), ptr, len)
+					.offset(offset)
+					.build()
+			}
+			(RingFile::Fd(fd), RingOpKind::Write, None) => {
+				opcode::Write::new(types::Fd(fd), ptr, len)
+					.offset(offset)
+					.build()
+			}
+		}
+	}
+
+	/// Builds the entry for the rest of a vectored operation. Fixed buffers
+	/// can't be used for vectored IO.
+	fn vectored_entry(
+		file: RingFile,
+		kind: RingOpKind,
+		offset: u64,
+		iovecs: &[libc::iovec],
+	) -> squeue::Entry {
+		let len = u32::try_from(iovecs.len()).unwrap();
+		let ptr = iovecs.as_ptr();
+
+		match (file, kind) {
+			(RingFile::Fixed(slot), RingOpKind::Read) => {
+				opcode::Readv::new(types::Fixed(slot), ptr, len)
+					.offset(offset)
+					.build()
+			}
+			(RingFile::Fixed(slot), RingOpKind::Write) => {
+				opcode::Writev::new(types::Fixed(slot), ptr, len)
+					.offset(offset)
+					.build()
+			}
+			(RingFile::Fd(fd), RingOpKind::Read) => opcode::Readv::new(types::Fd(fd), ptr, len)
+				.offset(offset)
+				.build(),
+			(RingFile::Fd(fd), RingOpKind::Write) => opcode::Writev::new(types::Fd(fd), ptr, len)
+				.offset(offset)
+				.build(),
+		}
+	}
+
+	/// The address and length of the parts of `bufs` that are left after
+	/// `skip` bytes were transferred already, if they follow each other in
+	/// memory, so that they can be transferred as one buffer.
+	fn contiguous_rest(bufs: &mut [&mut [u8]], skip: usize) -> Option<(*mut u8, usize)> {
+		let mut iovecs = Self::iovecs(bufs, skip).into_iter();
+		let first = iovecs.next()?;
+		let ptr: *mut u8 = first.iov_base.cast();
+		let mut len = first.iov_len;
+		for iovec in iovecs {
+			if iovec.iov_base as usize != ptr as usize + len {
+				return None;
+			}
+			len += iovec.iov_len;
+		}
+		Some((ptr, len))
+	}
+
+	/// The vectors for the parts of `bufs` that are left after `skip` bytes
+	/// were transferred already.
+	fn iovecs(bufs: &mut [&mut [u8]], mut skip: usize) -> Vec<libc::iovec> {
+		bufs.iter_mut()
+			.filter_map(|buf| {
+				if skip >= buf.len() {
+					skip -= buf.len();
+					return None;
+				}
+				let rest = &mut buf[skip..];
+				skip = 0;
+				Some(libc::iovec {
+					iov_base: rest.as_mut_ptr().cast(),
+					iov_len: rest.len(),
+				})
+			})
+			.collect()
+	}
+}
+
+/// Splits an address range into pieces that are small enough to be
+/// registered as fixed buffers.
+fn fixed_buffer_pieces(range: &Range<usize>) -> Vec<Range<usize>> {
+	range
+		.clone()
+		.step_by(MAX_FIXED_BUFFER_SIZE)
+		.map(|start| start..range.end.min(start + MAX_FIXED_BUFFER_SIZE))
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use std::os::unix::fs::FileExt;
+
+	use crate::consts::DEFAULT_PAGE_SIZE as PAGE_SIZE;
+
+	use super::*;
+
+	fn create_file(tempdir: &tempfile::TempDir) -> File {
+		File::options()
+			.create(true)
+			.truncate(true)
+			.read(true)
+			.write(true)
+			.open(tempdir.path().join("file"))
+			.unwrap()
+	}
+
+	fn address_range(buf: &[u8]) -> Range<usize> {
+		let range = buf.as_ptr_range();
+		range.start as usize..range.end as usize
+	}
+
+	#[test]
+	fn transfer_consecutive_buffers_as_one() {
+		// given
+		let ring = IoRing::new(1, 3, 1, PAGE_SIZE).unwrap();
+		let mut inner = ring.lock();
+		let (queue, mut buffers) = inner.split();
+		let staging = address_range(buffers[0]).start..address_range(buffers[2]).end;
+		let mut other = vec![0; PAGE_SIZE];
+
+		// when
+		let consecutive = RingQueue::contiguous_rest(&mut buffers, PAGE_SIZE / 2);
+		let scattered = RingQueue::contiguous_rest(&mut [&mut *buffers[0], &mut other], 0);
+
+		// then
+		let (ptr, len) = consecutive.unwrap();
+		assert_eq!(ptr as usize, staging.start + PAGE_SIZE / 2);
+		assert_eq!(len, 5 * PAGE_SIZE / 2);
+		assert_eq!(
+			RingQueue::fixed_buffer(&queue.fixed_buffers, &(ptr as usize..ptr as usize + len)),
+			Some(0)
+		);
+		assert_eq!(scattered, None);
+	}
+
+	#[test]
+	fn exec_more_ops_than_queue_entries() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let file = create_file(&tempdir);
+		let ring = IoRing::new(1, 4, 4, PAGE_SIZE).unwrap();
+		let ring_file = ring.register_file(&file);
+		let mut bufs: Vec<Vec<u8>> = (0..8).map(|i| vec![i as u8; PAGE_SIZE]).collect();
+
+		// when
+		let mut ops: Vec<RingOp> = bufs
+			.iter_mut()
+			.enumerate()
+			.map(|(i, buf)| RingOp {
+				kind: RingOpKind::Write,
+				offset: (i * PAGE_SIZE) as u64,
+				bufs: vec![buf],
+			})
+			.collect();
+		let mut inner = ring.lock();
+		let (queue, _) = inner.split();
+		queue.exec(ring_file, &mut ops).unwrap();
+		std::mem::drop(inner);
+		ring.release_file(ring_file);
+
+		// then
+		for i in 0..8 {
+			let mut buf = [0; PAGE_SIZE];
+			file.read_exact_at(&mut buf, (i * PAGE_SIZE) as u64)
+				.unwrap();
+			assert_eq!(buf, [i as u8; PAGE_SIZE]);
+		}
+	}
+
+	#[test]
+	fn exec_vectored_ops() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let file = create_file(&tempdir);
+		let ring = IoRing::new(1, 4, 1, PAGE_SIZE).unwrap();
+		let ring_file = ring.register_file(&file);
+		let mut inner = ring.lock();
+		let (queue, mut buffers) = inner.split();
+		for (i, buf) in buffers.iter_mut().enumerate() {
+			buf.fill(i as u8 + 1);
+		}
+
+		// when
+		let Ok([first, second, third, fourth]) = <[_; 4]>::try_from(buffers) else {
+			panic!("Expected four staging buffers");
+		};
+		queue
+			.exec(
+				ring_file,
+				&mut [
+					RingOp {
+						kind: RingOpKind::Write,
+						offset: 0,
+						bufs: vec![first, second, third],
+					},
+					RingOp {
+						kind: RingOpKind::Write,
+						offset: 4 * PAGE_SIZE as u64,
+						bufs: vec![fourth],
+					},
+				],
+			)
+			.unwrap();
+		let mut read_bufs = [vec![0; PAGE_SIZE], vec![0; PAGE_SIZE]];
+		let [read_first, read_second] = &mut read_bufs;
+		queue
+			.exec(
+				ring_file,
+				&mut [RingOp {
+					kind: RingOpKind::Read,
+					offset: PAGE_SIZE as u64,
+					bufs: vec![read_first, read_second],
+				}],
+			)
+			.unwrap();
+
+		// then
+		assert_eq!(read_bufs[0], [2; PAGE_SIZE]);
+		assert_eq!(read_bufs[1], [3; PAGE_SIZE]);
+		std::mem::drop(inner);
+		ring.release_file(ring_file);
+		let mut buf = [0; PAGE_SIZE];
+		file.read_exact_at(&mut buf, 4 * PAGE_SIZE as u64).unwrap();
+		assert_eq!(buf, [4; PAGE_SIZE]);
+	}
+
+	#[test]
+	fn read_past_end_of_file() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let file = create_file(&tempdir);
+		let ring = IoRing::new(1, 1, 1, PAGE_SIZE).unwrap();
+		let ring_file = ring.register_file(&file);
+
+		// when
+		let mut inner = ring.lock();
+		let (queue, mut buffers) = inner.split();
+		let result = queue.exec(
+			ring_file,
+			&mut [RingOp {
+				kind: RingOpKind::Read,
+				offset: 0,
+				bufs: vec![&mut *buffers[0]],
+			}],
+		);
+
+		// then
+		assert!(matches!(result, Err(FileError::UnexpectedEof)));
+	}
+
+	#[test]
+	fn read_into_registered_memory() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let file = create_file(&tempdir);
+		file.write_all_at(&[7; 2 * PAGE_SIZE], 0).unwrap();
+		let ring = IoRing::new(2, 1, 1, PAGE_SIZE).unwrap();
+		let ring_file = ring.register_file(&file);
+		let mut memory = AlignedPage::zeroed(2 * PAGE_SIZE);
+		let ptr = NonNull::new(memory.as_mut_ptr()).unwrap();
+		// Safety: the memory is unregistered before it's dropped.
+		assert!(unsafe { ring.register_memory(ptr, memory.len()) });
+
+		// when
+		let mut inner = ring.lock();
+		let (queue, _) = inner.split();
+		let index =
+			RingQueue::fixed_buffer(&queue.fixed_buffers, &address_range(&memory[PAGE_SIZE..]));
+		queue
+			.exec(
+				ring_file,
+				&mut [RingOp {
+					kind: RingOpKind::Read,
+					offset: 0,
+					bufs: vec![&mut memory[PAGE_SIZE..]],
+				}],
+			)
+			.unwrap();
+		std::mem::drop(inner);
+		ring.unregister_memory(ptr, memory.len());
+		ring.release_file(ring_file);
+
+		// then
+		assert_eq!(index, Some(1));
+		assert_eq!(memory[..PAGE_SIZE], [0; PAGE_SIZE]);
+		assert_eq!(memory[PAGE_SIZE..], [7; PAGE_SIZE]);
+		for ring in &ring.rings[..] {
+			let mut inner = ring.lock();
+			let (queue, _) = inner.split();
+			assert_eq!(
+				RingQueue::fixed_buffer(&queue.fixed_buffers, &address_range(&memory)),
+				None
+			);
+		}
+	}
+
+	#[test]
+	fn lock_skips_rings_in_use() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let file = create_file(&tempdir);
+		let ring = IoRing::new(2, 1, 1, PAGE_SIZE).unwrap();
+		let ring_file = ring.register_file(&file);
+		let first = ring.lock();
+
+		// when
+		let mut second = ring.lock();
+		let (queue, mut buffers) = second.split();
+		buffers[0].fill(1);
+		queue
+			.exec(
+				ring_file,
+				&mut [RingOp {
+					kind: RingOpKind::Write,
+					offset: 0,
+					bufs: vec![&mut *buffers[0]],
+				}],
+			)
+			.unwrap();
+		std::mem::drop(second);
+		std::mem::drop(first);
+		ring.release_file(ring_file);
+
+		// then
+		let mut buf = [0; PAGE_SIZE];
+		file.read_exact_at(&mut buf, 0).unwrap();
+		assert_eq!(buf, [1; PAGE_SIZE]);
+	}
+}
@@ -0,0 +1,116 @@
+use std::{
+	fs::File,
+	io::{self, Read, Seek, SeekFrom, Write},
+	os::fd::AsRawFd,
+	path::Path,
+};
+
+use log::warn;
+
+use super::FileError;
+
+/// An exclusive advisory lock on a database folder.
+///
+/// The lock is held with `flock` on a lock file, which contains the PID of
+/// the process holding it. Since the kernel releases the lock when the
+/// process exits, a lock file that is left behind by a crashed process does
+/// not prevent opening the database again; it is simply taken over.
+#[derive(Debug)]
+pub(super) struct FolderLock {
+	file: File,
+}
+
+impl FolderLock {
+	pub fn acquire(path: &Path) -> Result<Self, FileError> {
+		let mut file = File::options()
+			.read(true)
+			.write(true)
+			.create(true)
+			.truncate(false)
+			.open(path)?;
+
+		// Safety: the file descriptor is valid for the lifetime of `file`.
+		let result = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+		if result != 0 {
+			let error = io::Error::last_os_error();
+			if error.kind() == io::ErrorKind::WouldBlock {
+				return Err(FileError::AlreadyOpen(Self::read_pid(&mut file)?));
+			}
+			return Err(error.into());
+		}
+
+		if let Some(stale_pid) = Self::read_pid(&mut file)? {
+			warn!("Taking over the stale lock of process {stale_pid}; the database was not closed properly");
+		}
+
+		file.set_len(0)?;
+		file.seek(SeekFrom::Start(0))?;
+		write!(file, "{}", std::process::id())?;
+		file.sync_data()?;
+		Ok(Self { file })
+	}
+
+	fn read_pid(file: &mut File) -> Result<Option<u32>, FileError> {
+		let mut contents = String::new();
+		file.seek(SeekFrom::Start(0))?;
+		file.read_to_string(&mut contents)?;
+		Ok(contents.trim().parse().ok())
+	}
+}
+
+impl Drop for FolderLock {
+	fn drop(&mut self) {
+		// Clearing the PID marks the lock as properly released. The lock
+		// itself is released when the file is closed.
+		if let Err(err) = self.file.set_len(0) {
+			warn!("Failed to clear the database lock file: {err}");
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs;
+
+	use tempfile::tempdir;
+
+	use super::*;
+
+	#[test]
+	fn lock_is_exclusive() {
+		// given
+		let dir = tempdir().unwrap();
+		let path = dir.path().join("LOCK");
+		let lock = FolderLock::acquire(&path).unwrap();
+
+		// when
+		let result = FolderLock::acquire(&path);
+
+		// then
+		assert!(matches!(
+			result,
+			Err(FileError::AlreadyOpen(Some(pid))) if pid == std::process::id()
+		));
+		std::mem::drop(lock);
+		assert!(FolderLock::acquire(&path).is_ok());
+		assert_eq!(fs::read_to_string(&path).unwrap(), "");
+	}
+
+	#[test]
+	fn take_over_stale_lock() {
+		// given
+		let dir = tempdir().unwrap();
+		let path = dir.path().join("LOCK");
+		fs::write(&path, "4294967295").unwrap();
+
+		// when
+		let lock = FolderLock::acquire(&path).unwrap();
+
+		// then
+		assert_eq!(
+			fs::read_to_string(&path).unwrap(),
+			std::process::id().to_string()
+		);
+		std::mem::drop(lock);
+	}
+}
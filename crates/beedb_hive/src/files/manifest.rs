@@ -0,0 +1,493 @@
+use std::{
+	fmt,
+	fs::File,
+	io::{self, Read, Seek, SeekFrom, Write},
+	num::NonZeroU64,
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+use zerocopy::{
+	little_endian::{U32, U64},
+	FromBytes, Immutable, IntoBytes,
+};
+
+use crate::repr::{impl_swap_bytes, ByteOrder, IoRepr, Repr, SwapBytes};
+
+use super::{
+	generic::{FileType, GenericHeader, GenericHeaderRepr},
+	migration::{FileMigration, Migration},
+	segment::{self, PageSize},
+	utils::CRC32,
+	wal, FileError, WalIndex,
+};
+
+const FORMAT_VERSION_UNSIZED: u8 = 1;
+pub(super) const FORMAT_VERSION: u8 = 2;
+
+/// The random UUID that identifies a database. Every segment and WAL file
+/// records the ID of the database it was created for, so that files from
+/// different databases cannot silently be mixed up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct DatabaseId([u8; 16]);
+
+impl DatabaseId {
+	/// The ID stored in files that don't belong to any known database.
+	pub const NIL: Self = Self([0; 16]);
+
+	/// Generates a new random version 4 UUID.
+	pub fn generate() -> Result<Self, FileError> {
+		let mut bytes = [0; 16];
+		// Safety: the buffer is valid for writes of its whole length.
+		let result = unsafe { libc::getrandom(bytes.as_mut_ptr().cast(), bytes.len(), 0) };
+		if usize::try_from(result).ok() != Some(bytes.len()) {
+			return Err(io::Error::last_os_error().into());
+		}
+		bytes[6] = (bytes[6] & 0x0f) | 0x40;
+		bytes[8] = (bytes[8] & 0x3f) | 0x80;
+		Ok(Self(bytes))
+	}
+
+	pub const fn from_bytes(bytes: [u8; 16]) -> Self {
+		Self(bytes)
+	}
+
+	pub const fn to_bytes(self) -> [u8; 16] {
+		self.0
+	}
+
+	/// Checks that a file with the ID `found` belongs to this database. Only
+	/// files from before database IDs existed have no ID, and those are
+	/// accepted by any database.
+	pub fn verify(self, found: Option<Self>) -> Result<(), FileError> {
+		match found {
+			Some(found) if found != self => Err(FileError::DatabaseMismatch {
+				expected: self,
+				found,
+			}),
+			_ => Ok(()),
+		}
+	}
+}
+
+impl fmt::Display for DatabaseId {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		for (i, byte) in self.0.iter().enumerate() {
+			if matches!(i, 4 | 6 | 8 | 10) {
+				write!(f, "-")?;
+			}
+			write!(f, "{byte:02x}")?;
+		}
+		Ok(())
+	}
+}
+
+#[derive(Debug, Clone, Immutable, FromBytes, IntoBytes)]
+#[repr(C, packed)]
+struct ManifestRepr {
+	database_id: [u8; 16],
+	created_at: U64,
+	segment_version: u8,
+	wal_version: u8,
+	clean_shutdown: u8,
+	checkpoint_generation: U64,
+	checkpoint_offset: U64,
+	page_size: U32,
+	crc: U32,
+}
+impl_swap_bytes!(ManifestRepr {
+	created_at,
+	checkpoint_generation,
+	checkpoint_offset,
+	page_size,
+	crc
+});
+
+/// The manifest as it was stored before the page size was configurable.
+#[derive(Debug, Clone, Immutable, FromBytes, IntoBytes)]
+#[repr(C, packed)]
+struct UnsizedManifestRepr {
+	database_id: [u8; 16],
+	created_at: U64,
+	segment_version: u8,
+	wal_version: u8,
+	clean_shutdown: u8,
+	checkpoint_generation: U64,
+	checkpoint_offset: U64,
+	crc: U32,
+}
+impl_swap_bytes!(UnsizedManifestRepr {
+	created_at,
+	checkpoint_generation,
+	checkpoint_offset,
+	crc
+});
+
+/// Computes the checksum of a stored manifest, which covers everything but
+/// the checksum itself at the end.
+fn compute_crc(bytes: &[u8]) -> u32 {
+	CRC32.checksum(&bytes[..bytes.len() - size_of::<u32>()])
+}
+
+/// Reads a manifest repr in the given byte order, and checks its checksum.
+fn read_checked<R>(mut reader: impl Read, byte_order: ByteOrder) -> Result<R, FileError>
+where
+	R: FromBytes + IntoBytes + Immutable + SwapBytes,
+{
+	let mut repr = R::new_zeroed();
+	reader.read_exact(repr.as_mut_bytes())?;
+	// The checksum covers the bytes as they were stored, so it has to be
+	// checked before converting them.
+	let bytes = repr.as_bytes();
+	let crc_bytes: [u8; 4] = bytes[bytes.len() - size_of::<u32>()..].try_into().unwrap();
+	let crc = match byte_order {
+		ByteOrder::Little => u32::from_le_bytes(crc_bytes),
+		ByteOrder::Big => u32::from_be_bytes(crc_bytes),
+	};
+	if compute_crc(bytes) != crc {
+		return Err(FileError::ChecksumMismatch);
+	}
+	Ok(repr.convert(byte_order))
+}
+
+/// Describes a database as a whole. It is stored in the `MANIFEST` file at
+/// the root of the database folder.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Manifest {
+	pub database_id: DatabaseId,
+	/// Seconds since the Unix epoch.
+	pub created_at: u64,
+	/// The format version of the segment files the database was created
+	/// with.
+	pub segment_version: u8,
+	/// The format version of the WAL files the database was created with.
+	pub wal_version: u8,
+	/// Whether the database was closed properly the last time it was open.
+	pub clean_shutdown: bool,
+	pub last_checkpoint: Option<WalIndex>,
+	pub page_size: PageSize,
+}
+
+impl Manifest {
+	/// Creates the manifest for a new database with a fresh ID.
+	pub fn create(page_size: PageSize) -> Result<Self, FileError> {
+		let created_at = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map_or(0, |time| time.as_secs());
+		Ok(Self {
+			database_id: DatabaseId::generate()?,
+			created_at,
+			segment_version: segment::FORMAT_VERSION,
+			wal_version: wal::FORMAT_VERSION,
+			clean_shutdown: false,
+			last_checkpoint: None,
+			page_size,
+		})
+	}
+
+	/// Fails if the database uses file formats that are newer than the ones
+	/// this version can read.
+	pub fn check_versions(&self) -> Result<(), FileError> {
+		if self.segment_version > segment::FORMAT_VERSION {
+			return Err(FileError::IncompatibleVersion(
+				FileType::Segment,
+				self.segment_version,
+			));
+		}
+		if self.wal_version > wal::FORMAT_VERSION {
+			return Err(FileError::IncompatibleVersion(
+				FileType::Wal,
+				self.wal_version,
+			));
+		}
+		Ok(())
+	}
+
+	pub fn read(mut reader: impl Read) -> Result<Self, FileError> {
+		let header = GenericHeaderRepr::deserialize(&mut reader)?;
+		if header.file_type != FileType::Manifest {
+			return Err(FileError::WrongFileType(header.file_type));
+		}
+		match header.version {
+			// Unsized manifests are only read; they are written back in the
+			// current format.
+			FORMAT_VERSION_UNSIZED => {
+				Ok(read_checked::<UnsizedManifestRepr>(reader, header.byte_order)?.into())
+			}
+			FORMAT_VERSION => read_checked::<ManifestRepr>(reader, header.byte_order)?.try_into(),
+			_ => Err(FileError::IncompatibleVersion(
+				header.file_type,
+				header.version,
+			)),
+		}
+	}
+
+	pub fn write(&self, mut writer: impl Write) -> Result<(), FileError> {
+		let header = GenericHeader {
+			byte_order: ByteOrder::Little,
+			file_type: FileType::Manifest,
+			content_offset: u16::try_from(GenericHeaderRepr::SIZE).unwrap(),
+			version: FORMAT_VERSION,
+		};
+		GenericHeaderRepr::serialize(header, &mut writer)?;
+		ManifestRepr::serialize(self.clone(), writer)
+	}
+}
+
+impl From<Manifest> for ManifestRepr {
+	fn from(value: Manifest) -> Self {
+		let (checkpoint_generation, checkpoint_offset) = value
+			.last_checkpoint
+			.map_or((0, 0), |index| (index.generation, index.offset.get()));
+		let mut repr = Self {
+			database_id: value.database_id.to_bytes(),
+			created_at: value.created_at.into(),
+			segment_version: value.segment_version,
+			wal_version: value.wal_version,
+			clean_shutdown: value.clean_shutdown.into(),
+			checkpoint_generation: checkpoint_generation.into(),
+			checkpoint_offset: checkpoint_offset.into(),
+			page_size: u32::try_from(value.page_size.get()).unwrap().into(),
+			crc: 0.into(),
+		};
+		repr.crc = compute_crc(repr.as_bytes()).into();
+		repr
+	}
+}
+
+impl TryFrom<ManifestRepr> for Manifest {
+	type Error = FileError;
+
+	fn try_from(value: ManifestRepr) -> Result<Self, Self::Error> {
+		Ok(Self {
+			database_id: DatabaseId::from_bytes(value.database_id),
+			created_at: value.created_at.get(),
+			segment_version: value.segment_version,
+			wal_version: value.wal_version,
+			clean_shutdown: value.clean_shutdown != 0,
+			last_checkpoint: NonZeroU64::new(value.checkpoint_offset.get())
+				.map(|offset| WalIndex::new(value.checkpoint_generation.get(), offset)),
+			page_size: PageSize::new(value.page_size.get() as usize)?,
+		})
+	}
+}
+
+impl Repr<Manifest> for ManifestRepr {
+	type Error = FileError;
+}
+
+impl From<UnsizedManifestRepr> for Manifest {
+	fn from(value: UnsizedManifestRepr) -> Self {
+		Self {
+			database_id: DatabaseId::from_bytes(value.database_id),
+			created_at: value.created_at.get(),
+			segment_version: value.segment_version,
+			wal_version: value.wal_version,
+			clean_shutdown: value.clean_shutdown != 0,
+			last_checkpoint: NonZeroU64::new(value.checkpoint_offset.get())
+				.map(|offset| WalIndex::new(value.checkpoint_generation.get(), offset)),
+			page_size: PageSize::LEGACY,
+		}
+	}
+}
+
+/// The steps that upgrade manifests to the current format version.
+pub(super) const MIGRATIONS: &[FileMigration] = &[Migration {
+	from_version: FORMAT_VERSION_UNSIZED,
+	to_version: FORMAT_VERSION,
+	migrate: add_page_size,
+}];
+
+/// The manifest is rewritten in little-endian byte order, since its checksum
+/// covers the bytes as they are stored.
+fn add_page_size(file: &mut File, header: &mut GenericHeader) -> Result<(), FileError> {
+	file.seek(SeekFrom::Start(header.content_offset.into()))?;
+	let manifest = Manifest::from(read_checked::<UnsizedManifestRepr>(
+		&mut *file,
+		header.byte_order,
+	)?);
+	header.byte_order = ByteOrder::Little;
+	header.content_offset = u16::try_from(GenericHeaderRepr::SIZE).unwrap();
+	file.seek(SeekFrom::Start(header.content_offset.into()))?;
+	ManifestRepr::serialize(manifest, file)
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::files::{migration, test_helpers::wal_index};
+
+	use super::*;
+
+	#[test]
+	fn write_and_read_manifest() {
+		// given
+		let manifest = Manifest {
+			database_id: DatabaseId::from_bytes([0x69; 16]),
+			created_at: 420,
+			segment_version: 3,
+			wal_version: 2,
+			clean_shutdown: true,
+			last_checkpoint: Some(wal_index!(25, 69)),
+			page_size: PageSize::new(8 * 1024).unwrap(),
+		};
+
+		// when
+		let mut buf = Vec::new();
+		manifest.write(&mut buf).unwrap();
+
+		// then
+		assert_eq!(Manifest::read(buf.as_slice()).unwrap(), manifest);
+	}
+
+	#[test]
+	fn read_corrupted_manifest() {
+		// given
+		let mut buf = Vec::new();
+		Manifest::create(PageSize::DEFAULT)
+			.unwrap()
+			.write(&mut buf)
+			.unwrap();
+		buf[GenericHeaderRepr::SIZE + 3] ^= 0x01;
+
+		// when
+		let result = Manifest::read(buf.as_slice());
+
+		// then
+		assert!(matches!(result, Err(FileError::ChecksumMismatch)));
+	}
+
+	#[test]
+	fn read_unsized_manifest() {
+		// given
+		let mut repr = UnsizedManifestRepr {
+			database_id: [0x69; 16],
+			created_at: 420.into(),
+			segment_version: 3,
+			wal_version: 2,
+			clean_shutdown: 1,
+			checkpoint_generation: 0.into(),
+			checkpoint_offset: 0.into(),
+			crc: 0.into(),
+		};
+		repr.crc = compute_crc(repr.as_bytes()).into();
+		let mut buf = Vec::new();
+		GenericHeaderRepr::serialize(
+			GenericHeader {
+				byte_order: ByteOrder::Little,
+				file_type: FileType::Manifest,
+				content_offset: GenericHeaderRepr::SIZE as u16,
+				version: FORMAT_VERSION_UNSIZED,
+			},
+			&mut buf,
+		)
+		.unwrap();
+		buf.extend_from_slice(repr.as_bytes());
+
+		// when
+		let manifest = Manifest::read(buf.as_slice()).unwrap();
+
+		// then
+		assert_eq!(manifest.database_id, DatabaseId::from_bytes([0x69; 16]));
+		assert!(manifest.clean_shutdown);
+		assert_eq!(manifest.last_checkpoint, None);
+		assert_eq!(manifest.page_size, PageSize::LEGACY);
+	}
+
+	#[test]
+	fn upgrade_big_endian_unsized_manifest() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let path = tempdir.path().join("MANIFEST");
+		let mut repr = UnsizedManifestRepr {
+			database_id: [0x69; 16],
+			created_at: 420.into(),
+			segment_version: 3,
+			wal_version: 2,
+			clean_shutdown: 0,
+			checkpoint_generation: 25.into(),
+			checkpoint_offset: 69.into(),
+			crc: 0.into(),
+		}
+		.convert(ByteOrder::Big);
+		repr.crc = compute_crc(repr.as_bytes()).swap_bytes().into();
+		let mut file = File::create(&path).unwrap();
+		GenericHeaderRepr::serialize(
+			GenericHeader {
+				byte_order: ByteOrder::Big,
+				file_type: FileType::Manifest,
+				content_offset: GenericHeaderRepr::SIZE as u16,
+				version: FORMAT_VERSION_UNSIZED,
+			},
+			&mut file,
+		)
+		.unwrap();
+		file.write_all(repr.as_bytes()).unwrap();
+
+		// when
+		let result = migration::upgrade_file(&path, &tempdir.path().join("backup"));
+
+		// then
+		assert!(result.is_ok());
+		let manifest = Manifest {
+			database_id: DatabaseId::from_bytes([0x69; 16]),
+			created_at: 420,
+			segment_version: 3,
+			wal_version: 2,
+			clean_shutdown: false,
+			last_checkpoint: Some(wal_index!(25, 69)),
+			page_size: PageSize::LEGACY,
+		};
+		let mut expected = Vec::new();
+		manifest.write(&mut expected).unwrap();
+		assert_eq!(std::fs::read(&path).unwrap(), expected);
+	}
+
+	#[test]
+	fn read_big_endian_manifest() {
+		// given
+		let manifest = Manifest {
+			database_id: DatabaseId::from_bytes([0x69; 16]),
+			created_at: 420,
+			segment_version: 3,
+			wal_version: 2,
+			clean_shutdown: false,
+			last_checkpoint: Some(wal_index!(25, 69)),
+			page_size: PageSize::new(8 * 1024).unwrap(),
+		};
+		let mut repr = ManifestRepr::from(manifest.clone()).convert(ByteOrder::Big);
+		repr.crc = compute_crc(repr.as_bytes()).swap_bytes().into();
+		let mut buf = Vec::new();
+		GenericHeaderRepr::serialize(
+			GenericHeader {
+				byte_order: ByteOrder::Big,
+				file_type: FileType::Manifest,
+				content_offset: GenericHeaderRepr::SIZE as u16,
+				version: FORMAT_VERSION,
+			},
+			&mut buf,
+		)
+		.unwrap();
+		buf.extend_from_slice(repr.as_bytes());
+
+		// when
+		let result = Manifest::read(buf.as_slice());
+
+		// then
+		assert_eq!(result.unwrap(), manifest);
+	}
+
+	#[test]
+	fn generate_database_id() {
+		let id = DatabaseId::generate().unwrap();
+
+		assert_ne!(id, DatabaseId::generate().unwrap());
+		assert!(id.verify(Some(id)).is_ok());
+		assert!(id.verify(None).is_ok());
+		assert!(matches!(
+			id.verify(Some(DatabaseId::NIL)),
+			Err(FileError::DatabaseMismatch { .. })
+		));
+		let formatted = id.to_string();
+		assert_eq!(formatted.len(), 36);
+		assert_eq!(formatted.chars().nth(14), Some('4'));
+	}
+}
@@ -0,0 +1,404 @@
+use std::{
+	collections::{BTreeMap, HashMap},
+	io::{self, Read, Seek, SeekFrom, Write},
+	sync::Arc,
+};
+
+use parking_lot::{Mutex, RwLock};
+use static_assertions::assert_impl_all;
+
+use super::{
+	double_write::{DoubleWriteFileApi, DoubleWritePage},
+	manifest::{DatabaseId, Manifest},
+	segment::{
+		decode_page, encode_page, PageSize, SegmentConfig, SegmentFileApi, SegmentOp,
+		SegmentReadOp, SegmentWriteOp,
+	},
+	wal::WalFile,
+	working_set::WorkingSet,
+	DatabaseFolderApi, FileError,
+};
+
+/// A database folder that keeps all of its files in memory, so that an
+/// ephemeral database can be run without touching the file system.
+///
+/// Files opened from the folder share their contents with the folder, so
+/// they can be closed and opened again like regular files, for as long as
+/// the folder exists.
+#[derive(Default)]
+pub(crate) struct MemoryFolder {
+	segments: Mutex<HashMap<u32, MemorySegmentFile>>,
+	wal_files: Mutex<BTreeMap<u64, MemoryFile>>,
+	double_write: Mutex<Option<MemoryDoubleWriteFile>>,
+	manifest: Mutex<Option<Manifest>>,
+	working_set: Mutex<Option<WorkingSet>>,
+}
+assert_impl_all!(MemoryFolder: Send, Sync);
+
+impl MemoryFolder {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	fn database_id(&self) -> DatabaseId {
+		self.manifest
+			.lock()
+			.as_ref()
+			.map_or(DatabaseId::NIL, |manifest| manifest.database_id)
+	}
+}
+
+impl DatabaseFolderApi for MemoryFolder {
+	type SegmentFile = MemorySegmentFile;
+	type WalFile = WalFile<MemoryFile>;
+	type DoubleWriteFile = MemoryDoubleWriteFile;
+	type IterWalFiles = std::vec::IntoIter<Result<(u64, Self::WalFile), FileError>>;
+
+	fn open_segment_file(
+		&self,
+		segment_num: u32,
+		config: &SegmentConfig,
+	) -> Result<Self::SegmentFile, FileError> {
+		let database_id = self.database_id();
+		let segment = self
+			.segments
+			.lock()
+			.entry(segment_num)
+			.or_insert_with(|| MemorySegmentFile::new(database_id, config.page_size))
+			.clone();
+		if segment.page_size != config.page_size {
+			return Err(FileError::PageSizeMismatch {
+				expected: config.page_size,
+				found: segment.page_size,
+			});
+		}
+		Ok(segment)
+	}
+
+	fn delete_segment_file(&self, segment_num: u32) -> Result<(), FileError> {
+		match self.segments.lock().remove(&segment_num) {
+			Some(..) => Ok(()),
+			None => Err(io::Error::from(io::ErrorKind::NotFound).into()),
+		}
+	}
+
+	fn segment_nums(&self) -> Result<Vec<u32>, FileError> {
+		Ok(self.segments.lock().keys().copied().collect())
+	}
+
+	fn open_wal_file(&self, generation: u64) -> Result<Self::WalFile, FileError> {
+		let mut wal_files = self.wal_files.lock();
+		if let Some(file) = wal_files.get(&generation) {
+			return WalFile::open(file.reopen());
+		}
+		let file = MemoryFile::default();
+		wal_files.insert(generation, file.reopen());
+		WalFile::create(file, self.database_id())
+	}
+
+	fn delete_wal_file(&self, generation: u64) -> Result<(), FileError> {
+		match self.wal_files.lock().remove(&generation) {
+			Some(..) => Ok(()),
+			None => Err(io::Error::from(io::ErrorKind::NotFound).into()),
+		}
+	}
+
+	fn iter_wal_files(&self) -> Result<Self::IterWalFiles, FileError> {
+		let files: Vec<Result<(u64, Self::WalFile), FileError>> = self
+			.wal_files
+			.lock()
+			.iter()
+			.map(|(generation, file)| Ok((*generation, WalFile::open(file.reopen())?)))
+			.collect();
+		Ok(files.into_iter())
+	}
+
+	fn clear_wal_files(&self) -> Result<(), FileError> {
+		self.wal_files.lock().clear();
+		Ok(())
+	}
+
+	fn open_double_write_file(
+		&self,
+		_page_size: PageSize,
+	) -> Result<Self::DoubleWriteFile, FileError> {
+		Ok(self
+			.double_write
+			.lock()
+			.get_or_insert_with(Default::default)
+			.clone())
+	}
+
+	fn read_manifest(&self) -> Result<Option<Manifest>, FileError> {
+		Ok(self.manifest.lock().clone())
+	}
+
+	fn write_manifest(&self, manifest: &Manifest) -> Result<(), FileError> {
+		*self.manifest.lock() = Some(manifest.clone());
+		Ok(())
+	}
+
+	fn read_working_set(&self) -> Result<Option<WorkingSet>, FileError> {
+		Ok(self.working_set.lock().clone())
+	}
+
+	fn write_working_set(&self, working_set: &WorkingSet) -> Result<(), FileError> {
+		*self.working_set.lock() = Some(working_set.clone());
+		Ok(())
+	}
+}
+
+/// A growable byte vector that can be used like a file. Clones share the
+/// same contents, but each [`MemoryFile::reopen`]ed handle has its own
+/// position.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MemoryFile {
+	data: Arc<Mutex<Vec<u8>>>,
+	pos: u64,
+}
+
+impl MemoryFile {
+	pub fn reopen(&self) -> Self {
+		Self {
+			data: Arc::clone(&self.data),
+			pos: 0,
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		self.data.lock().len()
+	}
+}
+
+impl Read for MemoryFile {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let data = self.data.lock();
+		let start = usize::try_from(self.pos)
+			.unwrap_or(usize::MAX)
+			.min(data.len());
+		let len = buf.len().min(data.len() - start);
+		buf[..len].copy_from_slice(&data[start..start + len]);
+		self.pos += len as u64;
+		Ok(len)
+	}
+}
+
+impl Write for MemoryFile {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		let mut data = self.data.lock();
+		let start = usize::try_from(self.pos).map_err(|_| io::ErrorKind::FileTooLarge)?;
+		let end = start + buf.len();
+		if data.len() < end {
+			data.resize(end, 0);
+		}
+		data[start..end].copy_from_slice(buf);
+		self.pos = end as u64;
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+impl Seek for MemoryFile {
+	fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+		let new_pos = match pos {
+			SeekFrom::Start(offset) => Some(offset),
+			SeekFrom::End(offset) => (self.len() as u64).checked_add_signed(offset),
+			SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+		};
+		let Some(new_pos) = new_pos else {
+			return Err(io::ErrorKind::InvalidInput.into());
+		};
+		self.pos = new_pos;
+		Ok(new_pos)
+	}
+}
+
+/// An in-memory segment file. Pages are stored in the same format as in a
+/// segment file on disk, but only pages that were written take up memory.
+#[derive(Debug, Clone)]
+pub(crate) struct MemorySegmentFile {
+	pages: Arc<RwLock<HashMap<u16, Box<[u8]>>>>,
+	database_id: DatabaseId,
+	page_size: PageSize,
+}
+
+impl MemorySegmentFile {
+	fn new(database_id: DatabaseId, page_size: PageSize) -> Self {
+		Self {
+			pages: Arc::default(),
+			database_id,
+			page_size,
+		}
+	}
+
+	fn write_page(&self, op: &SegmentWriteOp) {
+		let mut buf = vec![0; self.page_size.get()].into_boxed_slice();
+		encode_page(op, &mut buf);
+		self.pages.write().insert(op.page_num.get(), buf);
+	}
+}
+
+impl SegmentFileApi for MemorySegmentFile {
+	fn read(&self, mut op: SegmentReadOp) -> Result<(), FileError> {
+		let pages = self.pages.read();
+		match pages.get(&op.page_num.get()) {
+			Some(page) => decode_page(page, &mut op),
+			None => {
+				op.buf.fill(0);
+				*op.wal_index = None;
+				Ok(())
+			}
+		}
+	}
+
+	fn write(&self, op: SegmentWriteOp) -> Result<(), FileError> {
+		self.write_page(&op);
+		Ok(())
+	}
+
+	fn batch(&self, ops: &mut [SegmentOp]) -> Result<(), FileError> {
+		for op in ops {
+			match op {
+				SegmentOp::Read(read_op) => self.read(SegmentReadOp {
+					page_num: read_op.page_num,
+					wal_index: read_op.wal_index,
+					buf: read_op.buf.reborrow(),
+				})?,
+				SegmentOp::Write(write_op) => self.write_page(write_op),
+			}
+		}
+		Ok(())
+	}
+
+	fn sync(&self) -> Result<(), FileError> {
+		Ok(())
+	}
+
+	fn read_mapped(&self, op: SegmentReadOp) -> Result<(), FileError> {
+		self.read(op)
+	}
+
+	fn truncate(&self, high_water_mark: u16) -> Result<(), FileError> {
+		self.pages
+			.write()
+			.retain(|page_num, _| *page_num <= high_water_mark);
+		Ok(())
+	}
+
+	fn high_water_mark(&self) -> u16 {
+		self.pages.read().keys().max().copied().unwrap_or(0)
+	}
+
+	fn database_id(&self) -> Option<DatabaseId> {
+		Some(self.database_id)
+	}
+}
+
+/// An in-memory double-write buffer.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MemoryDoubleWriteFile {
+	pages: Arc<Mutex<Vec<DoubleWritePage<'static>>>>,
+}
+
+impl DoubleWriteFileApi for MemoryDoubleWriteFile {
+	fn write_pages(&mut self, pages: &[DoubleWritePage]) -> Result<(), FileError> {
+		*self.pages.lock() = pages
+			.iter()
+			.map(|page| DoubleWritePage {
+				page_address: page.page_address,
+				wal_index: page.wal_index,
+				buf: page.buf.clone().into_owned().into(),
+			})
+			.collect();
+		Ok(())
+	}
+
+	fn read_pages(&mut self) -> Result<Vec<DoubleWritePage<'static>>, FileError> {
+		Ok(self.pages.lock().clone())
+	}
+
+	fn clear(&mut self) -> Result<(), FileError> {
+		self.pages.lock().clear();
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::borrow::Cow;
+
+	use crate::{
+		files::{
+			segment::{ReadBuf, PAGE_BODY_SIZE},
+			test_helpers::{page_address, wal_index},
+			wal::{Item, TransactionData, WalFileApi, WriteData},
+		},
+		utils::test_helpers::non_zero,
+	};
+
+	use super::*;
+
+	#[test]
+	fn reopen_segment_file() {
+		// given
+		let folder = MemoryFolder::new();
+		let segment = folder.open_segment_file(69, &Default::default()).unwrap();
+		segment
+			.write(SegmentWriteOp {
+				page_num: non_zero!(420),
+				wal_index: wal_index!(69, 420),
+				buf: &[25; PAGE_BODY_SIZE],
+			})
+			.unwrap();
+		std::mem::drop(segment);
+
+		// when
+		let segment = folder.open_segment_file(69, &Default::default()).unwrap();
+		let mut data = [0; PAGE_BODY_SIZE];
+		let mut wal_index = None;
+		segment
+			.read(SegmentReadOp {
+				page_num: non_zero!(420),
+				wal_index: &mut wal_index,
+				buf: ReadBuf::Body(&mut data),
+			})
+			.unwrap();
+
+		// then
+		assert_eq!(wal_index, Some(wal_index!(69, 420)));
+		assert_eq!(data, [25; PAGE_BODY_SIZE]);
+		assert_eq!(folder.segment_nums().unwrap(), vec![69]);
+	}
+
+	#[test]
+	fn reopen_wal_file() {
+		// given
+		let folder = MemoryFolder::new();
+		let item = Item::Write(WriteData {
+			transaction_data: TransactionData {
+				transaction_id: 0,
+				prev_transaction_item: None,
+			},
+			page_address: page_address!(123, 456),
+			offset: 420,
+			from: Some(Cow::Owned(vec![0, 0, 0, 0])),
+			to: Cow::Owned(vec![1, 2, 3, 4]),
+		});
+		let mut wal_file = folder.open_wal_file(3).unwrap();
+		let offset = wal_file.push_item(item.clone()).unwrap();
+		wal_file.flush().unwrap();
+		std::mem::drop(wal_file);
+
+		// when
+		let mut wal_files: Vec<(u64, WalFile<MemoryFile>)> =
+			Result::from_iter(folder.iter_wal_files().unwrap()).unwrap();
+
+		// then
+		assert_eq!(wal_files.len(), 1);
+		assert_eq!(wal_files[0].0, 3);
+		assert_eq!(wal_files[0].1.read_item_at(offset).unwrap(), item);
+	}
+}
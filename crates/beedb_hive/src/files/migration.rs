@@ -0,0 +1,402 @@
+use std::{
+	fs::{self, File, OpenOptions},
+	io::{Seek, SeekFrom},
+	path::Path,
+};
+
+use crate::repr::IoRepr;
+
+use super::{
+	double_write,
+	generic::{FileType, GenericHeader, GenericHeaderRepr},
+	manifest, segment, wal, working_set, FileError,
+};
+
+/// The extension of the copy that a file is upgraded in.
+const UPGRADE_EXTENSION: &str = "upgrading";
+
+/// A step that upgrades data from one format version to a newer one.
+pub(super) struct Migration<F> {
+	pub from_version: u8,
+	pub to_version: u8,
+	pub migrate: F,
+}
+
+/// Rewrites a copy of a file. Only the contents after the generic header are
+/// rewritten by the step; it may change the content offset or byte order of
+/// the header, which is then written with the new version.
+pub(super) type FileMigration =
+	Migration<fn(&mut File, &mut GenericHeader) -> Result<(), FileError>>;
+
+/// Upgrades the body of a page when it is read.
+pub(super) type PageMigration = Migration<fn(&mut [u8]) -> Result<(), FileError>>;
+
+/// The current format version of each file type, and the steps that upgrade
+/// older files to it.
+fn registry(file_type: FileType) -> (u8, &'static [FileMigration]) {
+	match file_type {
+		FileType::Segment => (segment::FORMAT_VERSION, segment::MIGRATIONS),
+		FileType::Manifest => (manifest::FORMAT_VERSION, manifest::MIGRATIONS),
+		// WAL items are referenced by their offsets, both by each other and by
+		// the pages, so WAL files can't be rewritten. Old ones are read as they
+		// are, and go away as the WAL is checkpointed.
+		FileType::Wal => (wal::FORMAT_VERSION, &[]),
+		FileType::DoubleWrite => (double_write::FORMAT_VERSION, &[]),
+		// The working set is only a hint, and is written again from scratch.
+		FileType::WorkingSet => (working_set::FORMAT_VERSION, &[]),
+	}
+}
+
+/// Finds the steps that lead from `from_version` to `to_version`, if there
+/// are any.
+fn upgrade_path<F>(
+	migrations: &[Migration<F>],
+	from_version: u8,
+	to_version: u8,
+) -> Option<Vec<&Migration<F>>> {
+	let mut steps = Vec::new();
+	let mut version = from_version;
+	while version != to_version {
+		let step = migrations
+			.iter()
+			.find(|step| step.from_version == version && step.to_version > version)?;
+		steps.push(step);
+		version = step.to_version;
+	}
+	Some(steps)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum FileUpgrade {
+	UpToDate,
+	Upgraded {
+		from_version: u8,
+		to_version: u8,
+	},
+	/// The file has an older version that can still be read, but it can't be
+	/// rewritten in the current one.
+	Unsupported {
+		version: u8,
+	},
+}
+
+/// Upgrades a file to the current version of its file type. Before the file
+/// is changed, it is copied to `backup_path`, which must not exist yet.
+///
+/// The steps are applied to another copy next to the backup, which then
+/// replaces the file, so that a crash can't leave a half-upgraded file behind.
+pub(super) fn upgrade_file(path: &Path, backup_path: &Path) -> Result<FileUpgrade, FileError> {
+	let mut header = GenericHeaderRepr::deserialize(File::open(path)?)?;
+	let (current_version, migrations) = registry(header.file_type);
+	if header.version > current_version {
+		return Err(FileError::IncompatibleVersion(
+			header.file_type,
+			header.version,
+		));
+	}
+	if header.version == current_version {
+		return Ok(FileUpgrade::UpToDate);
+	}
+	let Some(steps) = upgrade_path(migrations, header.version, current_version) else {
+		return Ok(FileUpgrade::Unsupported {
+			version: header.version,
+		});
+	};
+
+	backup(path, backup_path)?;
+	let temp_path = backup_path.with_extension(UPGRADE_EXTENSION);
+	fs::copy(path, &temp_path)?;
+	let mut file = OpenOptions::new().read(true).write(true).open(&temp_path)?;
+	let from_version = header.version;
+	for step in steps {
+		(step.migrate)(&mut file, &mut header)?;
+		header.version = step.to_version;
+		file.seek(SeekFrom::Start(0))?;
+		GenericHeaderRepr::serialize(header.clone(), &mut file)?;
+	}
+	file.sync_all()?;
+	fs::rename(&temp_path, path)?;
+	if let Some(parent) = path.parent() {
+		File::open(parent)?.sync_all()?;
+	}
+	Ok(FileUpgrade::Upgraded {
+		from_version,
+		to_version: current_version,
+	})
+}
+
+fn backup(path: &Path, backup_path: &Path) -> Result<(), FileError> {
+	// An existing backup may be the only intact copy of a file whose upgrade
+	// failed, so it is never overwritten.
+	if backup_path.exists() {
+		return Err(FileError::BackupExists(backup_path.to_path_buf()));
+	}
+	if let Some(parent) = backup_path.parent() {
+		fs::create_dir_all(parent)?;
+	}
+	fs::copy(path, backup_path)?;
+	File::open(backup_path)?.sync_all()?;
+	Ok(())
+}
+
+/// Upgrades the body of a page that was stored in an older page format. The
+/// page is only stored in the current format the next time it is written.
+pub(super) fn upgrade_page(version: u8, body: &mut [u8]) -> Result<(), FileError> {
+	upgrade_page_with(
+		segment::PAGE_MIGRATIONS,
+		segment::PAGE_FORMAT_VERSION,
+		version,
+		body,
+	)
+}
+
+fn upgrade_page_with(
+	migrations: &[PageMigration],
+	current_version: u8,
+	version: u8,
+	body: &mut [u8],
+) -> Result<(), FileError> {
+	let Some(steps) = upgrade_path(migrations, version, current_version) else {
+		return Err(FileError::IncompatiblePageVersion(version));
+	};
+	for step in steps {
+		(step.migrate)(body)?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Write;
+
+	use crate::{
+		files::{
+			manifest::DatabaseId,
+			segment::{
+				PageSize, ReadBuf, SegmentConfig, SegmentFile, SegmentFileApi, SegmentReadOp,
+			},
+			DatabaseFolder, UpgradeReport,
+		},
+		repr::{ByteOrder, Repr},
+		utils::test_helpers::non_zero,
+	};
+
+	use super::*;
+
+	fn write_legacy_segment(path: &Path, version: u8, num_pages: u64) {
+		let mut file = File::create(path).unwrap();
+		file.set_len(num_pages * PageSize::LEGACY.get() as u64)
+			.unwrap();
+		GenericHeaderRepr::serialize(
+			GenericHeader {
+				byte_order: ByteOrder::Little,
+				file_type: FileType::Segment,
+				content_offset: PageSize::LEGACY.get() as u16,
+				version,
+			},
+			&mut file,
+		)
+		.unwrap();
+	}
+
+	fn write_legacy_wal(path: &Path) {
+		GenericHeaderRepr::serialize(
+			GenericHeader {
+				byte_order: ByteOrder::Little,
+				file_type: FileType::Wal,
+				content_offset: GenericHeaderRepr::SIZE as u16,
+				version: 1,
+			},
+			File::create(path).unwrap(),
+		)
+		.unwrap();
+	}
+
+	#[test]
+	fn upgrade_preallocated_segment() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let path = tempdir.path().join("0");
+		let backup_path = tempdir.path().join("backup").join("0");
+		write_legacy_segment(&path, 1, 3);
+		let original = fs::read(&path).unwrap();
+
+		// when
+		let result = upgrade_file(&path, &backup_path).unwrap();
+
+		// then
+		assert_eq!(
+			result,
+			FileUpgrade::Upgraded {
+				from_version: 1,
+				to_version: segment::FORMAT_VERSION
+			}
+		);
+		assert_eq!(fs::read(&backup_path).unwrap(), original);
+		assert!(!backup_path.with_extension(UPGRADE_EXTENSION).exists());
+		let header = GenericHeaderRepr::deserialize(File::open(&path).unwrap()).unwrap();
+		assert_eq!(header.version, segment::FORMAT_VERSION);
+		assert_eq!(header.content_offset as usize, GenericHeaderRepr::SIZE);
+
+		let config = SegmentConfig {
+			page_size: PageSize::LEGACY,
+			..Default::default()
+		};
+		let segment = SegmentFile::open_file(&path, &config).unwrap();
+		let mut data = vec![0; PageSize::LEGACY.body_size()];
+		let mut wal_index = None;
+		segment
+			.read(SegmentReadOp {
+				page_num: non_zero!(2),
+				wal_index: &mut wal_index,
+				buf: ReadBuf::Body(&mut data),
+			})
+			.unwrap();
+		assert_eq!(wal_index, None);
+		assert_eq!(segment.database_id(), Some(DatabaseId::NIL));
+	}
+
+	#[test]
+	fn upgrade_up_to_date_segment() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let path = tempdir.path().join("0");
+		let backup_path = tempdir.path().join("backup").join("0");
+		SegmentFile::create_file(&path, &Default::default(), DatabaseId::NIL).unwrap();
+
+		// when
+		let result = upgrade_file(&path, &backup_path).unwrap();
+
+		// then
+		assert_eq!(result, FileUpgrade::UpToDate);
+		assert!(!backup_path.exists());
+	}
+
+	#[test]
+	fn keep_existing_backup() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let path = tempdir.path().join("0");
+		let backup_path = tempdir.path().join("0.bak");
+		write_legacy_segment(&path, 1, 3);
+		File::create(&backup_path)
+			.unwrap()
+			.write_all(b"original")
+			.unwrap();
+
+		// when
+		let result = upgrade_file(&path, &backup_path);
+
+		// then
+		assert!(matches!(result, Err(FileError::BackupExists(..))));
+		assert_eq!(fs::read(&backup_path).unwrap(), b"original");
+		let header = GenericHeaderRepr::deserialize(File::open(&path).unwrap()).unwrap();
+		assert_eq!(header.version, 1);
+	}
+
+	#[test]
+	fn leave_unsupported_wal_file() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let path = tempdir.path().join("0");
+		let backup_path = tempdir.path().join("backup").join("0");
+		write_legacy_wal(&path);
+
+		// when
+		let result = upgrade_file(&path, &backup_path).unwrap();
+
+		// then
+		assert_eq!(result, FileUpgrade::Unsupported { version: 1 });
+		assert!(!backup_path.exists());
+	}
+
+	#[test]
+	fn refuse_newer_version() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let path = tempdir.path().join("0");
+		write_legacy_segment(&path, segment::FORMAT_VERSION + 1, 1);
+
+		// when
+		let result = upgrade_file(&path, &tempdir.path().join("backup"));
+
+		// then
+		assert!(matches!(
+			result,
+			Err(FileError::IncompatibleVersion(FileType::Segment, version))
+				if version == segment::FORMAT_VERSION + 1
+		));
+	}
+
+	#[test]
+	fn upgrade_database_folder() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let folder = DatabaseFolder::open(tempdir.path().to_path_buf()).unwrap();
+		let segment_path = tempdir.path().join("segments").join("0");
+		let wal_path = tempdir.path().join("wal").join("0");
+		fs::create_dir_all(segment_path.parent().unwrap()).unwrap();
+		fs::create_dir_all(wal_path.parent().unwrap()).unwrap();
+		write_legacy_segment(&segment_path, 2, 3);
+		write_legacy_wal(&wal_path);
+
+		// when
+		let report = folder.upgrade().unwrap();
+
+		// then
+		assert_eq!(
+			report,
+			UpgradeReport {
+				upgraded: vec![(segment_path, 2)],
+				outdated: vec![(wal_path.clone(), 1)],
+			}
+		);
+		assert!(tempdir
+			.path()
+			.join("upgrade_backup")
+			.join("segments")
+			.join("0")
+			.is_file());
+		assert_eq!(
+			folder.upgrade().unwrap(),
+			UpgradeReport {
+				upgraded: vec![],
+				outdated: vec![(wal_path, 1)],
+			}
+		);
+	}
+
+	#[test]
+	fn upgrade_page_in_steps() {
+		// given
+		let migrations: &[PageMigration] = &[
+			Migration {
+				from_version: 2,
+				to_version: 3,
+				migrate: |body| {
+					body.reverse();
+					Ok(())
+				},
+			},
+			Migration {
+				from_version: 1,
+				to_version: 2,
+				migrate: |body| {
+					body[0] = 69;
+					Ok(())
+				},
+			},
+		];
+		let mut body = [1, 2, 3];
+
+		// when
+		upgrade_page_with(migrations, 3, 1, &mut body).unwrap();
+
+		// then
+		assert_eq!(body, [3, 2, 69]);
+		assert!(matches!(
+			upgrade_page_with(migrations, 3, 0, &mut body),
+			Err(FileError::IncompatiblePageVersion(0))
+		));
+	}
+}
@@ -0,0 +1,124 @@
+use std::{
+	fs::File,
+	io,
+	os::fd::AsRawFd,
+	ptr::{self, NonNull},
+	sync::atomic::{AtomicU32, Ordering},
+};
+
+use static_assertions::assert_impl_all;
+
+use super::FileError;
+
+/// A read-only, shared memory mapping of a segment file.
+///
+/// The mapping always covers the maximum size of a segment, which is
+/// `u16::MAX` pages after the header page, regardless of the current length
+/// of the file. It also keeps track of which pages have
+/// had their checksum verified since they were last written.
+///
+/// Each page has a state that counts its writes, with the lowest bit marking
+/// it as verified. A page is only marked as verified if it wasn't written
+/// since its state was read before the check, so a page that is rewritten
+/// while it is checked stays unverified.
+pub(crate) struct SegmentMapping {
+	ptr: NonNull<u8>,
+	len: usize,
+	page_size: usize,
+	states: Box<[AtomicU32]>,
+}
+assert_impl_all!(SegmentMapping: Send, Sync);
+
+impl SegmentMapping {
+	pub fn new(file: &File, page_size: usize) -> Result<Self, FileError> {
+		let len = page_size << 16;
+		// Safety: the file descriptor stays valid for the duration of the
+		// call, and the mapping is not tied to it afterwards.
+		let ptr = unsafe {
+			libc::mmap(
+				ptr::null_mut(),
+				len,
+				libc::PROT_READ,
+				libc::MAP_SHARED,
+				file.as_raw_fd(),
+				0,
+			)
+		};
+		if ptr == libc::MAP_FAILED {
+			return Err(io::Error::last_os_error().into());
+		}
+		Ok(Self {
+			ptr: NonNull::new(ptr.cast()).expect("mmap returned a null pointer"),
+			len,
+			page_size,
+			states: (0..1_usize << 16).map(|_| AtomicU32::new(0)).collect(),
+		})
+	}
+
+	/// Returns the raw contents of a page, including its header.
+	///
+	/// # Safety:
+	/// The caller must ensure that the page lies within the file for as long
+	/// as the returned slice is used; accessing a mapped page beyond the end
+	/// of the file raises `SIGBUS`.
+	pub unsafe fn page(&self, page_num: u16) -> &[u8] {
+		let offset = page_num as usize * self.page_size;
+		assert!(offset + self.page_size <= self.len);
+		std::slice::from_raw_parts(self.ptr.as_ptr().add(offset), self.page_size)
+	}
+
+	pub fn page_state(&self, page_num: u16) -> PageState {
+		PageState(self.states[page_num as usize].load(Ordering::Acquire))
+	}
+
+	/// Marks the page as verified, unless it was written since `state` was
+	/// read.
+	pub fn set_verified(&self, page_num: u16, state: PageState) {
+		let _ = self.states[page_num as usize].compare_exchange(
+			state.0,
+			state.0 | VERIFIED,
+			Ordering::AcqRel,
+			Ordering::Relaxed,
+		);
+	}
+
+	/// Counts a write to the page, which is no longer verified. Writers call
+	/// this both before and after writing, so that the page isn't read
+	/// unchecked while it's being written, and a check that overlaps the
+	/// write can't mark it as verified.
+	pub fn invalidate(&self, page_num: u16) {
+		let _ = self.states[page_num as usize].fetch_update(
+			Ordering::AcqRel,
+			Ordering::Acquire,
+			|state| Some(state.wrapping_add(2) & !VERIFIED),
+		);
+	}
+}
+
+const VERIFIED: u32 = 1;
+
+/// The state of a mapped page when it was read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PageState(u32);
+
+impl PageState {
+	pub fn is_verified(self) -> bool {
+		self.0 & VERIFIED != 0
+	}
+}
+
+// Safety: the mapping is read-only, and the page states are atomic.
+unsafe impl Send for SegmentMapping {}
+
+// Safety: see above.
+unsafe impl Sync for SegmentMapping {}
+
+impl Drop for SegmentMapping {
+	fn drop(&mut self) {
+		// Safety: the pointer and length are exactly the ones returned by
+		// mmap, and no references into the mapping outlive it.
+		unsafe {
+			libc::munmap(self.ptr.as_ptr().cast(), self.len);
+		}
+	}
+}
@@ -2,41 +2,59 @@ use std::{
 	convert::Infallible,
 	ffi::OsString,
 	fmt,
-	fs::{self, ReadDir},
-	io,
+	fs::{self, File, ReadDir},
+	io::{self, Write},
+	mem,
 	num::{NonZero, NonZeroU16, NonZeroU64},
-	path::PathBuf,
+	path::{Path, PathBuf},
+	str::FromStr,
 };
 
-#[cfg(feature = "io_uring")]
-use io_uring::squeue::PushError;
+use parking_lot::Mutex;
 use thiserror::Error;
 
 #[cfg(test)]
 use mockall::automock;
 
+use crate::repr::ByteOrder;
+
 use self::{
+	double_write::{DoubleWriteFile, DoubleWriteFileApi},
 	generic::FileType,
-	segment::{SegmentFile, SegmentFileApi},
+	lock::FolderLock,
+	manifest::{DatabaseId, Manifest},
+	migration::FileUpgrade,
+	segment::{PageSize, SegmentConfig, SegmentFile, SegmentFileApi},
 	wal::{WalFile, WalFileApi},
+	working_set::WorkingSet,
 };
 
 #[cfg(test)]
-use self::{segment::MockSegmentFileApi, wal::MockWalFileApi};
+use self::{
+	double_write::MockDoubleWriteFileApi, segment::MockSegmentFileApi, wal::MockWalFileApi,
+};
 
+pub(crate) mod double_write;
+#[cfg(test)]
+pub(crate) mod faulty;
 pub(super) mod generic;
+#[cfg(feature = "io_uring")]
+pub(crate) mod io_ring;
+mod lock;
+pub(crate) mod manifest;
+pub(crate) mod memory;
+mod migration;
+pub(super) mod mmap;
 pub(crate) mod segment;
 pub(super) mod utils;
 pub(crate) mod wal;
+pub(crate) mod working_set;
 
 #[derive(Debug, Error)]
 pub(crate) enum FileError {
 	#[error("The file is not a BeeDB database file")]
 	MissingMagic,
 
-	#[error("The file was created on a platform with a different byte order and cannot be opened")]
-	ByteOrderMismatch,
-
 	#[error("The file is corrupted: {0}")]
 	Corrupted(String),
 
@@ -61,16 +79,39 @@ pub(crate) enum FileError {
 	#[error("Too many concurrent IO operations!")]
 	TooManyConcurrent,
 
-	#[cfg(feature = "io_uring")]
-	#[error("Failed to push to IO queue: {0:?}")]
-	IoQueuePush(#[from] PushError),
-
 	#[error("Concurrent read failed with code {0}")]
 	ConcurrentReadFail(i32),
 
 	#[error("Concurrent write failed with code {0}")]
 	ConcurrentWriteFail(i32),
 
+	#[error("The database is already open{}", _0.map(|pid| format!(" in process {pid}")).unwrap_or_default())]
+	AlreadyOpen(Option<u32>),
+
+	#[error("The file belongs to the database {found}, but the database is {expected}")]
+	DatabaseMismatch {
+		expected: DatabaseId,
+		found: DatabaseId,
+	},
+
+	#[error("The segment is big-endian; upgrade the database folder to convert it")]
+	BigEndianSegment,
+
+	#[error("A WAL item of {0} bytes is too large")]
+	ItemTooLarge(usize),
+
+	#[error("The folder contains database files, but no manifest")]
+	MissingManifest,
+
+	#[error("Invalid page size {0}; it must be a power of two between 4 KiB and 64 KiB")]
+	InvalidPageSize(usize),
+
+	#[error("The file has a page size of {found}, but the database uses {expected}")]
+	PageSizeMismatch { expected: PageSize, found: PageSize },
+
+	#[error("A backup already exists at {}; remove it once the previous upgrade has been checked", _0.display())]
+	BackupExists(PathBuf),
+
 	#[error("An unexpected IO error occurred")]
 	Unexpected,
 
@@ -99,7 +140,7 @@ pub(crate) struct TransactionState {
 	pub last_index: WalIndex,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub(crate) struct PageAddress {
 	pub segment_num: u32,
 	pub page_num: NonZeroU16,
@@ -121,6 +162,20 @@ impl PageAddress {
 		let page_num = unsafe { NonZero::new_unchecked(page_num) };
 		Self::new(segment_num, page_num)
 	}
+
+	/// Returns the address of the following page, which is the first page of
+	/// the next segment after the last page of a segment.
+	pub fn next(self) -> Self {
+		match self.page_num.checked_add(1) {
+			Some(page_num) => Self::new(self.segment_num, page_num),
+			None => Self::new_unwrap(
+				self.segment_num
+					.checked_add(1)
+					.expect("You've somehow managed to exhaust the space of page IDs ¯\\_(ツ)_/¯"),
+				1,
+			),
+		}
+	}
 }
 
 impl fmt::Display for PageAddress {
@@ -129,6 +184,26 @@ impl fmt::Display for PageAddress {
 	}
 }
 
+#[derive(Debug, Error)]
+#[error("Invalid page address '{0}'")]
+pub(crate) struct ParsePageAddressError(String);
+
+/// Parses page addresses in the form they are displayed in.
+impl FromStr for PageAddress {
+	type Err = ParsePageAddressError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let error = || ParsePageAddressError(s.to_string());
+		let (segment_num, page_num) = s.split_once(':').ok_or_else(error)?;
+		let segment_num = u32::from_str_radix(segment_num, 16).map_err(|_| error())?;
+		let page_num = u16::from_str_radix(page_num, 16)
+			.ok()
+			.and_then(NonZeroU16::new)
+			.ok_or_else(error)?;
+		Ok(Self::new(segment_num, page_num))
+	}
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub(crate) struct WalIndex {
 	pub generation: u64,
@@ -141,16 +216,88 @@ impl WalIndex {
 	}
 }
 
+/// The files that were found in an outdated format by
+/// [`DatabaseFolder::upgrade`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct UpgradeReport {
+	/// The files that were rewritten, with the versions they had before.
+	pub upgraded: Vec<(PathBuf, u8)>,
+	/// The files that can still be read, but not be rewritten in the current
+	/// format, with their versions.
+	pub outdated: Vec<(PathBuf, u8)>,
+}
+
 pub(crate) struct DatabaseFolder {
 	path: PathBuf,
+	// The ID from the manifest, which new segment and WAL files are stamped
+	// with.
+	database_id: Mutex<DatabaseId>,
+	_lock: FolderLock,
 }
 
 impl DatabaseFolder {
 	const SEGMENTS_DIR_NAME: &'static str = "segments";
 	const WAL_DIR_NAME: &'static str = "wal";
+	const DOUBLE_WRITE_FILE_NAME: &'static str = "double_write";
+	const LOCK_FILE_NAME: &'static str = "LOCK";
+	const MANIFEST_FILE_NAME: &'static str = "MANIFEST";
+	const MANIFEST_TEMP_FILE_NAME: &'static str = "MANIFEST.tmp";
+	const WORKING_SET_FILE_NAME: &'static str = "WORKING_SET";
+	const WORKING_SET_TEMP_FILE_NAME: &'static str = "WORKING_SET.tmp";
+	const BACKUP_DIR_NAME: &'static str = "upgrade_backup";
+
+	/// Opens the database folder at `path`, creating it if necessary. The
+	/// folder stays locked against being opened by other processes (or
+	/// again by this process) until the returned value is dropped.
+	pub fn open(path: PathBuf) -> Result<Self, FileError> {
+		fs::create_dir_all(&path)?;
+		let lock = FolderLock::acquire(&path.join(Self::LOCK_FILE_NAME))?;
+		let folder = Self {
+			path,
+			database_id: Mutex::new(DatabaseId::NIL),
+			_lock: lock,
+		};
+		if let Some(manifest) = folder.read_manifest()? {
+			*folder.database_id.lock() = manifest.database_id;
+		}
+		Ok(folder)
+	}
+
+	/// Upgrades all files of the database to the current format versions.
+	/// This has to happen before the database is opened.
+	///
+	/// Each file is copied to the `upgrade_backup` folder before it is
+	/// upgraded, so that it can be restored if the upgrade fails. The upgraded
+	/// file replaces the original atomically. Segments that were written on
+	/// big-endian machines are converted to little-endian byte order.
+	/// Backups are never overwritten; they have to be removed before files
+	/// can be upgraded again.
+	pub fn upgrade(&self) -> Result<UpgradeReport, FileError> {
+		let mut paths = vec![self.path.join(Self::MANIFEST_FILE_NAME)];
+		for segment_num in self.segment_nums()? {
+			paths.push(self.segment_file_path(segment_num)?);
+		}
+		for entry in fs::read_dir(self.wal_dir()?)? {
+			paths.push(entry?.path());
+		}
+		paths.push(self.double_write_file_path()?);
+
+		let mut report = UpgradeReport::default();
+		for path in paths.into_iter().filter(|path| path.is_file()) {
+			match migration::upgrade_file(&path, &self.backup_path(&path))? {
+				FileUpgrade::UpToDate => {}
+				FileUpgrade::Upgraded { from_version, .. } => {
+					report.upgraded.push((path, from_version));
+				}
+				FileUpgrade::Unsupported { version } => report.outdated.push((path, version)),
+			}
+		}
+		Ok(report)
+	}
 
-	pub fn open(path: PathBuf) -> Self {
-		Self { path }
+	fn backup_path(&self, path: &Path) -> PathBuf {
+		let relative_path = path.strip_prefix(&self.path).unwrap_or(path);
+		self.path.join(Self::BACKUP_DIR_NAME).join(relative_path)
 	}
 
 	fn segments_dir(&self) -> Result<PathBuf, FileError> {
@@ -172,45 +319,111 @@ impl DatabaseFolder {
 	fn wal_file_path(&self, generation: u64) -> Result<PathBuf, FileError> {
 		self.wal_dir().map(|p| p.join(generation.to_string()))
 	}
+
+	fn double_write_file_path(&self) -> Result<PathBuf, FileError> {
+		fs::create_dir_all(&self.path)?;
+		Ok(self.path.join(Self::DOUBLE_WRITE_FILE_NAME))
+	}
 }
 
 #[cfg_attr(test, automock(
     type SegmentFile = MockSegmentFileApi;
     type WalFile = MockWalFileApi;
+    type DoubleWriteFile = MockDoubleWriteFileApi;
     type IterWalFiles = std::vec::IntoIter<Result<(u64, MockWalFileApi), FileError>>;
 ), allow(clippy::type_complexity))]
 pub(crate) trait DatabaseFolderApi {
 	type SegmentFile: SegmentFileApi + Send + Sync;
 	type WalFile: WalFileApi + Send + Sync;
+	type DoubleWriteFile: DoubleWriteFileApi + Send + Sync;
 	type IterWalFiles: Iterator<Item = Result<(u64, Self::WalFile), FileError>>;
 
-	fn open_segment_file(&self, segment_num: u32) -> Result<Self::SegmentFile, FileError>;
+	fn open_segment_file(
+		&self,
+		segment_num: u32,
+		config: &SegmentConfig,
+	) -> Result<Self::SegmentFile, FileError>;
+	fn delete_segment_file(&self, segment_num: u32) -> Result<(), FileError>;
+	fn segment_nums(&self) -> Result<Vec<u32>, FileError>;
 	fn open_wal_file(&self, generation: u64) -> Result<Self::WalFile, FileError>;
 	fn delete_wal_file(&self, generation: u64) -> Result<(), FileError>;
 	fn iter_wal_files(&self) -> Result<Self::IterWalFiles, FileError>;
 	fn clear_wal_files(&self) -> Result<(), FileError>;
+	fn open_double_write_file(
+		&self,
+		page_size: PageSize,
+	) -> Result<Self::DoubleWriteFile, FileError>;
+
+	/// Returns `None` if the folder has no manifest yet.
+	fn read_manifest(&self) -> Result<Option<Manifest>, FileError>;
+
+	/// Replaces the manifest atomically. Segment and WAL files that are
+	/// created afterwards belong to the database of the new manifest.
+	fn write_manifest(&self, manifest: &Manifest) -> Result<(), FileError>;
+
+	/// Returns `None` if no working set was saved yet.
+	fn read_working_set(&self) -> Result<Option<WorkingSet>, FileError>;
+
+	/// Replaces the saved working set. It is only a hint for prewarming the
+	/// page cache, so it isn't synced.
+	fn write_working_set(&self, working_set: &WorkingSet) -> Result<(), FileError>;
 }
 
 impl DatabaseFolderApi for DatabaseFolder {
 	type SegmentFile = SegmentFile;
 	type WalFile = WalFile;
+	type DoubleWriteFile = DoubleWriteFile;
 	type IterWalFiles = IterWalFiles;
 
-	fn open_segment_file(&self, segment_num: u32) -> Result<Self::SegmentFile, FileError> {
+	fn open_segment_file(
+		&self,
+		segment_num: u32,
+		config: &SegmentConfig,
+	) -> Result<Self::SegmentFile, FileError> {
 		let path = self.segment_file_path(segment_num)?;
 		if path.exists() {
-			SegmentFile::open_file(path)
+			let segment = SegmentFile::open_file(path, config)?;
+			// Page bodies are always read as little-endian; those of
+			// big-endian segments are converted by `upgrade`.
+			if segment.byte_order() == ByteOrder::Big {
+				return Err(FileError::BigEndianSegment);
+			}
+			// Segments are checked when they are opened rather than all at
+			// once when the database is opened, since there may be many of
+			// them.
+			self.database_id.lock().verify(segment.database_id())?;
+			Ok(segment)
 		} else {
-			SegmentFile::create_file(path)
+			SegmentFile::create_file(path, config, *self.database_id.lock())
 		}
 	}
 
+	fn delete_segment_file(&self, segment_num: u32) -> Result<(), FileError> {
+		let path = self.segment_file_path(segment_num)?;
+		fs::remove_file(path)?;
+		Ok(())
+	}
+
+	fn segment_nums(&self) -> Result<Vec<u32>, FileError> {
+		let mut segment_nums = Vec::new();
+		for entry in fs::read_dir(self.segments_dir()?)? {
+			let entry = entry?;
+			let Ok(segment_num) = entry.file_name().to_string_lossy().parse() else {
+				return Err(FileError::UnexpectedFile(entry.file_name()));
+			};
+			segment_nums.push(segment_num);
+		}
+		Ok(segment_nums)
+	}
+
 	fn open_wal_file(&self, generation: u64) -> Result<Self::WalFile, FileError> {
 		let path = self.wal_file_path(generation)?;
 		if path.exists() {
-			WalFile::open_file(path)
+			let wal_file = WalFile::open_file(path)?;
+			self.database_id.lock().verify(wal_file.database_id())?;
+			Ok(wal_file)
 		} else {
-			WalFile::create_file(path)
+			WalFile::create_file(path, *self.database_id.lock())
 		}
 	}
 
@@ -226,17 +439,75 @@ impl DatabaseFolderApi for DatabaseFolder {
 	}
 
 	fn iter_wal_files(&self) -> Result<Self::IterWalFiles, FileError> {
-		Ok(IterWalFiles(fs::read_dir(self.wal_dir()?)?))
+		Ok(IterWalFiles {
+			entries: fs::read_dir(self.wal_dir()?)?,
+			database_id: *self.database_id.lock(),
+		})
+	}
+
+	fn open_double_write_file(
+		&self,
+		page_size: PageSize,
+	) -> Result<Self::DoubleWriteFile, FileError> {
+		let path = self.double_write_file_path()?;
+		if path.exists() {
+			DoubleWriteFile::open_file(path, page_size)
+		} else {
+			DoubleWriteFile::create_file(path, page_size)
+		}
+	}
+
+	fn read_manifest(&self) -> Result<Option<Manifest>, FileError> {
+		match File::open(self.path.join(Self::MANIFEST_FILE_NAME)) {
+			Ok(file) => Ok(Some(Manifest::read(io::BufReader::new(file))?)),
+			Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+			Err(err) => Err(err.into()),
+		}
+	}
+
+	fn write_manifest(&self, manifest: &Manifest) -> Result<(), FileError> {
+		// The new manifest is written to a temporary file first, so that a
+		// crash can't leave a partially written manifest behind.
+		let temp_path = self.path.join(Self::MANIFEST_TEMP_FILE_NAME);
+		let mut file = File::create(&temp_path)?;
+		manifest.write(&mut file)?;
+		file.sync_all()?;
+		fs::rename(temp_path, self.path.join(Self::MANIFEST_FILE_NAME))?;
+		File::open(&self.path)?.sync_all()?;
+
+		*self.database_id.lock() = manifest.database_id;
+		Ok(())
+	}
+
+	fn read_working_set(&self) -> Result<Option<WorkingSet>, FileError> {
+		match File::open(self.path.join(Self::WORKING_SET_FILE_NAME)) {
+			Ok(file) => Ok(Some(WorkingSet::read(io::BufReader::new(file))?)),
+			Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+			Err(err) => Err(err.into()),
+		}
+	}
+
+	fn write_working_set(&self, working_set: &WorkingSet) -> Result<(), FileError> {
+		let temp_path = self.path.join(Self::WORKING_SET_TEMP_FILE_NAME);
+		let mut writer = io::BufWriter::new(File::create(&temp_path)?);
+		working_set.write(&mut writer)?;
+		writer.flush()?;
+		mem::drop(writer);
+		fs::rename(temp_path, self.path.join(Self::WORKING_SET_FILE_NAME))?;
+		Ok(())
 	}
 }
 
-pub(crate) struct IterWalFiles(ReadDir);
+pub(crate) struct IterWalFiles {
+	entries: ReadDir,
+	database_id: DatabaseId,
+}
 
 impl Iterator for IterWalFiles {
 	type Item = Result<(u64, WalFile), FileError>;
 
 	fn next(&mut self) -> Option<Self::Item> {
-		for entry_result in &mut self.0 {
+		for entry_result in &mut self.entries {
 			let entry = match entry_result {
 				Ok(entry) => entry,
 				Err(error) => return Some(Err(error.into())),
@@ -246,6 +517,9 @@ impl Iterator for IterWalFiles {
 					Ok(file) => file,
 					Err(error) => return Some(Err(error)),
 				};
+				if let Err(error) = self.database_id.verify(file.database_id()) {
+					return Some(Err(error));
+				}
 				let Ok(generation): Result<u64, _> = entry.file_name().to_string_lossy().parse()
 				else {
 					return Some(Err(FileError::UnexpectedFile(entry.file_name())));
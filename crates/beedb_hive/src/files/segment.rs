@@ -1,38 +1,63 @@
+#[cfg(feature = "io_uring")]
+use std::sync::Arc;
 use std::{
+	fmt,
 	fs::{File, OpenOptions},
-	io::{Seek, SeekFrom},
+	io::{self, IoSlice, Seek, SeekFrom},
+	mem,
 	num::{NonZeroU16, NonZeroU64},
+	ops::{Deref, DerefMut},
 	os::{self},
 	path::Path,
+	sync::{
+		atomic::{AtomicU16, Ordering},
+		OnceLock,
+	},
 };
 
-#[cfg(feature = "io_uring")]
-use io_uring::{cqueue, opcode, squeue, types};
-
+use log::warn;
 #[cfg(test)]
 use mockall::automock;
-use zerocopy::{FromBytes, FromZeros, Immutable, IntoBytes};
+use parking_lot::{Mutex, RwLock};
+use zerocopy::{
+	little_endian::{U16, U32, U64},
+	FromBytes, FromZeros, Immutable, IntoBytes,
+};
 
+#[cfg(feature = "io_uring")]
+use super::io_ring::{IoRing, RingFile, RingOp, RingOpKind};
 use super::{
 	generic::{GenericHeader, GenericHeaderRepr},
+	manifest::DatabaseId,
+	migration::{self, FileMigration, Migration, PageMigration},
+	mmap::SegmentMapping,
 	FileError, WalIndex,
 };
 use crate::{
-	consts::PAGE_SIZE,
-	files::{generic::FileType, utils::CRC16},
-	repr::{IoRepr, Repr},
+	consts::{
+		DEFAULT_PAGE_SIZE, DEFAULT_SEGMENT_EXTENT_SIZE, DEFAULT_USE_DIRECT_IO, DIRECT_IO_ALIGNMENT,
+		MAX_COALESCED_PAGES, MAX_PAGE_SIZE, MIN_PAGE_SIZE,
+	},
+	doc_store,
+	files::{
+		generic::FileType,
+		utils::{AlignedPage, CRC16},
+	},
+	repr::{impl_swap_bytes, ByteOrder, IoRepr, Repr, SwapBytes},
 };
 
 const FORMAT_VERSION_UNINIT: u8 = 0;
-const FORMAT_VERSION: u8 = 1;
-
-// 2 GiB when PAGE_SIZE = 32 KiB
-const SEGMENT_SIZE: usize = PAGE_SIZE << 16;
+const FORMAT_VERSION_PREALLOCATED: u8 = 1;
+const FORMAT_VERSION_UNIDENTIFIED: u8 = 2;
+const FORMAT_VERSION_UNSIZED: u8 = 3;
+const FORMAT_VERSION_NATIVE_ENDIAN: u8 = 4;
+pub(super) const FORMAT_VERSION: u8 = 5;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 struct InitPageHeader {
 	wal_index: WalIndex,
 	crc: u16,
+	format_version: u8,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -44,26 +69,58 @@ enum PageHeader {
 #[derive(Debug, Clone, Immutable, FromBytes, IntoBytes)]
 #[repr(C, packed)]
 struct PageHeaderRepr {
-	wal_generation: u64,
-	wal_offset: u64,
-	crc: u16,
+	wal_generation: U64,
+	wal_offset: U64,
+	crc: U16,
 	format_version: u8,
 }
 impl Repr<PageHeader> for PageHeaderRepr {
 	type Error = FileError;
 }
-
-const PAGE_FORMAT_VERSION: u8 = 1;
+impl_swap_bytes!(PageHeaderRepr {
+	wal_generation,
+	wal_offset,
+	crc
+});
+
+/// The number of bytes in front of the body of a page that the page header
+/// takes up on disk.
+pub(crate) const PAGE_HEADER_SIZE: usize = PageHeaderRepr::SIZE;
+
+/// Before version 2, the checksum of a page only covered its body. Now it
+/// covers the page number too, so that a page that was written to the wrong
+/// place doesn't pass as intact.
+const PAGE_FORMAT_VERSION_UNNUMBERED: u8 = 1;
+pub(super) const PAGE_FORMAT_VERSION: u8 = 2;
+
+/// The steps that upgrade pages from older page formats when they are read.
+pub(super) const PAGE_MIGRATIONS: &[PageMigration] = &[
+	// Only the checksum changed, which is checked before the body is upgraded.
+	Migration {
+		from_version: PAGE_FORMAT_VERSION_UNNUMBERED,
+		to_version: PAGE_FORMAT_VERSION,
+		migrate: |_| Ok(()),
+	},
+];
+
+fn page_checksum(format_version: u8, page_num: NonZeroU16, body: &[u8]) -> u16 {
+	let mut digest = CRC16.digest();
+	if format_version > PAGE_FORMAT_VERSION_UNNUMBERED {
+		digest.update(&page_num.get().to_le_bytes());
+	}
+	digest.update(body);
+	digest.finalize()
+}
 
 impl From<PageHeader> for PageHeaderRepr {
 	fn from(value: PageHeader) -> Self {
 		match value {
 			PageHeader::Uninit => Self::new_zeroed(),
 			PageHeader::Init(header) => Self {
-				wal_generation: header.wal_index.generation,
-				wal_offset: header.wal_index.offset.get(),
-				crc: header.crc,
-				format_version: PAGE_FORMAT_VERSION,
+				wal_generation: header.wal_index.generation.into(),
+				wal_offset: header.wal_index.offset.get().into(),
+				crc: header.crc.into(),
+				format_version: header.format_version,
 			},
 		}
 	}
@@ -77,32 +134,316 @@ impl TryFrom<PageHeaderRepr> for PageHeader {
 			return Ok(Self::Uninit);
 		}
 
-		if value.format_version != PAGE_FORMAT_VERSION {
+		if value.format_version > PAGE_FORMAT_VERSION {
 			return Err(FileError::IncompatiblePageVersion(value.format_version));
 		}
-		let Some(wal_offset) = NonZeroU64::new(value.wal_offset) else {
+		let Some(wal_offset) = NonZeroU64::new(value.wal_offset.get()) else {
 			return Err(FileError::Corrupted(
 				"Found invalid WAL offset '0'".to_string(),
 			));
 		};
 		Ok(Self::Init(InitPageHeader {
-			wal_index: WalIndex::new(value.wal_generation, wal_offset),
-			crc: value.crc,
+			wal_index: WalIndex::new(value.wal_generation.get(), wal_offset),
+			crc: value.crc.get(),
+			format_version: value.format_version,
 		}))
 	}
 }
 
-pub(crate) const PAGE_BODY_SIZE: usize = PAGE_SIZE - PageHeaderRepr::SIZE;
+/// The size of the pages of a database, including their headers. It is
+/// chosen when the database is created, and can't be changed afterwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct PageSize(u32);
+
+impl PageSize {
+	pub const DEFAULT: Self = Self::new_unwrap(DEFAULT_PAGE_SIZE);
+
+	/// The page size of databases from before the page size was
+	/// configurable.
+	pub const LEGACY: Self = Self::new_unwrap(32 * 1024);
+
+	/// Fails unless `size` is a power of two between 4 KiB and 64 KiB.
+	pub fn new(size: usize) -> Result<Self, FileError> {
+		if !Self::is_valid(size) {
+			return Err(FileError::InvalidPageSize(size));
+		}
+		Ok(Self(u32::try_from(size).unwrap()))
+	}
+
+	const fn new_unwrap(size: usize) -> Self {
+		if !Self::is_valid(size) {
+			panic!("Called PageSize::new_unwrap with an invalid page size");
+		}
+		// The size is at most 64 KiB at this point.
+		#[allow(clippy::cast_possible_truncation)]
+		Self(size as u32)
+	}
+
+	const fn is_valid(size: usize) -> bool {
+		size.is_power_of_two() && size >= MIN_PAGE_SIZE && size <= MAX_PAGE_SIZE
+	}
+
+	#[inline]
+	pub const fn get(self) -> usize {
+		self.0 as usize
+	}
+
+	/// The number of bytes of a page that are available for its contents.
+	#[inline]
+	pub const fn body_size(self) -> usize {
+		self.get() - PageHeaderRepr::SIZE
+	}
+
+	/// The maximum size of a segment file's page area, which holds up to
+	/// 2^16 pages.
+	#[inline]
+	pub const fn segment_size(self) -> usize {
+		self.get() << 16
+	}
+}
+
+impl Default for PageSize {
+	fn default() -> Self {
+		Self::DEFAULT
+	}
+}
+
+impl fmt::Display for PageSize {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{} bytes", self.0)
+	}
+}
+
+#[cfg(test)]
+pub(crate) const PAGE_BODY_SIZE: usize = PageSize::DEFAULT.body_size();
+
+#[derive(Debug, Clone, Immutable, FromBytes, IntoBytes)]
+#[repr(C, packed)]
+struct SegmentHeaderRepr {
+	high_water_mark: U16,
+	database_id: [u8; 16],
+	page_size: U32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SegmentHeader {
+	high_water_mark: u16,
+	database_id: DatabaseId,
+	page_size: PageSize,
+}
+
+impl From<SegmentHeader> for SegmentHeaderRepr {
+	fn from(value: SegmentHeader) -> Self {
+		Self {
+			high_water_mark: value.high_water_mark.into(),
+			database_id: value.database_id.to_bytes(),
+			page_size: value.page_size.0.into(),
+		}
+	}
+}
+
+impl TryFrom<SegmentHeaderRepr> for SegmentHeader {
+	type Error = FileError;
+
+	fn try_from(value: SegmentHeaderRepr) -> Result<Self, Self::Error> {
+		// Headers from before version 4 end before the page size, and the rest
+		// of the header page is zeroed.
+		let page_size = match value.page_size.get() {
+			0 => PageSize::LEGACY,
+			size => PageSize::new(size as usize)?,
+		};
+		Ok(Self {
+			high_water_mark: value.high_water_mark.get(),
+			database_id: DatabaseId::from_bytes(value.database_id),
+			page_size,
+		})
+	}
+}
+
+impl Repr<SegmentHeader> for SegmentHeaderRepr {
+	type Error = FileError;
+}
+
+impl_swap_bytes!(SegmentHeaderRepr {
+	high_water_mark,
+	page_size
+});
+
+/// The steps that upgrade segment files to the current format version.
+pub(super) const MIGRATIONS: &[FileMigration] = &[
+	Migration {
+		from_version: FORMAT_VERSION_PREALLOCATED,
+		to_version: FORMAT_VERSION_UNIDENTIFIED,
+		migrate: add_high_water_mark,
+	},
+	// The rest of the header page is zeroed, so the database ID reads as nil.
+	Migration {
+		from_version: FORMAT_VERSION_UNIDENTIFIED,
+		to_version: FORMAT_VERSION_UNSIZED,
+		migrate: |_, _| Ok(()),
+	},
+	Migration {
+		from_version: FORMAT_VERSION_UNSIZED,
+		to_version: FORMAT_VERSION_NATIVE_ENDIAN,
+		migrate: add_page_size,
+	},
+	Migration {
+		from_version: FORMAT_VERSION_NATIVE_ENDIAN,
+		to_version: FORMAT_VERSION,
+		migrate: convert_to_little_endian,
+	},
+];
+
+/// Preallocated segments are allocated up to the end of the file.
+fn add_high_water_mark(file: &mut File, header: &mut GenericHeader) -> Result<(), FileError> {
+	let num_pages = file.metadata()?.len() / PageSize::LEGACY.get() as u64;
+	let segment_header = SegmentHeader {
+		high_water_mark: u16::try_from(num_pages.saturating_sub(1)).unwrap_or(u16::MAX),
+		database_id: DatabaseId::NIL,
+		page_size: PageSize::LEGACY,
+	};
+	file.seek(SeekFrom::Start(GenericHeaderRepr::SIZE as u64))?;
+	SegmentHeaderRepr::serialize_in(segment_header, file, header.byte_order)
+}
+
+/// Stores the page size explicitly, and moves the content offset from the
+/// end of the header page to the end of the generic header. The pages stay
+/// where they are.
+fn add_page_size(file: &mut File, header: &mut GenericHeader) -> Result<(), FileError> {
+	file.seek(SeekFrom::Start(GenericHeaderRepr::SIZE as u64))?;
+	let segment_header = SegmentHeaderRepr::deserialize_in(&mut *file, header.byte_order)?;
+	file.seek(SeekFrom::Start(GenericHeaderRepr::SIZE as u64))?;
+	SegmentHeaderRepr::serialize_in(segment_header, file, header.byte_order)?;
+	header.content_offset = u16::try_from(GenericHeaderRepr::SIZE).unwrap();
+	Ok(())
+}
+
+/// Converts segments that were written on big-endian machines to
+/// little-endian byte order, including the page bodies, whose layout is
+/// known to the document store. The checksum of each page is checked before
+/// it is converted, and computed again afterwards. Segments that are
+/// little-endian already stay as they are.
+fn convert_to_little_endian(file: &mut File, header: &mut GenericHeader) -> Result<(), FileError> {
+	if header.byte_order == ByteOrder::Little {
+		return Ok(());
+	}
+	file.seek(SeekFrom::Start(GenericHeaderRepr::SIZE as u64))?;
+	let segment_header = SegmentHeaderRepr::deserialize_in(&mut *file, header.byte_order)?;
+	let page_size = segment_header.page_size.get();
+	let mut page = vec![0; page_size];
+	for page_num in (1..=segment_header.high_water_mark).filter_map(NonZeroU16::new) {
+		let offset = get_page_offset(page_num, page_size);
+		os::unix::fs::FileExt::read_exact_at(file, &mut page, offset)?;
+		let Some(mut page_header) =
+			RawReadOp::decode_header(&page, page_num, true, header.byte_order)?
+		else {
+			continue;
+		};
+		let body = &mut page[PAGE_HEADER_SIZE..];
+		doc_store::swap_page_bytes(body).map_err(|err| {
+			FileError::Corrupted(format!("Failed to convert page {page_num}: {err}"))
+		})?;
+		page_header.crc = page_checksum(page_header.format_version, page_num, body);
+		page[0..PAGE_HEADER_SIZE]
+			.copy_from_slice(PageHeaderRepr::from(PageHeader::Init(page_header)).as_bytes());
+		os::unix::fs::FileExt::write_all_at(file, &page, offset)?;
+	}
+	file.seek(SeekFrom::Start(GenericHeaderRepr::SIZE as u64))?;
+	SegmentHeaderRepr::serialize(segment_header, file)?;
+	header.byte_order = ByteOrder::Little;
+	Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct SegmentConfig {
+	/// The number of bytes by which a segment file grows when a page beyond
+	/// its end is written. Rounded up to a whole number of pages.
+	pub extent_size: usize,
+
+	/// Whether pages are read and written with `O_DIRECT`, bypassing the
+	/// kernel page cache.
+	pub direct_io: bool,
+
+	/// The page size of new segments. Existing segments must have been
+	/// created with the same page size.
+	pub page_size: PageSize,
+
+	/// The ring used for batched IO. If there is none, batches are executed
+	/// with synchronous reads and writes.
+	#[cfg(feature = "io_uring")]
+	pub io_ring: Option<Arc<IoRing>>,
+}
+
+impl Default for SegmentConfig {
+	fn default() -> Self {
+		Self {
+			extent_size: DEFAULT_SEGMENT_EXTENT_SIZE,
+			direct_io: DEFAULT_USE_DIRECT_IO,
+			page_size: PageSize::DEFAULT,
+			#[cfg(feature = "io_uring")]
+			io_ring: None,
+		}
+	}
+}
+
+// The IO ring is runtime state rather than configuration, so it is not
+// compared.
+impl PartialEq for SegmentConfig {
+	fn eq(&self, other: &Self) -> bool {
+		self.extent_size == other.extent_size
+			&& self.direct_io == other.direct_io
+			&& self.page_size == other.page_size
+	}
+}
+
+impl Eq for SegmentConfig {}
+
+impl SegmentConfig {
+	fn extent_pages(&self) -> u16 {
+		u16::try_from(self.extent_size.div_ceil(self.page_size.get()))
+			.unwrap_or(u16::MAX)
+			.max(1)
+	}
+}
 
+/// A segment file holds up to `u16::MAX` pages, but is only allocated up to
+/// its high-water mark, which is stored in the segment header. Pages beyond
+/// the high-water mark are considered uninitialized, and writing one of them
+/// grows the file by whole extents.
 pub(crate) struct SegmentFile {
 	file: File,
+	direct_file: Option<File>,
+	extent_pages: u16,
+	// `None` for segments from before database IDs existed.
+	database_id: Option<DatabaseId>,
+	page_size: PageSize,
+	byte_order: ByteOrder,
+	high_water_mark: AtomicU16,
+	grow_lock: Mutex<()>,
+	mapping: OnceLock<SegmentMapping>,
+	// Held exclusively while the file is truncated, so that no page beyond
+	// the new end is accessed through the mapping.
+	truncate_lock: RwLock<()>,
+	#[cfg(feature = "io_uring")]
+	io_ring: Option<(Arc<IoRing>, RingFile)>,
 }
 
-const READ_OP_ID: u64 = 1;
-const WRITE_OP_ID: u64 = 2;
+#[cfg(feature = "io_uring")]
+impl Drop for SegmentFile {
+	fn drop(&mut self) {
+		if let Some((io_ring, ring_file)) = &self.io_ring {
+			io_ring.release_file(*ring_file);
+		}
+	}
+}
 
 impl SegmentFile {
-	pub fn create_file(path: impl AsRef<Path>) -> Result<Self, FileError> {
+	pub fn create_file(
+		path: impl AsRef<Path>,
+		config: &SegmentConfig,
+		database_id: DatabaseId,
+	) -> Result<Self, FileError> {
+		let path = path.as_ref();
 		let mut file = OpenOptions::new()
 			.create(true)
 			.truncate(true)
@@ -111,18 +452,26 @@ impl SegmentFile {
 			.open(path)?;
 
 		let header = GenericHeader {
+			byte_order: ByteOrder::Little,
 			file_type: FileType::Segment,
-			content_offset: u16::try_from(PAGE_SIZE).unwrap(),
+			content_offset: u16::try_from(GenericHeaderRepr::SIZE).unwrap(),
 			version: FORMAT_VERSION,
 		};
 		GenericHeaderRepr::serialize(header, &mut file)?;
+		let segment_header = SegmentHeader {
+			high_water_mark: 0,
+			database_id,
+			page_size: config.page_size,
+		};
+		SegmentHeaderRepr::serialize(segment_header.clone(), &mut file)?;
 
-		file.set_len(SEGMENT_SIZE as u64)?;
+		file.set_len(config.page_size.get() as u64)?;
 
-		Ok(Self { file })
+		Self::new(path, file, config, segment_header, ByteOrder::Little)
 	}
 
-	pub fn open_file(path: impl AsRef<Path>) -> Result<Self, FileError> {
+	pub fn open_file(path: impl AsRef<Path>, config: &SegmentConfig) -> Result<Self, FileError> {
+		let path = path.as_ref();
 		let mut file = OpenOptions::new().read(true).write(true).open(path)?;
 
 		file.seek(SeekFrom::Start(0))?;
@@ -131,86 +480,392 @@ impl SegmentFile {
 		if header.file_type != FileType::Segment {
 			return Err(FileError::WrongFileType(header.file_type));
 		}
-		if header.version != FORMAT_VERSION {
-			return Err(FileError::IncompatibleVersion(
-				header.file_type,
-				FORMAT_VERSION,
-			));
-		}
-		if header.content_offset as usize != PAGE_SIZE {
+		// Before version 4, the content offset was the fixed page size. Now
+		// the page size is stored in the segment header, right after the
+		// generic one.
+		let expected_content_offset = match header.version {
+			FORMAT_VERSION_NATIVE_ENDIAN | FORMAT_VERSION => GenericHeaderRepr::SIZE,
+			_ => PageSize::LEGACY.get(),
+		};
+		if header.content_offset as usize != expected_content_offset {
 			return Err(FileError::Corrupted(format!(
-				"Expected content offset {PAGE_SIZE}, but found {}",
+				"Expected content offset {expected_content_offset}, but found {}",
 				header.content_offset
 			)));
 		}
-		if file.metadata()?.len() != SEGMENT_SIZE as u64 {
+
+		let segment_header = match header.version {
+			// Version 1 segments were always preallocated in full.
+			FORMAT_VERSION_PREALLOCATED => SegmentHeader {
+				high_water_mark: u16::MAX,
+				database_id: DatabaseId::NIL,
+				page_size: PageSize::LEGACY,
+			},
+			// Version 2 headers end before the database ID, and the rest of
+			// the header page is zeroed, so the ID reads as nil.
+			FORMAT_VERSION_UNIDENTIFIED
+			| FORMAT_VERSION_UNSIZED
+			| FORMAT_VERSION_NATIVE_ENDIAN
+			| FORMAT_VERSION => SegmentHeaderRepr::deserialize_in(&mut file, header.byte_order)?,
+			_ => {
+				return Err(FileError::IncompatibleVersion(
+					header.file_type,
+					FORMAT_VERSION,
+				))
+			}
+		};
+		if segment_header.page_size != config.page_size {
+			return Err(FileError::PageSizeMismatch {
+				expected: config.page_size,
+				found: segment_header.page_size,
+			});
+		}
+		let page_size = segment_header.page_size;
+		let file_len = file.metadata()?.len();
+		if file_len
+			< get_page_offset_raw(segment_header.high_water_mark, page_size)
+				+ page_size.get() as u64
+		{
 			return Err(FileError::Corrupted(
 				"Storage segment has been truncated".to_string(),
 			));
 		}
 
-		Ok(Self { file })
+		let mut segment = Self::new(path, file, config, segment_header, header.byte_order)?;
+		if header.version <= FORMAT_VERSION_UNIDENTIFIED {
+			segment.database_id = None;
+		}
+		Ok(segment)
 	}
 
-	#[cfg(unix)]
-	fn read_exact_at(&self, op: &mut RawReadOp) -> Result<(), FileError> {
-		os::unix::fs::FileExt::read_exact_at(&self.file, op.buf, op.offset)?;
+	fn new(
+		path: &Path,
+		file: File,
+		config: &SegmentConfig,
+		header: SegmentHeader,
+		byte_order: ByteOrder,
+	) -> Result<Self, FileError> {
+		let direct_file = if config.direct_io {
+			Self::open_direct(path)?
+		} else {
+			None
+		};
+		#[cfg(feature = "io_uring")]
+		let io_ring = config.io_ring.as_ref().map(|io_ring| {
+			let ring_file = io_ring.register_file(direct_file.as_ref().unwrap_or(&file));
+			(Arc::clone(io_ring), ring_file)
+		});
+		Ok(Self {
+			file,
+			direct_file,
+			extent_pages: config.extent_pages(),
+			database_id: Some(header.database_id),
+			page_size: header.page_size,
+			byte_order,
+			high_water_mark: AtomicU16::new(header.high_water_mark),
+			grow_lock: Mutex::new(()),
+			mapping: OnceLock::new(),
+			truncate_lock: RwLock::new(()),
+			#[cfg(feature = "io_uring")]
+			io_ring,
+		})
+	}
+
+	/// The byte order of the page headers. Page bodies are stored as they
+	/// are written, so those of big-endian segments are only converted by
+	/// upgrading them.
+	pub fn byte_order(&self) -> ByteOrder {
+		self.byte_order
+	}
+
+	#[inline]
+	fn is_allocated(&self, page_num: NonZeroU16) -> bool {
+		page_num.get() <= self.high_water_mark.load(Ordering::Acquire)
+	}
+
+	/// Makes sure the file is allocated up to and including the given page,
+	/// growing it by whole extents if necessary.
+	fn ensure_allocated(&self, page_num: NonZeroU16) -> Result<(), FileError> {
+		if self.is_allocated(page_num) {
+			return Ok(());
+		}
+
+		let _guard = self.grow_lock.lock();
+		let high_water_mark = self.high_water_mark.load(Ordering::Acquire);
+		if page_num.get() <= high_water_mark {
+			return Ok(());
+		}
+
+		let new_high_water_mark = page_num
+			.get()
+			.checked_next_multiple_of(self.extent_pages)
+			.unwrap_or(u16::MAX);
+		let start = self.end_offset(high_water_mark);
+		let end = self.end_offset(new_high_water_mark);
+		self.allocate(start, end - start)?;
+
+		// The new high-water mark must be persisted before any pages beyond
+		// the old one are written; otherwise they would be lost after a
+		// crash.
+		self.write_high_water_mark(new_high_water_mark)?;
+
+		self.high_water_mark
+			.store(new_high_water_mark, Ordering::Release);
 		Ok(())
 	}
 
-	#[cfg(unix)]
-	fn write_all_at(&self, op: &RawWriteOp) -> Result<(), FileError> {
-		os::unix::fs::FileExt::write_all_at(&self.file, op.buf, op.offset)?;
+	/// The length of the file if it is allocated up to the given high-water
+	/// mark.
+	#[inline]
+	fn end_offset(&self, high_water_mark: u16) -> u64 {
+		get_page_offset_raw(high_water_mark, self.page_size) + self.page_size.get() as u64
+	}
+
+	fn write_high_water_mark(&self, high_water_mark: u16) -> Result<(), FileError> {
+		let header = SegmentHeaderRepr::from(SegmentHeader {
+			high_water_mark,
+			database_id: self.database_id.unwrap_or(DatabaseId::NIL),
+			page_size: self.page_size,
+		})
+		.convert(self.byte_order);
+		os::unix::fs::FileExt::write_all_at(
+			&self.file,
+			header.as_bytes(),
+			GenericHeaderRepr::SIZE as u64,
+		)?;
+		self.file.sync_data()?;
 		Ok(())
 	}
 
-	#[cfg(feature = "io_uring")]
-	fn exec_batch(&self, ops: &mut [RawIoOp]) -> Result<(), FileError> {
-		use io_uring::IoUring;
-		use std::mem;
+	#[cfg(target_os = "linux")]
+	fn allocate(&self, offset: u64, len: u64) -> Result<(), FileError> {
+		use std::os::fd::AsRawFd;
 
-		let num_ops = ops.len();
+		let raw_offset = libc::off_t::try_from(offset).expect("Segment offset must fit in off_t");
+		let raw_len = libc::off_t::try_from(len).expect("Segment extent must fit in off_t");
 
-		let Ok(queue_size): Result<u32, _> = ops.len().next_power_of_two().try_into() else {
-			return Err(FileError::TooManyConcurrent);
-		};
+		// Safety: the file descriptor stays valid for the duration of the
+		// call.
+		let result = unsafe { libc::fallocate(self.file.as_raw_fd(), 0, raw_offset, raw_len) };
+		if result == 0 {
+			return Ok(());
+		}
 
-		let mut ring = IoUring::new(queue_size)?;
+		let error = io::Error::last_os_error();
+		if error.raw_os_error() != Some(libc::EOPNOTSUPP) {
+			return Err(error.into());
+		}
+		// Not all file systems support fallocate; just extend the file then.
+		self.file.set_len(offset + len)?;
+		Ok(())
+	}
+
+	#[cfg(not(target_os = "linux"))]
+	fn allocate(&self, offset: u64, len: u64) -> Result<(), FileError> {
+		self.file.set_len(offset + len)?;
+		Ok(())
+	}
+
+	fn mapping(&self) -> Result<&SegmentMapping, FileError> {
+		if let Some(mapping) = self.mapping.get() {
+			return Ok(mapping);
+		}
+		let mapping = SegmentMapping::new(&self.file, self.page_size.get())?;
+		// If another thread was faster, its mapping is used and ours dropped.
+		Ok(self.mapping.get_or_init(|| mapping))
+	}
+
+	/// Makes sure that the checksum of a page is checked again the next time
+	/// it is read through the mapping. Writes call this before and after
+	/// writing the page.
+	#[inline]
+	fn invalidate_mapped(&self, page_num: NonZeroU16) {
+		if let Some(mapping) = self.mapping.get() {
+			mapping.invalidate(page_num.get());
+		}
+	}
 
-		let mut queue = ring.submission();
+	fn invalidate_mapped_writes(&self, ops: &[&mut SegmentOp]) {
 		for op in ops {
-			let entry = op.as_cqueue_entry(&self.file);
-			unsafe { queue.push(&entry) }?;
+			if let SegmentOp::Write(write_op) = op {
+				self.invalidate_mapped(write_op.page_num);
+			}
+		}
+	}
+
+	/// The file used for reading and writing pages. If direct IO is enabled,
+	/// this is a separate descriptor opened with `O_DIRECT`, while headers are
+	/// still accessed through the buffered one.
+	#[inline]
+	fn page_file(&self) -> &File {
+		self.direct_file.as_ref().unwrap_or(&self.file)
+	}
+
+	#[cfg(target_os = "linux")]
+	fn open_direct(path: &Path) -> Result<Option<File>, FileError> {
+		use std::os::unix::fs::OpenOptionsExt;
+
+		let result = OpenOptions::new()
+			.read(true)
+			.write(true)
+			.custom_flags(libc::O_DIRECT)
+			.open(path);
+		let file = match result {
+			Ok(file) => file,
+			Err(err) if err.raw_os_error() == Some(libc::EINVAL) => {
+				warn!("File system does not support direct IO, falling back to buffered IO");
+				return Ok(None);
+			}
+			Err(err) => return Err(err.into()),
+		};
+
+		// Some file systems accept O_DIRECT when opening, but then reject the
+		// actual IO, so make sure reading the start of the header page works.
+		let mut probe = AlignedPage::zeroed(DIRECT_IO_ALIGNMENT);
+		match os::unix::fs::FileExt::read_exact_at(&file, &mut probe, 0) {
+			Ok(()) => Ok(Some(file)),
+			Err(err) if err.raw_os_error() == Some(libc::EINVAL) => {
+				warn!("File system rejected direct IO, falling back to buffered IO");
+				Ok(None)
+			}
+			Err(err) => Err(err.into()),
 		}
-		mem::drop(queue);
+	}
+
+	#[cfg(not(target_os = "linux"))]
+	fn open_direct(_path: &Path) -> Result<Option<File>, FileError> {
+		warn!("Direct IO is not supported on this platform, falling back to buffered IO");
+		Ok(None)
+	}
+
+	#[cfg(unix)]
+	fn read_exact_at(&self, op: &mut RawReadOp) -> Result<(), FileError> {
+		os::unix::fs::FileExt::read_exact_at(self.page_file(), op.buf, op.offset)?;
+		Ok(())
+	}
+
+	#[cfg(unix)]
+	fn write_all_at(&self, op: &RawWriteOp) -> Result<(), FileError> {
+		os::unix::fs::FileExt::write_all_at(self.page_file(), op.buf, op.offset)?;
+		Ok(())
+	}
 
-		ring.submit_and_wait(num_ops)?;
+	/// Writes pages at adjacent offsets with a single `pwritev`.
+	#[cfg(unix)]
+	fn write_all_vectored_at(&self, ops: &[&RawWriteOp]) -> Result<(), FileError> {
+		use std::os::fd::AsRawFd;
 
-		let mut cqueue = ring.completion();
-		for _ in 0..num_ops {
-			let Some(cqe) = cqueue.next() else {
-				return Err(FileError::Unexpected);
+		let mut slices: Vec<IoSlice> = ops.iter().map(|op| IoSlice::new(op.buf)).collect();
+		let mut slices = &mut slices[..];
+		let mut offset = ops[0].offset;
+		while !slices.is_empty() {
+			let num_slices = libc::c_int::try_from(slices.len()).unwrap();
+			let file_offset = libc::off_t::try_from(offset).unwrap();
+			// Safety: `IoSlice` is ABI compatible with `iovec`, and the slices stay
+			// borrowed for the whole call.
+			let result = unsafe {
+				libc::pwritev(
+					self.page_file().as_raw_fd(),
+					slices.as_ptr().cast(),
+					num_slices,
+					file_offset,
+				)
 			};
+			let num_written = match usize::try_from(result) {
+				Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero).into()),
+				Ok(num_written) => num_written,
+				Err(..) => {
+					let err = io::Error::last_os_error();
+					if err.kind() == io::ErrorKind::Interrupted {
+						continue;
+					}
+					return Err(err.into());
+				}
+			};
+			IoSlice::advance_slices(&mut slices, num_written);
+			offset += num_written as u64;
+		}
+		Ok(())
+	}
 
-			if cqe.result() < 0 {
-				match cqe.user_data() {
-					READ_OP_ID => return Err(FileError::ConcurrentReadFail(cqe.result())),
-					WRITE_OP_ID => return Err(FileError::ConcurrentWriteFail(cqe.result())),
-					_ => return Err(FileError::Unexpected),
+	fn exec_batch(&self, ops: &mut [&mut SegmentOp]) -> Result<(), FileError> {
+		let num_scratch_pages = ops.iter().filter(|op| needs_scratch_page(op)).count();
+		let mut buffers = vec![AlignedPage::zeroed(self.page_size.get()); num_scratch_pages];
+		let mut scratch_pages = buffers.iter_mut().map(|buf| &mut buf[..]);
+		let mut raw_ops: Vec<RawIoOp> = ops
+			.iter_mut()
+			.map(|op| RawIoOp::new(op, &mut scratch_pages, self.byte_order))
+			.collect();
+
+		let mut start = 0;
+		while start < raw_ops.len() {
+			let run_len = coalesced_run_len(&raw_ops[start..]);
+			if run_len > 1 {
+				let write_ops: Vec<&RawWriteOp> = raw_ops[start..start + run_len]
+					.iter()
+					.filter_map(|raw_op| match raw_op {
+						RawIoOp::Write(write_op) => Some(write_op),
+						RawIoOp::Read(..) => None,
+					})
+					.collect();
+				self.write_all_vectored_at(&write_ops)?;
+			} else {
+				match &mut raw_ops[start] {
+					RawIoOp::Read(read_op) => self.read_exact_at(read_op)?,
+					RawIoOp::Write(write_op) => self.write_all_at(write_op)?,
 				}
 			}
+			start += run_len;
 		}
+		mem::drop(raw_ops);
 
-		Ok(())
+		complete_batch(ops, buffers.iter().map(|buf| &buf[..]), self.byte_order)
 	}
 
-	#[cfg(not(feature = "io_uring"))]
-	fn exec_batch(&self, ops: &mut [RawIoOp]) -> Result<(), FileError> {
-		for op in ops {
-			match op {
-				RawIoOp::Read(read_op) => self.read_exact_at(read_op)?,
-				RawIoOp::Write(write_op) => self.write_all_at(write_op)?,
+	/// Executes the batch on one of the shared rings, using its staging
+	/// buffers for everything that isn't read in place. If there are more
+	/// operations than fit into the ring, they are executed in multiple
+	/// rounds. The writes of a coalesced run take up consecutive staging
+	/// buffers, so they are issued as a single write of a fixed buffer.
+	#[cfg(feature = "io_uring")]
+	fn exec_batch_on_ring(
+		io_ring: &IoRing,
+		ring_file: RingFile,
+		ops: &mut [&mut SegmentOp],
+		byte_order: ByteOrder,
+	) -> Result<(), FileError> {
+		let mut ring = io_ring.lock();
+		let (queue, mut buffers) = ring.split();
+		for chunk in ops.chunks_mut(io_ring.queue_depth()) {
+			let mut scratch_pages = buffers.iter_mut().map(|buf| &mut buf[..]);
+			let raw_ops: Vec<RawIoOp> = chunk
+				.iter_mut()
+				.map(|op| RawIoOp::new(op, &mut scratch_pages, byte_order))
+				.collect();
+			let mut run_lens: Vec<usize> = Vec::new();
+			let mut start = 0;
+			while start < raw_ops.len() {
+				let run_len = coalesced_run_len(&raw_ops[start..]);
+				run_lens.push(run_len);
+				start += run_len;
 			}
+			let mut raw_ops = raw_ops.into_iter();
+			let mut ring_ops: Vec<RingOp> = run_lens
+				.into_iter()
+				.map(|run_len| {
+					let mut ring_op = raw_ops.next().unwrap().into_ring_op();
+					for raw_op in raw_ops.by_ref().take(run_len - 1) {
+						ring_op.bufs.extend(raw_op.into_ring_op().bufs);
+					}
+					ring_op
+				})
+				.collect();
+
+			queue.exec(ring_file, &mut ring_ops)?;
+			mem::drop(ring_ops);
+
+			let scratch_pages = buffers.iter().map(|buf| &buf[..]);
+			complete_batch(chunk, scratch_pages, byte_order)?;
 		}
 		Ok(())
 	}
@@ -220,97 +875,212 @@ impl SegmentFile {
 }
 
 #[inline]
-fn get_page_offset(page_num: NonZeroU16) -> u64 {
-	page_num.get() as u64 * PAGE_SIZE as u64
+fn get_page_offset(page_num: NonZeroU16, page_size: usize) -> u64 {
+	page_num.get() as u64 * page_size as u64
+}
+
+#[inline]
+fn get_page_offset_raw(page_num: u16, page_size: PageSize) -> u64 {
+	page_num as u64 * page_size.get() as u64
+}
+
+/// The number of operations at the start of `ops` that can be issued as a
+/// single vectored write, because they write pages that follow each other.
+/// Reads are never coalesced.
+fn coalesced_run_len(ops: &[RawIoOp]) -> usize {
+	let Some(RawIoOp::Write(first)) = ops.first() else {
+		return 1;
+	};
+	let mut end_offset = first.offset + first.buf.len() as u64;
+	let mut run_len = 1;
+	for op in ops[1..].iter().take(MAX_COALESCED_PAGES - 1) {
+		match op {
+			RawIoOp::Write(write_op) if write_op.offset == end_offset => {
+				end_offset += write_op.buf.len() as u64;
+				run_len += 1;
+			}
+			_ => break,
+		}
+	}
+	run_len
+}
+
+/// Whether the operation needs a scratch page to be executed, because it
+/// isn't a read into a buffer that the page can be read into directly.
+fn needs_scratch_page(op: &SegmentOp) -> bool {
+	!matches!(op, SegmentOp::Read(read_op) if read_op.buf.is_in_place())
+}
+
+/// Completes the reads of a batch that was executed, taking the scratch pages
+/// of the operations that needed one in order.
+fn complete_batch<'b>(
+	ops: &mut [&mut SegmentOp],
+	mut scratch_pages: impl Iterator<Item = &'b [u8]>,
+	byte_order: ByteOrder,
+) -> Result<(), FileError> {
+	for op in ops {
+		let scratch_page = if needs_scratch_page(op) {
+			scratch_pages.next()
+		} else {
+			None
+		};
+		if let SegmentOp::Read(read_op) = op {
+			match scratch_page {
+				Some(page) => RawReadOp::complete_from(page, read_op, true, byte_order)?,
+				None => RawReadOp::complete_in_place(read_op, true, byte_order)?,
+			}
+		}
+	}
+	Ok(())
+}
+
+/// Encodes a page with its header, the way it is stored in a segment file.
+/// The buffer has to be exactly one page long.
+pub(super) fn encode_page(op: &SegmentWriteOp, buf: &mut [u8]) {
+	RawWriteOp::new(op, buf, ByteOrder::Little);
+}
+
+/// Decodes a page that was encoded with [`encode_page`], checking its
+/// checksum. An all-zero page is read as uninitialized.
+pub(super) fn decode_page(page: &[u8], op: &mut SegmentReadOp) -> Result<(), FileError> {
+	RawReadOp::complete_from(page, op, true, ByteOrder::Little)
 }
 
 #[derive(Debug)]
 struct RawReadOp<'a> {
 	offset: u64,
 	buf: &'a mut [u8],
+	byte_order: ByteOrder,
 }
 
 impl<'a> RawReadOp<'a> {
-	fn new(op: &SegmentReadOp, buf: &'a mut [u8]) -> Self {
-		debug_assert_eq!(buf.len(), PAGE_SIZE);
+	/// Creates the operation for reading a page into `buf`, which has to be
+	/// exactly one page long.
+	fn new(op: &SegmentReadOp, buf: &'a mut [u8], byte_order: ByteOrder) -> Self {
+		debug_assert_eq!(op.buf.page_len(), buf.len());
+		Self {
+			offset: get_page_offset(op.page_num, buf.len()),
+			buf,
+			byte_order,
+		}
+	}
+
+	/// Creates the operation for reading a page directly into the buffer of
+	/// `op`, which has to be in place.
+	fn in_place<'b>(op: &'a mut SegmentReadOp<'b>, byte_order: ByteOrder) -> Self {
+		let offset = get_page_offset(op.page_num, op.buf.page_len());
+		let ReadBuf::Page(buf) = &mut op.buf else {
+			unreachable!("Tried to read a page in place into its body!");
+		};
 		Self {
-			offset: get_page_offset(op.page_num),
+			offset,
 			buf,
+			byte_order,
 		}
 	}
 
 	fn complete(&self, op: &mut SegmentReadOp) -> Result<(), FileError> {
-		debug_assert_eq!(op.buf.len(), PAGE_BODY_SIZE);
+		Self::complete_from(self.buf, op, true, self.byte_order)
+	}
 
-		let header = PageHeaderRepr::from_bytes(&self.buf[0..PageHeaderRepr::SIZE])?;
-		let PageHeader::Init(header) = header else {
-			op.buf.fill(0);
-			*op.wal_index = None;
+	/// Fills the read operation from the raw contents of a page. The checksum
+	/// is only checked if `verify_crc` is set.
+	fn complete_from(
+		page: &[u8],
+		op: &mut SegmentReadOp,
+		verify_crc: bool,
+		byte_order: ByteOrder,
+	) -> Result<(), FileError> {
+		debug_assert_eq!(op.buf.page_len(), page.len());
+
+		let Some(header) = Self::decode_header(page, op.page_num, verify_crc, byte_order)? else {
+			Self::complete_uninit(op);
 			return Ok(());
 		};
-
-		let body = &self.buf[PageHeaderRepr::SIZE..];
-
-		let crc = CRC16.checksum(body);
-		if header.crc != crc {
-			return Err(FileError::ChecksumMismatch);
+		*op.wal_index = Some(header.wal_index);
+		op.buf.copy_from_slice(&page[PAGE_HEADER_SIZE..]);
+		if header.format_version < PAGE_FORMAT_VERSION {
+			migration::upgrade_page(header.format_version, &mut op.buf)?;
 		}
+		Ok(())
+	}
 
+	/// Completes a read of a page directly into the buffer of `op`.
+	fn complete_in_place(
+		op: &mut SegmentReadOp,
+		verify_crc: bool,
+		byte_order: ByteOrder,
+	) -> Result<(), FileError> {
+		let ReadBuf::Page(page) = &op.buf else {
+			unreachable!("Tried to complete a read in place into a page body!");
+		};
+		let Some(header) = Self::decode_header(page, op.page_num, verify_crc, byte_order)? else {
+			Self::complete_uninit(op);
+			return Ok(());
+		};
 		*op.wal_index = Some(header.wal_index);
-		op.buf.copy_from_slice(body);
+		if header.format_version < PAGE_FORMAT_VERSION {
+			migration::upgrade_page(header.format_version, &mut op.buf)?;
+		}
 		Ok(())
 	}
 
-	#[cfg(feature = "io_uring")]
-	fn as_opcode(&mut self, fd: &File) -> opcode::Read {
-		use std::os::fd::AsRawFd;
+	/// Decodes the header of the raw page, and checks the checksum of its
+	/// body if `verify_crc` is set. Returns `None` for an uninitialized page.
+	fn decode_header(
+		page: &[u8],
+		page_num: NonZeroU16,
+		verify_crc: bool,
+		byte_order: ByteOrder,
+	) -> Result<Option<InitPageHeader>, FileError> {
+		let header = PageHeaderRepr::from_bytes_in(&page[0..PAGE_HEADER_SIZE], byte_order)?;
+		let PageHeader::Init(header) = header else {
+			return Ok(None);
+		};
+		if verify_crc
+			&& header.crc
+				!= page_checksum(header.format_version, page_num, &page[PAGE_HEADER_SIZE..])
+		{
+			return Err(FileError::ChecksumMismatch);
+		}
+		Ok(Some(header))
+	}
 
-		opcode::Read::new(
-			types::Fd(fd.as_raw_fd()),
-			self.buf.as_mut_ptr(),
-			self.buf.len().try_into().expect("Read operation too large"),
-		)
-		.offset(self.offset)
+	fn complete_uninit(op: &mut SegmentReadOp) {
+		op.buf.fill(0);
+		*op.wal_index = None;
 	}
 }
 
 #[derive(Debug)]
 struct RawWriteOp<'a> {
 	offset: u64,
-	buf: &'a [u8],
+	buf: &'a mut [u8],
 }
 
 impl<'a> RawWriteOp<'a> {
-	fn new(op: &SegmentWriteOp, buf: &'a mut [u8]) -> Self {
-		debug_assert_eq!(op.buf.len(), PAGE_BODY_SIZE);
-		debug_assert_eq!(buf.len(), PAGE_SIZE);
+	/// Encodes the page into `buf`, which has to be exactly one page long.
+	/// Unlike reads, writes always go through a scratch page, since callers
+	/// keep their own data in front of the body while the page is written.
+	fn new(op: &SegmentWriteOp, buf: &'a mut [u8], byte_order: ByteOrder) -> Self {
+		debug_assert_eq!(op.buf.len() + PageHeaderRepr::SIZE, buf.len());
 
-		let crc = CRC16.checksum(op.buf);
+		let crc = page_checksum(PAGE_FORMAT_VERSION, op.page_num, op.buf);
 		let header = PageHeader::Init(InitPageHeader {
 			wal_index: op.wal_index,
 			crc,
+			format_version: PAGE_FORMAT_VERSION,
 		});
 
-		buf[0..PageHeaderRepr::SIZE].copy_from_slice(PageHeaderRepr::from(header).as_bytes());
+		buf[0..PageHeaderRepr::SIZE]
+			.copy_from_slice(PageHeaderRepr::from(header).convert(byte_order).as_bytes());
 		buf[PageHeaderRepr::SIZE..].copy_from_slice(op.buf);
 
 		Self {
-			offset: get_page_offset(op.page_num),
+			offset: get_page_offset(op.page_num, buf.len()),
 			buf,
 		}
 	}
-
-	#[cfg(feature = "io_uring")]
-	fn as_opcode(&self, fd: &File) -> opcode::Write {
-		use std::os::fd::AsRawFd;
-
-		opcode::Write::new(
-			types::Fd(fd.as_raw_fd()),
-			self.buf.as_ptr(),
-			self.buf.len().try_into().expect("Read operation too large"),
-		)
-		.offset(self.offset)
-	}
 }
 
 #[derive(Debug)]
@@ -320,28 +1090,43 @@ enum RawIoOp<'a> {
 }
 
 impl<'a> RawIoOp<'a> {
-	fn new(op: &SegmentOp, buf: &'a mut [u8]) -> Self {
-		match op {
-			SegmentOp::Read(read_op) => Self::Read(RawReadOp::new(read_op, buf)),
-			SegmentOp::Write(write_op) => Self::Write(RawWriteOp::new(write_op, buf)),
+	/// Creates the raw operation, which takes the next scratch page unless
+	/// the page is read in place.
+	fn new<'b>(
+		op: &'a mut SegmentOp<'b>,
+		scratch_pages: &mut impl Iterator<Item = &'a mut [u8]>,
+		byte_order: ByteOrder,
+	) -> Self {
+		if !needs_scratch_page(op) {
+			let SegmentOp::Read(read_op) = op else {
+				unreachable!("Only reads are executed in place!");
+			};
+			return Self::Read(RawReadOp::in_place(read_op, byte_order));
 		}
-	}
-
-	fn complete(&self, op: &mut SegmentOp) -> Result<(), FileError> {
-		match (self, op) {
-			(Self::Read(read_op), SegmentOp::Read(ref mut segment_read_op)) => {
-				read_op.complete(segment_read_op)
-			}
-			(Self::Write(..), SegmentOp::Write(..)) => Ok(()),
-			_ => Err(FileError::Unexpected),
+		let buf = scratch_pages
+			.next()
+			.expect("Too few scratch pages for the batch!");
+		match op {
+			SegmentOp::Read(read_op) => Self::Read(RawReadOp::new(read_op, buf, byte_order)),
+			SegmentOp::Write(write_op) => Self::Write(RawWriteOp::new(write_op, buf, byte_order)),
 		}
 	}
 
+	/// Converts the operation to one on the ring, which transfers its whole
+	/// buffer.
 	#[cfg(feature = "io_uring")]
-	fn as_cqueue_entry(&mut self, fd: &File) -> squeue::Entry {
+	fn into_ring_op(self) -> RingOp<'a> {
 		match self {
-			Self::Read(read_op) => read_op.as_opcode(fd).build().user_data(READ_OP_ID),
-			Self::Write(write_op) => write_op.as_opcode(fd).build().user_data(WRITE_OP_ID),
+			Self::Read(read_op) => RingOp {
+				kind: RingOpKind::Read,
+				offset: read_op.offset,
+				bufs: vec![read_op.buf],
+			},
+			Self::Write(write_op) => RingOp {
+				kind: RingOpKind::Write,
+				offset: write_op.offset,
+				bufs: vec![write_op.buf],
+			},
 		}
 	}
 }
@@ -350,128 +1135,1016 @@ impl<'a> RawIoOp<'a> {
 pub(crate) struct SegmentReadOp<'a> {
 	pub page_num: NonZeroU16,
 	pub wal_index: &'a mut Option<WalIndex>,
-	pub buf: &'a mut [u8],
-}
-
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub(crate) struct SegmentWriteOp<'a> {
-	pub page_num: NonZeroU16,
-	pub wal_index: WalIndex,
-	pub buf: &'a [u8],
+	pub buf: ReadBuf<'a>,
 }
 
+/// The buffer that a page is read into. It dereferences to the body of the
+/// page either way.
 #[derive(Debug, PartialEq, Eq)]
-pub(crate) enum SegmentOp<'a> {
-	Read(SegmentReadOp<'a>),
-	Write(SegmentWriteOp<'a>),
+pub(crate) enum ReadBuf<'a> {
+	/// Only the body of the page, which is copied out of a scratch page.
+	Body(&'a mut [u8]),
+
+	/// A whole page, with room for the page header in front of the body. If
+	/// it is aligned for direct IO, the page is read into it directly, and
+	/// the room for the header is overwritten.
+	Page(&'a mut [u8]),
 }
 
-#[cfg_attr(test, automock)]
-#[allow(clippy::needless_lifetimes)]
-pub(crate) trait SegmentFileApi {
-	fn read<'a>(&self, op: SegmentReadOp<'a>) -> Result<(), FileError>;
-	fn write<'a>(&self, op: SegmentWriteOp<'a>) -> Result<(), FileError>;
-	fn batch<'a>(&self, ops: &mut [SegmentOp<'a>]) -> Result<(), FileError>;
-}
+impl ReadBuf<'_> {
+	/// Whether the page is read directly into the buffer.
+	fn is_in_place(&self) -> bool {
+		match self {
+			Self::Body(..) => false,
+			Self::Page(page) => page.as_ptr().align_offset(DIRECT_IO_ALIGNMENT) == 0,
+		}
+	}
+
+	/// Borrows the buffer for another operation.
+	pub fn reborrow(&mut self) -> ReadBuf<'_> {
+		match self {
+			Self::Body(body) => ReadBuf::Body(body),
+			Self::Page(page) => ReadBuf::Page(page),
+		}
+	}
+
+	/// The size of the page that is read, including its header.
+	fn page_len(&self) -> usize {
+		self.len() + PAGE_HEADER_SIZE
+	}
+}
+
+impl Deref for ReadBuf<'_> {
+	type Target = [u8];
+
+	fn deref(&self) -> &Self::Target {
+		match self {
+			Self::Body(body) => body,
+			Self::Page(page) => &page[PAGE_HEADER_SIZE..],
+		}
+	}
+}
+
+impl DerefMut for ReadBuf<'_> {
+	fn deref_mut(&mut self) -> &mut Self::Target {
+		match self {
+			Self::Body(body) => body,
+			Self::Page(page) => &mut page[PAGE_HEADER_SIZE..],
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SegmentWriteOp<'a> {
+	pub page_num: NonZeroU16,
+	pub wal_index: WalIndex,
+	pub buf: &'a [u8],
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum SegmentOp<'a> {
+	Read(SegmentReadOp<'a>),
+	Write(SegmentWriteOp<'a>),
+}
+
+impl SegmentOp<'_> {
+	fn page_num(&self) -> NonZeroU16 {
+		match self {
+			Self::Read(read_op) => read_op.page_num,
+			Self::Write(write_op) => write_op.page_num,
+		}
+	}
+}
+
+#[cfg_attr(test, automock)]
+#[allow(clippy::needless_lifetimes)]
+pub(crate) trait SegmentFileApi {
+	fn read<'a>(&self, op: SegmentReadOp<'a>) -> Result<(), FileError>;
+	fn write<'a>(&self, op: SegmentWriteOp<'a>) -> Result<(), FileError>;
+	fn batch<'a>(&self, ops: &mut [SegmentOp<'a>]) -> Result<(), FileError>;
+	fn sync(&self) -> Result<(), FileError>;
+
+	/// Reads a page through a memory mapping of the file instead of a system
+	/// call. The checksum of a page is only checked the first time it is
+	/// read after being written.
+	fn read_mapped<'a>(&self, op: SegmentReadOp<'a>) -> Result<(), FileError>;
+
+	/// Discards all pages after `high_water_mark`, returning their space to
+	/// the file system.
+	fn truncate(&self, high_water_mark: u16) -> Result<(), FileError>;
+
+	/// The last page that is allocated in the file. Pages after it read as
+	/// uninitialized.
+	fn high_water_mark(&self) -> u16;
+
+	/// The ID of the database the segment was created for, or `None` if the
+	/// segment is from before database IDs existed.
+	fn database_id(&self) -> Option<DatabaseId>;
+}
 
 impl SegmentFileApi for SegmentFile {
 	fn read(&self, mut op: SegmentReadOp) -> Result<(), FileError> {
-		debug_assert_eq!(op.buf.len(), PAGE_BODY_SIZE);
+		debug_assert_eq!(op.buf.len(), self.page_size.body_size());
 
-		let mut page_buf = [0; PAGE_SIZE];
-		let mut raw_op = RawReadOp::new(&op, &mut page_buf);
+		if !self.is_allocated(op.page_num) {
+			RawReadOp::complete_uninit(&mut op);
+			return Ok(());
+		}
+
+		if op.buf.is_in_place() {
+			let mut raw_op = RawReadOp::in_place(&mut op, self.byte_order);
+			self.read_exact_at(&mut raw_op)?;
+			return RawReadOp::complete_in_place(&mut op, true, self.byte_order);
+		}
+
+		let mut page_buf = AlignedPage::zeroed(self.page_size.get());
+		let mut raw_op = RawReadOp::new(&op, &mut page_buf, self.byte_order);
 		self.read_exact_at(&mut raw_op)?;
 		raw_op.complete(&mut op)
 	}
 
 	fn write(&self, op: SegmentWriteOp) -> Result<(), FileError> {
-		debug_assert_eq!(op.buf.len(), PAGE_BODY_SIZE);
+		debug_assert_eq!(op.buf.len(), self.page_size.body_size());
 
-		let mut page_buf = [0; PAGE_SIZE];
-		let raw_op = RawWriteOp::new(&op, &mut page_buf);
-		self.write_all_at(&raw_op)?;
+		self.ensure_allocated(op.page_num)?;
 
-		Ok(())
+		let mut page_buf = AlignedPage::zeroed(self.page_size.get());
+		let raw_op = RawWriteOp::new(&op, &mut page_buf, self.byte_order);
+		self.invalidate_mapped(op.page_num);
+		let result = self.write_all_at(&raw_op);
+		self.invalidate_mapped(op.page_num);
+
+		result
 	}
 
 	fn batch(&self, ops: &mut [SegmentOp]) -> Result<(), FileError> {
-		let mut buffers = vec![[0; PAGE_SIZE]; ops.len()];
-		let mut raw_ops: Vec<RawIoOp> = Vec::with_capacity(ops.len());
-		for (op, buf) in ops.iter().zip(buffers.iter_mut()) {
-			raw_ops.push(RawIoOp::new(op, buf));
+		let max_write_page = ops
+			.iter()
+			.filter_map(|op| match op {
+				SegmentOp::Write(write_op) => Some(write_op.page_num),
+				SegmentOp::Read(..) => None,
+			})
+			.max();
+		if let Some(page_num) = max_write_page {
+			self.ensure_allocated(page_num)?;
+		}
+
+		let (mut ops, unallocated): (Vec<&mut SegmentOp>, Vec<&mut SegmentOp>) = ops
+			.iter_mut()
+			.partition(|op| self.is_allocated(op.page_num()));
+		for op in unallocated {
+			if let SegmentOp::Read(read_op) = op {
+				RawReadOp::complete_uninit(read_op);
+			}
 		}
+		// Writes to adjacent pages are coalesced. The sort is stable, so
+		// operations on the same page still happen in order.
+		ops.sort_by_key(|op| op.page_num());
+		self.invalidate_mapped_writes(&ops);
+
+		#[cfg(feature = "io_uring")]
+		let result = match &self.io_ring {
+			Some((io_ring, ring_file)) => {
+				Self::exec_batch_on_ring(io_ring, *ring_file, &mut ops, self.byte_order)
+			}
+			None => self.exec_batch(&mut ops),
+		};
+		#[cfg(not(feature = "io_uring"))]
+		let result = self.exec_batch(&mut ops);
 
-		self.exec_batch(&mut raw_ops)?;
+		self.invalidate_mapped_writes(&ops);
+		result
+	}
 
-		for (raw_op, op) in raw_ops.iter().zip(ops.iter_mut()) {
-			raw_op.complete(op)?
+	fn sync(&self) -> Result<(), FileError> {
+		self.file.sync_data()?;
+		Ok(())
+	}
+
+	fn read_mapped(&self, mut op: SegmentReadOp) -> Result<(), FileError> {
+		debug_assert_eq!(op.buf.len(), self.page_size.body_size());
+
+		let _guard = self.truncate_lock.read();
+		if !self.is_allocated(op.page_num) {
+			RawReadOp::complete_uninit(&mut op);
+			return Ok(());
 		}
 
+		let mapping = self.mapping()?;
+		let page_num = op.page_num.get();
+		// Safety: the page is allocated, so it lies within the file, and the file
+		// can't be truncated while the guard is held.
+		let page = unsafe { mapping.page(page_num) };
+		let state = mapping.page_state(page_num);
+		RawReadOp::complete_from(page, &mut op, !state.is_verified(), self.byte_order)?;
+		if !state.is_verified() {
+			mapping.set_verified(page_num, state);
+		}
 		Ok(())
 	}
+
+	fn truncate(&self, high_water_mark: u16) -> Result<(), FileError> {
+		let _truncate_guard = self.truncate_lock.write();
+		let _guard = self.grow_lock.lock();
+		if high_water_mark >= self.high_water_mark.load(Ordering::Acquire) {
+			return Ok(());
+		}
+
+		// The high-water mark is lowered before the file is truncated, so that
+		// a crash in between can't leave a header pointing past the end of the
+		// file.
+		self.high_water_mark
+			.store(high_water_mark, Ordering::Release);
+		self.write_high_water_mark(high_water_mark)?;
+		self.file.set_len(self.end_offset(high_water_mark))?;
+		Ok(())
+	}
+
+	fn high_water_mark(&self) -> u16 {
+		self.high_water_mark.load(Ordering::Acquire)
+	}
+
+	fn database_id(&self) -> Option<DatabaseId> {
+		self.database_id
+	}
 }
 
 #[cfg(test)]
 mod tests {
-	use std::io::{Read, Write};
+	use std::{
+		fs,
+		io::{Read, Write},
+	};
 
 	use pretty_assertions::assert_buf_eq;
 
 	use crate::{
-		files::{generic::GenericHeaderRepr, test_helpers::wal_index},
+		files::{
+			generic::GenericHeaderRepr, test_helpers::wal_index, DatabaseFolder, DatabaseFolderApi,
+		},
 		utils::test_helpers::non_zero,
 	};
 
 	use super::*;
 
+	const PAGE_SIZE: usize = PageSize::DEFAULT.get();
+
 	#[test]
 	fn create_segment_file() {
 		// given
 		let tempdir = tempfile::tempdir().unwrap();
+		let database_id = DatabaseId::from_bytes([69; 16]);
 
 		// when
-		SegmentFile::create_file(tempdir.path().join("0")).unwrap();
+		let segment =
+			SegmentFile::create_file(tempdir.path().join("0"), &Default::default(), database_id)
+				.unwrap();
 
 		// then
-		let expected: Vec<u8> = GenericHeaderRepr::from(GenericHeader {
-			file_type: FileType::Segment,
-			content_offset: PAGE_SIZE as u16,
-			version: FORMAT_VERSION,
-		})
-		.as_bytes()
-		.to_vec();
+		let expected: Vec<u8> = [
+			GenericHeaderRepr::from(GenericHeader {
+				byte_order: ByteOrder::Little,
+				file_type: FileType::Segment,
+				content_offset: GenericHeaderRepr::SIZE as u16,
+				version: FORMAT_VERSION,
+			})
+			.as_bytes(),
+			SegmentHeaderRepr::from(SegmentHeader {
+				high_water_mark: 0,
+				database_id,
+				page_size: PageSize::DEFAULT,
+			})
+			.as_bytes(),
+		]
+		.concat();
 
 		let mut file = File::open(tempdir.path().join("0")).unwrap();
-		let received: &mut [u8] = &mut [0; GenericHeaderRepr::SIZE];
+		let received: &mut [u8] = &mut [0; GenericHeaderRepr::SIZE + SegmentHeaderRepr::SIZE];
 		file.read_exact(received).unwrap();
 
 		assert_buf_eq!(received, expected);
+		assert_eq!(file.metadata().unwrap().len(), PAGE_SIZE as u64);
+		assert_eq!(segment.database_id(), Some(database_id));
 	}
 
 	#[test]
 	fn open_segment_file() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let file_start: Vec<u8> = [
+			GenericHeaderRepr::from(GenericHeader {
+				byte_order: ByteOrder::Little,
+				file_type: FileType::Segment,
+				content_offset: GenericHeaderRepr::SIZE as u16,
+				version: FORMAT_VERSION,
+			})
+			.as_bytes(),
+			SegmentHeaderRepr::from(SegmentHeader {
+				high_water_mark: 2,
+				database_id: DatabaseId::from_bytes([69; 16]),
+				page_size: PageSize::DEFAULT,
+			})
+			.as_bytes(),
+		]
+		.concat();
+		let mut file = File::create(tempdir.path().join("0")).unwrap();
+		file.set_len(3 * PAGE_SIZE as u64).unwrap();
+		file.write_all(&file_start).unwrap();
+
+		// when
+		let segment =
+			SegmentFile::open_file(tempdir.path().join("0"), &Default::default()).unwrap();
+
+		// then
+		assert!(segment.is_allocated(non_zero!(2)));
+		assert!(!segment.is_allocated(non_zero!(3)));
+		assert_eq!(
+			segment.database_id(),
+			Some(DatabaseId::from_bytes([69; 16]))
+		);
+	}
+
+	#[test]
+	fn open_big_endian_segment_file() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let mut file_start: Vec<u8> = [
+			GenericHeaderRepr::from(GenericHeader {
+				byte_order: ByteOrder::Big,
+				file_type: FileType::Segment,
+				content_offset: PAGE_SIZE as u16,
+				version: FORMAT_VERSION_UNSIZED,
+			})
+			.as_bytes(),
+			&1_u16.to_be_bytes(),
+			&[69; 16],
+		]
+		.concat();
+		file_start.resize(PAGE_SIZE, 0);
+		file_start.extend(69_u64.to_be_bytes());
+		file_start.extend(420_u64.to_be_bytes());
+		file_start.extend(CRC16.checksum(&[25; PAGE_BODY_SIZE]).to_be_bytes());
+		file_start.push(PAGE_FORMAT_VERSION_UNNUMBERED);
+		file_start.extend([25; PAGE_BODY_SIZE]);
+		fs::write(tempdir.path().join("0"), file_start).unwrap();
+
+		// when
+		let segment =
+			SegmentFile::open_file(tempdir.path().join("0"), &Default::default()).unwrap();
+		let mut data = [0; PAGE_BODY_SIZE];
+		let mut wal_index = None;
+		segment
+			.read(SegmentReadOp {
+				page_num: non_zero!(1),
+				wal_index: &mut wal_index,
+				buf: ReadBuf::Body(&mut data),
+			})
+			.unwrap();
+		segment
+			.write(SegmentWriteOp {
+				page_num: non_zero!(2),
+				wal_index: wal_index!(1, 2),
+				buf: &[3; PAGE_BODY_SIZE],
+			})
+			.unwrap();
+
+		// then
+		assert_eq!(wal_index, Some(wal_index!(69, 420)));
+		assert_eq!(data, [25; PAGE_BODY_SIZE]);
+		assert_eq!(
+			segment.database_id(),
+			Some(DatabaseId::from_bytes([69; 16]))
+		);
+		let file = fs::read(tempdir.path().join("0")).unwrap();
+		assert_eq!(
+			file[GenericHeaderRepr::SIZE..GenericHeaderRepr::SIZE + 2],
+			segment
+				.high_water_mark
+				.load(Ordering::Acquire)
+				.to_be_bytes()
+		);
+		assert_eq!(file[2 * PAGE_SIZE..2 * PAGE_SIZE + 8], 1_u64.to_be_bytes());
+	}
+
+	fn records_page_body(byte_order: ByteOrder) -> Vec<u8> {
+		let to_bytes = |value: u16| match byte_order {
+			ByteOrder::Big => value.to_be_bytes(),
+			ByteOrder::Little => value.to_le_bytes(),
+		};
+		let mut body = vec![0; PAGE_BODY_SIZE];
+		body[0] = 2;
+		body[1..3].copy_from_slice(&to_bytes(4));
+		body[3..5].copy_from_slice(&to_bytes(1));
+		body[5..7].copy_from_slice(&to_bytes(100));
+		body[100..104].copy_from_slice(&[1, 2, 3, 4]);
+		body
+	}
+
+	#[test]
+	fn database_folder_converts_big_endian_segment() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let mut file_start: Vec<u8> = [
+			GenericHeaderRepr::from(GenericHeader {
+				byte_order: ByteOrder::Big,
+				file_type: FileType::Segment,
+				content_offset: PAGE_SIZE as u16,
+				version: FORMAT_VERSION_UNSIZED,
+			})
+			.as_bytes(),
+			&2_u16.to_be_bytes(),
+		]
+		.concat();
+		file_start.resize(PAGE_SIZE, 0);
+		let body = records_page_body(ByteOrder::Big);
+		file_start.extend(69_u64.to_be_bytes());
+		file_start.extend(420_u64.to_be_bytes());
+		file_start.extend(CRC16.checksum(&body).to_be_bytes());
+		file_start.push(PAGE_FORMAT_VERSION_UNNUMBERED);
+		file_start.extend(body);
+		file_start.resize(3 * PAGE_SIZE, 0);
+		let path = tempdir.path().join("segments/0");
+		fs::create_dir(tempdir.path().join("segments")).unwrap();
+		fs::write(&path, file_start).unwrap();
+		let folder = DatabaseFolder::open(tempdir.path().to_path_buf()).unwrap();
+		let unconverted_result = folder.open_segment_file(0, &Default::default());
+
+		// when
+		let report = folder.upgrade().unwrap();
+		let segment = folder.open_segment_file(0, &Default::default()).unwrap();
+		let mut data = vec![0; PAGE_BODY_SIZE];
+		let mut wal_index = None;
+		segment
+			.read(SegmentReadOp {
+				page_num: non_zero!(1),
+				wal_index: &mut wal_index,
+				buf: ReadBuf::Body(&mut data),
+			})
+			.unwrap();
+
+		// then
+		assert!(matches!(
+			unconverted_result,
+			Err(FileError::BigEndianSegment)
+		));
+		assert_eq!(report.upgraded, vec![(path, FORMAT_VERSION_UNSIZED)]);
+		assert_eq!(segment.byte_order(), ByteOrder::Little);
+		assert!(segment.is_allocated(non_zero!(2)));
+		assert_eq!(wal_index, Some(wal_index!(69, 420)));
+		assert_buf_eq!(data, records_page_body(ByteOrder::Little));
+	}
+
+	#[test]
+	fn open_unidentified_segment_file() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let file_start: Vec<u8> = [
+			GenericHeaderRepr::from(GenericHeader {
+				byte_order: ByteOrder::Little,
+				file_type: FileType::Segment,
+				content_offset: PageSize::LEGACY.get() as u16,
+				version: FORMAT_VERSION_UNIDENTIFIED,
+			})
+			.as_bytes(),
+			&2_u16.to_le_bytes(),
+		]
+		.concat();
+		let mut file = File::create(tempdir.path().join("0")).unwrap();
+		file.set_len(3 * PAGE_SIZE as u64).unwrap();
+		file.write_all(&file_start).unwrap();
+
+		// when
+		let segment =
+			SegmentFile::open_file(tempdir.path().join("0"), &Default::default()).unwrap();
+
+		// then
+		assert!(segment.is_allocated(non_zero!(2)));
+		assert_eq!(segment.database_id(), None);
+	}
+
+	#[test]
+	fn open_preallocated_segment_file() {
 		// given
 		let tempdir = tempfile::tempdir().unwrap();
 		let file_start: Vec<u8> = GenericHeaderRepr::from(GenericHeader {
+			byte_order: ByteOrder::Little,
 			file_type: FileType::Segment,
-			content_offset: PAGE_SIZE as u16,
-			version: FORMAT_VERSION,
+			content_offset: PageSize::LEGACY.get() as u16,
+			version: FORMAT_VERSION_PREALLOCATED,
 		})
 		.as_bytes()
 		.to_vec();
 		let mut file = File::create(tempdir.path().join("0")).unwrap();
-		file.set_len(SEGMENT_SIZE as u64).unwrap();
+		file.set_len(PageSize::LEGACY.segment_size() as u64)
+			.unwrap();
 		file.write_all(&file_start).unwrap();
 
+		// when
+		let segment =
+			SegmentFile::open_file(tempdir.path().join("0"), &Default::default()).unwrap();
+
 		// then
-		SegmentFile::open_file(tempdir.path().join("0")).unwrap();
+		assert!(segment.is_allocated(non_zero!(u16::MAX)));
+	}
+
+	#[test]
+	fn open_truncated_segment_file() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let file_start: Vec<u8> = [
+			GenericHeaderRepr::from(GenericHeader {
+				byte_order: ByteOrder::Little,
+				file_type: FileType::Segment,
+				content_offset: GenericHeaderRepr::SIZE as u16,
+				version: FORMAT_VERSION,
+			})
+			.as_bytes(),
+			SegmentHeaderRepr::from(SegmentHeader {
+				high_water_mark: 2,
+				database_id: DatabaseId::NIL,
+				page_size: PageSize::DEFAULT,
+			})
+			.as_bytes(),
+		]
+		.concat();
+		let mut file = File::create(tempdir.path().join("0")).unwrap();
+		file.set_len(2 * PAGE_SIZE as u64).unwrap();
+		file.write_all(&file_start).unwrap();
+
+		// when
+		let result = SegmentFile::open_file(tempdir.path().join("0"), &Default::default());
+
+		// then
+		assert!(matches!(result, Err(FileError::Corrupted(..))));
+	}
+
+	#[test]
+	fn grow_segment_by_extents() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let config = SegmentConfig {
+			extent_size: 4 * PAGE_SIZE,
+			..Default::default()
+		};
+		let segment =
+			SegmentFile::create_file(tempdir.path().join("0"), &config, DatabaseId::NIL).unwrap();
+
+		// when
+		segment
+			.write(SegmentWriteOp {
+				page_num: non_zero!(5),
+				wal_index: wal_index!(69, 420),
+				buf: &[1; PAGE_BODY_SIZE],
+			})
+			.unwrap();
+
+		// then
+		let mut file = File::open(tempdir.path().join("0")).unwrap();
+		assert_eq!(file.metadata().unwrap().len(), 9 * PAGE_SIZE as u64);
+		file.seek(SeekFrom::Start(GenericHeaderRepr::SIZE as u64))
+			.unwrap();
+		let received: &mut [u8] = &mut [0; SegmentHeaderRepr::SIZE];
+		file.read_exact(received).unwrap();
+		assert_buf_eq!(
+			received,
+			SegmentHeaderRepr::from(SegmentHeader {
+				high_water_mark: 8,
+				database_id: DatabaseId::NIL,
+				page_size: PageSize::DEFAULT,
+			})
+			.as_bytes()
+		);
+	}
+
+	#[test]
+	fn truncate_segment() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let config = SegmentConfig {
+			extent_size: 4 * PAGE_SIZE,
+			..Default::default()
+		};
+		let segment =
+			SegmentFile::create_file(tempdir.path().join("0"), &config, DatabaseId::NIL).unwrap();
+		segment
+			.write(SegmentWriteOp {
+				page_num: non_zero!(7),
+				wal_index: wal_index!(69, 420),
+				buf: &[1; PAGE_BODY_SIZE],
+			})
+			.unwrap();
+
+		// when
+		segment.truncate(3).unwrap();
+
+		// then
+		let mut file = File::open(tempdir.path().join("0")).unwrap();
+		assert_eq!(file.metadata().unwrap().len(), 4 * PAGE_SIZE as u64);
+		file.seek(SeekFrom::Start(GenericHeaderRepr::SIZE as u64))
+			.unwrap();
+		let received: &mut [u8] = &mut [0; SegmentHeaderRepr::SIZE];
+		file.read_exact(received).unwrap();
+		assert_buf_eq!(
+			received,
+			SegmentHeaderRepr::from(SegmentHeader {
+				high_water_mark: 3,
+				database_id: DatabaseId::NIL,
+				page_size: PageSize::DEFAULT,
+			})
+			.as_bytes()
+		);
+		assert!(!segment.is_allocated(non_zero!(4)));
+	}
+
+	#[test]
+	fn open_segment_file_with_other_page_size() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let config = SegmentConfig {
+			page_size: PageSize::new(4 * 1024).unwrap(),
+			..Default::default()
+		};
+		SegmentFile::create_file(tempdir.path().join("0"), &config, DatabaseId::NIL).unwrap();
+
+		// when
+		let result = SegmentFile::open_file(tempdir.path().join("0"), &Default::default());
+
+		// then
+		assert!(matches!(
+			result,
+			Err(FileError::PageSizeMismatch { expected, found })
+				if expected == PageSize::DEFAULT && found == config.page_size
+		));
+	}
+
+	#[test]
+	fn write_and_read_small_pages() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let page_size = PageSize::new(4 * 1024).unwrap();
+		let config = SegmentConfig {
+			page_size,
+			extent_size: 4 * 1024,
+			..Default::default()
+		};
+		let segment =
+			SegmentFile::create_file(tempdir.path().join("0"), &config, DatabaseId::NIL).unwrap();
+
+		// when
+		segment
+			.write(SegmentWriteOp {
+				page_num: non_zero!(3),
+				wal_index: wal_index!(69, 420),
+				buf: &vec![3; page_size.body_size()],
+			})
+			.unwrap();
+		std::mem::drop(segment);
+		let segment = SegmentFile::open_file(tempdir.path().join("0"), &config).unwrap();
+
+		let mut data = vec![0; page_size.body_size()];
+		let mut wal_index = None;
+		segment
+			.read(SegmentReadOp {
+				page_num: non_zero!(3),
+				wal_index: &mut wal_index,
+				buf: ReadBuf::Body(&mut data),
+			})
+			.unwrap();
+
+		// then
+		let file_len = File::open(tempdir.path().join("0"))
+			.unwrap()
+			.metadata()
+			.unwrap()
+			.len();
+		assert_eq!(file_len, 4 * 4 * 1024);
+		assert_eq!(wal_index, Some(wal_index!(69, 420)));
+		assert_eq!(data, vec![3; page_size.body_size()]);
+	}
+
+	#[test]
+	fn reject_invalid_page_sizes() {
+		assert!(PageSize::new(32 * 1024).is_ok());
+		assert!(matches!(
+			PageSize::new(2 * 1024),
+			Err(FileError::InvalidPageSize(..))
+		));
+		assert!(matches!(
+			PageSize::new(128 * 1024),
+			Err(FileError::InvalidPageSize(..))
+		));
+		assert!(matches!(
+			PageSize::new(12 * 1024),
+			Err(FileError::InvalidPageSize(..))
+		));
+	}
+
+	#[test]
+	fn read_unallocated_page() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let segment = SegmentFile::create_file(
+			tempdir.path().join("0"),
+			&Default::default(),
+			DatabaseId::NIL,
+		)
+		.unwrap();
+
+		// when
+		let mut data = [1; PAGE_BODY_SIZE];
+		let mut wal_index = Some(wal_index!(69, 420));
+		segment
+			.read(SegmentReadOp {
+				page_num: non_zero!(1000),
+				wal_index: &mut wal_index,
+				buf: ReadBuf::Body(&mut data),
+			})
+			.unwrap();
+
+		// then
+		assert_eq!(wal_index, None);
+		assert_eq!(data, [0; PAGE_BODY_SIZE]);
+	}
+
+	#[test]
+	fn batch_across_high_water_mark() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let config = SegmentConfig {
+			extent_size: PAGE_SIZE,
+			..Default::default()
+		};
+		let segment =
+			SegmentFile::create_file(tempdir.path().join("0"), &config, DatabaseId::NIL).unwrap();
+
+		// when
+		let mut data = [1; PAGE_BODY_SIZE];
+		let mut wal_index = Some(wal_index!(69, 420));
+		segment
+			.batch(&mut [
+				SegmentOp::Write(SegmentWriteOp {
+					page_num: non_zero!(2),
+					wal_index: wal_index!(69, 420),
+					buf: &[2; PAGE_BODY_SIZE],
+				}),
+				SegmentOp::Read(SegmentReadOp {
+					page_num: non_zero!(3),
+					wal_index: &mut wal_index,
+					buf: ReadBuf::Body(&mut data),
+				}),
+			])
+			.unwrap();
+
+		// then
+		assert_eq!(wal_index, None);
+		assert_eq!(data, [0; PAGE_BODY_SIZE]);
+		assert!(segment.is_allocated(non_zero!(2)));
+		assert!(!segment.is_allocated(non_zero!(3)));
+	}
+
+	#[test]
+	fn batch_unordered_writes() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let segment = SegmentFile::create_file(
+			tempdir.path().join("0"),
+			&Default::default(),
+			DatabaseId::NIL,
+		)
+		.unwrap();
+
+		// when
+		let pages: Vec<(u16, [u8; PAGE_BODY_SIZE])> = [(4, 4), (2, 1), (3, 3), (2, 2), (7, 7)]
+			.into_iter()
+			.map(|(page_num, value)| (page_num, [value; PAGE_BODY_SIZE]))
+			.collect();
+		let mut write_ops: Vec<SegmentOp> = pages
+			.iter()
+			.map(|(page_num, buf)| {
+				SegmentOp::Write(SegmentWriteOp {
+					page_num: NonZeroU16::new(*page_num).unwrap(),
+					wal_index: wal_index!(69, buf[0].into()),
+					buf,
+				})
+			})
+			.collect();
+		segment.batch(&mut write_ops).unwrap();
+
+		// then
+		for (page_num, value) in [(2, 2), (3, 3), (4, 4), (7, 7)] {
+			let mut data = [0; PAGE_BODY_SIZE];
+			let mut wal_index = None;
+			segment
+				.read(SegmentReadOp {
+					page_num: NonZeroU16::new(page_num).unwrap(),
+					wal_index: &mut wal_index,
+					buf: ReadBuf::Body(&mut data),
+				})
+				.unwrap();
+			assert_eq!(wal_index, Some(wal_index!(69, value.into())));
+			assert_eq!(data, [value; PAGE_BODY_SIZE]);
+		}
+	}
+
+	#[test]
+	fn coalesce_writes_to_adjacent_pages() {
+		// given
+		let mut buffers = vec![AlignedPage::zeroed(PAGE_SIZE); 5];
+		let mut wal_index = None;
+		let mut data = [0; PAGE_BODY_SIZE];
+		let mut ops = [
+			SegmentOp::Write(SegmentWriteOp {
+				page_num: non_zero!(1),
+				wal_index: wal_index!(69, 420),
+				buf: &[1; PAGE_BODY_SIZE],
+			}),
+			SegmentOp::Write(SegmentWriteOp {
+				page_num: non_zero!(2),
+				wal_index: wal_index!(69, 420),
+				buf: &[2; PAGE_BODY_SIZE],
+			}),
+			SegmentOp::Write(SegmentWriteOp {
+				page_num: non_zero!(4),
+				wal_index: wal_index!(69, 420),
+				buf: &[4; PAGE_BODY_SIZE],
+			}),
+			SegmentOp::Read(SegmentReadOp {
+				page_num: non_zero!(5),
+				wal_index: &mut wal_index,
+				buf: ReadBuf::Body(&mut data),
+			}),
+			SegmentOp::Write(SegmentWriteOp {
+				page_num: non_zero!(6),
+				wal_index: wal_index!(69, 420),
+				buf: &[6; PAGE_BODY_SIZE],
+			}),
+		];
+
+		// when
+		let mut scratch_pages = buffers.iter_mut().map(|buf| &mut buf[..]);
+		let raw_ops: Vec<RawIoOp> = ops
+			.iter_mut()
+			.map(|op| RawIoOp::new(op, &mut scratch_pages, ByteOrder::Little))
+			.collect();
+
+		// then
+		assert_eq!(coalesced_run_len(&raw_ops), 2);
+		assert_eq!(coalesced_run_len(&raw_ops[2..]), 1);
+		assert_eq!(coalesced_run_len(&raw_ops[3..]), 1);
+		assert_eq!(coalesced_run_len(&raw_ops[4..]), 1);
+	}
+
+	#[cfg(feature = "io_uring")]
+	#[test]
+	fn batch_larger_than_io_ring() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let config = SegmentConfig {
+			io_ring: Some(Arc::new(IoRing::new(1, 2, 1, PAGE_SIZE).unwrap())),
+			..Default::default()
+		};
+		let segment =
+			SegmentFile::create_file(tempdir.path().join("0"), &config, DatabaseId::NIL).unwrap();
+		let bufs: Vec<[u8; PAGE_BODY_SIZE]> = (1..=5).map(|i| [i; PAGE_BODY_SIZE]).collect();
+
+		// when
+		let mut write_ops: Vec<SegmentOp> = bufs
+			.iter()
+			.zip(1..)
+			.map(|(buf, page_num)| {
+				SegmentOp::Write(SegmentWriteOp {
+					page_num: NonZeroU16::new(page_num).unwrap(),
+					wal_index: wal_index!(69, 420),
+					buf,
+				})
+			})
+			.collect();
+		segment.batch(&mut write_ops).unwrap();
+
+		let mut read_bufs = vec![[0; PAGE_BODY_SIZE]; 5];
+		let mut wal_indices = vec![None; 5];
+		let mut read_ops: Vec<SegmentOp> = read_bufs
+			.iter_mut()
+			.zip(wal_indices.iter_mut())
+			.zip(1..)
+			.map(|((buf, wal_index), page_num)| {
+				SegmentOp::Read(SegmentReadOp {
+					page_num: NonZeroU16::new(page_num).unwrap(),
+					wal_index,
+					buf: ReadBuf::Body(buf),
+				})
+			})
+			.collect();
+		segment.batch(&mut read_ops).unwrap();
+		std::mem::drop(read_ops);
+
+		// then
+		assert_eq!(read_bufs, bufs);
+		assert_eq!(wal_indices, vec![Some(wal_index!(69, 420)); 5]);
+	}
+
+	#[test]
+	fn read_page_with_old_format() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let segment = SegmentFile::create_file(
+			tempdir.path().join("0"),
+			&Default::default(),
+			DatabaseId::NIL,
+		)
+		.unwrap();
+		segment
+			.write(SegmentWriteOp {
+				page_num: non_zero!(2),
+				wal_index: wal_index!(1, 1),
+				buf: &[0; PAGE_BODY_SIZE],
+			})
+			.unwrap();
+		let mut file = OpenOptions::new()
+			.write(true)
+			.open(tempdir.path().join("0"))
+			.unwrap();
+		file.seek(SeekFrom::Start((2 * PAGE_SIZE) as u64)).unwrap();
+		file.write_all(
+			PageHeaderRepr {
+				wal_generation: 69.into(),
+				wal_offset: 420.into(),
+				crc: CRC16.checksum(&[25; PAGE_BODY_SIZE]).into(),
+				format_version: PAGE_FORMAT_VERSION_UNNUMBERED,
+			}
+			.as_bytes(),
+		)
+		.unwrap();
+		file.write_all(&[25; PAGE_BODY_SIZE]).unwrap();
+
+		// when
+		let mut data = vec![0; PAGE_BODY_SIZE];
+		let mut wal_index = None;
+		segment
+			.read(SegmentReadOp {
+				page_num: non_zero!(2),
+				wal_index: &mut wal_index,
+				buf: ReadBuf::Body(&mut data),
+			})
+			.unwrap();
+
+		// then
+		assert_eq!(wal_index, Some(wal_index!(69, 420)));
+		assert_eq!(data, vec![25; PAGE_BODY_SIZE]);
+	}
+
+	#[test]
+	fn detect_misplaced_page() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let segment = SegmentFile::create_file(
+			tempdir.path().join("0"),
+			&Default::default(),
+			DatabaseId::NIL,
+		)
+		.unwrap();
+		for (page_num, byte) in [(non_zero!(2), 25), (non_zero!(3), 3)] {
+			segment
+				.write(SegmentWriteOp {
+					page_num,
+					wal_index: wal_index!(69, 420),
+					buf: &[byte; PAGE_BODY_SIZE],
+				})
+				.unwrap();
+		}
+		let mut page = vec![0; PAGE_SIZE];
+		let mut file = OpenOptions::new()
+			.read(true)
+			.write(true)
+			.open(tempdir.path().join("0"))
+			.unwrap();
+		file.seek(SeekFrom::Start((2 * PAGE_SIZE) as u64)).unwrap();
+		file.read_exact(&mut page).unwrap();
+		file.write_all(&page).unwrap();
+
+		// when
+		let mut data = vec![0; PAGE_BODY_SIZE];
+		let mut wal_index = None;
+		let result = segment.read(SegmentReadOp {
+			page_num: non_zero!(3),
+			wal_index: &mut wal_index,
+			buf: ReadBuf::Body(&mut data),
+		});
+
+		// then
+		assert!(matches!(result, Err(FileError::ChecksumMismatch)));
 	}
 
 	#[test]
 	fn write_to_page() {
 		// given
 		let tempdir = tempfile::tempdir().unwrap();
-		let segment = SegmentFile::create_file(tempdir.path().join("0")).unwrap();
+		let segment = SegmentFile::create_file(
+			tempdir.path().join("0"),
+			&Default::default(),
+			DatabaseId::NIL,
+		)
+		.unwrap();
 
 		// when
 		segment
@@ -492,10 +2165,10 @@ mod tests {
 			received,
 			[
 				PageHeaderRepr {
-					wal_generation: 69,
-					wal_offset: 420,
-					crc: 0x0c78,
-					format_version: 1
+					wal_generation: 69.into(),
+					wal_offset: 420.into(),
+					crc: 0x4034.into(),
+					format_version: PAGE_FORMAT_VERSION
 				}
 				.as_bytes(),
 				&[3; PAGE_BODY_SIZE]
@@ -508,7 +2181,12 @@ mod tests {
 	fn read_from_page() {
 		// given
 		let tempdir = tempfile::tempdir().unwrap();
-		let segment = SegmentFile::create_file(tempdir.path().join("0")).unwrap();
+		let segment = SegmentFile::create_file(
+			tempdir.path().join("0"),
+			&Default::default(),
+			DatabaseId::NIL,
+		)
+		.unwrap();
 		segment
 			.write(SegmentWriteOp {
 				page_num: non_zero!(5),
@@ -524,7 +2202,44 @@ mod tests {
 			.read(SegmentReadOp {
 				page_num: non_zero!(5),
 				wal_index: &mut wal_index,
-				buf: &mut data,
+				buf: ReadBuf::Body(&mut data),
+			})
+			.unwrap();
+
+		// then
+		assert_eq!(wal_index, Some(wal_index!(69, 420)));
+		assert_eq!(data, [25; PAGE_BODY_SIZE]);
+	}
+
+	#[test]
+	fn read_and_write_with_direct_io() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let config = SegmentConfig {
+			direct_io: true,
+			..Default::default()
+		};
+		let segment =
+			SegmentFile::create_file(tempdir.path().join("0"), &config, DatabaseId::NIL).unwrap();
+
+		// when
+		segment
+			.write(SegmentWriteOp {
+				page_num: non_zero!(5),
+				wal_index: wal_index!(69, 420),
+				buf: &[25; PAGE_BODY_SIZE],
+			})
+			.unwrap();
+		std::mem::drop(segment);
+		let segment = SegmentFile::open_file(tempdir.path().join("0"), &config).unwrap();
+
+		let mut data = [0; PAGE_BODY_SIZE];
+		let mut wal_index = None;
+		segment
+			.read(SegmentReadOp {
+				page_num: non_zero!(5),
+				wal_index: &mut wal_index,
+				buf: ReadBuf::Body(&mut data),
 			})
 			.unwrap();
 
@@ -532,4 +2247,145 @@ mod tests {
 		assert_eq!(wal_index, Some(wal_index!(69, 420)));
 		assert_eq!(data, [25; PAGE_BODY_SIZE]);
 	}
+
+	#[test]
+	fn read_pages_in_place() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let config = SegmentConfig {
+			direct_io: true,
+			#[cfg(feature = "io_uring")]
+			io_ring: Some(Arc::new(IoRing::new(1, 2, 1, PAGE_SIZE).unwrap())),
+			..Default::default()
+		};
+		let segment =
+			SegmentFile::create_file(tempdir.path().join("0"), &config, DatabaseId::NIL).unwrap();
+		for page_num in 1..=3 {
+			segment
+				.write(SegmentWriteOp {
+					page_num: NonZeroU16::new(page_num).unwrap(),
+					wal_index: wal_index!(69, page_num as u64),
+					buf: &[page_num as u8; PAGE_BODY_SIZE],
+				})
+				.unwrap();
+		}
+
+		// when
+		let mut pages = vec![AlignedPage::zeroed(PAGE_SIZE); 4];
+		let mut wal_indices = vec![None; 4];
+		let [first, rest @ ..] = &mut pages[..] else {
+			unreachable!();
+		};
+		segment
+			.read(SegmentReadOp {
+				page_num: non_zero!(1),
+				wal_index: &mut wal_indices[0],
+				buf: ReadBuf::Page(first),
+			})
+			.unwrap();
+		let mut ops: Vec<SegmentOp> = rest
+			.iter_mut()
+			.zip(&mut wal_indices[1..])
+			.zip(2..)
+			.map(|((page, wal_index), page_num)| {
+				SegmentOp::Read(SegmentReadOp {
+					page_num: NonZeroU16::new(page_num).unwrap(),
+					wal_index,
+					buf: ReadBuf::Page(page),
+				})
+			})
+			.collect();
+		segment.batch(&mut ops).unwrap();
+		std::mem::drop(ops);
+
+		// then
+		for (page_num, page) in (1..=3).zip(&pages) {
+			assert_eq!(page[PAGE_HEADER_SIZE..], [page_num; PAGE_BODY_SIZE]);
+		}
+		assert_eq!(pages[3][PAGE_HEADER_SIZE..], [0; PAGE_BODY_SIZE]);
+		assert_eq!(
+			wal_indices,
+			[
+				Some(wal_index!(69, 1)),
+				Some(wal_index!(69, 2)),
+				Some(wal_index!(69, 3)),
+				None
+			]
+		);
+	}
+
+	#[test]
+	fn read_mapped_checks_crc_on_first_access() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let path = tempdir.path().join("0");
+		let segment =
+			SegmentFile::create_file(&path, &Default::default(), DatabaseId::NIL).unwrap();
+		let write_op = SegmentWriteOp {
+			page_num: non_zero!(5),
+			wal_index: wal_index!(69, 420),
+			buf: &[25; PAGE_BODY_SIZE],
+		};
+		segment.write(write_op.clone()).unwrap();
+
+		let file = OpenOptions::new().write(true).open(&path).unwrap();
+		os::unix::fs::FileExt::write_all_at(&file, &[0xff], 5 * PAGE_SIZE as u64 + 100).unwrap();
+
+		// when
+		let mut data = [0; PAGE_BODY_SIZE];
+		let mut wal_index = None;
+		let corrupted_result = segment.read_mapped(SegmentReadOp {
+			page_num: non_zero!(5),
+			wal_index: &mut wal_index,
+			buf: ReadBuf::Body(&mut data),
+		});
+
+		segment.write(write_op).unwrap();
+		segment
+			.read_mapped(SegmentReadOp {
+				page_num: non_zero!(5),
+				wal_index: &mut wal_index,
+				buf: ReadBuf::Body(&mut data),
+			})
+			.unwrap();
+
+		// then
+		assert!(matches!(corrupted_result, Err(FileError::ChecksumMismatch)));
+		assert_eq!(wal_index, Some(wal_index!(69, 420)));
+		assert_eq!(data, [25; PAGE_BODY_SIZE]);
+	}
+
+	#[test]
+	fn read_mapped_rechecks_page_written_during_check() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let path = tempdir.path().join("0");
+		let segment =
+			SegmentFile::create_file(&path, &Default::default(), DatabaseId::NIL).unwrap();
+		let write_op = SegmentWriteOp {
+			page_num: non_zero!(5),
+			wal_index: wal_index!(69, 420),
+			buf: &[25; PAGE_BODY_SIZE],
+		};
+		segment.write(write_op.clone()).unwrap();
+		let mapping = segment.mapping().unwrap();
+		let state = mapping.page_state(5);
+
+		// when
+		segment.write(write_op).unwrap();
+		mapping.set_verified(5, state);
+
+		let file = OpenOptions::new().write(true).open(&path).unwrap();
+		os::unix::fs::FileExt::write_all_at(&file, &[0xff], 5 * PAGE_SIZE as u64 + 100).unwrap();
+		let mut data = [0; PAGE_BODY_SIZE];
+		let result = segment.read_mapped(SegmentReadOp {
+			page_num: non_zero!(5),
+			wal_index: &mut None,
+			buf: ReadBuf::Body(&mut data),
+		});
+
+		// then
+		assert!(!mapping.page_state(5).is_verified());
+		assert!(matches!(result, Err(FileError::ChecksumMismatch)));
+	}
 }
@@ -1,6 +1,44 @@
+use std::ops::{Deref, DerefMut};
+
 use crc::Crc;
+use static_assertions::const_assert_eq;
+use zerocopy::{FromBytes, Immutable, IntoBytes};
+
+use crate::consts::DIRECT_IO_ALIGNMENT;
 
 // TODO: there are tradeoffs here. Perhaps I should look more into selecting an
 // algorithm.
 pub(crate) const CRC32: Crc<u32> = Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
 pub(crate) const CRC16: Crc<u16> = Crc::<u16>::new(&crc::CRC_16_IBM_SDLC);
+
+#[derive(Clone, Immutable, FromBytes, IntoBytes)]
+#[repr(C, align(4096))]
+struct AlignedBlock([u8; DIRECT_IO_ALIGNMENT]);
+const_assert_eq!(std::mem::align_of::<AlignedBlock>(), DIRECT_IO_ALIGNMENT);
+
+/// A page-sized buffer that is aligned for direct IO.
+#[derive(Clone)]
+pub(crate) struct AlignedPage(Box<[AlignedBlock]>);
+
+impl AlignedPage {
+	/// Allocates a zeroed buffer of `page_size` bytes, which has to be a
+	/// multiple of the direct IO alignment.
+	pub fn zeroed(page_size: usize) -> Self {
+		debug_assert_eq!(page_size % DIRECT_IO_ALIGNMENT, 0);
+		Self(vec![AlignedBlock([0; DIRECT_IO_ALIGNMENT]); page_size / DIRECT_IO_ALIGNMENT].into())
+	}
+}
+
+impl Deref for AlignedPage {
+	type Target = [u8];
+
+	fn deref(&self) -> &Self::Target {
+		self.0.as_bytes()
+	}
+}
+
+impl DerefMut for AlignedPage {
+	fn deref_mut(&mut self) -> &mut Self::Target {
+		self.0.as_mut_bytes()
+	}
+}
@@ -8,20 +8,27 @@ use std::{
 };
 
 use static_assertions::assert_impl_all;
-use zerocopy::{FromBytes, Immutable, IntoBytes};
+use zerocopy::{
+	little_endian::{U16, U32, U64},
+	FromBytes, Immutable, IntoBytes,
+};
 
-const FORMAT_VERSION: u8 = 1;
+const FORMAT_VERSION_UNIDENTIFIED: u8 = 1;
+/// Before version 3, the length of item bodies was 16-bit.
+const FORMAT_VERSION_SHORT_ITEMS: u8 = 2;
+pub(super) const FORMAT_VERSION: u8 = 3;
 
 #[cfg(test)]
 use mockall::automock;
 
 use crate::{
-	repr::{IoRepr, Repr},
+	repr::{impl_swap_bytes, ByteOrder, IoRepr, Repr},
 	utils::units::MIB,
 };
 
 use super::{
 	generic::{FileType, GenericHeader, GenericHeaderRepr},
+	manifest::DatabaseId,
 	utils::CRC32,
 	FileError, PageAddress, TransactionState, WalIndex,
 };
@@ -33,62 +40,115 @@ const FLAG_UNDO: u8 = 0b00000001;
 struct ItemHeaderRepr {
 	kind: u8,
 	flags: u8,
-	body_length: u16,
-	crc: u32,
-	prev_item: Option<NonZeroU64>,
+	body_length: U32,
+	crc: U32,
+	prev_item: U64,
+}
+impl_swap_bytes!(ItemHeaderRepr {
+	body_length,
+	crc,
+	prev_item
+});
+
+#[derive(Debug, Clone, Immutable, FromBytes, IntoBytes)]
+#[repr(C)]
+struct ShortItemHeaderRepr {
+	kind: u8,
+	flags: u8,
+	body_length: U16,
+	crc: U32,
+	prev_item: U64,
 }
+impl_swap_bytes!(ShortItemHeaderRepr {
+	body_length,
+	crc,
+	prev_item
+});
 
 #[derive(Debug, Clone, Immutable, FromBytes, IntoBytes)]
 #[repr(C)]
 struct ItemFooterRepr {
-	item_start: u64,
+	item_start: U64,
 }
+impl_swap_bytes!(ItemFooterRepr { item_start });
 
 #[derive(Debug, Clone, Immutable, FromBytes, IntoBytes)]
 #[repr(C)]
 struct TransactionBlockRepr {
-	transaction_id: u64,
-	prev_transaction_generation: u64,
-	prev_transaction_offset: Option<NonZeroU64>,
+	transaction_id: U64,
+	prev_transaction_generation: U64,
+	prev_transaction_offset: U64,
 }
+impl_swap_bytes!(TransactionBlockRepr {
+	transaction_id,
+	prev_transaction_generation,
+	prev_transaction_offset
+});
 
 #[derive(Debug, Clone, Immutable, FromBytes, IntoBytes)]
 #[repr(C, packed)]
 struct WriteBlockRepr {
-	segment_num: u32,
-	page_num: u16,
-	offset: u16,
-	write_length: u16,
+	segment_num: U32,
+	page_num: U16,
+	offset: U16,
+	write_length: U16,
 }
+impl_swap_bytes!(WriteBlockRepr {
+	segment_num,
+	page_num,
+	offset,
+	write_length
+});
 
 #[derive(Debug, Clone, Immutable, FromBytes, IntoBytes)]
 #[repr(C)]
 struct CheckpointBlockRepr {
-	num_dirty_pages: u64,
-	num_transactions: u64,
+	num_dirty_pages: U64,
+	num_transactions: U64,
 }
+impl_swap_bytes!(CheckpointBlockRepr {
+	num_dirty_pages,
+	num_transactions
+});
 
 #[derive(Debug, Clone, Immutable, FromBytes, IntoBytes)]
 #[repr(C, packed)]
 struct PageAddressRepr {
-	segment_num: u32,
-	page_num: u16,
+	segment_num: U32,
+	page_num: U16,
 }
+impl_swap_bytes!(PageAddressRepr {
+	segment_num,
+	page_num
+});
 
 #[derive(Debug, Clone, Immutable, FromBytes, IntoBytes)]
 #[repr(C)]
 struct WalIndexRepr {
-	generation: u64,
-	offset: u64,
+	generation: U64,
+	offset: U64,
 }
+impl_swap_bytes!(WalIndexRepr { generation, offset });
+
+#[derive(Debug, Clone, Immutable, FromBytes, IntoBytes)]
+#[repr(C)]
+struct TransactionIdRepr {
+	transaction_id: U64,
+}
+impl_swap_bytes!(TransactionIdRepr { transaction_id });
 
 #[derive(Debug, Clone, Immutable, FromBytes, IntoBytes)]
 #[repr(C)]
 struct TransactionStateRepr {
-	first_generation: u64,
-	last_generation: u64,
-	last_offset: u64,
+	first_generation: U64,
+	last_generation: U64,
+	last_offset: U64,
 }
+impl_swap_bytes!(TransactionStateRepr {
+	first_generation,
+	last_generation,
+	last_offset
+});
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
@@ -96,6 +156,7 @@ enum ItemKind {
 	Write = 0,
 	Commit = 1,
 	Checkpoint = 2,
+	Truncate = 3,
 }
 
 impl TryFrom<u8> for ItemKind {
@@ -106,6 +167,7 @@ impl TryFrom<u8> for ItemKind {
 			0 => Ok(Self::Write),
 			1 => Ok(Self::Commit),
 			2 => Ok(Self::Checkpoint),
+			3 => Ok(Self::Truncate),
 			_ => Err(FileError::Corrupted(format!(
 				"Unknown WAL item kind {value}"
 			))),
@@ -117,7 +179,7 @@ impl TryFrom<u8> for ItemKind {
 struct ItemHeader {
 	kind: ItemKind,
 	flags: u8,
-	body_length: u16,
+	body_length: u32,
 	crc: u32,
 	prev_item: Option<NonZeroU64>,
 }
@@ -127,9 +189,9 @@ impl From<ItemHeader> for ItemHeaderRepr {
 		Self {
 			kind: value.kind as u8,
 			flags: value.flags,
-			body_length: value.body_length,
-			crc: value.crc,
-			prev_item: value.prev_item,
+			body_length: value.body_length.into(),
+			crc: value.crc.into(),
+			prev_item: value.prev_item.map_or(0, NonZeroU64::get).into(),
 		}
 	}
 }
@@ -141,9 +203,9 @@ impl TryFrom<ItemHeaderRepr> for ItemHeader {
 		Ok(Self {
 			kind: ItemKind::try_from(value.kind)?,
 			flags: value.flags,
-			body_length: value.body_length,
-			crc: value.crc,
-			prev_item: value.prev_item,
+			body_length: value.body_length.get(),
+			crc: value.crc.get(),
+			prev_item: NonZeroU64::new(value.prev_item.get()),
 		})
 	}
 }
@@ -152,6 +214,47 @@ impl Repr<ItemHeader> for ItemHeaderRepr {
 	type Error = FileError;
 }
 
+impl From<ItemHeader> for ShortItemHeaderRepr {
+	fn from(value: ItemHeader) -> Self {
+		// Items that don't fit are rejected before they are written.
+		debug_assert!(value.body_length <= u16::MAX.into());
+		Self {
+			kind: value.kind as u8,
+			flags: value.flags,
+			body_length: u16::try_from(value.body_length).unwrap_or(u16::MAX).into(),
+			crc: value.crc.into(),
+			prev_item: value.prev_item.map_or(0, NonZeroU64::get).into(),
+		}
+	}
+}
+
+impl TryFrom<ShortItemHeaderRepr> for ItemHeader {
+	type Error = FileError;
+
+	fn try_from(value: ShortItemHeaderRepr) -> Result<Self, Self::Error> {
+		Ok(Self {
+			kind: ItemKind::try_from(value.kind)?,
+			flags: value.flags,
+			body_length: value.body_length.get().into(),
+			crc: value.crc.get(),
+			prev_item: NonZeroU64::new(value.prev_item.get()),
+		})
+	}
+}
+
+impl Repr<ItemHeader> for ShortItemHeaderRepr {
+	type Error = FileError;
+}
+
+/// The size of item headers in files of the given version.
+fn item_header_size(version: u8) -> usize {
+	if version < FORMAT_VERSION {
+		ShortItemHeaderRepr::SIZE
+	} else {
+		ItemHeaderRepr::SIZE
+	}
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 struct ItemFooter {
 	item_start: NonZeroU64,
@@ -160,7 +263,7 @@ struct ItemFooter {
 impl From<ItemFooter> for ItemFooterRepr {
 	fn from(value: ItemFooter) -> Self {
 		Self {
-			item_start: value.item_start.get(),
+			item_start: value.item_start.get().into(),
 		}
 	}
 }
@@ -169,7 +272,7 @@ impl TryFrom<ItemFooterRepr> for ItemFooter {
 	type Error = FileError;
 
 	fn try_from(value: ItemFooterRepr) -> Result<Self, Self::Error> {
-		let Some(item_start) = NonZeroU64::new(value.item_start) else {
+		let Some(item_start) = NonZeroU64::new(value.item_start.get()) else {
 			return Err(FileError::Corrupted(
 				"WAL items cannot start at position 0".to_string(),
 			));
@@ -190,12 +293,16 @@ struct TransactionBlock {
 impl From<TransactionBlock> for TransactionBlockRepr {
 	fn from(value: TransactionBlock) -> Self {
 		Self {
-			transaction_id: value.transaction_id,
+			transaction_id: value.transaction_id.into(),
 			prev_transaction_generation: value
 				.prev_transaction_item
 				.map(|idx| idx.generation)
-				.unwrap_or_default(),
-			prev_transaction_offset: value.prev_transaction_item.map(|idx| idx.offset),
+				.unwrap_or_default()
+				.into(),
+			prev_transaction_offset: value
+				.prev_transaction_item
+				.map_or(0, |idx| idx.offset.get())
+				.into(),
 		}
 	}
 }
@@ -203,10 +310,9 @@ impl From<TransactionBlock> for TransactionBlockRepr {
 impl From<TransactionBlockRepr> for TransactionBlock {
 	fn from(value: TransactionBlockRepr) -> Self {
 		Self {
-			transaction_id: value.transaction_id,
-			prev_transaction_item: value
-				.prev_transaction_offset
-				.map(|offset| WalIndex::new(value.prev_transaction_generation, offset)),
+			transaction_id: value.transaction_id.get(),
+			prev_transaction_item: NonZeroU64::new(value.prev_transaction_offset.get())
+				.map(|offset| WalIndex::new(value.prev_transaction_generation.get(), offset)),
 		}
 	}
 }
@@ -225,10 +331,10 @@ struct WriteBlock {
 impl From<WriteBlock> for WriteBlockRepr {
 	fn from(value: WriteBlock) -> Self {
 		Self {
-			segment_num: value.page_address.segment_num,
-			page_num: value.page_address.page_num.get(),
-			offset: value.offset,
-			write_length: value.write_length,
+			segment_num: value.page_address.segment_num.into(),
+			page_num: value.page_address.page_num.get().into(),
+			offset: value.offset.into(),
+			write_length: value.write_length.into(),
 		}
 	}
 }
@@ -237,15 +343,15 @@ impl TryFrom<WriteBlockRepr> for WriteBlock {
 	type Error = FileError;
 
 	fn try_from(value: WriteBlockRepr) -> Result<Self, Self::Error> {
-		let Some(page_num) = NonZeroU16::new(value.page_num) else {
+		let Some(page_num) = NonZeroU16::new(value.page_num.get()) else {
 			return Err(FileError::Corrupted(
 				"0 is not a valid page number".to_string(),
 			));
 		};
 		Ok(Self {
-			page_address: PageAddress::new(value.segment_num, page_num),
-			offset: value.offset,
-			write_length: value.write_length,
+			page_address: PageAddress::new(value.segment_num.get(), page_num),
+			offset: value.offset.get(),
+			write_length: value.write_length.get(),
 		})
 	}
 }
@@ -254,7 +360,28 @@ impl Repr<WriteBlock> for WriteBlockRepr {
 	type Error = FileError;
 }
 
-type CheckpointBlock = CheckpointBlockRepr;
+struct CheckpointBlock {
+	num_dirty_pages: u64,
+	num_transactions: u64,
+}
+
+impl From<CheckpointBlock> for CheckpointBlockRepr {
+	fn from(value: CheckpointBlock) -> Self {
+		Self {
+			num_dirty_pages: value.num_dirty_pages.into(),
+			num_transactions: value.num_transactions.into(),
+		}
+	}
+}
+
+impl From<CheckpointBlockRepr> for CheckpointBlock {
+	fn from(value: CheckpointBlockRepr) -> Self {
+		Self {
+			num_dirty_pages: value.num_dirty_pages.get(),
+			num_transactions: value.num_transactions.get(),
+		}
+	}
+}
 
 impl Repr<CheckpointBlock> for CheckpointBlockRepr {
 	type Error = FileError;
@@ -263,8 +390,8 @@ impl Repr<CheckpointBlock> for CheckpointBlockRepr {
 impl From<PageAddress> for PageAddressRepr {
 	fn from(value: PageAddress) -> Self {
 		Self {
-			segment_num: value.segment_num,
-			page_num: value.page_num.get(),
+			segment_num: value.segment_num.into(),
+			page_num: value.page_num.get().into(),
 		}
 	}
 }
@@ -273,12 +400,12 @@ impl TryFrom<PageAddressRepr> for PageAddress {
 	type Error = FileError;
 
 	fn try_from(value: PageAddressRepr) -> Result<Self, Self::Error> {
-		let Some(page_num) = NonZeroU16::new(value.page_num) else {
+		let Some(page_num) = NonZeroU16::new(value.page_num.get()) else {
 			return Err(FileError::Corrupted(
 				"Found invalid page number 0".to_string(),
 			));
 		};
-		Ok(PageAddress::new(value.segment_num, page_num))
+		Ok(PageAddress::new(value.segment_num.get(), page_num))
 	}
 }
 
@@ -289,8 +416,8 @@ impl Repr<PageAddress> for PageAddressRepr {
 impl From<WalIndex> for WalIndexRepr {
 	fn from(value: WalIndex) -> Self {
 		Self {
-			offset: value.offset.get(),
-			generation: value.generation,
+			offset: value.offset.get().into(),
+			generation: value.generation.into(),
 		}
 	}
 }
@@ -299,13 +426,13 @@ impl TryFrom<WalIndexRepr> for WalIndex {
 	type Error = FileError;
 
 	fn try_from(value: WalIndexRepr) -> Result<Self, Self::Error> {
-		let Some(offset) = NonZeroU64::new(value.offset) else {
+		let Some(offset) = NonZeroU64::new(value.offset.get()) else {
 			return Err(FileError::Corrupted(
 				"Found invalid WAL offset '0'".to_string(),
 			));
 		};
 		Ok(Self {
-			generation: value.generation,
+			generation: value.generation.get(),
 			offset,
 		})
 	}
@@ -315,12 +442,30 @@ impl Repr<WalIndex> for WalIndexRepr {
 	type Error = FileError;
 }
 
+impl From<u64> for TransactionIdRepr {
+	fn from(value: u64) -> Self {
+		Self {
+			transaction_id: value.into(),
+		}
+	}
+}
+
+impl From<TransactionIdRepr> for u64 {
+	fn from(value: TransactionIdRepr) -> Self {
+		value.transaction_id.get()
+	}
+}
+
+impl Repr<u64> for TransactionIdRepr {
+	type Error = FileError;
+}
+
 impl From<TransactionState> for TransactionStateRepr {
 	fn from(value: TransactionState) -> Self {
 		Self {
-			first_generation: value.first_gen,
-			last_generation: value.last_index.generation,
-			last_offset: value.last_index.offset.get(),
+			first_generation: value.first_gen.into(),
+			last_generation: value.last_index.generation.into(),
+			last_offset: value.last_index.offset.get().into(),
 		}
 	}
 }
@@ -333,21 +478,58 @@ impl TryFrom<TransactionStateRepr> for TransactionState {
 	type Error = FileError;
 
 	fn try_from(value: TransactionStateRepr) -> Result<Self, Self::Error> {
-		let Some(last_offset) = NonZeroU64::new(value.last_offset) else {
+		let Some(last_offset) = NonZeroU64::new(value.last_offset.get()) else {
 			return Err(FileError::Corrupted(
 				"Found invalid WAL offset '0'".to_string(),
 			));
 		};
 		Ok(Self {
-			first_gen: value.first_generation,
-			last_index: WalIndex::new(value.last_generation, last_offset),
+			first_gen: value.first_generation.get(),
+			last_index: WalIndex::new(value.last_generation.get(), last_offset),
 		})
 	}
 }
 
+#[derive(Debug, Clone, Immutable, FromBytes, IntoBytes)]
+#[repr(C, packed)]
+struct WalHeaderRepr {
+	database_id: [u8; 16],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct WalHeader {
+	database_id: DatabaseId,
+}
+
+impl From<WalHeader> for WalHeaderRepr {
+	fn from(value: WalHeader) -> Self {
+		Self {
+			database_id: value.database_id.to_bytes(),
+		}
+	}
+}
+
+impl From<WalHeaderRepr> for WalHeader {
+	fn from(value: WalHeaderRepr) -> Self {
+		Self {
+			database_id: DatabaseId::from_bytes(value.database_id),
+		}
+	}
+}
+
+impl Repr<WalHeader> for WalHeaderRepr {
+	type Error = FileError;
+}
+
 const WRITE_BUF_LIMIT: usize = 2 * MIB;
 
 pub(crate) struct WalFile<F: Seek + Read + Write = File> {
+	// `None` for WAL files from before database IDs existed.
+	database_id: Option<DatabaseId>,
+	byte_order: ByteOrder,
+	// Items are written in the format of the file's version, so that old
+	// files can still be appended to.
+	version: u8,
 	body_start: u64,
 	prev_item: Option<NonZeroU64>,
 	write_buf: Vec<u8>,
@@ -357,7 +539,7 @@ pub(crate) struct WalFile<F: Seek + Read + Write = File> {
 assert_impl_all!(WalFile: Send, Sync);
 
 impl WalFile {
-	pub fn create_file(path: impl AsRef<Path>) -> Result<Self, FileError> {
+	pub fn create_file(path: impl AsRef<Path>, database_id: DatabaseId) -> Result<Self, FileError> {
 		Self::create(
 			OpenOptions::new()
 				.create(true)
@@ -365,6 +547,7 @@ impl WalFile {
 				.read(true)
 				.write(true)
 				.open(path)?,
+			database_id,
 		)
 	}
 
@@ -374,45 +557,62 @@ impl WalFile {
 }
 
 impl<F: Seek + Read + Write> WalFile<F> {
-	fn create(mut file: F) -> Result<Self, FileError> {
+	pub(super) fn create(mut file: F, database_id: DatabaseId) -> Result<Self, FileError> {
 		file.seek(SeekFrom::Start(0))?;
-		let content_offset = u16::try_from(GenericHeaderRepr::SIZE).unwrap();
+		let content_offset = u16::try_from(GenericHeaderRepr::SIZE + WalHeaderRepr::SIZE).unwrap();
 		let meta = GenericHeader {
+			byte_order: ByteOrder::Little,
 			file_type: FileType::Wal,
 			content_offset,
 			version: FORMAT_VERSION,
 		};
-		GenericHeaderRepr::serialize(meta, &mut file)?;
-		Self::new(file, content_offset.into())
+		GenericHeaderRepr::serialize(meta.clone(), &mut file)?;
+		WalHeaderRepr::serialize(WalHeader { database_id }, &mut file)?;
+		Self::new(file, meta, Some(database_id))
 	}
 
-	fn open(mut file: F) -> Result<Self, FileError> {
+	pub(super) fn open(mut file: F) -> Result<Self, FileError> {
 		file.seek(SeekFrom::Start(0))?;
 		let header = GenericHeaderRepr::deserialize(&mut file)?;
 		if header.file_type != FileType::Wal {
 			return Err(FileError::WrongFileType(header.file_type));
 		}
-		if header.version != FORMAT_VERSION {
-			return Err(FileError::IncompatibleVersion(
-				header.file_type,
-				header.version,
-			));
-		}
+		let database_id = match header.version {
+			FORMAT_VERSION_UNIDENTIFIED => None,
+			FORMAT_VERSION_SHORT_ITEMS | FORMAT_VERSION => {
+				Some(WalHeaderRepr::deserialize(&mut file)?.database_id)
+			}
+			_ => {
+				return Err(FileError::IncompatibleVersion(
+					header.file_type,
+					header.version,
+				))
+			}
+		};
 
-		Self::new(file, header.content_offset.into())
+		Self::new(file, header, database_id)
 	}
 
-	fn new(mut file: F, body_start: u64) -> Result<Self, FileError> {
+	fn new(
+		mut file: F,
+		header: GenericHeader,
+		database_id: Option<DatabaseId>,
+	) -> Result<Self, FileError> {
+		let body_start = u64::from(header.content_offset);
+		let byte_order = header.byte_order;
 		let prev_footer_start =
 			file.seek(SeekFrom::End(-i64::try_from(ItemFooterRepr::SIZE).unwrap()))?;
 		let prev_item = if prev_footer_start > body_start {
-			let footer = ItemFooterRepr::deserialize(&mut file)?;
+			let footer = ItemFooterRepr::deserialize_in(&mut file, byte_order)?;
 			Some(footer.item_start)
 		} else {
 			None
 		};
 		let next_offset = NonZeroU64::new(file.seek(SeekFrom::End(0))?).unwrap();
 		Ok(Self {
+			database_id,
+			byte_order,
+			version: header.version,
 			body_start,
 			file,
 			write_buf: Vec::new(),
@@ -421,17 +621,25 @@ impl<F: Seek + Read + Write> WalFile<F> {
 		})
 	}
 
-	fn write_transaction_block(writer: impl Write, data: TransactionData) -> Result<(), FileError> {
+	fn write_transaction_block(
+		writer: impl Write,
+		data: TransactionData,
+		byte_order: ByteOrder,
+	) -> Result<(), FileError> {
 		let block = TransactionBlock {
 			transaction_id: data.transaction_id,
 			prev_transaction_item: data.prev_transaction_item,
 		};
-		TransactionBlockRepr::serialize(block, writer)?;
+		TransactionBlockRepr::serialize_in(block, writer, byte_order)?;
 		Ok(())
 	}
 
-	fn write_write_block(mut writer: impl Write, data: WriteData) -> Result<(), FileError> {
-		Self::write_transaction_block(&mut writer, data.transaction_data)?;
+	fn write_write_block(
+		mut writer: impl Write,
+		data: WriteData,
+		byte_order: ByteOrder,
+	) -> Result<(), FileError> {
+		Self::write_transaction_block(&mut writer, data.transaction_data, byte_order)?;
 
 		let block = WriteBlock {
 			page_address: data.page_address,
@@ -440,9 +648,9 @@ impl<F: Seek + Read + Write> WalFile<F> {
 				.to
 				.len()
 				.try_into()
-				.expect("Write length must be 16-bit!"),
+				.map_err(|_| FileError::ItemTooLarge(data.to.len()))?,
 		};
-		WriteBlockRepr::serialize(block, &mut writer)?;
+		WriteBlockRepr::serialize_in(block, &mut writer, byte_order)?;
 		if let Some(from) = data.from {
 			debug_assert_eq!(from.len(), data.to.len());
 			writer.write_all(&from)?;
@@ -454,19 +662,20 @@ impl<F: Seek + Read + Write> WalFile<F> {
 	fn write_checkpoint_block(
 		mut writer: impl Write,
 		data: CheckpointData,
+		byte_order: ByteOrder,
 	) -> Result<(), FileError> {
 		let block = CheckpointBlock {
 			num_dirty_pages: data.dirty_pages.len() as u64,
 			num_transactions: data.transactions.len() as u64,
 		};
-		CheckpointBlockRepr::serialize(block, &mut writer)?;
+		CheckpointBlockRepr::serialize_in(block, &mut writer, byte_order)?;
 		for (page_address, wal_index) in data.dirty_pages.iter() {
-			PageAddressRepr::serialize(*page_address, &mut writer)?;
-			WalIndexRepr::serialize(*wal_index, &mut writer)?;
+			PageAddressRepr::serialize_in(*page_address, &mut writer, byte_order)?;
+			WalIndexRepr::serialize_in(*wal_index, &mut writer, byte_order)?;
 		}
 		for (transaction_id, transaction_state) in data.transactions.iter() {
-			writer.write_all(transaction_id.as_bytes())?;
-			TransactionStateRepr::serialize(transaction_state.clone(), &mut writer)?;
+			TransactionIdRepr::serialize_in(*transaction_id, &mut writer, byte_order)?;
+			TransactionStateRepr::serialize_in(transaction_state.clone(), &mut writer, byte_order)?;
 		}
 
 		Ok(())
@@ -499,6 +708,7 @@ pub(crate) enum Item<'a> {
 	Write(WriteData<'a>),
 	Commit(TransactionData),
 	Checkpoint(CheckpointData<'a>),
+	Truncate(PageAddress),
 }
 
 #[cfg_attr(test, automock(
@@ -521,6 +731,10 @@ pub(crate) trait WalFileApi {
 	fn iter_items_reverse<'a>(&'a mut self) -> Result<Self::IterItemsReverse<'a>, FileError>;
 	fn next_offset(&self) -> NonZeroU64;
 	fn size(&self) -> usize;
+
+	/// The ID of the database the WAL file was created for, or `None` if the
+	/// file is from before database IDs existed.
+	fn database_id(&self) -> Option<DatabaseId>;
 }
 
 impl<F: Seek + Read + Write> WalFileApi for WalFile<F> {
@@ -539,37 +753,51 @@ impl<F: Seek + Read + Write> WalFileApi for WalFile<F> {
 				if write_data.from.is_none() {
 					flags |= FLAG_UNDO;
 				}
-				Self::write_write_block(&mut body_buffer, write_data)?;
+				Self::write_write_block(&mut body_buffer, write_data, self.byte_order)?;
 			}
 			Item::Commit(transaction_data) => {
 				kind = ItemKind::Commit;
-				Self::write_transaction_block(&mut body_buffer, transaction_data)?
+				Self::write_transaction_block(&mut body_buffer, transaction_data, self.byte_order)?
 			}
 			Item::Checkpoint(checkpoint_data) => {
 				kind = ItemKind::Checkpoint;
-				Self::write_checkpoint_block(&mut body_buffer, checkpoint_data)?
+				Self::write_checkpoint_block(&mut body_buffer, checkpoint_data, self.byte_order)?
+			}
+			Item::Truncate(end) => {
+				kind = ItemKind::Truncate;
+				PageAddressRepr::serialize_in(end, &mut body_buffer, self.byte_order)?
 			}
 		};
 		let crc = CRC32.checksum(&body_buffer);
 
+		let max_body_length = if self.version < FORMAT_VERSION {
+			u16::MAX.into()
+		} else {
+			u32::MAX
+		};
+		let body_length = u32::try_from(body_buffer.len())
+			.ok()
+			.filter(|length| *length <= max_body_length)
+			.ok_or(FileError::ItemTooLarge(body_buffer.len()))?;
 		let item_header = ItemHeader {
 			kind,
 			flags,
-			body_length: body_buffer
-				.len()
-				.try_into()
-				.expect("WAL item body length must be 16-bit!"),
+			body_length,
 			crc,
 			prev_item: self.prev_item,
 		};
-		ItemHeaderRepr::serialize(item_header, &mut self.write_buf)?;
+		if self.version < FORMAT_VERSION {
+			ShortItemHeaderRepr::serialize_in(item_header, &mut self.write_buf, self.byte_order)?;
+		} else {
+			ItemHeaderRepr::serialize_in(item_header, &mut self.write_buf, self.byte_order)?;
+		}
 
 		self.write_buf.write_all(&body_buffer)?;
 
 		let item_footer = ItemFooter {
 			item_start: current_pos,
 		};
-		ItemFooterRepr::serialize(item_footer, &mut self.write_buf)?;
+		ItemFooterRepr::serialize_in(item_footer, &mut self.write_buf, self.byte_order)?;
 
 		self.prev_item = Some(current_pos);
 
@@ -595,7 +823,7 @@ impl<F: Seek + Read + Write> WalFileApi for WalFile<F> {
 
 		self.flush()?;
 		self.file.seek(SeekFrom::Start(offset.get()))?;
-		let mut reader = ItemReader::new(&mut self.file, None)?;
+		let mut reader = ItemReader::new(&mut self.file, None, self.byte_order, self.version)?;
 		let Some((read_offset, item)) = reader.read_item()? else {
 			return Err(FileError::UnexpectedEof);
 		};
@@ -607,13 +835,18 @@ impl<F: Seek + Read + Write> WalFileApi for WalFile<F> {
 	fn iter_items(&mut self) -> Result<Self::IterItems<'_>, FileError> {
 		self.flush()?;
 		self.file.seek(SeekFrom::Start(self.body_start))?;
-		IterItems::new(&mut self.file)
+		IterItems::new(&mut self.file, self.byte_order, self.version)
 	}
 
 	fn iter_items_reverse(&mut self) -> Result<Self::IterItemsReverse<'_>, FileError> {
 		self.flush()?;
 		self.file.seek(SeekFrom::End(0))?;
-		IterItemsReverse::new(&mut self.file, self.prev_item)
+		IterItemsReverse::new(
+			&mut self.file,
+			self.prev_item,
+			self.byte_order,
+			self.version,
+		)
 	}
 
 	#[inline]
@@ -621,6 +854,10 @@ impl<F: Seek + Read + Write> WalFileApi for WalFile<F> {
 		usize::try_from(self.next_offset.get()).expect("Wal size exceeded usize::MAX")
 	}
 
+	fn database_id(&self) -> Option<DatabaseId> {
+		self.database_id
+	}
+
 	#[inline]
 	fn next_offset(&self) -> NonZeroU64 {
 		self.next_offset
@@ -631,20 +868,32 @@ struct ItemReader<F: Read + Seek> {
 	offset: u64,
 	reader: BufReader<F>,
 	prev_item: Option<NonZeroU64>,
+	byte_order: ByteOrder,
+	version: u8,
 }
 
 impl<F: Read + Seek> ItemReader<F> {
-	fn new(mut file: F, prev_item: Option<NonZeroU64>) -> Result<Self, FileError> {
+	fn new(
+		mut file: F,
+		prev_item: Option<NonZeroU64>,
+		byte_order: ByteOrder,
+		version: u8,
+	) -> Result<Self, FileError> {
 		let offset = file.stream_position()?;
 		Ok(Self {
 			offset,
 			reader: BufReader::new(file),
 			prev_item,
+			byte_order,
+			version,
 		})
 	}
 
-	fn read_transaction_data(body: impl Read) -> Result<TransactionData, FileError> {
-		let transaction_block = TransactionBlockRepr::deserialize(body)?;
+	fn read_transaction_data(
+		body: impl Read,
+		byte_order: ByteOrder,
+	) -> Result<TransactionData, FileError> {
+		let transaction_block = TransactionBlockRepr::deserialize_in(body, byte_order)?;
 
 		Ok(TransactionData {
 			transaction_id: transaction_block.transaction_id,
@@ -655,10 +904,11 @@ impl<F: Read + Seek> ItemReader<F> {
 	fn read_write_data(
 		mut body: impl Read,
 		is_undo: bool,
+		byte_order: ByteOrder,
 	) -> Result<WriteData<'static>, FileError> {
-		let transaction_data = Self::read_transaction_data(&mut body)?;
+		let transaction_data = Self::read_transaction_data(&mut body, byte_order)?;
 
-		let write_block = WriteBlockRepr::deserialize(&mut body)?;
+		let write_block = WriteBlockRepr::deserialize_in(&mut body, byte_order)?;
 		let from: Option<Vec<u8>> = if is_undo {
 			None
 		} else {
@@ -678,22 +928,23 @@ impl<F: Read + Seek> ItemReader<F> {
 		})
 	}
 
-	fn read_checkpoint_data(mut body: impl Read) -> Result<CheckpointData<'static>, FileError> {
-		let checkpoint_block = CheckpointBlock::deserialize(&mut body)?;
+	fn read_checkpoint_data(
+		mut body: impl Read,
+		byte_order: ByteOrder,
+	) -> Result<CheckpointData<'static>, FileError> {
+		let checkpoint_block = CheckpointBlockRepr::deserialize_in(&mut body, byte_order)?;
 
 		let mut dirty_pages: HashMap<PageAddress, WalIndex> = HashMap::new();
 		for _ in 0..checkpoint_block.num_dirty_pages {
-			let page_address = PageAddressRepr::deserialize(&mut body)?;
-			let wal_index = WalIndexRepr::deserialize(&mut body)?;
+			let page_address = PageAddressRepr::deserialize_in(&mut body, byte_order)?;
+			let wal_index = WalIndexRepr::deserialize_in(&mut body, byte_order)?;
 			dirty_pages.insert(page_address, wal_index);
 		}
 
 		let mut transactions: HashMap<u64, TransactionState> = HashMap::new();
 		for _ in 0..checkpoint_block.num_transactions {
-			let mut tid_bytes = [0; 8];
-			body.read_exact(&mut tid_bytes)?;
-			let transaction_id = u64::from_ne_bytes(tid_bytes);
-			let transaction_state = TransactionStateRepr::deserialize(&mut body)?;
+			let transaction_id = TransactionIdRepr::deserialize_in(&mut body, byte_order)?;
+			let transaction_state = TransactionStateRepr::deserialize_in(&mut body, byte_order)?;
 			transactions.insert(transaction_id, transaction_state);
 		}
 
@@ -704,8 +955,12 @@ impl<F: Read + Seek> ItemReader<F> {
 	}
 
 	fn read_item_exact(&mut self) -> Result<(NonZeroU64, Item<'static>), FileError> {
-		let header = ItemHeaderRepr::deserialize(&mut self.reader)?;
-		let mut body_buf: Box<[u8]> = vec![0; header.body_length.into()].into();
+		let header = if self.version < FORMAT_VERSION {
+			ShortItemHeaderRepr::deserialize_in(&mut self.reader, self.byte_order)?
+		} else {
+			ItemHeaderRepr::deserialize_in(&mut self.reader, self.byte_order)?
+		};
+		let mut body_buf: Box<[u8]> = vec![0; header.body_length as usize].into();
 		self.reader.read_exact(&mut body_buf)?;
 		self.prev_item = header.prev_item;
 
@@ -717,17 +972,32 @@ impl<F: Read + Seek> ItemReader<F> {
 
 		let mut body_cursor = Cursor::new(body_buf);
 		let item = match header.kind {
-			ItemKind::Write => Item::Write(Self::read_write_data(&mut body_cursor, is_undo)?),
-			ItemKind::Commit => Item::Commit(Self::read_transaction_data(&mut body_cursor)?),
-			ItemKind::Checkpoint => Item::Checkpoint(Self::read_checkpoint_data(&mut body_cursor)?),
+			ItemKind::Write => Item::Write(Self::read_write_data(
+				&mut body_cursor,
+				is_undo,
+				self.byte_order,
+			)?),
+			ItemKind::Commit => Item::Commit(Self::read_transaction_data(
+				&mut body_cursor,
+				self.byte_order,
+			)?),
+			ItemKind::Checkpoint => Item::Checkpoint(Self::read_checkpoint_data(
+				&mut body_cursor,
+				self.byte_order,
+			)?),
+			ItemKind::Truncate => Item::Truncate(PageAddressRepr::deserialize_in(
+				&mut body_cursor,
+				self.byte_order,
+			)?),
 		};
 
 		self.reader
 			.seek_relative(i64::try_from(ItemFooterRepr::SIZE).unwrap())?;
 
 		let item_offset = self.offset;
-		self.offset +=
-			(ItemHeaderRepr::SIZE + header.body_length as usize + ItemFooterRepr::SIZE) as u64;
+		self.offset += (item_header_size(self.version)
+			+ header.body_length as usize
+			+ ItemFooterRepr::SIZE) as u64;
 
 		Ok((
 			NonZeroU64::new(item_offset).expect("WAL was unexpectedly read at offset 0"),
@@ -765,9 +1035,9 @@ pub(crate) struct IterItems<F: Read + Seek> {
 }
 
 impl<F: Read + Seek> IterItems<F> {
-	fn new(file: F) -> Result<Self, FileError> {
+	fn new(file: F, byte_order: ByteOrder, version: u8) -> Result<Self, FileError> {
 		Ok(Self {
-			reader: ItemReader::new(file, None)?,
+			reader: ItemReader::new(file, None, byte_order, version)?,
 		})
 	}
 }
@@ -785,9 +1055,14 @@ pub(crate) struct IterItemsReverse<F: Read + Seek> {
 }
 
 impl<F: Read + Seek> IterItemsReverse<F> {
-	fn new(file: F, prev_item: Option<NonZeroU64>) -> Result<Self, FileError> {
+	fn new(
+		file: F,
+		prev_item: Option<NonZeroU64>,
+		byte_order: ByteOrder,
+		version: u8,
+	) -> Result<Self, FileError> {
 		Ok(Self {
-			reader: ItemReader::new(file, prev_item)?,
+			reader: ItemReader::new(file, prev_item, byte_order, version)?,
 		})
 	}
 }
@@ -814,26 +1089,30 @@ mod tests {
 
 	use super::*;
 
+	const HEADER_SIZE: usize = GenericHeaderRepr::SIZE + WalHeaderRepr::SIZE;
+
 	#[test]
 	fn create_wal() {
 		// given
 		let mut file = Vec::<u8>::new();
 
 		// when
-		WalFile::create(Cursor::new(&mut file)).unwrap();
+		WalFile::create(Cursor::new(&mut file), DatabaseId::from_bytes([69; 16])).unwrap();
 
 		// then
 		let mut expected_data = Vec::<u8>::new();
 		expected_data.extend(
 			GenericHeaderRepr::from(GenericHeader {
+				byte_order: ByteOrder::Little,
 				file_type: FileType::Wal,
-				content_offset: GenericHeaderRepr::SIZE as u16,
+				content_offset: HEADER_SIZE as u16,
 				version: FORMAT_VERSION,
 			})
 			.as_bytes(),
 		);
+		expected_data.extend([69; 16]);
 
-		assert_eq!(file.len(), GenericHeaderRepr::SIZE);
+		assert_eq!(file.len(), HEADER_SIZE);
 		assert_buf_eq!(file, expected_data);
 	}
 
@@ -843,25 +1122,51 @@ mod tests {
 		let mut file = Vec::<u8>::new();
 		file.extend(
 			GenericHeaderRepr::from(GenericHeader {
+				byte_order: ByteOrder::Little,
 				file_type: FileType::Wal,
-				content_offset: GenericHeaderRepr::SIZE as u16,
+				content_offset: HEADER_SIZE as u16,
 				version: FORMAT_VERSION,
 			})
 			.as_bytes(),
 		);
+		file.extend([69; 16]);
+
+		// when
+		let wal_file = WalFile::open(Cursor::new(&mut file)).unwrap();
+
+		// then
+		assert_eq!(
+			wal_file.database_id(),
+			Some(DatabaseId::from_bytes([69; 16]))
+		);
+	}
+
+	#[test]
+	fn open_unidentified_wal() {
+		// given
+		let mut file = Vec::<u8>::new();
+		file.extend(
+			GenericHeaderRepr::from(GenericHeader {
+				byte_order: ByteOrder::Little,
+				file_type: FileType::Wal,
+				content_offset: GenericHeaderRepr::SIZE as u16,
+				version: FORMAT_VERSION_UNIDENTIFIED,
+			})
+			.as_bytes(),
+		);
 
 		// when
-		let result = WalFile::open(Cursor::new(&mut file));
+		let wal_file = WalFile::open(Cursor::new(&mut file)).unwrap();
 
 		// then
-		assert!(result.is_ok());
+		assert_eq!(wal_file.database_id(), None);
 	}
 
 	#[test]
 	fn push_write_item() {
 		// given
 		let mut file = Vec::<u8>::new();
-		let mut wal_file = WalFile::create(Cursor::new(&mut file)).unwrap();
+		let mut wal_file = WalFile::create(Cursor::new(&mut file), DatabaseId::NIL).unwrap();
 
 		// when
 		wal_file
@@ -884,26 +1189,26 @@ mod tests {
 			ItemHeaderRepr {
 				kind: ItemKind::Write as u8,
 				flags: 0,
-				body_length: 42,
-				crc: 0x994f0abc,
-				prev_item: NonZeroU64::new(0),
+				body_length: 42.into(),
+				crc: 0x994f0abc.into(),
+				prev_item: 0.into(),
 			}
 			.as_bytes(),
 		);
 		expected_body.extend(
 			TransactionBlockRepr {
-				prev_transaction_generation: 123,
-				prev_transaction_offset: NonZeroU64::new(24),
-				transaction_id: 25,
+				prev_transaction_generation: 123.into(),
+				prev_transaction_offset: 24.into(),
+				transaction_id: 25.into(),
 			}
 			.as_bytes(),
 		);
 		expected_body.extend(
 			WriteBlockRepr {
-				segment_num: 123,
-				page_num: 456,
-				offset: 445,
-				write_length: 4,
+				segment_num: 123.into(),
+				page_num: 456.into(),
+				offset: 445.into(),
+				write_length: 4.into(),
 			}
 			.as_bytes(),
 		);
@@ -911,20 +1216,20 @@ mod tests {
 		expected_body.extend([4, 5, 6, 7]);
 		expected_body.extend(
 			ItemFooterRepr {
-				item_start: GenericHeaderRepr::SIZE as u64,
+				item_start: (HEADER_SIZE as u64).into(),
 			}
 			.as_bytes(),
 		);
 
 		assert_eq!(wal_file.size(), file.len());
-		assert_buf_eq!(&file[GenericHeaderRepr::SIZE..], expected_body);
+		assert_buf_eq!(&file[HEADER_SIZE..], expected_body);
 	}
 
 	#[test]
 	fn push_commit_item() {
 		// given
 		let mut file = Vec::<u8>::new();
-		let mut wal_file = WalFile::create(Cursor::new(&mut file)).unwrap();
+		let mut wal_file = WalFile::create(Cursor::new(&mut file), DatabaseId::NIL).unwrap();
 
 		// when
 		wal_file
@@ -941,36 +1246,36 @@ mod tests {
 			ItemHeaderRepr {
 				kind: ItemKind::Commit as u8,
 				flags: 0,
-				body_length: 24,
-				crc: 0x8b777949,
-				prev_item: NonZeroU64::new(0),
+				body_length: 24.into(),
+				crc: 0x8b777949.into(),
+				prev_item: 0.into(),
 			}
 			.as_bytes(),
 		);
 		expected_body.extend(
 			TransactionBlockRepr {
-				prev_transaction_generation: 123,
-				prev_transaction_offset: NonZeroU64::new(25),
-				transaction_id: 69,
+				prev_transaction_generation: 123.into(),
+				prev_transaction_offset: 25.into(),
+				transaction_id: 69.into(),
 			}
 			.as_bytes(),
 		);
 		expected_body.extend(
 			ItemFooterRepr {
-				item_start: GenericHeaderRepr::SIZE as u64,
+				item_start: (HEADER_SIZE as u64).into(),
 			}
 			.as_bytes(),
 		);
 
 		assert_eq!(wal_file.size(), file.len());
-		assert_buf_eq!(&file[GenericHeaderRepr::SIZE..], expected_body);
+		assert_buf_eq!(&file[HEADER_SIZE..], expected_body);
 	}
 
 	#[test]
 	fn push_undo_item() {
 		// given
 		let mut file = Vec::<u8>::new();
-		let mut wal_file = WalFile::create(Cursor::new(&mut file)).unwrap();
+		let mut wal_file = WalFile::create(Cursor::new(&mut file), DatabaseId::NIL).unwrap();
 
 		// when
 		wal_file
@@ -993,46 +1298,46 @@ mod tests {
 			ItemHeaderRepr {
 				kind: ItemKind::Write as u8,
 				flags: FLAG_UNDO,
-				body_length: 38,
-				crc: 0x1af2b54e,
-				prev_item: NonZeroU64::new(0),
+				body_length: 38.into(),
+				crc: 0x1af2b54e.into(),
+				prev_item: 0.into(),
 			}
 			.as_bytes(),
 		);
 		expected_body.extend(
 			TransactionBlockRepr {
-				prev_transaction_generation: 123,
-				prev_transaction_offset: NonZeroU64::new(24),
-				transaction_id: 25,
+				prev_transaction_generation: 123.into(),
+				prev_transaction_offset: 24.into(),
+				transaction_id: 25.into(),
 			}
 			.as_bytes(),
 		);
 		expected_body.extend(
 			WriteBlockRepr {
-				offset: 445,
-				segment_num: 123,
-				page_num: 456,
-				write_length: 4,
+				offset: 445.into(),
+				segment_num: 123.into(),
+				page_num: 456.into(),
+				write_length: 4.into(),
 			}
 			.as_bytes(),
 		);
 		expected_body.extend([4, 5, 6, 7]);
 		expected_body.extend(
 			ItemFooterRepr {
-				item_start: GenericHeaderRepr::SIZE as u64,
+				item_start: (HEADER_SIZE as u64).into(),
 			}
 			.as_bytes(),
 		);
 
 		assert_eq!(wal_file.size(), file.len());
-		assert_buf_eq!(&file[GenericHeaderRepr::SIZE..], expected_body);
+		assert_buf_eq!(&file[HEADER_SIZE..], expected_body);
 	}
 
 	#[test]
 	fn push_checkpoint_item() {
 		// given
 		let mut file = Vec::<u8>::new();
-		let mut wal_file = WalFile::create(Cursor::new(&mut file)).unwrap();
+		let mut wal_file = WalFile::create(Cursor::new(&mut file), DatabaseId::NIL).unwrap();
 
 		// when
 		let mut dirty_pages = HashMap::new();
@@ -1059,57 +1364,71 @@ mod tests {
 			ItemHeaderRepr {
 				kind: ItemKind::Checkpoint as u8,
 				flags: 0,
-				body_length: 70,
-				crc: 0x3420af22,
-				prev_item: NonZeroU64::new(0),
+				body_length: 70.into(),
+				crc: 0x3420af22.into(),
+				prev_item: 0.into(),
 			}
 			.as_bytes(),
 		);
 		expected_body.extend(
 			CheckpointBlockRepr {
-				num_dirty_pages: 1,
-				num_transactions: 1,
+				num_dirty_pages: 1.into(),
+				num_transactions: 1.into(),
 			}
 			.as_bytes(),
 		);
 		expected_body.extend(
 			PageAddressRepr {
-				segment_num: 1,
-				page_num: 2,
+				segment_num: 1.into(),
+				page_num: 2.into(),
 			}
 			.as_bytes(),
 		);
 		expected_body.extend(
 			WalIndexRepr {
-				generation: 0,
-				offset: 3,
+				generation: 0.into(),
+				offset: 3.into(),
 			}
 			.as_bytes(),
 		);
-		expected_body.extend(69_u64.to_ne_bytes());
+		expected_body.extend(69_u64.to_le_bytes());
 		expected_body.extend(
 			TransactionStateRepr {
-				first_generation: 0,
-				last_generation: 1,
-				last_offset: 420,
+				first_generation: 0.into(),
+				last_generation: 1.into(),
+				last_offset: 420.into(),
 			}
 			.as_bytes(),
 		);
 		expected_body.extend(
 			ItemFooterRepr {
-				item_start: GenericHeaderRepr::SIZE as u64,
+				item_start: (HEADER_SIZE as u64).into(),
 			}
 			.as_bytes(),
 		);
 
 		assert_eq!(wal_file.size(), file.len());
-		assert_buf_eq!(&file[GenericHeaderRepr::SIZE..], expected_body);
+		assert_buf_eq!(&file[HEADER_SIZE..], expected_body);
+	}
+
+	#[test]
+	fn write_and_read_truncate() {
+		// given
+		let mut wal_file = WalFile::create(Cursor::new(Vec::new()), DatabaseId::NIL).unwrap();
+		let item = Item::Truncate(page_address!(3, 25));
+
+		// when
+		let offset = wal_file.push_item(item.clone()).unwrap();
+		wal_file.flush().unwrap();
+
+		// then
+		assert_eq!(wal_file.read_item_at(offset).unwrap(), item)
 	}
 
 	#[test]
 	fn write_and_read() {
 		// given
-		let mut wal_file = WalFile::create(Cursor::new(Vec::new())).unwrap();
+		let mut wal_file = WalFile::create(Cursor::new(Vec::new()), DatabaseId::NIL).unwrap();
 		let item = Item::Write(WriteData {
 			transaction_data: TransactionData {
 				transaction_id: 0,
@@ -1132,7 +1451,7 @@ mod tests {
 	#[test]
 	fn write_and_iter() {
 		// given
-		let mut wal_file = WalFile::create(Cursor::new(Vec::new())).unwrap();
+		let mut wal_file = WalFile::create(Cursor::new(Vec::new()), DatabaseId::NIL).unwrap();
 		let items = [
 			Item::Write(WriteData {
 				transaction_data: TransactionData {
@@ -1160,19 +1479,111 @@ mod tests {
 		let mut iter = wal_file.iter_items().unwrap();
 		assert_eq!(
 			iter.next().unwrap().unwrap(),
-			(non_zero!(9), items[0].clone())
+			(non_zero!(25), items[0].clone())
 		);
 		assert_eq!(
 			iter.next().unwrap().unwrap(),
-			(non_zero!(75), items[1].clone())
+			(non_zero!(93), items[1].clone())
 		);
 		assert!(iter.next().is_none());
 	}
 
+	#[test]
+	fn write_and_iter_big_endian() {
+		// given
+		let mut file = Vec::<u8>::new();
+		file.extend(
+			GenericHeaderRepr::from(GenericHeader {
+				byte_order: ByteOrder::Big,
+				file_type: FileType::Wal,
+				content_offset: GenericHeaderRepr::SIZE as u16,
+				version: FORMAT_VERSION_UNIDENTIFIED,
+			})
+			.as_bytes(),
+		);
+		let mut wal_file = WalFile::open(Cursor::new(&mut file)).unwrap();
+		let item = Item::Commit(TransactionData {
+			transaction_id: 69,
+			prev_transaction_item: Some(wal_index!(1, 2)),
+		});
+
+		// when
+		wal_file.push_item(item.clone()).unwrap();
+		wal_file.flush().unwrap();
+
+		// then
+		let mut iter = wal_file.iter_items().unwrap();
+		assert_eq!(
+			iter.next().unwrap().unwrap(),
+			(non_zero!(GenericHeaderRepr::SIZE as u64), item)
+		);
+		assert!(iter.next().is_none());
+		assert_eq!(
+			file[file.len() - ItemFooterRepr::SIZE..],
+			(GenericHeaderRepr::SIZE as u64).to_be_bytes()
+		);
+	}
+
+	#[test]
+	fn write_and_read_large_item() {
+		// given
+		let mut wal_file = WalFile::create(Cursor::new(Vec::new()), DatabaseId::NIL).unwrap();
+		let item = Item::Write(WriteData {
+			transaction_data: TransactionData {
+				transaction_id: 0,
+				prev_transaction_item: None,
+			},
+			page_address: page_address!(123, 456),
+			offset: 0,
+			from: Some(Cow::Owned(vec![1; u16::MAX.into()])),
+			to: Cow::Owned(vec![2; u16::MAX.into()]),
+		});
+
+		// when
+		let offset = wal_file.push_item(item.clone()).unwrap();
+		wal_file.flush().unwrap();
+
+		// then
+		assert_eq!(wal_file.read_item_at(offset).unwrap(), item);
+	}
+
+	#[test]
+	fn reject_large_item_in_short_item_file() {
+		// given
+		let mut file = Vec::<u8>::new();
+		file.extend(
+			GenericHeaderRepr::from(GenericHeader {
+				byte_order: ByteOrder::Little,
+				file_type: FileType::Wal,
+				content_offset: (GenericHeaderRepr::SIZE + WalHeaderRepr::SIZE) as u16,
+				version: FORMAT_VERSION_SHORT_ITEMS,
+			})
+			.as_bytes(),
+		);
+		file.extend([69; 16]);
+		let mut wal_file = WalFile::open(Cursor::new(&mut file)).unwrap();
+		let item = Item::Write(WriteData {
+			transaction_data: TransactionData {
+				transaction_id: 0,
+				prev_transaction_item: None,
+			},
+			page_address: page_address!(123, 456),
+			offset: 0,
+			from: Some(Cow::Owned(vec![1; u16::MAX.into()])),
+			to: Cow::Owned(vec![2; u16::MAX.into()]),
+		});
+
+		// when
+		let result = wal_file.push_item(item);
+
+		// then
+		assert!(matches!(result, Err(FileError::ItemTooLarge(..))));
+	}
+
 	#[test]
 	fn write_and_iter_reverse() {
 		// given
-		let mut wal_file = WalFile::create(Cursor::new(Vec::new())).unwrap();
+		let mut wal_file = WalFile::create(Cursor::new(Vec::new()), DatabaseId::NIL).unwrap();
 		let items = [
 			Item::Write(WriteData {
 				transaction_data: TransactionData {
@@ -1200,11 +1611,11 @@ mod tests {
 		let mut iter = wal_file.iter_items_reverse().unwrap();
 		assert_eq!(
 			iter.next().unwrap().unwrap(),
-			(non_zero!(75), items[1].clone())
+			(non_zero!(93), items[1].clone())
 		);
 		assert_eq!(
 			iter.next().unwrap().unwrap(),
-			(non_zero!(9), items[0].clone())
+			(non_zero!(25), items[0].clone())
 		);
 		assert!(iter.next().is_none());
 	}
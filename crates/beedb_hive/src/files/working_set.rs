@@ -0,0 +1,209 @@
+use std::{
+	io::{Read, Write},
+	num::NonZeroU16,
+};
+
+use zerocopy::{
+	little_endian::{U16, U32},
+	FromBytes, Immutable, IntoBytes,
+};
+
+use crate::{
+	repr::{impl_swap_bytes, ByteOrder, IoRepr, Repr},
+	utils::cache::CacheList,
+};
+
+use super::{
+	generic::{FileType, GenericHeader, GenericHeaderRepr},
+	utils::CRC32,
+	FileError, PageAddress,
+};
+
+pub(super) const FORMAT_VERSION: u8 = 1;
+
+const LIST_RECENT: u8 = 0;
+const LIST_FREQUENT: u8 = 1;
+
+#[derive(Debug, Clone, Immutable, FromBytes, IntoBytes)]
+#[repr(C, packed)]
+struct WorkingSetPageRepr {
+	segment_num: U32,
+	page_num: U16,
+	list: u8,
+}
+impl_swap_bytes!(WorkingSetPageRepr {
+	segment_num,
+	page_num
+});
+
+/// A page that was in the page cache, and the list of the replacement policy
+/// it was on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct WorkingSetPage {
+	pub page_address: PageAddress,
+	pub list: CacheList,
+}
+
+impl From<WorkingSetPage> for WorkingSetPageRepr {
+	fn from(value: WorkingSetPage) -> Self {
+		Self {
+			segment_num: value.page_address.segment_num.into(),
+			page_num: value.page_address.page_num.get().into(),
+			list: match value.list {
+				CacheList::Recent => LIST_RECENT,
+				CacheList::Frequent => LIST_FREQUENT,
+			},
+		}
+	}
+}
+
+impl TryFrom<WorkingSetPageRepr> for WorkingSetPage {
+	type Error = FileError;
+
+	fn try_from(value: WorkingSetPageRepr) -> Result<Self, Self::Error> {
+		let Some(page_num) = NonZeroU16::new(value.page_num.get()) else {
+			return Err(FileError::Corrupted(
+				"Working set contains page number 0".to_string(),
+			));
+		};
+		let list = match value.list {
+			LIST_RECENT => CacheList::Recent,
+			LIST_FREQUENT => CacheList::Frequent,
+			list => {
+				return Err(FileError::Corrupted(format!(
+					"Unknown cache list {list} in working set"
+				)))
+			}
+		};
+		Ok(Self {
+			page_address: PageAddress::new(value.segment_num.get(), page_num),
+			list,
+		})
+	}
+}
+
+impl Repr<WorkingSetPage> for WorkingSetPageRepr {
+	type Error = FileError;
+}
+
+/// The pages that were in the page cache, so that the cache can be
+/// prewarmed when the database is opened again. It is stored in the
+/// `WORKING_SET` file of the database folder, as the list of pages followed
+/// by a checksum.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct WorkingSet {
+	pub pages: Vec<WorkingSetPage>,
+}
+
+impl WorkingSet {
+	pub fn read(mut reader: impl Read) -> Result<Self, FileError> {
+		let header = GenericHeaderRepr::deserialize(&mut reader)?;
+		if header.file_type != FileType::WorkingSet {
+			return Err(FileError::WrongFileType(header.file_type));
+		}
+		if header.version != FORMAT_VERSION {
+			return Err(FileError::IncompatibleVersion(
+				header.file_type,
+				header.version,
+			));
+		}
+
+		let mut bytes = Vec::new();
+		reader.read_to_end(&mut bytes)?;
+		let Some(crc_offset) = bytes.len().checked_sub(size_of::<u32>()) else {
+			return Err(FileError::UnexpectedEof);
+		};
+		let (body, crc_bytes) = bytes.split_at(crc_offset);
+		let crc_bytes: [u8; 4] = crc_bytes.try_into().unwrap();
+		let crc = match header.byte_order {
+			ByteOrder::Little => u32::from_le_bytes(crc_bytes),
+			ByteOrder::Big => u32::from_be_bytes(crc_bytes),
+		};
+		if CRC32.checksum(body) != crc {
+			return Err(FileError::ChecksumMismatch);
+		}
+		if body.len() % WorkingSetPageRepr::SIZE != 0 {
+			return Err(FileError::Corrupted(
+				"Working set ends in the middle of a page".to_string(),
+			));
+		}
+
+		let pages = body
+			.chunks(WorkingSetPageRepr::SIZE)
+			.map(|chunk| WorkingSetPageRepr::from_bytes_in(chunk, header.byte_order))
+			.collect::<Result<_, _>>()?;
+		Ok(Self { pages })
+	}
+
+	pub fn write(&self, mut writer: impl Write) -> Result<(), FileError> {
+		let header = GenericHeader {
+			byte_order: ByteOrder::Little,
+			file_type: FileType::WorkingSet,
+			content_offset: u16::try_from(GenericHeaderRepr::SIZE).unwrap(),
+			version: FORMAT_VERSION,
+		};
+		GenericHeaderRepr::serialize(header, &mut writer)?;
+
+		let mut body = Vec::with_capacity(self.pages.len() * WorkingSetPageRepr::SIZE);
+		for page in &self.pages {
+			body.extend_from_slice(WorkingSetPageRepr::from(*page).as_bytes());
+		}
+		writer.write_all(&body)?;
+		writer.write_all(&CRC32.checksum(&body).to_le_bytes())?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::files::test_helpers::page_address;
+
+	use super::*;
+
+	#[test]
+	fn write_and_read_working_set() {
+		// given
+		let working_set = WorkingSet {
+			pages: vec![
+				WorkingSetPage {
+					page_address: page_address!(0, 1),
+					list: CacheList::Frequent,
+				},
+				WorkingSetPage {
+					page_address: page_address!(69, 420),
+					list: CacheList::Recent,
+				},
+			],
+		};
+
+		// when
+		let mut buf = Vec::new();
+		working_set.write(&mut buf).unwrap();
+
+		// then
+		assert_eq!(WorkingSet::read(buf.as_slice()).unwrap(), working_set);
+	}
+
+	#[test]
+	fn read_truncated_working_set() {
+		// given
+		let working_set = WorkingSet {
+			pages: vec![
+				WorkingSetPage {
+					page_address: page_address!(0, 1),
+					list: CacheList::Frequent,
+				};
+				4
+			],
+		};
+		let mut buf = Vec::new();
+		working_set.write(&mut buf).unwrap();
+		buf.truncate(buf.len() - 3);
+
+		// when
+		let result = WorkingSet::read(buf.as_slice());
+
+		// then
+		assert!(matches!(result, Err(FileError::ChecksumMismatch)));
+	}
+}
@@ -18,3 +18,5 @@ mod page_store;
 mod repr;
 mod tasks;
 mod utils;
+
+pub use utils::cache::replay::replay_trace_file;
@@ -1,39 +1,55 @@
 use std::{
 	alloc::{alloc_zeroed, dealloc, Layout},
-	collections::HashMap,
+	collections::{hash_map::RandomState, HashMap, HashSet},
+	hash::BuildHasher,
 	marker::PhantomData,
 	mem,
 	num::NonZeroU64,
-	ptr::{self, NonNull},
+	ops::Range,
+	path::PathBuf,
+	ptr::NonNull,
 	sync::{
-		atomic::{AtomicBool, AtomicUsize, Ordering},
-		Arc,
+		atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+		Arc, OnceLock,
 	},
-	time::Duration,
+	thread,
+	time::{Duration, Instant},
 };
 
-use futures::executor::ThreadPool;
-use log::error;
+use log::{error, warn};
 use parking_lot::{
 	lock_api::{RawRwLock as _, RawRwLockDowngrade},
-	Mutex, RawRwLock, RwLock, RwLockReadGuard,
+	Condvar, Mutex, RawRwLock, RwLock, RwLockReadGuard,
 };
-use static_assertions::assert_impl_all;
+use static_assertions::{assert_impl_all, const_assert};
 
 #[cfg(test)]
 use mockall::automock;
 
-use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
+use zerocopy::{FromBytes, FromZeros, Immutable, IntoBytes, KnownLayout};
 
 use crate::{
-	consts::{DEFAULT_FLUSH_PERIOD, DEFAULT_MAX_DIRTY_PAGES, DEFAULT_PAGE_CACHE_SIZE},
-	files::{segment::PAGE_BODY_SIZE, WalIndex},
-	tasks::{Timer, TimerHandle},
-	utils::cache::CacheReplacer,
+	consts::{
+		DEFAULT_DIRTY_PAGES_CEILING, DEFAULT_FLUSH_PERIOD, DEFAULT_MAX_DIRTY_PAGES,
+		DEFAULT_MAX_SCAN_PAGES, DEFAULT_MAX_WRITE_STALL, DEFAULT_NUM_PAGE_CACHE_SHARDS,
+		DEFAULT_PAGE_CACHE_SIZE, DEFAULT_PERSIST_WORKING_SET, DEFAULT_READ_AHEAD_PAGES,
+		DEFAULT_REGISTER_IO_BUFFERS, DIRECT_IO_ALIGNMENT, MIN_PAGES_PER_CACHE_SHARD,
+		PREWARM_BATCH_PAGES,
+	},
+	files::{
+		segment::{PageSize, ReadBuf, PAGE_HEADER_SIZE},
+		working_set::{WorkingSet, WorkingSetPage},
+		WalIndex,
+	},
+	tasks::{Runtime, Timer, TimerHandle},
+	utils::cache::{
+		replay::TraceRecorder, CacheList, CacheReplacer, IndexedList, ReplacementPolicy,
+		ReplacementPolicyKind,
+	},
 };
 
 use super::{
-	physical::{Op, PhysicalStorage, PhysicalStorageApi, WriteOp},
+	physical::{Op, PhysicalBackend, PhysicalStorageApi, ReadOp, WriteOp},
 	PageAddress, StorageError,
 };
 
@@ -41,7 +57,44 @@ use super::{
 pub(crate) struct PageCacheConfig {
 	pub page_cache_size: usize,
 	pub max_dirty_pages: f32,
+
+	/// The share of the cache that dirty pages can take up before writers
+	/// have to wait for a flush, and how long they wait at most. A writer may
+	/// hold pages that the flush needs, so it can't wait forever. A zero
+	/// limit only starts the flush.
+	pub dirty_pages_ceiling: f32,
+	pub max_write_stall: Duration,
 	pub flush_period: Duration,
+	pub replacement_policy: ReplacementPolicyKind,
+
+	/// The maximum number of shards that the page table is split into. Small
+	/// caches use fewer shards, so that each one still holds enough pages for
+	/// the replacement policy to be effective.
+	pub num_shards: usize,
+
+	/// The number of pages that are read ahead once a sequential scan is
+	/// detected. Zero disables read-ahead.
+	pub read_ahead_pages: usize,
+
+	/// The share of the cache that pages read with [`AccessHint::Scan`] can
+	/// take up, before they start replacing each other.
+	pub max_scan_pages: f32,
+
+	/// Whether the cached pages are saved when the storage is closed and
+	/// after every periodic flush, so that the cache can be prewarmed with
+	/// them when the storage is opened again.
+	pub persist_working_set: bool,
+
+	/// Whether the memory of the cache is registered with the IO backend, so
+	/// that pages are read directly into it without mapping it for every
+	/// read. Registered memory is pinned, so it's backed up front and never
+	/// returned to the OS when the cache shrinks.
+	pub register_io_buffers: bool,
+
+	/// If set, every page that is loaded from the cache is appended to this
+	/// file, so that the replacement policies can be compared on the real
+	/// access pattern with the `replay_trace` example.
+	pub access_trace: Option<PathBuf>,
 }
 
 impl Default for PageCacheConfig {
@@ -49,8 +102,140 @@ impl Default for PageCacheConfig {
 		Self {
 			page_cache_size: DEFAULT_PAGE_CACHE_SIZE,
 			max_dirty_pages: DEFAULT_MAX_DIRTY_PAGES,
+			dirty_pages_ceiling: DEFAULT_DIRTY_PAGES_CEILING,
+			max_write_stall: DEFAULT_MAX_WRITE_STALL,
 			flush_period: DEFAULT_FLUSH_PERIOD,
+			replacement_policy: ReplacementPolicyKind::default(),
+			num_shards: DEFAULT_NUM_PAGE_CACHE_SHARDS,
+			read_ahead_pages: DEFAULT_READ_AHEAD_PAGES,
+			max_scan_pages: DEFAULT_MAX_SCAN_PAGES,
+			persist_working_set: DEFAULT_PERSIST_WORKING_SET,
+			register_io_buffers: DEFAULT_REGISTER_IO_BUFFERS,
+			access_trace: None,
+		}
+	}
+}
+
+/// A snapshot of the page cache's counters. Apart from `dirty_pages`,
+/// `free_scrap_pages`, `pinned_pages` and `num_pages`, which describe the
+/// current state, all values are totals since the cache was created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct PageCacheStats {
+	pub hits: u64,
+	pub misses: u64,
+	pub evictions: u64,
+
+	/// The number of times an eviction candidate was skipped, because the page
+	/// was locked.
+	pub blocked_evictions: u64,
+
+	/// The number of distinct pages that were written since they were last
+	/// flushed.
+	pub dirty_pages: usize,
+
+	/// The number of flushes that wrote at least one page.
+	pub flushes: u64,
+	pub failed_flushes: u64,
+	pub flush_duration: Duration,
+	pub flushed_bytes: u64,
+
+	/// The number of pages that were scrapped, and the number of times a
+	/// scrapped slot was used for a new page instead of evicting one.
+	pub scrapped_pages: u64,
+	pub reused_scrap_pages: u64,
+	pub free_scrap_pages: usize,
+
+	/// The number of pages that were read into the cache before they were
+	/// requested, either explicitly or by read-ahead.
+	pub prefetched_pages: u64,
+
+	/// The number of pages that were read back into the cache from the
+	/// working set that was saved before the storage was opened.
+	pub prewarmed_pages: u64,
+
+	/// The number of distinct pages that are currently pinned.
+	pub pinned_pages: usize,
+
+	/// The number of pages the cache can currently hold.
+	pub num_pages: usize,
+
+	/// The number of times a writer had to wait for a flush, because there
+	/// were too many dirty pages, and how long they waited in total.
+	pub write_stalls: u64,
+	pub write_stall_duration: Duration,
+}
+
+impl PageCacheStats {
+	pub fn hit_ratio(&self) -> f64 {
+		let accesses = self.hits + self.misses;
+		if accesses == 0 {
+			return 0.0;
+		}
+		self.hits as f64 / accesses as f64
+	}
+}
+
+/// The counters behind [`PageCacheStats`]. They are only ever incremented,
+/// so relaxed atomics are enough to keep them cheap on the hot path. The
+/// cache keeps the counters of page accesses in each shard, and everything
+/// else in a set of its own.
+#[derive(Debug, Default)]
+struct CacheCounters {
+	hits: AtomicU64,
+	misses: AtomicU64,
+	evictions: AtomicU64,
+	blocked_evictions: AtomicU64,
+	flushes: AtomicU64,
+	failed_flushes: AtomicU64,
+	flush_nanos: AtomicU64,
+	flushed_bytes: AtomicU64,
+	scrapped_pages: AtomicU64,
+	reused_scrap_pages: AtomicU64,
+	prefetched_pages: AtomicU64,
+	prewarmed_pages: AtomicU64,
+	write_stalls: AtomicU64,
+	write_stall_nanos: AtomicU64,
+}
+
+impl CacheCounters {
+	fn record_flush(&self, duration: Duration, bytes: u64, result: &Result<(), StorageError>) {
+		if result.is_err() {
+			self.failed_flushes.fetch_add(1, Ordering::Relaxed);
+			return;
 		}
+		self.flushes.fetch_add(1, Ordering::Relaxed);
+		self.flush_nanos.fetch_add(
+			u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX),
+			Ordering::Relaxed,
+		);
+		self.flushed_bytes.fetch_add(bytes, Ordering::Relaxed);
+	}
+
+	fn record_stall(&self, duration: Duration) {
+		self.write_stalls.fetch_add(1, Ordering::Relaxed);
+		self.write_stall_nanos.fetch_add(
+			u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX),
+			Ordering::Relaxed,
+		);
+	}
+
+	/// Adds the counters to the totals of a snapshot.
+	fn add_to(&self, stats: &mut PageCacheStats) {
+		stats.hits += self.hits.load(Ordering::Relaxed);
+		stats.misses += self.misses.load(Ordering::Relaxed);
+		stats.evictions += self.evictions.load(Ordering::Relaxed);
+		stats.blocked_evictions += self.blocked_evictions.load(Ordering::Relaxed);
+		stats.flushes += self.flushes.load(Ordering::Relaxed);
+		stats.failed_flushes += self.failed_flushes.load(Ordering::Relaxed);
+		stats.flush_duration += Duration::from_nanos(self.flush_nanos.load(Ordering::Relaxed));
+		stats.flushed_bytes += self.flushed_bytes.load(Ordering::Relaxed);
+		stats.scrapped_pages += self.scrapped_pages.load(Ordering::Relaxed);
+		stats.reused_scrap_pages += self.reused_scrap_pages.load(Ordering::Relaxed);
+		stats.prefetched_pages += self.prefetched_pages.load(Ordering::Relaxed);
+		stats.prewarmed_pages += self.prewarmed_pages.load(Ordering::Relaxed);
+		stats.write_stalls += self.write_stalls.load(Ordering::Relaxed);
+		stats.write_stall_duration +=
+			Duration::from_nanos(self.write_stall_nanos.load(Ordering::Relaxed));
 	}
 }
 
@@ -71,6 +256,15 @@ impl BufferedPageHeader {
 		}
 	}
 
+	/// Resets the header of a page that was just read, which is clean, and
+	/// has no WAL index if it is uninitialized.
+	fn reset(&mut self, wal_index: Option<WalIndex>) {
+		*self = match wal_index {
+			Some(wal_index) => Self::new(wal_index, false),
+			None => Self::new_zeroed(),
+		};
+	}
+
 	pub fn wal_index(&self) -> WalIndex {
 		WalIndex::new(
 			self.wal_generation,
@@ -93,46 +287,223 @@ impl BufferedPageHeader {
 }
 
 const HEADER_SIZE: usize = mem::size_of::<BufferedPageHeader>();
-const BUFFERED_PAGE_SIZE: usize = PAGE_BODY_SIZE + HEADER_SIZE;
 
-struct PageBuffer {
-	buf: Option<NonNull<u8>>,
+/// Bodies start at the same offset in their slots as in the segment files, so
+/// that pages can be read into their slots directly. The buffered header
+/// takes the place of the on-disk one.
+const BODY_OFFSET: usize = PAGE_HEADER_SIZE;
+const_assert!(HEADER_SIZE <= BODY_OFFSET);
+
+/// The maximum number of chunks that the page buffer can grow to. Every
+/// chunk is at least as large as all previous ones together, so this is never
+/// reached in practice.
+const MAX_BUFFER_CHUNKS: usize = 48;
+
+/// A contiguous part of the page buffer. Chunks are only ever added, so that
+/// references into them stay valid while the cache grows.
+struct BufferChunk {
+	// The start of the allocation, and the first aligned address within it.
+	alloc: NonNull<u8>,
+	buf: NonNull<u8>,
+	start: usize,
 	num_pages: usize,
-	num_filled: AtomicUsize,
+	stride: usize,
+	locks: Box<[RawRwLock]>,
+	/// Registered chunks are pinned by the IO backend, so their memory must
+	/// not be released.
+	registered: AtomicBool,
 }
 
-impl PageBuffer {
-	fn new(num_pages: usize) -> Self {
-		let buf_size = num_pages * BUFFERED_PAGE_SIZE;
-		let buf = if buf_size != 0 {
-			// Safety: buf_size is guaranteed not to be zero, so the layout is not
-			// zero-sized.
-			unsafe { alloc_zeroed(Layout::from_size_align(buf_size, 1).unwrap()) }
-		} else {
-			ptr::null_mut()
-		};
+impl BufferChunk {
+	// Allocating with the alignment directly would make the allocator zero the
+	// whole buffer eagerly, so it is over-allocated and aligned manually.
+	fn layout(num_pages: usize, stride: usize) -> Layout {
+		Layout::from_size_align(num_pages * stride + DIRECT_IO_ALIGNMENT, 1).unwrap()
+	}
+
+	fn new(start: usize, num_pages: usize, stride: usize) -> Self {
+		// Safety: the layout is guaranteed not to be zero-sized.
+		let alloc = NonNull::new(unsafe { alloc_zeroed(Self::layout(num_pages, stride)) })
+			.expect("Failed to allocate the page buffer!");
+		// Safety: the allocation has enough room to align the start of the
+		// buffer.
+		let buf = unsafe { alloc.add(alloc.align_offset(DIRECT_IO_ALIGNMENT)) };
 		Self {
-			buf: NonNull::new(buf),
+			alloc,
+			buf,
+			start,
 			num_pages,
+			stride,
+			locks: std::iter::repeat_with(|| RawRwLock::INIT)
+				.take(num_pages)
+				.collect(),
+			registered: AtomicBool::new(false),
+		}
+	}
+}
+
+impl Drop for BufferChunk {
+	fn drop(&mut self) {
+		// Safety:
+		// - `self.alloc` is guaranteed not to be null
+		// - The chunk is never reallocated, so the layout stays the same
+		unsafe {
+			dealloc(
+				self.alloc.as_ptr(),
+				Self::layout(self.num_pages, self.stride),
+			)
+		}
+	}
+}
+
+struct PageBuffer {
+	chunks: Box<[OnceLock<BufferChunk>]>,
+	capacity: AtomicUsize,
+	buffered_page_size: usize,
+	stride: usize,
+	num_filled: AtomicUsize,
+
+	/// Slots that were given up when the cache shrank. Their memory is
+	/// returned to the OS, and they are handed out again before new ones.
+	released: Mutex<Vec<usize>>,
+}
+
+impl PageBuffer {
+	/// The number of bytes of the buffer that each page takes up. Every page
+	/// starts at an aligned offset, so that it can be used for direct IO.
+	fn stride(page_size: PageSize) -> usize {
+		page_size.get().next_multiple_of(DIRECT_IO_ALIGNMENT)
+	}
+
+	fn new(num_pages: usize, page_size: PageSize) -> Self {
+		let buf = Self {
+			chunks: std::iter::repeat_with(OnceLock::new)
+				.take(MAX_BUFFER_CHUNKS)
+				.collect(),
+			capacity: AtomicUsize::new(0),
+			buffered_page_size: page_size.get(),
+			stride: Self::stride(page_size),
 			num_filled: AtomicUsize::new(0),
+			released: Mutex::new(Vec::new()),
+		};
+		buf.grow(num_pages);
+		buf
+	}
+
+	/// Makes room for at least `num_pages` pages. Growing is not thread safe,
+	/// so the caller has to serialize it.
+	fn grow(&self, num_pages: usize) {
+		let capacity = self.capacity.load(Ordering::Acquire);
+		if num_pages <= capacity {
+			return;
+		}
+		let chunk_num = self
+			.chunks
+			.iter()
+			.position(|chunk| chunk.get().is_none())
+			.expect("The page buffer has too many chunks!");
+		// Growing at least geometrically keeps the number of chunks small.
+		// Memory that is never used isn't touched, so the OS doesn't back it.
+		let chunk_pages = usize::max(num_pages - capacity, capacity);
+		let chunk = BufferChunk::new(capacity, chunk_pages, self.stride);
+		if self.chunks[chunk_num].set(chunk).is_err() {
+			unreachable!("The page buffer was grown concurrently!");
 		}
+		self.capacity
+			.store(capacity + chunk_pages, Ordering::Release);
 	}
 
 	fn push_page(&self) -> Option<usize> {
-		let num_filled = self.num_filled.load(Ordering::Acquire);
-		if num_filled == self.num_pages {
-			return None;
+		if let Some(index) = self.released.lock().pop() {
+			return Some(index);
 		}
-		self.num_filled.store(num_filled + 1, Ordering::Release);
-		Some(num_filled)
+		let capacity = self.capacity.load(Ordering::Acquire);
+		self.num_filled
+			.fetch_update(Ordering::AcqRel, Ordering::Acquire, |num_filled| {
+				(num_filled < capacity).then_some(num_filled + 1)
+			})
+			.ok()
 	}
 
-	fn page_ptr(&self, index: usize) -> Option<NonNull<u8>> {
-		if index >= self.num_pages {
-			return None;
+	/// Registers the chunks that aren't registered yet with the storage.
+	fn register_chunks<PS: PhysicalStorageApi>(&self, physical_storage: &PS) {
+		for chunk in self.chunks.iter().map_while(OnceLock::get) {
+			if chunk.registered.load(Ordering::Acquire) {
+				continue;
+			}
+			// Safety: the memory of registered chunks is never released, and
+			// the cache unregisters them before the buffer can be dropped.
+			let registered = unsafe {
+				physical_storage.register_buffer(chunk.buf, chunk.num_pages * chunk.stride)
+			};
+			chunk.registered.store(registered, Ordering::Release);
+		}
+	}
+
+	fn unregister_chunks<PS: PhysicalStorageApi>(&self, physical_storage: &PS) {
+		for chunk in self.chunks.iter().map_while(OnceLock::get) {
+			if chunk.registered.swap(false, Ordering::AcqRel) {
+				physical_storage.unregister_buffer(chunk.buf, chunk.num_pages * chunk.stride);
+			}
+		}
+	}
+
+	/// Gives up a slot that doesn't hold a page anymore.
+	fn release(&self, index: usize) {
+		let chunk = self
+			.chunk(index)
+			.expect("Tried to release a slot out of bounds!");
+		if chunk.registered.load(Ordering::Acquire) {
+			self.released.lock().push(index);
+			return;
+		}
+		let page = self
+			.page_ptr(index)
+			.expect("Tried to release a slot out of bounds!");
+		// Only the memory pages that lie within the slot can be released,
+		// because the others are shared with neighbouring slots.
+		// Safety: sysconf has no preconditions.
+		let os_page_size = usize::try_from(unsafe { libc::sysconf(libc::_SC_PAGESIZE) })
+			.expect("Failed to get the memory page size!");
+		let start = page.as_ptr().align_offset(os_page_size);
+		let len = (self.stride.saturating_sub(start) / os_page_size) * os_page_size;
+		if len == 0 {
+			self.released.lock().push(index);
+			return;
+		}
+		// Safety: The released memory lies within the slot, and its contents
+		// are never read again before a new page is stored in it. Afterwards,
+		// it reads as zeroes.
+		let result =
+			unsafe { libc::madvise(page.as_ptr().add(start).cast(), len, libc::MADV_DONTNEED) };
+		if result != 0 {
+			// The memory stays allocated, which is still correct.
+			warn!(
+				"Failed to release page buffer memory: {}",
+				std::io::Error::last_os_error()
+			);
 		}
+		self.released.lock().push(index);
+	}
+
+	fn chunk(&self, index: usize) -> Option<&BufferChunk> {
+		self.chunks
+			.iter()
+			.map_while(OnceLock::get)
+			.find(|chunk| index < chunk.start + chunk.num_pages)
+	}
+
+	fn lock(&self, index: usize) -> &RawRwLock {
+		let chunk = self
+			.chunk(index)
+			.expect("Tried to index page buffer out of bounds!");
+		&chunk.locks[index - chunk.start]
+	}
+
+	fn page_ptr(&self, index: usize) -> Option<NonNull<u8>> {
+		let chunk = self.chunk(index)?;
 		// Safety: the resulting pointer is guaranteed to be in the allocated buffer.
-		Some(unsafe { self.buf?.add(index * BUFFERED_PAGE_SIZE) })
+		Some(unsafe { chunk.buf.add((index - chunk.start) * self.stride) })
 	}
 
 	/// # Safety:
@@ -141,17 +512,18 @@ impl PageBuffer {
 	unsafe fn get_page(&self, index: usize) -> Option<&[u8]> {
 		Some(std::slice::from_raw_parts(
 			self.page_ptr(index)?.as_ptr(),
-			BUFFERED_PAGE_SIZE,
+			self.buffered_page_size,
 		))
 	}
 
 	/// # Safety:
 	/// The caller must ensure that no shared reference, and no other mutable
 	/// references to the same page exist.
+	#[allow(clippy::mut_from_ref)]
 	unsafe fn get_page_mut(&self, index: usize) -> Option<&mut [u8]> {
 		Some(std::slice::from_raw_parts_mut(
 			self.page_ptr(index)?.as_ptr(),
-			BUFFERED_PAGE_SIZE,
+			self.buffered_page_size,
 		))
 	}
 }
@@ -164,640 +536,3163 @@ unsafe impl Send for PageBuffer {}
 // `get_page` and `get_page_mut`
 unsafe impl Sync for PageBuffer {}
 
-impl Drop for PageBuffer {
-	fn drop(&mut self) {
-		if let Some(buf) = self.buf {
-			let buf_size = self.num_pages * BUFFERED_PAGE_SIZE;
-			// Safety:
-			// - `self.buf` is guaranteed not to be null
-			// - The buffer is never reallocated, so the layout stays the same
-			unsafe {
-				dealloc(
-					buf.as_ptr(),
-					Layout::from_size_align(buf_size, mem::align_of::<BufferedPageHeader>())
-						.unwrap(),
-				)
-			}
-		}
-	}
-}
-
-#[derive(Clone)]
-pub(crate) struct PageReadGuard<'a> {
-	page: &'a [u8],
-	lock: &'a RawRwLock,
-	_marker: PhantomData<RwLockReadGuard<'a, [u8]>>,
-}
-
-#[cfg_attr(test, automock)]
-pub(crate) trait PageReadGuardApi {
-	fn header(&self) -> &BufferedPageHeader;
-	fn body(&self) -> &[u8];
-	fn read(&self, offset: usize, buf: &mut [u8]);
-}
-
-impl<'a> PageReadGuardApi for PageReadGuard<'a> {
-	fn header(&self) -> &BufferedPageHeader {
-		BufferedPageHeader::ref_from_bytes(&self.page[0..HEADER_SIZE]).unwrap()
-	}
-
-	fn body(&self) -> &[u8] {
-		&self.page[HEADER_SIZE..]
-	}
-
-	fn read(&self, offset: usize, buf: &mut [u8]) {
-		buf.copy_from_slice(&self.body()[offset..offset + buf.len()]);
-	}
+/// How a page is going to be accessed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AccessHint {
+	#[default]
+	Normal,
+
+	/// The page is read by a bulk scan, and probably not needed again soon.
+	/// Pages that are brought into the cache by scans wait in a small
+	/// probationary segment, and are only handed to the replacer once they
+	/// are accessed normally.
+	Scan,
 }
 
-impl<'a> Drop for PageReadGuard<'a> {
-	fn drop(&mut self) {
-		// Safety: the existence of this object guarantees the lock is owned by the
-		// current context
-		unsafe { self.lock.unlock_shared() };
-	}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Residency {
+	/// The page is managed by the shard's replacer.
+	Replaced,
+	/// The page was brought in by a scan, and is evicted first.
+	Probation,
+	/// The page was pinned this many times, and is never evicted.
+	Pinned(usize),
+	/// The page is being written back, so that a shrink can evict it. Its
+	/// slot stays locked exclusively until then.
+	Evicting,
 }
 
-pub(crate) struct PageWriteGuard<'a> {
+#[derive(Debug, Clone, Copy)]
+struct Slot {
 	index: usize,
-	page: &'a mut [u8],
-	lock: &'a RawRwLock,
-	_marker: PhantomData<RwLockReadGuard<'a, [u8]>>,
+	residency: Residency,
 }
 
-#[cfg_attr(test, automock)]
-pub(crate) trait PageWriteGuardApi {
-	fn header(&self) -> &BufferedPageHeader;
-	fn body(&self) -> &[u8];
-	fn body_mut(&mut self) -> &mut [u8];
-	fn header_mut(&mut self) -> &mut BufferedPageHeader;
-	fn read(&self, offset: usize, buf: &mut [u8]);
-	fn write(&mut self, offset: usize, buf: &[u8], wal_index: WalIndex);
+/// The pages of a shard, and which of them are outside of the replacer.
+#[derive(Debug, Default)]
+struct ShardPages {
+	slots: HashMap<PageAddress, Slot>,
+	// Oldest first
+	probation: IndexedList<PageAddress, ()>,
+	num_pinned: usize,
+	num_evicting: usize,
+	size: usize,
+	max_probation: usize,
+	max_pinned: usize,
 }
 
-impl<'a> PageWriteGuardApi for PageWriteGuard<'a> {
-	fn header(&self) -> &BufferedPageHeader {
-		BufferedPageHeader::ref_from_bytes(&self.page[0..HEADER_SIZE]).unwrap()
-	}
-
-	fn header_mut(&mut self) -> &mut BufferedPageHeader {
-		BufferedPageHeader::mut_from_bytes(&mut self.page[0..HEADER_SIZE]).unwrap()
-	}
-
-	fn body(&self) -> &[u8] {
-		&self.page[HEADER_SIZE..]
+impl ShardPages {
+	fn new(size: usize, max_scan_pages: f32) -> Self {
+		let mut pages = Self::default();
+		pages.set_size(size, max_scan_pages);
+		pages
 	}
 
-	fn body_mut(&mut self) -> &mut [u8] {
-		&mut self.page[HEADER_SIZE..]
+	fn set_size(&mut self, size: usize, max_scan_pages: f32) {
+		#[allow(clippy::cast_possible_truncation)]
+		let max_probation = (size as f32 * max_scan_pages) as usize;
+		self.size = size;
+		self.max_probation = max_probation;
+		self.max_pinned = size / 2;
 	}
 
-	fn read(&self, offset: usize, buf: &mut [u8]) {
-		buf.copy_from_slice(&self.body()[offset..offset + buf.len()]);
+	fn replacer_size(&self) -> usize {
+		self.size - self.num_pinned - self.num_evicting - self.probation.len()
 	}
 
-	fn write(&mut self, offset: usize, buf: &[u8], wal_index: WalIndex) {
-		let header = self.header_mut();
-		header.set_wal_index(wal_index);
-		header.set_dirty(true);
-		self.body_mut()[offset..offset + buf.len()].copy_from_slice(buf);
+	fn get(&self, page_address: PageAddress) -> Option<usize> {
+		self.slots.get(&page_address).map(|slot| slot.index)
 	}
-}
 
-impl<'a> Drop for PageWriteGuard<'a> {
-	fn drop(&mut self) {
-		// Safety: the existence of this object guarantees the lock is owned by the
-		// current context
-		unsafe { self.lock.unlock_exclusive() };
+	fn remove_from_probation(&mut self, page_address: PageAddress) {
+		self.probation
+			.remove(&page_address)
+			.expect("Probation page is missing from the probationary segment!");
 	}
 }
 
-pub(crate) struct PageCache<PS: PhysicalStorageApi = PhysicalStorage> {
-	buf: Arc<PageBuffer>,
-	physical_storage: Arc<PS>,
-	thread_pool: Arc<ThreadPool>,
-	indices: Arc<RwLock<HashMap<PageAddress, usize>>>,
+/// One partition of the page table. Every shard owns a share of the buffer's
+/// slots, and evicts only its own pages, so that loads and stores of pages in
+/// different shards don't contend.
+///
+/// Pinned and probation pages take their slots away from the replacer, so
+/// the replacer's size is always what's left of the shard's size.
+struct Shard {
+	pages: RwLock<ShardPages>,
 	replacer: RwLock<CacheReplacer<PageAddress>>,
-	scrap: Mutex<Vec<usize>>,
-	has_scrap: AtomicBool,
-	dirty_list: Arc<Mutex<Vec<PageAddress>>>,
-	locks: Arc<Box<[RawRwLock]>>,
-	max_num_dirty: usize,
-	flush_timer_handle: TimerHandle,
-}
-assert_impl_all!(PageCache: Send, Sync);
 
-// Safety: `buf`'s internal pointer is never leaked in any form.
-unsafe impl<PS: PhysicalStorageApi + Send + Sync> Send for PageCache<PS> {}
-
-// Safety: `buf` is only accessed through the `load` and `store` methods,
-// which guarantee the safety of the references by acquiring the corresponding
-// locks.
-unsafe impl<PS: PhysicalStorageApi + Send + Sync> Sync for PageCache<PS> {}
+	/// Slots of scrapped pages, which are reused before anything is evicted.
+	scrap: Mutex<Vec<usize>>,
 
-struct DirtyPage<'a> {
-	page_address: PageAddress,
-	index: usize,
-	guard: PageReadGuard<'a>,
+	/// The counters of accesses to the shard's pages, which are kept per
+	/// shard, so that they don't become a point of contention.
+	counters: CacheCounters,
 }
 
-impl<PS: PhysicalStorageApi + Send + Sync + 'static> PageCache<PS> {
-	pub fn new(
-		config: &PageCacheConfig,
-		physical_storage: Arc<PS>,
-		thread_pool: Arc<ThreadPool>,
-	) -> Self {
-		let num_pages = config.page_cache_size / BUFFERED_PAGE_SIZE;
-		let buf = Arc::new(PageBuffer::new(num_pages));
-		let replacer = CacheReplacer::new(num_pages);
-		let indices = Arc::new(RwLock::new(HashMap::new()));
-		let dirty_list = Arc::new(Mutex::new(Vec::new()));
-		let locks = Arc::new(
-			std::iter::repeat_with(|| RawRwLock::INIT)
-				.take(num_pages)
-				.collect(),
-		);
-
-		let (flush_timer, flush_timer_handle) = Timer::new(config.flush_period);
-		thread_pool.spawn_ok(Self::periodic_flush_task(
-			flush_timer,
-			Arc::clone(&physical_storage),
-			Arc::clone(&dirty_list),
-			Arc::clone(&indices),
-			Arc::clone(&locks),
-			Arc::clone(&buf),
-		));
-
-		Self {
-			buf,
-			physical_storage,
-			thread_pool,
-			replacer: RwLock::new(replacer),
-			indices,
-			scrap: Mutex::new(Vec::new()),
-			has_scrap: AtomicBool::new(false),
-			dirty_list,
-			locks,
-			#[allow(clippy::cast_possible_truncation)]
-			max_num_dirty: usize::max((num_pages as f32 * config.max_dirty_pages) as usize, 1),
-			flush_timer_handle,
-		}
-	}
-
-	fn evict_for(&self, page_address: PageAddress) -> Option<PageAddress> {
-		let mut replacer = self.replacer.write();
-		let mut maybe_evict = replacer.evict_replace(page_address);
-		mem::drop(replacer);
-
-		loop {
-			if let Some(evicted) = maybe_evict {
-				let indices = self.indices.read();
-				let index = *indices
-					.get(&evicted)
+impl Shard {
+	/// Skips eviction candidates that are locked, or that are the page being
+	/// inserted, by reinserting them and evicting the next one. The caller
+	/// has to hold the pages for the whole store, so that the replacer and the
+	/// pages agree on which pages are in the cache.
+	///
+	/// Once every page was skipped, this gives up and returns the candidate
+	/// it stopped at, which is out of the replacer, so that the caller can
+	/// undo the insertion and release the pages while it waits.
+	fn skip_locked(
+		&self,
+		replacer: &mut CacheReplacer<PageAddress>,
+		pages: &ShardPages,
+		mut maybe_evict: Option<PageAddress>,
+		page_address: PageAddress,
+		buf: &PageBuffer,
+	) -> Result<Option<PageAddress>, PageAddress> {
+		let mut num_skipped = 0;
+		while let Some(evicted) = maybe_evict {
+			let skip = evicted == page_address || {
+				let index = pages
+					.get(evicted)
 					.expect("Tried to evict a page that is not in the cache!");
-
-				// If we are trying to evict the same page that we're inserting, or if the page
-				// we're trying to evict is currently locked, we reinsert it and try the next
-				// candidate.
-				//
-				// Note that this ends up in an infinite loop if all pages in the cache are
-				// locked over an extended period, but that should rarely happen.
-				if evicted == page_address || self.locks[index].is_locked() {
-					let mut replacer = self.replacer.write();
-					maybe_evict = replacer.evict_replace(evicted);
-					continue;
+				let is_locked = buf.lock(index).is_locked();
+				if is_locked {
+					self.counters
+						.blocked_evictions
+						.fetch_add(1, Ordering::Relaxed);
 				}
+				is_locked
+			};
+			if !skip {
+				break;
 			}
-			break;
-		}
-		maybe_evict
-	}
-
-	fn get_store_index(&self, page_address: PageAddress) -> usize {
-		let indices = self.indices.read();
-		if let Some(stored_index) = indices.get(&page_address).copied() {
-			return stored_index;
-		}
-		mem::drop(indices);
-
-		if self.has_scrap.load(Ordering::Relaxed) {
-			let mut scrap = self.scrap.lock();
-			if let Some(scrap_index) = scrap.pop() {
-				return scrap_index;
+			num_skipped += 1;
+			if num_skipped > replacer.len() {
+				return Err(evicted);
 			}
+			maybe_evict = replacer.evict_replace(evicted);
 		}
+		if maybe_evict.is_some() {
+			self.counters.evictions.fetch_add(1, Ordering::Relaxed);
+		}
+		Ok(maybe_evict)
+	}
 
-		let maybe_evict = self.evict_for(page_address);
-		let mut indices = self.indices.write();
-		if let Some(evict) = maybe_evict {
-			let index = indices
-				.remove(&evict)
-				.expect("Tried to evict a page that is not in the cache!");
-			indices.insert(page_address, index);
-			index
+	/// Takes the slot of the evicted page, or a free one, if nothing was
+	/// evicted.
+	fn free_slot(
+		&self,
+		pages: &mut ShardPages,
+		evicted: Option<PageAddress>,
+		buf: &PageBuffer,
+	) -> usize {
+		// Scrapped pages are removed from the replacer, so it only evicts once
+		// the shard has no scrap left.
+		if let Some(evicted) = evicted {
+			pages
+				.slots
+				.remove(&evicted)
+				.expect("Tried to evict a page that is not in the cache!")
+				.index
+		} else if let Some(scrap_index) = self.scrap.lock().pop() {
+			self.counters
+				.reused_scrap_pages
+				.fetch_add(1, Ordering::Relaxed);
+			scrap_index
 		} else {
-			let index = self
-				.buf
-				.push_page()
-				.expect("Failed to evict a page when the buffer was full!");
-			indices.insert(page_address, index);
-			index
+			buf.push_page()
+				.expect("Failed to evict a page when the buffer was full!")
 		}
 	}
 
-	fn get_load_index(&self, page_address: PageAddress) -> Option<usize> {
-		let indices = self.indices.read();
-		let index = indices.get(&page_address).copied()?;
-		mem::drop(indices);
+	/// Inserts a page that isn't cached yet. Returns false if every page
+	/// that could be evicted for it is locked.
+	fn insert(
+		&self,
+		pages: &mut ShardPages,
+		page_address: PageAddress,
+		hint: AccessHint,
+		buf: &PageBuffer,
+	) -> bool {
+		let inserted = hint == AccessHint::Scan && self.insert_probation(pages, page_address, buf);
+		inserted || self.insert_replaced(pages, page_address, buf)
+	}
 
-		let replacer = self.replacer.read();
-		let access_successful = replacer.access(&page_address);
-		debug_assert!(access_successful);
+	/// Inserts a page into the replacer. Returns false if every page that
+	/// could be evicted for it is locked.
+	fn insert_replaced(
+		&self,
+		pages: &mut ShardPages,
+		page_address: PageAddress,
+		buf: &PageBuffer,
+	) -> bool {
+		let mut replacer = self.replacer.write();
+		let maybe_evict = replacer.evict_replace(page_address);
+		let result = self.skip_locked(&mut replacer, pages, maybe_evict, page_address, buf);
+		let evicted = match result {
+			Ok(evicted) => evicted,
+			Err(blocked) => {
+				if blocked != page_address {
+					replacer.remove(&page_address);
+					let evicted = replacer.evict_replace(blocked);
+					debug_assert!(evicted.is_none());
+				}
+				return false;
+			}
+		};
 		mem::drop(replacer);
 
-		Some(index)
+		let index = self.free_slot(pages, evicted, buf);
+		pages.slots.insert(
+			page_address,
+			Slot {
+				index,
+				residency: Residency::Replaced,
+			},
+		);
+		true
+	}
+
+	/// Inserts a page into the probationary segment. Until the segment is
+	/// full, it grows at the expense of the replacer. After that, it replaces
+	/// its own oldest page. Returns false if that isn't possible, because all
+	/// pages that could be evicted for it are locked.
+	fn insert_probation(
+		&self,
+		pages: &mut ShardPages,
+		page_address: PageAddress,
+		buf: &PageBuffer,
+	) -> bool {
+		let index = if pages.probation.len() < pages.max_probation && pages.replacer_size() > 0 {
+			let mut replacer = self.replacer.write();
+			let replacer_size = pages.replacer_size() - 1;
+			replacer.resize(replacer_size);
+			let maybe_evict = if replacer.len() > replacer_size {
+				replacer.evict()
+			} else {
+				None
+			};
+			let result = self.skip_locked(&mut replacer, pages, maybe_evict, page_address, buf);
+			let evicted = match result {
+				Ok(evicted) => evicted,
+				Err(blocked) => {
+					replacer.resize(replacer_size + 1);
+					let evicted = replacer.evict_replace(blocked);
+					debug_assert!(evicted.is_none());
+					return false;
+				}
+			};
+			mem::drop(replacer);
+			self.free_slot(pages, evicted, buf)
+		} else {
+			let Some(evicted) = pages
+				.probation
+				.iter()
+				.map(|(probation_page, _)| *probation_page)
+				.find(|probation_page| {
+					let index = pages.get(*probation_page).unwrap();
+					!buf.lock(index).is_locked()
+				})
+			else {
+				return false;
+			};
+			pages.remove_from_probation(evicted);
+			self.counters.evictions.fetch_add(1, Ordering::Relaxed);
+			self.free_slot(pages, Some(evicted), buf)
+		};
+		pages.probation.push_back(page_address, ());
+		pages.slots.insert(
+			page_address,
+			Slot {
+				index,
+				residency: Residency::Probation,
+			},
+		);
+		true
 	}
 
-	fn load_direct<'a>(
-		locks: &'a [RawRwLock],
-		buf: &'a PageBuffer,
-		index: usize,
-	) -> PageReadGuard<'a> {
-		let lock = &locks[index];
-		lock.lock_shared();
-		// Safety: The safety of the reference is guaranteed by acquiring the shared
-		// lock.
-		let page =
-			unsafe { buf.get_page(index) }.expect("Tried to index page buffer out of bounds!");
+	/// Hands a page from the probationary segment or the pinned pages back to
+	/// the replacer. The replacer grows by the slot the page takes up, so
+	/// nothing is evicted.
+	fn return_to_replacer(&self, pages: &mut ShardPages, page_address: PageAddress) {
+		let mut replacer = self.replacer.write();
+		replacer.resize(pages.replacer_size());
+		let evicted = replacer.evict_replace(page_address);
+		debug_assert!(evicted.is_none());
+		pages.slots.get_mut(&page_address).unwrap().residency = Residency::Replaced;
+	}
 
-		PageReadGuard {
-			lock,
-			page,
-			_marker: PhantomData,
+	/// Moves a probation page to the replacer, once it is accessed normally.
+	fn promote(&self, page_address: PageAddress) {
+		let mut pages = self.pages.write();
+		// The page may have been evicted or promoted in the meantime.
+		let is_probation = pages
+			.slots
+			.get(&page_address)
+			.is_some_and(|slot| slot.residency == Residency::Probation);
+		if is_probation {
+			pages.remove_from_probation(page_address);
+			self.return_to_replacer(&mut pages, page_address);
 		}
 	}
 
-	fn load_mut_direct<'a>(
-		locks: &'a [RawRwLock],
-		buf: &'a PageBuffer,
-		index: usize,
-	) -> PageWriteGuard<'a> {
-		let lock = &locks[index];
-		lock.lock_exclusive();
-		// Safety: The safety of the reference is guaranteed by acquiring the exclusive
-		// lock.
-		let page =
-			unsafe { buf.get_page_mut(index) }.expect("Triet to index page buffer out of bounds!");
-
-		PageWriteGuard {
-			index,
-			lock,
-			page,
-			_marker: PhantomData,
+	fn pin(&self, page_address: PageAddress) -> bool {
+		let mut pages = self.pages.write();
+		let Some(slot) = pages.slots.get(&page_address).copied() else {
+			return false;
+		};
+		let num_pins = match slot.residency {
+			Residency::Pinned(num_pins) => num_pins + 1,
+			Residency::Evicting => return false,
+			_ if pages.num_pinned >= pages.max_pinned => return false,
+			Residency::Replaced => {
+				let mut replacer = self.replacer.write();
+				replacer.remove(&page_address);
+				pages.num_pinned += 1;
+				replacer.resize(pages.replacer_size());
+				1
+			}
+			// The replacer's size stays the same, because the slot just moves
+			// from the probationary segment to the pinned pages.
+			Residency::Probation => {
+				pages.remove_from_probation(page_address);
+				pages.num_pinned += 1;
+				1
+			}
+		};
+		pages.slots.get_mut(&page_address).unwrap().residency = Residency::Pinned(num_pins);
+		true
+	}
+
+	fn unpin(&self, page_address: PageAddress) -> bool {
+		let mut pages = self.pages.write();
+		let Some(slot) = pages.slots.get_mut(&page_address) else {
+			return false;
+		};
+		match slot.residency {
+			Residency::Pinned(1) => {
+				pages.num_pinned -= 1;
+				self.return_to_replacer(&mut pages, page_address);
+			}
+			Residency::Pinned(num_pins) => slot.residency = Residency::Pinned(num_pins - 1),
+			Residency::Replaced | Residency::Probation | Residency::Evicting => return false,
 		}
+		true
 	}
 
-	fn flush(
+	/// Inserts a page into the given list of the replacer, without evicting
+	/// anything for it. Returns the page's slot, which is locked exclusively
+	/// as in [`Shard::lock_inserted`], or `None` if the page is cached already
+	/// or the replacer is full.
+	fn restore(
+		&self,
+		page_address: PageAddress,
+		list: CacheList,
+		buf: &PageBuffer,
+	) -> Option<usize> {
+		let mut pages = self.pages.write();
+		if pages.slots.contains_key(&page_address) {
+			return None;
+		}
+		let mut replacer = self.replacer.write();
+		if !replacer.restore(page_address, list) {
+			return None;
+		}
+		let maybe_index = self.scrap.lock().pop().map_or_else(
+			|| buf.push_page(),
+			|scrap_index| {
+				self.counters
+					.reused_scrap_pages
+					.fetch_add(1, Ordering::Relaxed);
+				Some(scrap_index)
+			},
+		);
+		// A shrink that couldn't evict locked pages may have left other
+		// shards larger than their share of the buffer.
+		let Some(index) = maybe_index else {
+			replacer.remove(&page_address);
+			return None;
+		};
+		mem::drop(replacer);
+		pages.slots.insert(
+			page_address,
+			Slot {
+				index,
+				residency: Residency::Replaced,
+			},
+		);
+		self.lock_inserted(&mut pages, page_address, buf)
+	}
+
+	/// Locks the slot of a page that was just inserted exclusively, before
+	/// anyone else can look it up, so that nobody sees the page until it is
+	/// filled. Someone who looked up the slot for the page that was evicted
+	/// from it may still hold it, in which case the page is removed again.
+	fn lock_inserted(
+		&self,
+		pages: &mut ShardPages,
+		page_address: PageAddress,
+		buf: &PageBuffer,
+	) -> Option<usize> {
+		let index = pages.get(page_address).unwrap();
+		// Waiting for the lock could deadlock with someone who holds it and
+		// waits for the pages.
+		if buf.lock(index).try_lock_exclusive() {
+			return Some(index);
+		}
+		self.remove(pages, page_address);
+		None
+	}
+
+	/// Removes a page from the shard, and keeps its slot for reuse. Returns
+	/// false if the page wasn't cached.
+	fn remove(&self, pages: &mut ShardPages, page_address: PageAddress) -> bool {
+		let Some(slot) = pages.slots.remove(&page_address) else {
+			return false;
+		};
+		// The pages stay locked, so that the page can't be picked for
+		// eviction in the meantime.
+		let mut replacer = self.replacer.write();
+		match slot.residency {
+			Residency::Replaced => {
+				replacer.remove(&page_address);
+			}
+			Residency::Probation => pages.remove_from_probation(page_address),
+			Residency::Pinned(_) => pages.num_pinned -= 1,
+			Residency::Evicting => pages.num_evicting -= 1,
+		}
+		replacer.resize(pages.replacer_size());
+		self.scrap.lock().push(slot.index);
+		true
+	}
+
+	/// Changes the number of slots the shard owns. When shrinking, scrapped
+	/// slots are given up first, and then pages are evicted. Evicted pages
+	/// are written back if they are dirty, and stay cached if that fails.
+	///
+	/// Pinned and locked pages are never evicted, so the shard may stay
+	/// larger than requested.
+	fn resize<PS: PhysicalStorageApi>(
+		&self,
+		size: usize,
+		max_scan_pages: f32,
 		physical_storage: &PS,
-		dirty_list: &Mutex<Vec<PageAddress>>,
-		indices: &RwLock<HashMap<PageAddress, usize>>,
-		locks: &[RawRwLock],
 		buf: &PageBuffer,
 	) -> Result<(), StorageError> {
-		let mut dirty_list_guard = dirty_list.lock();
-		let dirty_list_copy = dirty_list_guard.clone();
-		dirty_list_guard.clear();
-		mem::drop(dirty_list_guard);
+		let Some(victims) = self.take_victims(size, max_scan_pages, buf) else {
+			return Ok(());
+		};
 
-		let mut error: Option<StorageError> = None;
-		let mut dirty_pages: Vec<DirtyPage> = Vec::with_capacity(dirty_list_copy.len());
+		// The victims are written back without holding the shard, so that
+		// loads and stores of its other pages can go on in the meantime.
+		let ops: Vec<Op> = victims
+			.iter()
+			.filter_map(|(page_address, index)| {
+				// Safety: the slot is locked exclusively.
+				let page = unsafe { buf.get_page(*index) }.unwrap();
+				let header = BufferedPageHeader::ref_from_bytes(&page[0..HEADER_SIZE]).unwrap();
+				header.dirty().then(|| {
+					Op::Write(WriteOp {
+						wal_index: header.wal_index(),
+						page_address: *page_address,
+						buf: &page[BODY_OFFSET..],
+					})
+				})
+			})
+			.collect();
+		let result = if ops.is_empty() {
+			Ok(())
+		} else {
+			physical_storage.batch(ops.into())
+		};
 
-		for page_address in dirty_list_copy.iter() {
-			let indices = indices.read();
-			let Some(index) = indices.get(page_address).copied() else {
-				continue;
+		let mut pages = self.pages.write();
+		let mut replacer = self.replacer.write();
+		// Victims may have been scrapped in the meantime, which already gave
+		// up their slots.
+		let victims: Vec<(PageAddress, usize)> = victims
+			.into_iter()
+			.filter(|(page_address, _)| {
+				pages
+					.slots
+					.get(page_address)
+					.is_some_and(|slot| slot.residency == Residency::Evicting)
+			})
+			.collect();
+		pages.num_evicting -= victims.len();
+		if result.is_ok() {
+			for (page_address, _) in &victims {
+				pages.slots.remove(page_address);
+			}
+			let size = pages.size - victims.len();
+			pages.set_size(size, max_scan_pages);
+			self.counters
+				.evictions
+				.fetch_add(victims.len() as u64, Ordering::Relaxed);
+		} else {
+			replacer.resize(pages.replacer_size());
+			for (page_address, _) in &victims {
+				let evicted = replacer.evict_replace(*page_address);
+				debug_assert!(evicted.is_none());
+				pages.slots.get_mut(page_address).unwrap().residency = Residency::Replaced;
+			}
+		}
+		mem::drop(replacer);
+		mem::drop(pages);
+
+		for (_, index) in victims {
+			if result.is_ok() {
+				buf.release(index);
+			}
+			// Safety: the lock was acquired in `take_victims`.
+			unsafe { buf.lock(index).unlock_exclusive() };
+		}
+		result
+	}
+
+	/// Shrinks the shard as far as that is possible without writing anything
+	/// back, and picks the pages that have to be evicted for the rest. Their
+	/// slots are locked exclusively, and they are marked as evicting, which
+	/// keeps them out of the replacer. Returns `None` if nothing needs to be
+	/// evicted.
+	fn take_victims(
+		&self,
+		size: usize,
+		max_scan_pages: f32,
+		buf: &PageBuffer,
+	) -> Option<Vec<(PageAddress, usize)>> {
+		let mut pages = self.pages.write();
+		let mut replacer = self.replacer.write();
+		if size >= pages.size {
+			pages.set_size(size, max_scan_pages);
+			replacer.resize(pages.replacer_size());
+			return None;
+		}
+
+		// Probation pages are handed to the replacer, so that there is only
+		// one place to evict from.
+		replacer.resize(pages.size - pages.num_pinned);
+		while let Some((page_address, ())) = pages.probation.pop_front() {
+			let evicted = replacer.evict_replace(page_address);
+			debug_assert!(evicted.is_none());
+			pages.slots.get_mut(&page_address).unwrap().residency = Residency::Replaced;
+		}
+
+		let size = usize::max(size, pages.num_pinned);
+		let mut scrap = self.scrap.lock();
+		while pages.slots.len() + scrap.len() > size {
+			let Some(index) = scrap.pop() else {
+				break;
 			};
-			mem::drop(indices);
+			buf.release(index);
+		}
+		mem::drop(scrap);
 
-			let guard = Self::load_direct(locks, buf, index);
-			if !guard.header().dirty() {
+		let mut replacer_size = size - pages.num_pinned;
+		replacer.resize(replacer_size);
+		let mut victims: Vec<(PageAddress, usize)> = Vec::new();
+		let mut maybe_evict = if replacer.len() > replacer_size {
+			replacer.evict()
+		} else {
+			None
+		};
+		let mut num_blocked = 0;
+		while let Some(evicted) = maybe_evict {
+			let index = pages
+				.get(evicted)
+				.expect("Tried to evict a page that is not in the cache!");
+			// Waiting for the lock could deadlock with someone who holds it
+			// and waits for the pages.
+			if buf.lock(index).try_lock_exclusive() {
+				pages.slots.get_mut(&evicted).unwrap().residency = Residency::Evicting;
+				victims.push((evicted, index));
+				maybe_evict = if replacer.len() > replacer_size {
+					replacer.evict()
+				} else {
+					None
+				};
 				continue;
 			}
-
-			dirty_pages.push(DirtyPage {
-				page_address: *page_address,
-				index,
-				guard,
-			});
+			self.counters
+				.blocked_evictions
+				.fetch_add(1, Ordering::Relaxed);
+			num_blocked += 1;
+			if num_blocked > replacer.len() {
+				replacer_size = replacer.len() + 1;
+				replacer.resize(replacer_size);
+				replacer.evict_replace(evicted);
+				break;
+			}
+			maybe_evict = replacer.evict_replace(evicted);
 		}
 
-		let ops: Vec<Op> = dirty_pages
-			.iter()
-			.map(|dp| {
-				Op::Write(WriteOp {
-					wal_index: dp.guard.header().wal_index(),
-					page_address: dp.page_address,
-					buf: dp.guard.body(),
-				})
+		// The victims keep their slots until they are written back.
+		pages.num_evicting += victims.len();
+		let size = pages.num_pinned + pages.num_evicting + replacer_size;
+		pages.set_size(size, max_scan_pages);
+		(!victims.is_empty()).then_some(victims)
+	}
+}
+
+struct PageTable {
+	shards: Box<[Shard]>,
+	// The hash maps in the shards use their own random state, so the shard
+	// doesn't correlate with the position of a page in its map.
+	hasher: RandomState,
+	max_scan_pages: f32,
+}
+
+impl PageTable {
+	fn new(num_pages: usize, num_shards: usize, config: &PageCacheConfig) -> Self {
+		let shards = (0..num_shards)
+			.map(|shard_num| {
+				let size = Self::shard_size(num_pages, num_shards, shard_num);
+				Shard {
+					pages: RwLock::new(ShardPages::new(size, config.max_scan_pages)),
+					replacer: RwLock::new(CacheReplacer::new(config.replacement_policy, size)),
+					scrap: Mutex::new(Vec::new()),
+					counters: CacheCounters::default(),
+				}
 			})
 			.collect();
+		Self {
+			shards,
+			hasher: RandomState::new(),
+			max_scan_pages: config.max_scan_pages,
+		}
+	}
 
-		if let Err(err) = physical_storage.batch(ops.into()) {
-			error = Some(err);
+	fn shard_size(num_pages: usize, num_shards: usize, shard_num: usize) -> usize {
+		// The remainder is spread over the first shards.
+		num_pages / num_shards + usize::from(shard_num < num_pages % num_shards)
+	}
+
+	fn shard(&self, page_address: PageAddress) -> &Shard {
+		#[allow(clippy::cast_possible_truncation)]
+		let shard_num = self.hasher.hash_one(page_address) as usize % self.shards.len();
+		&self.shards[shard_num]
+	}
+
+	fn get(&self, page_address: PageAddress) -> Option<usize> {
+		self.shard(page_address).pages.read().get(page_address)
+	}
+
+	/// Returns the slot of the page, and whether it was newly inserted.
+	fn insert(
+		&self,
+		page_address: PageAddress,
+		hint: AccessHint,
+		buf: &PageBuffer,
+	) -> (usize, bool) {
+		let shard = self.shard(page_address);
+		let pages = shard.pages.read();
+		if let Some(stored_index) = pages.get(page_address) {
+			return (stored_index, false);
+		}
+		mem::drop(pages);
+
+		loop {
+			let mut pages = shard.pages.write();
+			// The page may have been stored by someone else in the meantime.
+			if let Some(stored_index) = pages.get(page_address) {
+				return (stored_index, false);
+			}
+			if shard.insert(&mut pages, page_address, hint, buf) {
+				return (pages.get(page_address).unwrap(), true);
+			}
+			// Whoever holds the locked pages may need the shard to release
+			// them, so it isn't held while waiting.
+			mem::drop(pages);
+			thread::yield_now();
+		}
+	}
+
+	/// Inserts a page that isn't cached yet, and returns its slot, which is
+	/// locked exclusively as in [`Shard::lock_inserted`]. Returns `None` if the
+	/// page is cached already, or there is no slot that can be locked.
+	fn claim(
+		&self,
+		page_address: PageAddress,
+		hint: AccessHint,
+		buf: &PageBuffer,
+	) -> Option<usize> {
+		let shard = self.shard(page_address);
+		let mut pages = shard.pages.write();
+		if pages.slots.contains_key(&page_address) {
+			return None;
+		}
+		if !shard.insert(&mut pages, page_address, hint, buf) {
+			return None;
+		}
+		shard.lock_inserted(&mut pages, page_address, buf)
+	}
+
+	/// Removes a page from the cache, and keeps its slot for reuse. Returns
+	/// false if the page wasn't cached.
+	fn remove(&self, page_address: PageAddress) -> bool {
+		let shard = self.shard(page_address);
+		let mut pages = shard.pages.write();
+		shard.remove(&mut pages, page_address)
+	}
+
+	/// Removes all cached pages starting at `end` from the cache, and counts
+	/// them as scrapped.
+	fn remove_from(&self, end: PageAddress) {
+		for shard in self.shards.iter() {
+			let mut pages = shard.pages.write();
+			let removed: Vec<PageAddress> = pages
+				.slots
+				.keys()
+				.filter(|page_address| **page_address >= end)
+				.copied()
+				.collect();
+			for page_address in removed {
+				if shard.remove(&mut pages, page_address) {
+					shard
+						.counters
+						.scrapped_pages
+						.fetch_add(1, Ordering::Relaxed);
+				}
+			}
+		}
+	}
+
+	fn resize<PS: PhysicalStorageApi>(
+		&self,
+		num_pages: usize,
+		physical_storage: &PS,
+		buf: &PageBuffer,
+	) -> Result<(), StorageError> {
+		// A shard that fails to write back its pages stays larger, but the
+		// others are still resized.
+		let mut result = Ok(());
+		for (shard_num, shard) in self.shards.iter().enumerate() {
+			let shard_result = shard.resize(
+				Self::shard_size(num_pages, self.shards.len(), shard_num),
+				self.max_scan_pages,
+				physical_storage,
+				buf,
+			);
+			if result.is_ok() {
+				result = shard_result;
+			}
+		}
+		result
+	}
+
+	fn num_pages(&self) -> usize {
+		self.shards
+			.iter()
+			.map(|shard| shard.pages.read().size)
+			.sum()
+	}
+
+	/// Returns the pages of the shards' replacers with the lists they are on,
+	/// and the pinned pages, which count as frequently used. Probation pages
+	/// were only scanned, so they are left out.
+	fn working_set(&self) -> WorkingSet {
+		let mut working_set = WorkingSet::default();
+		for shard in &*self.shards {
+			let pages = shard.pages.read();
+			let replacer = shard.replacer.read();
+			let pinned = pages
+				.slots
+				.iter()
+				.filter(|(_, slot)| matches!(slot.residency, Residency::Pinned(_)))
+				.map(|(page_address, _)| (*page_address, CacheList::Frequent));
+			working_set.pages.extend(
+				pinned
+					.chain(replacer.entries())
+					.map(|(page_address, list)| WorkingSetPage { page_address, list }),
+			);
+		}
+		working_set
+	}
+
+	fn num_pinned(&self) -> usize {
+		self.shards
+			.iter()
+			.map(|shard| shard.pages.read().num_pinned)
+			.sum()
+	}
+}
+
+/// The number of consecutive misses on consecutive pages after which reads
+/// are considered to be a sequential scan.
+const SEQUENTIAL_MISSES: usize = 2;
+
+/// Detects sequential scans from the pattern of cache misses.
+#[derive(Debug, Default)]
+struct ReadAhead {
+	/// The page that continues the current run of misses.
+	expected: Option<PageAddress>,
+	num_sequential: usize,
+}
+
+impl ReadAhead {
+	/// Records a miss, and returns the pages that should be read ahead, if
+	/// the misses form a sequential scan.
+	fn miss(&mut self, page_address: PageAddress, window: usize) -> Option<Range<PageAddress>> {
+		if self.expected == Some(page_address) {
+			self.num_sequential += 1;
+		} else {
+			self.num_sequential = 1;
+		}
+		let start = page_address.next();
+		self.expected = Some(start);
+		if window == 0 || self.num_sequential < SEQUENTIAL_MISSES {
+			return None;
+		}
+
+		let end = (0..window).fold(start, |page_address, _| page_address.next());
+		// The scan continues with a miss right after the pages read ahead.
+		self.expected = Some(end);
+		Some(start..end)
+	}
+}
+
+/// Iterates over the addresses in the range, in order.
+fn page_range(range: Range<PageAddress>) -> impl Iterator<Item = PageAddress> {
+	std::iter::successors(Some(range.start), |page_address| Some(page_address.next()))
+		.take_while(move |page_address| *page_address < range.end)
+}
+
+#[derive(Clone)]
+pub(crate) struct PageReadGuard<'a> {
+	page: &'a [u8],
+	lock: &'a RawRwLock,
+	_marker: PhantomData<RwLockReadGuard<'a, [u8]>>,
+}
+
+#[cfg_attr(test, automock)]
+pub(crate) trait PageReadGuardApi {
+	fn header(&self) -> &BufferedPageHeader;
+	fn body(&self) -> &[u8];
+	fn read(&self, offset: usize, buf: &mut [u8]);
+}
+
+impl<'a> PageReadGuardApi for PageReadGuard<'a> {
+	fn header(&self) -> &BufferedPageHeader {
+		BufferedPageHeader::ref_from_bytes(&self.page[0..HEADER_SIZE]).unwrap()
+	}
+
+	fn body(&self) -> &[u8] {
+		&self.page[BODY_OFFSET..]
+	}
+
+	fn read(&self, offset: usize, buf: &mut [u8]) {
+		buf.copy_from_slice(&self.body()[offset..offset + buf.len()]);
+	}
+}
+
+impl<'a> Drop for PageReadGuard<'a> {
+	fn drop(&mut self) {
+		// Safety: the existence of this object guarantees the lock is owned by the
+		// current context
+		unsafe { self.lock.unlock_shared() };
+	}
+}
+
+pub(crate) struct PageWriteGuard<'a> {
+	index: usize,
+	page: &'a mut [u8],
+	lock: &'a RawRwLock,
+	_marker: PhantomData<RwLockReadGuard<'a, [u8]>>,
+}
+
+#[cfg_attr(test, automock)]
+pub(crate) trait PageWriteGuardApi {
+	fn header(&self) -> &BufferedPageHeader;
+	fn body(&self) -> &[u8];
+	fn body_mut(&mut self) -> &mut [u8];
+	fn header_mut(&mut self) -> &mut BufferedPageHeader;
+
+	/// The whole slot of the page, which the page can be read into directly,
+	/// overwriting its header. The header has to be reset afterwards.
+	fn slot_mut(&mut self) -> &mut [u8];
+	fn reset_header(&mut self, wal_index: Option<WalIndex>);
+	fn read(&self, offset: usize, buf: &mut [u8]);
+	fn write(&mut self, offset: usize, buf: &[u8], wal_index: WalIndex);
+}
+
+impl<'a> PageWriteGuardApi for PageWriteGuard<'a> {
+	fn header(&self) -> &BufferedPageHeader {
+		BufferedPageHeader::ref_from_bytes(&self.page[0..HEADER_SIZE]).unwrap()
+	}
+
+	fn header_mut(&mut self) -> &mut BufferedPageHeader {
+		BufferedPageHeader::mut_from_bytes(&mut self.page[0..HEADER_SIZE]).unwrap()
+	}
+
+	fn body(&self) -> &[u8] {
+		&self.page[BODY_OFFSET..]
+	}
+
+	fn body_mut(&mut self) -> &mut [u8] {
+		&mut self.page[BODY_OFFSET..]
+	}
+
+	fn slot_mut(&mut self) -> &mut [u8] {
+		self.page
+	}
+
+	fn reset_header(&mut self, wal_index: Option<WalIndex>) {
+		self.header_mut().reset(wal_index);
+	}
+
+	fn read(&self, offset: usize, buf: &mut [u8]) {
+		buf.copy_from_slice(&self.body()[offset..offset + buf.len()]);
+	}
+
+	fn write(&mut self, offset: usize, buf: &[u8], wal_index: WalIndex) {
+		let header = self.header_mut();
+		header.set_wal_index(wal_index);
+		header.set_dirty(true);
+		self.body_mut()[offset..offset + buf.len()].copy_from_slice(buf);
+	}
+}
+
+impl<'a> Drop for PageWriteGuard<'a> {
+	fn drop(&mut self) {
+		// Safety: the existence of this object guarantees the lock is owned by the
+		// current context
+		unsafe { self.lock.unlock_exclusive() };
+	}
+}
+
+#[derive(Debug, Default)]
+struct DirtyState {
+	pages: HashSet<PageAddress>,
+	// The number of pages that the running flush took from `pages`.
+	num_flushing: usize,
+}
+
+impl DirtyState {
+	/// The number of pages that aren't written back yet, including the ones
+	/// that are being flushed.
+	fn len(&self) -> usize {
+		self.pages.len() + self.num_flushing
+	}
+}
+
+/// The pages that were written since they were last flushed. Writers are
+/// throttled against it, so that flushing can keep up with them.
+struct DirtyList {
+	state: Mutex<DirtyState>,
+	/// Notified whenever a flush finishes.
+	flushed: Condvar,
+	/// Held for the whole flush, so that flushes don't overlap.
+	flush_lock: Mutex<()>,
+	/// Set while a background flush is spawned and not finished yet, so that
+	/// flush tasks don't pile up.
+	flush_scheduled: AtomicBool,
+	/// Set under the flush lock once the cache is closed, after which nothing
+	/// is written back anymore.
+	closed: AtomicBool,
+	max_dirty_pages: f32,
+	dirty_pages_ceiling: f32,
+	/// The number of dirty pages that starts a background flush, and the
+	/// number at which writers have to wait for it.
+	max_num_dirty: AtomicUsize,
+	max_num_dirty_hard: AtomicUsize,
+}
+
+impl DirtyList {
+	fn new(num_pages: usize, config: &PageCacheConfig) -> Self {
+		let dirty_list = Self {
+			state: Mutex::new(DirtyState::default()),
+			flushed: Condvar::new(),
+			flush_lock: Mutex::new(()),
+			flush_scheduled: AtomicBool::new(false),
+			closed: AtomicBool::new(false),
+			max_dirty_pages: config.max_dirty_pages,
+			dirty_pages_ceiling: config.dirty_pages_ceiling,
+			max_num_dirty: AtomicUsize::new(0),
+			max_num_dirty_hard: AtomicUsize::new(0),
+		};
+		dirty_list.set_num_pages(num_pages);
+		dirty_list
+	}
+
+	fn set_num_pages(&self, num_pages: usize) {
+		#[allow(clippy::cast_possible_truncation)]
+		let max_num_dirty = usize::max((num_pages as f32 * self.max_dirty_pages) as usize, 1);
+		#[allow(clippy::cast_possible_truncation)]
+		let max_num_dirty_hard = usize::max(
+			(num_pages as f32 * self.dirty_pages_ceiling) as usize,
+			max_num_dirty,
+		);
+		self.max_num_dirty.store(max_num_dirty, Ordering::Relaxed);
+		self.max_num_dirty_hard
+			.store(max_num_dirty_hard, Ordering::Relaxed);
+	}
+
+	fn needs_flush(&self) -> bool {
+		self.state.lock().len() >= self.max_num_dirty.load(Ordering::Relaxed)
+	}
+
+	/// Takes the pages to flush. They still count as dirty until the flush
+	/// is finished.
+	fn take(&self) -> HashSet<PageAddress> {
+		let mut state = self.state.lock();
+		let pages = mem::take(&mut state.pages);
+		state.num_flushing = pages.len();
+		pages
+	}
+
+	/// Finishes a flush, and puts back the pages that weren't written.
+	fn finish_flush(&self, unflushed: HashSet<PageAddress>) {
+		let mut state = self.state.lock();
+		state.num_flushing = 0;
+		state.pages.extend(unflushed);
+		mem::drop(state);
+		self.flushed.notify_all();
+	}
+}
+
+pub(crate) struct PageCache<PS: PhysicalStorageApi = PhysicalBackend> {
+	buf: Arc<PageBuffer>,
+	physical_storage: Arc<PS>,
+	runtime: Runtime,
+	page_table: Arc<PageTable>,
+	dirty_list: Arc<DirtyList>,
+	max_write_stall: Duration,
+	// Prefetching can't take up more than part of the cache, so it doesn't
+	// evict everything else.
+	max_prefetch_pages: AtomicUsize,
+	// Resizes are serialized, because the buffer can only grow from one
+	// thread at a time.
+	resize_lock: Mutex<()>,
+	read_ahead_pages: usize,
+	// Scans stay within a segment, while the page table spreads neighbouring
+	// pages over its shards, so the read-ahead state is sharded by segment.
+	read_ahead: Box<[Mutex<ReadAhead>]>,
+	persist_working_set: bool,
+	register_io_buffers: bool,
+	flush_timer_handle: Mutex<Option<TimerHandle>>,
+	counters: Arc<CacheCounters>,
+	access_trace: Option<TraceRecorder>,
+}
+assert_impl_all!(PageCache: Send, Sync);
+
+// Safety: `buf`'s internal pointer is never leaked in any form.
+unsafe impl<PS: PhysicalStorageApi + Send + Sync> Send for PageCache<PS> {}
+
+// Safety: `buf` is only accessed through the `load` and `store` methods,
+// which guarantee the safety of the references by acquiring the corresponding
+// locks.
+unsafe impl<PS: PhysicalStorageApi + Send + Sync> Sync for PageCache<PS> {}
+
+impl<PS: PhysicalStorageApi> Drop for PageCache<PS> {
+	fn drop(&mut self) {
+		// Background tasks may keep the buffer alive, but they don't keep it
+		// registered.
+		self.buf.unregister_chunks(&*self.physical_storage);
+	}
+}
+
+struct DirtyPage<'a> {
+	page_address: PageAddress,
+	index: usize,
+	guard: PageReadGuard<'a>,
+}
+
+impl<PS: PhysicalStorageApi + Send + Sync + 'static> PageCache<PS> {
+	pub fn new(
+		config: &PageCacheConfig,
+		page_size: PageSize,
+		physical_storage: Arc<PS>,
+		runtime: Runtime,
+	) -> Self {
+		let num_pages = config.page_cache_size / PageBuffer::stride(page_size);
+		let buf = Arc::new(PageBuffer::new(num_pages, page_size));
+		if config.register_io_buffers {
+			buf.register_chunks(&*physical_storage);
+		}
+		let num_shards = usize::clamp(
+			num_pages / MIN_PAGES_PER_CACHE_SHARD,
+			1,
+			usize::max(config.num_shards, 1),
+		);
+		let page_table = Arc::new(PageTable::new(num_pages, num_shards, config));
+		let dirty_list = Arc::new(DirtyList::new(num_pages, config));
+		let counters = Arc::new(CacheCounters::default());
+
+		let (flush_timer, flush_timer_handle) = runtime.timer(config.flush_period);
+		runtime.spawn(Self::periodic_flush_task(
+			flush_timer,
+			Arc::clone(&physical_storage),
+			Arc::clone(&dirty_list),
+			Arc::clone(&page_table),
+			Arc::clone(&buf),
+			Arc::clone(&counters),
+			config.persist_working_set,
+		));
+
+		Self {
+			buf,
+			physical_storage,
+			runtime,
+			page_table,
+			dirty_list,
+			max_write_stall: config.max_write_stall,
+			max_prefetch_pages: AtomicUsize::new(num_pages / 4),
+			resize_lock: Mutex::new(()),
+			read_ahead_pages: config.read_ahead_pages,
+			read_ahead: (0..num_shards)
+				.map(|_| Mutex::new(ReadAhead::default()))
+				.collect(),
+			persist_working_set: config.persist_working_set,
+			register_io_buffers: config.register_io_buffers,
+			flush_timer_handle: Mutex::new(Some(flush_timer_handle)),
+			counters,
+			access_trace: config.access_trace.as_ref().and_then(|path| {
+				TraceRecorder::create(path)
+					.inspect_err(|err| {
+						warn!(
+							"Failed to create the access trace at {}: {err}",
+							path.display()
+						);
+					})
+					.ok()
+			}),
+		}
+	}
+
+	/// Checks that a slot that was just locked still holds the page. Locked
+	/// pages are never evicted, but the page may have been evicted between
+	/// looking up its slot and locking it.
+	fn holds_page(&self, index: usize, page_address: PageAddress) -> bool {
+		self.page_table.get(page_address) == Some(index)
+	}
+
+	fn read_ahead_after_miss(&self, page_address: PageAddress, hint: AccessHint) {
+		let shard = page_address.segment_num as usize % self.read_ahead.len();
+		let mut read_ahead = self.read_ahead[shard].lock();
+		let maybe_pages = read_ahead.miss(page_address, self.read_ahead_pages);
+		mem::drop(read_ahead);
+		if let Some(pages) = maybe_pages {
+			self.prefetch_impl(pages, hint);
+		}
+	}
+
+	/// Prefetches the pages that aren't cached yet. They are inserted with
+	/// the hint of the access that they are prefetched for.
+	fn prefetch_impl(&self, pages: Range<PageAddress>, hint: AccessHint) {
+		let pages: Vec<PageAddress> = page_range(pages)
+			.filter(|page_address| self.page_table.get(*page_address).is_none())
+			.take(self.max_prefetch_pages.load(Ordering::Relaxed))
+			.collect();
+		if pages.is_empty() {
+			return;
+		}
+		self.runtime.spawn(Self::prefetch_task(
+			Arc::clone(&self.physical_storage),
+			Arc::clone(&self.page_table),
+			Arc::clone(&self.buf),
+			Arc::clone(&self.counters),
+			pages,
+			hint,
+		));
+	}
+
+	/// Wraps the slot of a page that was claimed, which is locked
+	/// exclusively already.
+	fn claimed_guard(buf: &PageBuffer, index: usize) -> PageWriteGuard<'_> {
+		// Safety: the claim locked the slot exclusively.
+		let page =
+			unsafe { buf.get_page_mut(index) }.expect("Tried to index page buffer out of bounds!");
+		PageWriteGuard {
+			index,
+			lock: buf.lock(index),
+			page,
+			_marker: PhantomData,
+		}
+	}
+
+	/// Reads claimed pages directly into their slots, with a single batch if
+	/// possible. If the batch fails, the pages are read one by one, and the
+	/// ones that still fail are removed from the cache again before their
+	/// slots are unlocked. Returns the number of pages that were read.
+	fn read_claimed(
+		physical_storage: &PS,
+		page_table: &PageTable,
+		buf: &PageBuffer,
+		claimed: &[(PageAddress, usize)],
+	) -> usize {
+		if claimed.is_empty() {
+			return 0;
+		}
+		let mut guards: Vec<PageWriteGuard> = claimed
+			.iter()
+			.map(|(_, index)| Self::claimed_guard(buf, *index))
+			.collect();
+		let mut wal_indices = vec![None; claimed.len()];
+		let mut failed = vec![false; claimed.len()];
+
+		let ops: Vec<Op> = claimed
+			.iter()
+			.zip(guards.iter_mut())
+			.zip(wal_indices.iter_mut())
+			.map(|(((page_address, _), guard), wal_index)| {
+				Op::Read(ReadOp {
+					page_address: *page_address,
+					wal_index,
+					buf: ReadBuf::Page(guard.slot_mut()),
+				})
+			})
+			.collect();
+		if let Err(err) = physical_storage.batch(ops.into()) {
+			warn!("Reading pages into the page cache failed, retrying them one by one: {err}");
+			for (((page_address, _), guard), (wal_index, failed)) in claimed
+				.iter()
+				.zip(guards.iter_mut())
+				.zip(wal_indices.iter_mut().zip(failed.iter_mut()))
+			{
+				let result = physical_storage.read(ReadOp {
+					page_address: *page_address,
+					wal_index,
+					buf: ReadBuf::Page(guard.slot_mut()),
+				});
+				if let Err(err) = result {
+					warn!("Skipping page {page_address:?}, which couldn't be read: {err}");
+					*failed = true;
+				}
+			}
+		}
+
+		let mut num_read = 0;
+		for (((page_address, _), mut guard), (wal_index, failed)) in claimed
+			.iter()
+			.zip(guards)
+			.zip(wal_indices.into_iter().zip(failed))
+		{
+			if failed {
+				page_table.remove(*page_address);
+			} else {
+				guard.reset_header(wal_index);
+				num_read += 1;
+			}
+		}
+		num_read
+	}
+
+	/// Claims the pages that still aren't cached, and reads them.
+	async fn prefetch_task(
+		physical_storage: Arc<PS>,
+		page_table: Arc<PageTable>,
+		buf: Arc<PageBuffer>,
+		counters: Arc<CacheCounters>,
+		pages: Vec<PageAddress>,
+		hint: AccessHint,
+	) {
+		// Pages that were loaded in the meantime may have been changed.
+		let claimed: Vec<(PageAddress, usize)> = pages
+			.into_iter()
+			.filter_map(|page_address| {
+				let index = page_table.claim(page_address, hint, &buf)?;
+				Some((page_address, index))
+			})
+			.collect();
+		let num_read = Self::read_claimed(&physical_storage, &page_table, &buf, &claimed);
+		counters
+			.prefetched_pages
+			.fetch_add(num_read as u64, Ordering::Relaxed);
+	}
+
+	/// Puts the pages of the saved working set that aren't cached yet back on
+	/// the lists they were on, and reads them in batches. Pages that were
+	/// loaded in the meantime are more recent, so they are never evicted for
+	/// this, and prewarming stops once nothing fits anymore. Pages that can't
+	/// be read are skipped.
+	async fn prewarm_task(
+		physical_storage: Arc<PS>,
+		page_table: Arc<PageTable>,
+		buf: Arc<PageBuffer>,
+		counters: Arc<CacheCounters>,
+	) {
+		let working_set = match physical_storage.read_working_set() {
+			Ok(Some(working_set)) => working_set,
+			Ok(None) => return,
+			Err(err) => {
+				warn!("Reading the working set of the page cache failed: {err}");
+				return;
+			}
+		};
+
+		for batch in working_set.pages.chunks(PREWARM_BATCH_PAGES) {
+			let batch: Vec<WorkingSetPage> = batch
+				.iter()
+				.filter(|page| page_table.get(page.page_address).is_none())
+				.copied()
+				.collect();
+			if batch.is_empty() {
+				continue;
+			}
+			let claimed: Vec<(PageAddress, usize)> = batch
+				.iter()
+				.filter_map(|page| {
+					let index = page_table.shard(page.page_address).restore(
+						page.page_address,
+						page.list,
+						&buf,
+					)?;
+					Some((page.page_address, index))
+				})
+				.collect();
+			if claimed.is_empty() {
+				break;
+			}
+			let num_read = Self::read_claimed(&physical_storage, &page_table, &buf, &claimed);
+			counters
+				.prewarmed_pages
+				.fetch_add(num_read as u64, Ordering::Relaxed);
+		}
+	}
+
+	/// Adds a page that is about to be written to the dirty list, and starts
+	/// a flush if there are too many dirty pages. If there are far too many,
+	/// the writer waits for the flush.
+	fn track_dirty(&self, page_address: PageAddress) {
+		let mut state = self.dirty_list.state.lock();
+		state.pages.insert(page_address);
+		if state.len() < self.dirty_list.max_num_dirty.load(Ordering::Relaxed) {
+			return;
+		}
+		mem::drop(state);
+		self.schedule_flush();
+	}
+
+	/// Spawns a background flush, unless one is in flight already.
+	fn schedule_flush(&self) {
+		if self.dirty_list.flush_scheduled.swap(true, Ordering::AcqRel) {
+			return;
+		}
+		self.runtime.spawn(Self::single_flush_task(
+			Arc::clone(&self.physical_storage),
+			Arc::clone(&self.dirty_list),
+			Arc::clone(&self.page_table),
+			Arc::clone(&self.buf),
+			Arc::clone(&self.counters),
+		));
+	}
+
+	/// Pages that miss are stored right after, so only loads are recorded.
+	fn record_access(&self, page_address: PageAddress) {
+		if let Some(access_trace) = &self.access_trace {
+			access_trace.record(&page_address);
+		}
+	}
+
+	fn get_store_index(&self, page_address: PageAddress, hint: AccessHint) -> usize {
+		let (index, _) = self.page_table.insert(page_address, hint, &self.buf);
+		index
+	}
+
+	fn get_load_index(&self, page_address: PageAddress, hint: AccessHint) -> Option<usize> {
+		let shard = self.page_table.shard(page_address);
+		let pages = shard.pages.read();
+		let Some(slot) = pages.slots.get(&page_address).copied() else {
+			mem::drop(pages);
+			shard.counters.misses.fetch_add(1, Ordering::Relaxed);
+			self.read_ahead_after_miss(page_address, hint);
+			return None;
+		};
+		shard.counters.hits.fetch_add(1, Ordering::Relaxed);
+
+		match (slot.residency, hint) {
+			(Residency::Replaced, _) => {
+				// The page can't be evicted while the pages are held.
+				let replacer = shard.replacer.read();
+				let access_successful = replacer.access(&page_address);
+				debug_assert!(access_successful);
+			}
+			(Residency::Probation, AccessHint::Normal) => {
+				mem::drop(pages);
+				shard.promote(page_address);
+			}
+			(Residency::Probation, AccessHint::Scan)
+			| (Residency::Pinned(_), _)
+			| (Residency::Evicting, _) => {}
+		}
+
+		Some(slot.index)
+	}
+
+	fn load_direct<'a>(buf: &'a PageBuffer, index: usize) -> PageReadGuard<'a> {
+		let lock = buf.lock(index);
+		lock.lock_shared();
+		// Safety: The safety of the reference is guaranteed by acquiring the shared
+		// lock.
+		let page =
+			unsafe { buf.get_page(index) }.expect("Tried to index page buffer out of bounds!");
+
+		PageReadGuard {
+			lock,
+			page,
+			_marker: PhantomData,
+		}
+	}
+
+	fn load_mut_direct<'a>(buf: &'a PageBuffer, index: usize) -> PageWriteGuard<'a> {
+		let lock = buf.lock(index);
+		lock.lock_exclusive();
+		// Safety: The safety of the reference is guaranteed by acquiring the exclusive
+		// lock.
+		let page =
+			unsafe { buf.get_page_mut(index) }.expect("Triet to index page buffer out of bounds!");
+
+		PageWriteGuard {
+			index,
+			lock,
+			page,
+			_marker: PhantomData,
+		}
+	}
+
+	fn flush(
+		physical_storage: &PS,
+		dirty_list: &DirtyList,
+		page_table: &PageTable,
+		buf: &PageBuffer,
+		counters: &CacheCounters,
+	) -> Result<(), StorageError> {
+		let _flush_guard = dirty_list.flush_lock.lock();
+		if dirty_list.closed.load(Ordering::Acquire) {
+			return Ok(());
+		}
+		let dirty_addresses = dirty_list.take();
+
+		let mut error: Option<StorageError> = None;
+		let mut dirty_pages: Vec<DirtyPage> = Vec::with_capacity(dirty_addresses.len());
+
+		for page_address in dirty_addresses.iter() {
+			let Some(index) = page_table.get(*page_address) else {
+				continue;
+			};
+
+			let guard = Self::load_direct(buf, index);
+			// The slot may have been given to another page in the meantime.
+			if page_table.get(*page_address) != Some(index) || !guard.header().dirty() {
+				continue;
+			}
+
+			dirty_pages.push(DirtyPage {
+				page_address: *page_address,
+				index,
+				guard,
+			});
+		}
+
+		// Pages that follow each other are written with a single operation.
+		dirty_pages.sort_unstable_by_key(|dp| dp.page_address);
+		let ops: Vec<Op> = dirty_pages
+			.iter()
+			.map(|dp| {
+				Op::Write(WriteOp {
+					wal_index: dp.guard.header().wal_index(),
+					page_address: dp.page_address,
+					buf: dp.guard.body(),
+				})
+			})
+			.collect();
+
+		let num_bytes: usize = dirty_pages.iter().map(|dp| dp.guard.body().len()).sum();
+		let start = Instant::now();
+		let result = if ops.is_empty() {
+			Ok(())
+		} else {
+			physical_storage.batch(ops.into())
+		};
+		if !dirty_pages.is_empty() {
+			counters.record_flush(start.elapsed(), num_bytes as u64, &result);
+		}
+		if let Err(err) = result {
+			error = Some(err);
+		}
+
+		for dirty_page in dirty_pages.into_iter() {
+			let wal_index = dirty_page.guard.header().wal_index();
+			mem::drop(dirty_page.guard);
+			if error.is_some() {
+				continue;
+			}
+
+			// The page may have been written again after it was flushed
+			let mut guard_mut = Self::load_mut_direct(buf, dirty_page.index);
+			if guard_mut.header().wal_index() == wal_index {
+				guard_mut.header_mut().set_dirty(false);
+			}
+		}
+
+		if let Some(err) = error {
+			dirty_list.finish_flush(dirty_addresses);
+			return Err(err);
+		}
+		dirty_list.finish_flush(HashSet::new());
+
+		Ok(())
+	}
+
+	/// Flushes, and logs errors instead of returning them. Returns whether
+	/// the flush succeeded.
+	async fn flush_ok(
+		physical_storage: &PS,
+		dirty_list: &DirtyList,
+		page_table: &PageTable,
+		buf: &PageBuffer,
+		counters: &CacheCounters,
+	) -> bool {
+		if let Err(err) = Self::flush(physical_storage, dirty_list, page_table, buf, counters) {
+			error!("Page cache flush failed: {err}");
+			return false;
+		}
+		true
+	}
+
+	async fn single_flush_task(
+		physical_storage: Arc<PS>,
+		dirty_list: Arc<DirtyList>,
+		page_table: Arc<PageTable>,
+		buf: Arc<PageBuffer>,
+		counters: Arc<CacheCounters>,
+	) {
+		loop {
+			let flushed =
+				Self::flush_ok(&physical_storage, &dirty_list, &page_table, &buf, &counters).await;
+			dirty_list.flush_scheduled.store(false, Ordering::Release);
+			// Writers that went over the limit during the flush couldn't
+			// schedule another one.
+			if !flushed
+				|| !dirty_list.needs_flush()
+				|| dirty_list.flush_scheduled.swap(true, Ordering::AcqRel)
+			{
+				break;
+			}
+		}
+	}
+
+	async fn periodic_flush_task(
+		mut timer: Timer,
+		physical_storage: Arc<PS>,
+		dirty_list: Arc<DirtyList>,
+		page_table: Arc<PageTable>,
+		buf: Arc<PageBuffer>,
+		counters: Arc<CacheCounters>,
+		persist_working_set: bool,
+	) {
+		while timer.wait().await {
+			Self::flush_ok(&physical_storage, &dirty_list, &page_table, &buf, &counters).await;
+			if !persist_working_set {
+				continue;
+			}
+			let _flush_guard = dirty_list.flush_lock.lock();
+			if dirty_list.closed.load(Ordering::Acquire) {
+				break;
+			}
+			// The working set is only a hint, so it is simply saved again
+			// after the next flush.
+			if let Err(err) = physical_storage.write_working_set(&page_table.working_set()) {
+				warn!("Saving the working set of the page cache failed: {err}");
+			}
+		}
+	}
+}
+
+#[cfg_attr(test, automock(
+    type ReadGuard<'a> = MockPageReadGuardApi;
+    type WriteGuard<'a> = MockPageWriteGuardApi;
+))]
+#[allow(clippy::needless_lifetimes)]
+pub(crate) trait PageCacheApi {
+	type ReadGuard<'a>: PageReadGuardApi + 'a
+	where
+		Self: 'a;
+	type WriteGuard<'a>: PageWriteGuardApi + 'a
+	where
+		Self: 'a;
+
+	fn has_page(&self, page_address: PageAddress) -> bool;
+	fn load<'a>(&'a self, page_address: PageAddress) -> Option<Self::ReadGuard<'a>>;
+	fn load_with_hint<'a>(
+		&'a self,
+		page_address: PageAddress,
+		hint: AccessHint,
+	) -> Option<Self::ReadGuard<'a>>;
+	fn load_mut<'a>(&'a self, page_address: PageAddress) -> Option<Self::WriteGuard<'a>>;
+	fn store<'a>(&'a self, page_address: PageAddress) -> Self::WriteGuard<'a>;
+	fn store_with_hint<'a>(
+		&'a self,
+		page_address: PageAddress,
+		hint: AccessHint,
+	) -> Self::WriteGuard<'a>;
+	fn flush(&self);
+
+	/// Stalls the caller while the dirty pages are above the ceiling, until
+	/// a flush brings them below it or the maximum write stall has passed on
+	/// the runtime's clock. The flush may have to wait for any page guard, so
+	/// writers call this before they take one.
+	fn wait_for_flush(&self);
+	fn flush_sync(&self) -> Result<(), StorageError>;
+	fn scrap(&self, page_address: PageAddress);
+
+	/// Scraps all cached pages starting at `end`, without writing them back,
+	/// e.g. before they are truncated. Dirty pages have to be flushed first
+	/// if their content is still needed.
+	fn scrap_from(&self, end: PageAddress);
+
+	/// Starts reading the pages in the range that aren't cached yet, without
+	/// waiting for them.
+	fn prefetch(&self, pages: Range<PageAddress>);
+
+	/// Takes a cached page out of eviction until it is unpinned as often as
+	/// it was pinned. Returns false if the page isn't cached, or if too many
+	/// pages are pinned already.
+	fn pin(&self, page_address: PageAddress) -> bool;
+
+	/// Returns false if the page wasn't pinned.
+	fn unpin(&self, page_address: PageAddress) -> bool;
+
+	/// Grows or shrinks the cache to `page_cache_size` bytes while it is in
+	/// use. Pages in the slots that are given up are evicted, and written
+	/// back if they are dirty.
+	fn resize(&self, page_cache_size: usize) -> Result<(), StorageError>;
+
+	/// Saves which pages are cached, and the lists of the replacement policy
+	/// they are on, so that [`Self::prewarm`] can restore them after the
+	/// storage is opened again.
+	fn save_working_set(&self) -> Result<(), StorageError>;
+
+	/// Starts reading the pages of the saved working set back into the cache
+	/// in the background. Errors are only logged.
+	fn prewarm(&self);
+
+	/// Stops the background flushes, and waits for one that is in flight.
+	/// Nothing is written back afterwards.
+	fn close(&self);
+	fn downgrade_guard<'a>(&'a self, guard: Self::WriteGuard<'a>) -> Self::ReadGuard<'a>;
+	fn stats(&self) -> PageCacheStats;
+}
+
+impl<PS: PhysicalStorageApi + Send + Sync + 'static> PageCacheApi for PageCache<PS> {
+	type ReadGuard<'a> = PageReadGuard<'a>;
+	type WriteGuard<'a> = PageWriteGuard<'a>;
+
+	fn has_page(&self, page_address: PageAddress) -> bool {
+		self.page_table.get(page_address).is_some()
+	}
+
+	fn load(&self, page_address: PageAddress) -> Option<PageReadGuard<'_>> {
+		self.load_with_hint(page_address, AccessHint::Normal)
+	}
+
+	fn load_with_hint(
+		&self,
+		page_address: PageAddress,
+		hint: AccessHint,
+	) -> Option<PageReadGuard<'_>> {
+		self.record_access(page_address);
+		loop {
+			let index = self.get_load_index(page_address, hint)?;
+			let guard = Self::load_direct(&self.buf, index);
+			if self.holds_page(index, page_address) {
+				return Some(guard);
+			}
+		}
+	}
+
+	fn load_mut(&self, page_address: PageAddress) -> Option<Self::WriteGuard<'_>> {
+		self.record_access(page_address);
+		loop {
+			let index = self.get_load_index(page_address, AccessHint::Normal)?;
+			let guard = Self::load_mut_direct(&self.buf, index);
+			if self.holds_page(index, page_address) {
+				// Only tracked once the page is locked, so that a miss doesn't
+				// count as a dirty page. A flush that took the dirty pages
+				// before can't have seen the page written yet, so it is
+				// flushed by the next one.
+				self.track_dirty(page_address);
+				return Some(guard);
+			}
+		}
+	}
+
+	fn store(&self, page_address: PageAddress) -> PageWriteGuard<'_> {
+		self.store_with_hint(page_address, AccessHint::Normal)
+	}
+
+	fn store_with_hint(&self, page_address: PageAddress, hint: AccessHint) -> PageWriteGuard<'_> {
+		self.track_dirty(page_address);
+		loop {
+			let index = self.get_store_index(page_address, hint);
+			let guard = Self::load_mut_direct(&self.buf, index);
+			if self.holds_page(index, page_address) {
+				return guard;
+			}
+		}
+	}
+
+	fn flush(&self) {
+		self.schedule_flush();
+	}
+
+	fn wait_for_flush(&self) {
+		let is_above_ceiling = |state: &DirtyState| {
+			state.len() >= self.dirty_list.max_num_dirty_hard.load(Ordering::Relaxed)
+		};
+		let mut state = self.dirty_list.state.lock();
+		if self.max_write_stall.is_zero() || !is_above_ceiling(&state) {
+			return;
+		}
+		self.schedule_flush();
+
+		// The condition variable can't wait on the runtime's clock, so a task
+		// wakes the writers up once the stall is over.
+		let start = self.runtime.now();
+		let deadline = start + self.max_write_stall;
+		let sleep = self.runtime.sleep(self.max_write_stall);
+		let dirty_list = Arc::clone(&self.dirty_list);
+		self.runtime.spawn(async move {
+			sleep.await;
+			let _state = dirty_list.state.lock();
+			dirty_list.flushed.notify_all();
+		});
+		while is_above_ceiling(&state) && self.runtime.now() < deadline {
+			self.dirty_list.flushed.wait(&mut state);
+			// Pages that were written during the last flush may need another
+			// one.
+			self.schedule_flush();
+		}
+		self.counters
+			.record_stall(self.runtime.now().saturating_sub(start));
+	}
+
+	fn flush_sync(&self) -> Result<(), StorageError> {
+		Self::flush(
+			&self.physical_storage,
+			&self.dirty_list,
+			&self.page_table,
+			&self.buf,
+			&self.counters,
+		)
+	}
+
+	fn scrap(&self, page_address: PageAddress) {
+		if self.page_table.remove(page_address) {
+			self.page_table
+				.shard(page_address)
+				.counters
+				.scrapped_pages
+				.fetch_add(1, Ordering::Relaxed);
+		}
+	}
+
+	fn scrap_from(&self, end: PageAddress) {
+		self.page_table.remove_from(end);
+	}
+
+	fn prefetch(&self, pages: Range<PageAddress>) {
+		self.prefetch_impl(pages, AccessHint::Normal);
+	}
+
+	fn pin(&self, page_address: PageAddress) -> bool {
+		self.page_table.shard(page_address).pin(page_address)
+	}
+
+	fn unpin(&self, page_address: PageAddress) -> bool {
+		self.page_table.shard(page_address).unpin(page_address)
+	}
+
+	fn resize(&self, page_cache_size: usize) -> Result<(), StorageError> {
+		let _resize_guard = self.resize_lock.lock();
+		let num_pages = page_cache_size / self.buf.stride;
+		// The buffer has to be large enough before the shards grow.
+		self.buf.grow(num_pages);
+		if self.register_io_buffers {
+			self.buf.register_chunks(&*self.physical_storage);
+		}
+		let result = self
+			.page_table
+			.resize(num_pages, &*self.physical_storage, &self.buf);
+
+		let num_pages = self.page_table.num_pages();
+		self.dirty_list.set_num_pages(num_pages);
+		self.max_prefetch_pages
+			.store(num_pages / 4, Ordering::Relaxed);
+		result
+	}
+
+	fn save_working_set(&self) -> Result<(), StorageError> {
+		if !self.persist_working_set {
+			return Ok(());
+		}
+		self.physical_storage
+			.write_working_set(&self.page_table.working_set())
+	}
+
+	fn close(&self) {
+		self.flush_timer_handle.lock().take();
+		let _flush_guard = self.dirty_list.flush_lock.lock();
+		self.dirty_list.closed.store(true, Ordering::Release);
+	}
+
+	fn prewarm(&self) {
+		if !self.persist_working_set {
+			return;
+		}
+		self.runtime.spawn(Self::prewarm_task(
+			Arc::clone(&self.physical_storage),
+			Arc::clone(&self.page_table),
+			Arc::clone(&self.buf),
+			Arc::clone(&self.counters),
+		));
+	}
+
+	fn downgrade_guard<'a>(&'a self, guard: PageWriteGuard<'a>) -> PageReadGuard<'a> {
+		let lock = guard.lock;
+		// Safety: the existance of the PageWriteGuard guarantees that the lock is owned
+		// in the current context
+		unsafe { lock.downgrade() };
+
+		// Safety: we have the shared lock for this page
+		let page = unsafe { self.buf.get_page(guard.index) }
+			.expect("Got out of bounds buffer index while upgrading guard");
+
+		mem::forget(guard);
+		PageReadGuard {
+			page,
+			lock,
+			_marker: PhantomData,
+		}
+	}
+
+	fn stats(&self) -> PageCacheStats {
+		let dirty_pages = self.dirty_list.state.lock().len();
+
+		let mut stats = PageCacheStats {
+			dirty_pages,
+			free_scrap_pages: self
+				.page_table
+				.shards
+				.iter()
+				.map(|shard| shard.scrap.lock().len())
+				.sum(),
+			pinned_pages: self.page_table.num_pinned(),
+			num_pages: self.page_table.num_pages(),
+			..Default::default()
+		};
+		self.counters.add_to(&mut stats);
+		for shard in &*self.page_table.shards {
+			shard.counters.add_to(&mut stats);
+		}
+		stats
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use futures::executor::ThreadPool;
+	use mockall::Sequence;
+	use pretty_assertions::assert_buf_eq;
+
+	use crate::{
+		consts::MIN_PAGE_SIZE,
+		files::{segment::PAGE_BODY_SIZE, FileError},
+		page_store::{
+			physical::MockPhysicalStorageApi,
+			test_helpers::{page_address, wal_index},
+		},
+		tasks::sim::Simulator,
+		utils::{cache::replay::read_trace, units::MIB},
+	};
+
+	use super::*;
+
+	/// The periodic flush blocks a thread while it waits, so the pool needs
+	/// another one for background flushes, or writers stall.
+	fn runtime() -> Runtime {
+		Runtime::from(Arc::new(
+			ThreadPool::builder().pool_size(2).create().unwrap(),
+		))
+	}
+
+	#[test]
+	fn page_buffer_pages_are_aligned() {
+		// given
+		let buf = PageBuffer::new(3, PageSize::DEFAULT);
+
+		// then
+		for index in 0..3 {
+			let ptr = buf.page_ptr(index).unwrap();
+			assert_eq!(ptr.as_ptr() as usize % DIRECT_IO_ALIGNMENT, 0);
+		}
+	}
+
+	#[test]
+	fn load_and_store() {
+		// given
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 2 * MIB,
+				..Default::default()
+			},
+			PageSize::DEFAULT,
+			Arc::new(MockPhysicalStorageApi::new()),
+			runtime(),
+		);
+
+		// when
+		let expected_page = [69; PAGE_BODY_SIZE];
+		cache
+			.store(page_address!(69, 420))
+			.write(0, &expected_page, wal_index!(1, 2));
+
+		let mut received_page = [0; PAGE_BODY_SIZE];
+		cache
+			.load(page_address!(69, 420))
+			.unwrap()
+			.read(0, &mut received_page);
+
+		// then
+		assert_buf_eq!(expected_page, received_page);
+	}
+
+	#[test]
+	fn record_access_trace() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let trace_path = tempdir.path().join("trace");
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 2 * MIB,
+				access_trace: Some(trace_path.clone()),
+				..Default::default()
+			},
+			PageSize::DEFAULT,
+			Arc::new(MockPhysicalStorageApi::new()),
+			runtime(),
+		);
+
+		// when
+		assert!(cache.load(page_address!(69, 420)).is_none());
+		cache
+			.store(page_address!(69, 420))
+			.write(0, &[69; PAGE_BODY_SIZE], wal_index!(1, 2));
+		assert!(cache.load(page_address!(69, 420)).is_some());
+		mem::drop(cache);
+
+		// then
+		let file = std::io::BufReader::new(std::fs::File::open(trace_path).unwrap());
+		let trace: Vec<PageAddress> = read_trace(file).unwrap();
+		assert_eq!(trace, vec![page_address!(69, 420); 2]);
+	}
+
+	#[test]
+	fn load_cache_miss() {
+		// given
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 2 * MIB,
+				..Default::default()
+			},
+			PageSize::DEFAULT,
+			Arc::new(MockPhysicalStorageApi::new()),
+			runtime(),
+		);
+
+		// when
+		let guard = cache.load(page_address!(69, 420));
+
+		// then
+		assert!(guard.is_none())
+	}
+
+	#[test]
+	fn evict_correct_page() {
+		// given
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 4 * PageBuffer::stride(PageSize::DEFAULT),
+				..Default::default()
+			},
+			PageSize::DEFAULT,
+			Arc::new(MockPhysicalStorageApi::new()),
+			runtime(),
+		);
+
+		// when
+		cache.store(page_address!(1, 1)); // add 1, 1 to recent
+		cache.store(page_address!(2, 2)); // add 2, 2 to recent
+		cache.store(page_address!(3, 3)); // add 3, 3 to recent
+		cache.store(page_address!(4, 4)); // add 4, 4 to recent
+		cache.load(page_address!(1, 1)); // 1, 1 was referenced in recent
+		cache.load(page_address!(2, 2)); // 2, 2 was referenced in recent
+		cache.load(page_address!(1, 1)); // 1, 1 is promoted to frequent
+
+		// recent is large, therefore 3, 3 is evicted as it is the first
+		// non-referenced item in frequent
+		cache.store(page_address!(5, 5));
+
+		// then
+		assert!(cache.load(page_address!(1, 1)).is_some());
+		assert!(cache.load(page_address!(2, 2)).is_some());
+		assert!(cache.load(page_address!(3, 3)).is_none());
+		assert!(cache.load(page_address!(4, 4)).is_some());
+		assert!(cache.load(page_address!(5, 5)).is_some());
+	}
+
+	#[test]
+	fn doesnt_evict_locked_page() {
+		// given
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 4 * PageBuffer::stride(PageSize::DEFAULT),
+				..Default::default()
+			},
+			PageSize::DEFAULT,
+			Arc::new(MockPhysicalStorageApi::new()),
+			runtime(),
+		);
+
+		// when
+		cache.store(page_address!(1, 1)); // add 1, 1 to recent
+		cache.store(page_address!(2, 2)); // add 2, 2 to recent
+		let guard = cache.store(page_address!(3, 3)); // add 3, 3 to recent
+		cache.store(page_address!(4, 4)); // add 4, 4 to recent
+		cache.load(page_address!(1, 1)); // 1, 1 was referenced in recent
+		cache.load(page_address!(2, 2)); // 2, 2 was referenced in recent
+		cache.load(page_address!(1, 1)); // 1, 1 is promoted to frequent
+
+		// recent is large, therefore 3, 3 would be evicted, but it is locked, so 4, 4
+		// is evicted instead
+		cache.store(page_address!(5, 5));
+
+		mem::drop(guard);
+
+		// then
+		assert!(cache.load(page_address!(1, 1)).is_some());
+		assert!(cache.load(page_address!(2, 2)).is_some());
+		assert!(cache.load(page_address!(3, 3)).is_some());
+		assert!(cache.load(page_address!(4, 4)).is_none());
+		assert!(cache.load(page_address!(5, 5)).is_some());
+	}
+
+	#[test]
+	fn wait_for_locked_pages_without_holding_the_shard() {
+		// given
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 2 * PageBuffer::stride(PageSize::DEFAULT),
+				..Default::default()
+			},
+			PageSize::DEFAULT,
+			Arc::new(MockPhysicalStorageApi::new()),
+			runtime(),
+		);
+		let first = cache.store(page_address!(1, 1));
+		let second = cache.store(page_address!(1, 2));
+
+		// when
+		thread::scope(|scope| {
+			let store = scope.spawn(|| {
+				cache.store(page_address!(1, 3));
+			});
+			thread::sleep(Duration::from_millis(10));
+			// This would block forever if the store held on to the shard.
+			assert!(cache.has_page(page_address!(1, 1)));
+			mem::drop(first);
+			store.join().unwrap();
+		});
+		mem::drop(second);
+
+		// then
+		assert!(!cache.has_page(page_address!(1, 1)));
+		assert!(cache.has_page(page_address!(1, 2)));
+		assert!(cache.has_page(page_address!(1, 3)));
+	}
+
+	#[test]
+	fn keep_shards_within_their_size() {
+		// given
+		let page_size = PageSize::new(MIN_PAGE_SIZE).unwrap();
+		let mut physical_storage = MockPhysicalStorageApi::new();
+		physical_storage.expect_batch().returning(|_| Ok(()));
+		let num_pages = 4 * MIN_PAGES_PER_CACHE_SHARD + 3;
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: num_pages * PageBuffer::stride(page_size),
+				num_shards: 8,
+				..Default::default()
+			},
+			page_size,
+			Arc::new(physical_storage),
+			runtime(),
+		);
+
+		// when
+		let num_stored = 2 * num_pages;
+		for page_num in 0..num_stored {
+			cache.store(page_address!(1, page_num as u16 + 1));
+		}
+
+		// then
+		let shards = &cache.page_table.shards;
+		assert_eq!(shards.len(), 4);
+		let mut num_resident = 0;
+		for (shard_num, shard) in shards.iter().enumerate() {
+			let size = MIN_PAGES_PER_CACHE_SHARD + usize::from(shard_num < 3);
+			let len = shard.pages.read().slots.len();
+			assert_eq!(len, size);
+			num_resident += len;
+		}
+		assert_eq!(num_resident, num_pages);
+		assert_eq!(cache.stats().evictions as usize, num_stored - num_pages);
+	}
+
+	#[test]
+	fn load_and_store_concurrently() {
+		// given
+		let page_size = PageSize::new(MIN_PAGE_SIZE).unwrap();
+		let mut physical_storage = MockPhysicalStorageApi::new();
+		physical_storage.expect_batch().returning(|_| Ok(()));
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 4 * MIN_PAGES_PER_CACHE_SHARD * PageBuffer::stride(page_size),
+				// The writers outpace the flushes, and this is about the page
+				// locks, not about throttling them.
+				max_write_stall: Duration::ZERO,
+				..Default::default()
+			},
+			page_size,
+			Arc::new(physical_storage),
+			runtime(),
+		);
+
+		// when
+		std::thread::scope(|scope| {
+			for thread_num in 0..4 {
+				let cache = &cache;
+				scope.spawn(move || {
+					for page_num in 1..=2 * MIN_PAGES_PER_CACHE_SHARD as u16 {
+						let page_address = page_address!(thread_num + 1, page_num);
+						let marker = page_num.to_le_bytes();
+						cache
+							.store(page_address)
+							.write(0, &marker, wal_index!(1, 2));
+
+						// then
+						let mut received = [0; 2];
+						if let Some(guard) = cache.load(page_address) {
+							guard.read(0, &mut received);
+							assert_eq!(received, marker);
+						}
+					}
+				});
+			}
+		});
+	}
+
+	fn read_addresses(ops: &[Op]) -> Vec<PageAddress> {
+		ops.iter()
+			.map(|op| match op {
+				Op::Read(op) => op.page_address,
+				Op::Write(_) => panic!("Unexpected write"),
+			})
+			.collect()
+	}
+
+	fn fill_with_page_num(ops: Box<[Op]>) -> Result<(), StorageError> {
+		for op in ops {
+			if let Op::Read(mut op) = op {
+				op.buf.fill(op.page_address.page_num.get() as u8);
+			}
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn prefetch_uncached_pages() {
+		// given
+		let sim = Simulator::new(69);
+		let mut physical_storage = MockPhysicalStorageApi::new();
+		physical_storage
+			.expect_batch()
+			.once()
+			.withf(|ops| read_addresses(ops) == [page_address!(1, 1), page_address!(1, 3)])
+			.returning(fill_with_page_num);
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 2 * MIB,
+				..Default::default()
+			},
+			PageSize::DEFAULT,
+			Arc::new(physical_storage),
+			sim.runtime(),
+		);
+		cache
+			.store(page_address!(1, 2))
+			.write(0, &[69], wal_index!(1, 2));
+
+		// when
+		cache.prefetch(page_address!(1, 1)..page_address!(1, 4));
+		sim.run_until_idle();
+
+		// then
+		for (page_num, expected) in [(1, 1), (2, 69), (3, 3)] {
+			let mut data = [0];
+			cache
+				.load(page_address!(1, page_num))
+				.unwrap()
+				.read(0, &mut data);
+			assert_eq!(data, [expected]);
+		}
+		assert!(!cache.load(page_address!(1, 1)).unwrap().header().dirty());
+		assert_eq!(cache.stats().prefetched_pages, 2);
+	}
+
+	#[test]
+	fn save_working_set_with_pinned_pages() {
+		// given
+		let sim = Simulator::new(69);
+		let mut physical_storage = MockPhysicalStorageApi::new();
+		physical_storage
+			.expect_write_working_set()
+			.once()
+			.withf(|working_set| {
+				working_set.pages
+					== [
+						WorkingSetPage {
+							page_address: page_address!(1, 1),
+							list: CacheList::Frequent,
+						},
+						WorkingSetPage {
+							page_address: page_address!(1, 2),
+							list: CacheList::Recent,
+						},
+					]
+			})
+			.returning(|_| Ok(()));
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 4 * PageBuffer::stride(PageSize::DEFAULT),
+				..Default::default()
+			},
+			PageSize::DEFAULT,
+			Arc::new(physical_storage),
+			sim.runtime(),
+		);
+		for page_num in 1..=2 {
+			cache
+				.store(page_address!(1, page_num))
+				.write(0, &[0], wal_index!(1, 2));
+		}
+		assert!(cache.pin(page_address!(1, 1)));
+
+		// when
+		let result = cache.save_working_set();
+
+		// then
+		assert!(result.is_ok());
+	}
+
+	#[test]
+	fn prewarm_only_fills_free_slots() {
+		// given
+		let sim = Simulator::new(69);
+		let mut physical_storage = MockPhysicalStorageApi::new();
+		physical_storage
+			.expect_read_working_set()
+			.once()
+			.returning(|| {
+				Ok(Some(WorkingSet {
+					pages: (1..=6)
+						.map(|page_num| WorkingSetPage {
+							page_address: page_address!(1, page_num),
+							list: CacheList::Recent,
+						})
+						.collect(),
+				}))
+			});
+		physical_storage
+			.expect_batch()
+			.once()
+			.withf(|ops| {
+				read_addresses(ops)
+					== [
+						page_address!(1, 1),
+						page_address!(1, 3),
+						page_address!(1, 4),
+					]
+			})
+			.returning(fill_with_page_num);
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 4 * PageBuffer::stride(PageSize::DEFAULT),
+				// The stored page shouldn't be flushed.
+				max_dirty_pages: 1.0,
+				..Default::default()
+			},
+			PageSize::DEFAULT,
+			Arc::new(physical_storage),
+			sim.runtime(),
+		);
+		cache
+			.store(page_address!(1, 2))
+			.write(0, &[69], wal_index!(1, 2));
+
+		// when
+		cache.prewarm();
+		sim.run_until_idle();
+
+		// then
+		for (page_num, expected) in [(1, 1), (2, 69), (3, 3), (4, 4)] {
+			let mut data = [0];
+			cache
+				.load(page_address!(1, page_num))
+				.unwrap()
+				.read(0, &mut data);
+			assert_eq!(data, [expected]);
+		}
+		assert!(!cache.has_page(page_address!(1, 5)));
+		assert!(!cache.has_page(page_address!(1, 6)));
+		let stats = cache.stats();
+		assert_eq!(stats.prewarmed_pages, 3);
+		assert_eq!(stats.evictions, 0);
+	}
+
+	#[test]
+	fn prewarm_skips_pages_that_cant_be_read() {
+		// given
+		let sim = Simulator::new(69);
+		let mut physical_storage = MockPhysicalStorageApi::new();
+		physical_storage
+			.expect_read_working_set()
+			.once()
+			.returning(|| {
+				Ok(Some(WorkingSet {
+					pages: (1..=3)
+						.map(|page_num| WorkingSetPage {
+							page_address: page_address!(1, page_num),
+							list: CacheList::Recent,
+						})
+						.collect(),
+				}))
+			});
+		physical_storage
+			.expect_batch()
+			.once()
+			.returning(|_| Err(StorageError::File(FileError::ChecksumMismatch)));
+		physical_storage.expect_read().times(3).returning(|mut op| {
+			if op.page_address == page_address!(1, 2) {
+				return Err(StorageError::File(FileError::ChecksumMismatch));
+			}
+			op.buf.fill(op.page_address.page_num.get() as u8);
+			Ok(())
+		});
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 4 * PageBuffer::stride(PageSize::DEFAULT),
+				..Default::default()
+			},
+			PageSize::DEFAULT,
+			Arc::new(physical_storage),
+			sim.runtime(),
+		);
+
+		// when
+		cache.prewarm();
+		sim.run_until_idle();
+
+		// then
+		for page_num in [1, 3] {
+			let mut data = [0];
+			cache
+				.load(page_address!(1, page_num))
+				.unwrap()
+				.read(0, &mut data);
+			assert_eq!(data, [page_num as u8]);
+		}
+		assert!(!cache.has_page(page_address!(1, 2)));
+		assert_eq!(cache.stats().prewarmed_pages, 2);
+	}
+
+	#[test]
+	fn read_ahead_of_scans_is_on_probation() {
+		// given
+		let sim = Simulator::new(69);
+		let mut physical_storage = MockPhysicalStorageApi::new();
+		physical_storage
+			.expect_batch()
+			.once()
+			.returning(fill_with_page_num);
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 2 * MIB,
+				read_ahead_pages: 2,
+				max_scan_pages: 0.5,
+				..Default::default()
+			},
+			PageSize::DEFAULT,
+			Arc::new(physical_storage),
+			sim.runtime(),
+		);
+
+		// when
+		for page_num in 1..=2 {
+			let page_address = page_address!(1, page_num);
+			if cache
+				.load_with_hint(page_address, AccessHint::Scan)
+				.is_none()
+			{
+				cache.store_with_hint(page_address, AccessHint::Scan);
+			}
+		}
+		sim.run_until_idle();
+
+		// then
+		for page_num in 3..=4 {
+			let page_address = page_address!(1, page_num);
+			let pages = cache.page_table.shard(page_address).pages.read();
+			assert_eq!(pages.slots[&page_address].residency, Residency::Probation);
+		}
+		assert_eq!(cache.stats().prefetched_pages, 2);
+	}
+
+	#[test]
+	fn read_ahead_on_sequential_misses() {
+		// given
+		let sim = Simulator::new(69);
+		let mut physical_storage = MockPhysicalStorageApi::new();
+		let mut seq = Sequence::new();
+		for start in [3, 8] {
+			physical_storage
+				.expect_batch()
+				.once()
+				.in_sequence(&mut seq)
+				.withf(move |ops| {
+					read_addresses(ops)
+						== (start..start + 4)
+							.map(|page_num| page_address!(1, page_num))
+							.collect::<Vec<_>>()
+				})
+				.returning(fill_with_page_num);
+		}
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 2 * MIB,
+				read_ahead_pages: 4,
+				..Default::default()
+			},
+			PageSize::DEFAULT,
+			Arc::new(physical_storage),
+			sim.runtime(),
+		);
+
+		// when
+		// Each miss is followed by loading the page, as the page storage does.
+		for page_num in 1..=11 {
+			if cache.load(page_address!(1, page_num)).is_none() {
+				cache.store(page_address!(1, page_num));
+			}
+			sim.run_until_idle();
+		}
+
+		// then
+		let stats = cache.stats();
+		assert_eq!(stats.misses, 3);
+		assert_eq!(stats.prefetched_pages, 8);
+	}
+
+	#[test]
+	fn scan_pages_dont_evict_cached_pages() {
+		// given
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 4 * PageBuffer::stride(PageSize::DEFAULT),
+				max_scan_pages: 0.5,
+				..Default::default()
+			},
+			PageSize::DEFAULT,
+			Arc::new(MockPhysicalStorageApi::new()),
+			runtime(),
+		);
+		cache.store(page_address!(1, 1));
+		cache.store(page_address!(1, 2));
+
+		// when
+		for page_num in 10..20 {
+			cache.store_with_hint(page_address!(1, page_num), AccessHint::Scan);
+		}
+
+		// then
+		for page_num in 10..18 {
+			assert!(cache.load(page_address!(1, page_num)).is_none());
+		}
+		for page_num in [1, 2, 18, 19] {
+			assert!(cache.load(page_address!(1, page_num)).is_some());
+		}
+	}
+
+	#[test]
+	fn promote_scan_pages_on_normal_access() {
+		// given
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 4 * PageBuffer::stride(PageSize::DEFAULT),
+				max_scan_pages: 0.5,
+				..Default::default()
+			},
+			PageSize::DEFAULT,
+			Arc::new(MockPhysicalStorageApi::new()),
+			runtime(),
+		);
+		cache.store(page_address!(1, 1));
+		cache.store_with_hint(page_address!(1, 10), AccessHint::Scan);
+		cache.store_with_hint(page_address!(1, 11), AccessHint::Scan);
+
+		// when
+		cache.load(page_address!(1, 10)); // 1, 10 is promoted
+		cache.load_with_hint(page_address!(1, 11), AccessHint::Scan); // 1, 11 stays on probation
+		cache.store_with_hint(page_address!(1, 12), AccessHint::Scan);
+		cache.store_with_hint(page_address!(1, 13), AccessHint::Scan);
+
+		// then
+		assert!(cache.load(page_address!(1, 1)).is_some());
+		assert!(cache.load(page_address!(1, 10)).is_some());
+		assert!(cache.load(page_address!(1, 11)).is_none());
+		assert!(cache.load(page_address!(1, 12)).is_some());
+		assert!(cache.load(page_address!(1, 13)).is_some());
+	}
+
+	#[test]
+	fn pinned_pages_are_not_evicted() {
+		// given
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 4 * PageBuffer::stride(PageSize::DEFAULT),
+				..Default::default()
+			},
+			PageSize::DEFAULT,
+			Arc::new(MockPhysicalStorageApi::new()),
+			runtime(),
+		);
+		cache.store(page_address!(1, 1));
+		cache.store(page_address!(1, 2));
+
+		// when
+		assert!(cache.pin(page_address!(1, 1)));
+		assert!(cache.pin(page_address!(1, 1)));
+		assert!(cache.pin(page_address!(1, 2)));
+		for page_num in 3..10 {
+			cache.store(page_address!(1, page_num));
+		}
+
+		// then
+		assert!(cache.load(page_address!(1, 1)).is_some());
+		assert!(cache.load(page_address!(1, 2)).is_some());
+		assert_eq!(cache.stats().pinned_pages, 2);
+		assert!(!cache.pin(page_address!(1, 9)));
+		assert!(!cache.pin(page_address!(1, 3)));
+	}
+
+	#[test]
+	fn evict_pages_once_unpinned() {
+		// given
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 4 * PageBuffer::stride(PageSize::DEFAULT),
+				..Default::default()
+			},
+			PageSize::DEFAULT,
+			Arc::new(MockPhysicalStorageApi::new()),
+			runtime(),
+		);
+		cache.store(page_address!(1, 1));
+		cache.pin(page_address!(1, 1));
+		cache.pin(page_address!(1, 1));
+
+		// when
+		assert!(cache.unpin(page_address!(1, 1)));
+		assert!(cache.unpin(page_address!(1, 1)));
+		assert!(!cache.unpin(page_address!(1, 1)));
+		for page_num in 2..10 {
+			cache.store(page_address!(1, page_num));
+		}
+
+		// then
+		assert!(cache.load(page_address!(1, 1)).is_none());
+		assert_eq!(cache.stats().pinned_pages, 0);
+	}
+
+	fn write_addresses(ops: &[Op]) -> Vec<PageAddress> {
+		ops.iter()
+			.map(|op| match op {
+				Op::Write(op) => op.page_address,
+				Op::Read(_) => panic!("Unexpected read"),
+			})
+			.collect()
+	}
+
+	#[test]
+	fn grow_cache() {
+		// given
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 4 * PageBuffer::stride(PageSize::DEFAULT),
+				..Default::default()
+			},
+			PageSize::DEFAULT,
+			Arc::new(MockPhysicalStorageApi::new()),
+			runtime(),
+		);
+		for page_num in 1..=4 {
+			cache.store(page_address!(1, page_num));
+		}
+
+		// when
+		cache
+			.resize(8 * PageBuffer::stride(PageSize::DEFAULT))
+			.unwrap();
+		for page_num in 5..=8 {
+			cache.store(page_address!(1, page_num));
+		}
+
+		// then
+		for page_num in 1..=8 {
+			assert!(cache.load(page_address!(1, page_num)).is_some());
+		}
+		let stats = cache.stats();
+		assert_eq!(stats.num_pages, 8);
+		assert_eq!(stats.evictions, 0);
+	}
+
+	#[test]
+	fn shrink_cache_and_write_back_dirty_pages() {
+		// given
+		let sim = Simulator::new(69);
+		let mut physical_storage = MockPhysicalStorageApi::new();
+		physical_storage
+			.expect_batch()
+			.once()
+			.withf(|ops| write_addresses(ops) == [page_address!(1, 1), page_address!(1, 3)])
+			.returning(|_| Ok(()));
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 8 * PageBuffer::stride(PageSize::DEFAULT),
+				// Flushes only run when the simulator steps.
+				max_write_stall: Duration::ZERO,
+				..Default::default()
+			},
+			PageSize::DEFAULT,
+			Arc::new(physical_storage),
+			sim.runtime(),
+		);
+		for page_num in 1..=8 {
+			let mut guard = cache.store(page_address!(1, page_num));
+			if page_num % 2 == 1 {
+				guard.write(0, &[1, 2, 3], wal_index!(1, 2));
+			}
 		}
 
-		for dirty_page in dirty_pages.into_iter() {
-			mem::drop(dirty_page.guard);
-			let mut guard_mut = Self::load_mut_direct(locks, buf, dirty_page.index);
-			guard_mut.header_mut().set_dirty(false);
-		}
+		// when
+		cache
+			.resize(4 * PageBuffer::stride(PageSize::DEFAULT))
+			.unwrap();
 
-		if let Some(err) = error {
-			let mut dirty_list_guard = dirty_list.lock();
-			dirty_list_guard.extend(&dirty_list_copy);
-			return Err(err);
+		// then
+		for page_num in 1..=4 {
+			assert!(cache.load(page_address!(1, page_num)).is_none());
 		}
-
-		Ok(())
+		for page_num in 5..=8 {
+			assert!(cache.load(page_address!(1, page_num)).is_some());
+		}
+		let stats = cache.stats();
+		assert_eq!(stats.num_pages, 4);
+		assert_eq!(stats.evictions, 4);
+		assert_eq!(cache.buf.released.lock().len(), 4);
 	}
 
-	async fn flush_ok(
-		physical_storage: &PS,
-		dirty_list: &Mutex<Vec<PageAddress>>,
-		indices: &RwLock<HashMap<PageAddress, usize>>,
-		locks: &[RawRwLock],
-		buf: &PageBuffer,
-	) {
-		if let Err(err) = Self::flush(physical_storage, dirty_list, indices, locks, buf) {
-			error!("Page cache flush failed: {err}");
+	#[test]
+	fn scrap_pages_from_end_without_writing_them_back() {
+		// given
+		let sim = Simulator::new(69);
+		let mut physical_storage = MockPhysicalStorageApi::new();
+		physical_storage
+			.expect_batch()
+			.once()
+			.withf(|ops| {
+				write_addresses(ops)
+					== [
+						page_address!(1, 1),
+						page_address!(1, 2),
+						page_address!(1, 3),
+					]
+			})
+			.returning(|_| Ok(()));
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 8 * PageBuffer::stride(PageSize::DEFAULT),
+				..Default::default()
+			},
+			PageSize::DEFAULT,
+			Arc::new(physical_storage),
+			sim.runtime(),
+		);
+		for page_num in 1..=6 {
+			cache
+				.store(page_address!(1, page_num))
+				.write(0, &[1, 2, 3], wal_index!(1, 2));
 		}
-	}
+		cache.store(page_address!(2, 1));
 
-	async fn single_flush_task(
-		physical_storage: Arc<PS>,
-		dirty_list: Arc<Mutex<Vec<PageAddress>>>,
-		indices: Arc<RwLock<HashMap<PageAddress, usize>>>,
-		locks: Arc<Box<[RawRwLock]>>,
-		buf: Arc<PageBuffer>,
-	) {
-		Self::flush_ok(&physical_storage, &dirty_list, &indices, &locks, &buf).await;
-	}
+		// when
+		cache.scrap_from(page_address!(1, 4));
+		cache.flush_sync().unwrap();
 
-	async fn periodic_flush_task(
-		timer: Timer,
-		physical_storage: Arc<PS>,
-		dirty_list: Arc<Mutex<Vec<PageAddress>>>,
-		indices: Arc<RwLock<HashMap<PageAddress, usize>>>,
-		locks: Arc<Box<[RawRwLock]>>,
-		buf: Arc<PageBuffer>,
-	) {
-		while timer.wait() {
-			Self::flush_ok(&physical_storage, &dirty_list, &indices, &locks, &buf).await;
+		// then
+		for page_num in 1..=3 {
+			assert!(cache.load(page_address!(1, page_num)).is_some());
 		}
+		for page_num in 4..=6 {
+			assert!(cache.load(page_address!(1, page_num)).is_none());
+		}
+		assert!(cache.load(page_address!(2, 1)).is_none());
+		assert_eq!(cache.stats().scrapped_pages, 4);
 	}
-}
-
-#[cfg_attr(test, automock(
-    type ReadGuard<'a> = MockPageReadGuardApi;
-    type WriteGuard<'a> = MockPageWriteGuardApi;
-))]
-#[allow(clippy::needless_lifetimes)]
-pub(crate) trait PageCacheApi {
-	type ReadGuard<'a>: PageReadGuardApi + 'a
-	where
-		Self: 'a;
-	type WriteGuard<'a>: PageWriteGuardApi + 'a
-	where
-		Self: 'a;
-
-	fn has_page(&self, page_address: PageAddress) -> bool;
-	fn load<'a>(&'a self, page_address: PageAddress) -> Option<Self::ReadGuard<'a>>;
-	fn load_mut<'a>(&'a self, page_address: PageAddress) -> Option<Self::WriteGuard<'a>>;
-	fn store<'a>(&'a self, page_address: PageAddress) -> Self::WriteGuard<'a>;
-	fn flush(&self);
-	fn flush_sync(&self) -> Result<(), StorageError>;
-	fn scrap(&self, page_address: PageAddress);
-	fn downgrade_guard<'a>(&'a self, guard: Self::WriteGuard<'a>) -> Self::ReadGuard<'a>;
-}
 
-impl<PS: PhysicalStorageApi + Send + Sync + 'static> PageCacheApi for PageCache<PS> {
-	type ReadGuard<'a> = PageReadGuard<'a>;
-	type WriteGuard<'a> = PageWriteGuard<'a>;
+	#[test]
+	fn load_pages_while_shrinking() {
+		// given
+		let sim = Simulator::new(69);
+		let (writing_tx, writing_rx) = std::sync::mpsc::channel();
+		let (done_tx, done_rx) = std::sync::mpsc::channel::<()>();
+		let done_rx = Mutex::new(done_rx);
+		let mut physical_storage = MockPhysicalStorageApi::new();
+		physical_storage.expect_batch().once().returning(move |_| {
+			writing_tx.send(()).unwrap();
+			done_rx.lock().recv().unwrap();
+			Ok(())
+		});
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 8 * PageBuffer::stride(PageSize::DEFAULT),
+				max_write_stall: Duration::ZERO,
+				..Default::default()
+			},
+			PageSize::DEFAULT,
+			Arc::new(physical_storage),
+			sim.runtime(),
+		);
+		for page_num in 1..=8 {
+			cache
+				.store(page_address!(1, page_num))
+				.write(0, &[1, 2, 3], wal_index!(1, 2));
+		}
 
-	fn has_page(&self, page_address: PageAddress) -> bool {
-		let indices = self.indices.read();
-		indices.contains_key(&page_address)
+		std::thread::scope(|scope| {
+			let cache = &cache;
+			let resize =
+				scope.spawn(move || cache.resize(4 * PageBuffer::stride(PageSize::DEFAULT)));
+			writing_rx.recv().unwrap();
+
+			// when
+			let guard = cache.load(page_address!(1, 8));
+
+			// then
+			assert!(guard.is_some());
+			mem::drop(guard);
+			done_tx.send(()).unwrap();
+			resize.join().unwrap().unwrap();
+		});
+		assert_eq!(cache.stats().num_pages, 4);
+		assert!(cache.load(page_address!(1, 1)).is_none());
 	}
 
-	fn load(&self, page_address: PageAddress) -> Option<PageReadGuard<'_>> {
-		let index = self.get_load_index(page_address)?;
-		Some(Self::load_direct(&self.locks, &self.buf, index))
-	}
+	#[test]
+	fn register_buffer_chunks() {
+		// given
+		let stride = PageBuffer::stride(PageSize::DEFAULT);
+		let mut physical_storage = MockPhysicalStorageApi::new();
+		physical_storage
+			.expect_register_buffer()
+			.times(2)
+			.withf(move |_, len| *len == 4 * stride)
+			.returning(|_, _| true);
+		physical_storage
+			.expect_unregister_buffer()
+			.times(2)
+			.return_const(());
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 4 * stride,
+				register_io_buffers: true,
+				..Default::default()
+			},
+			PageSize::DEFAULT,
+			Arc::new(physical_storage),
+			runtime(),
+		);
 
-	fn load_mut(&self, page_address: PageAddress) -> Option<Self::WriteGuard<'_>> {
-		let index = self.get_load_index(page_address)?;
-		Some(Self::load_mut_direct(&self.locks, &self.buf, index))
+		// when
+		cache.resize(8 * stride).unwrap();
+		for page_num in 1..=8 {
+			cache.store(page_address!(1, page_num));
+		}
+		cache.resize(4 * stride).unwrap();
+
+		// then
+		assert_eq!(cache.buf.released.lock().len(), 4);
+		mem::drop(cache);
 	}
 
-	fn store(&self, page_address: PageAddress) -> PageWriteGuard<'_> {
-		let mut dirty_list = self.dirty_list.lock();
-		dirty_list.push(page_address);
-		if dirty_list.len() >= self.max_num_dirty {
-			self.thread_pool.spawn_ok(Self::single_flush_task(
-				Arc::clone(&self.physical_storage),
-				Arc::clone(&self.dirty_list),
-				Arc::clone(&self.indices),
-				Arc::clone(&self.locks),
-				Arc::clone(&self.buf),
-			));
+	#[test]
+	fn reuse_released_slots_when_growing() {
+		// given
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 8 * PageBuffer::stride(PageSize::DEFAULT),
+				..Default::default()
+			},
+			PageSize::DEFAULT,
+			Arc::new(MockPhysicalStorageApi::new()),
+			runtime(),
+		);
+		for page_num in 1..=8 {
+			cache.store(page_address!(1, page_num));
 		}
-		mem::drop(dirty_list);
+		cache
+			.resize(2 * PageBuffer::stride(PageSize::DEFAULT))
+			.unwrap();
 
-		let index = self.get_store_index(page_address);
-		Self::load_mut_direct(&self.locks, &self.buf, index)
-	}
+		// when
+		cache
+			.resize(8 * PageBuffer::stride(PageSize::DEFAULT))
+			.unwrap();
+		for page_num in 9..=14 {
+			cache.store(page_address!(1, page_num));
+		}
 
-	fn flush(&self) {
-		let physical_storage = Arc::clone(&self.physical_storage);
-		let dirty_list = Arc::clone(&self.dirty_list);
-		let indices = Arc::clone(&self.indices);
-		let locks = Arc::clone(&self.locks);
-		let buf = Arc::clone(&self.buf);
-		self.thread_pool.spawn_ok(Self::single_flush_task(
-			physical_storage,
-			dirty_list,
-			indices,
-			locks,
-			buf,
-		))
+		// then
+		assert!(cache.buf.released.lock().is_empty());
+		assert_eq!(cache.buf.num_filled.load(Ordering::Relaxed), 8);
+		assert_eq!(cache.buf.capacity.load(Ordering::Relaxed), 8);
+		for page_num in 7..=14 {
+			assert!(cache.load(page_address!(1, page_num)).is_some());
+		}
 	}
 
-	fn flush_sync(&self) -> Result<(), StorageError> {
-		Self::flush(
-			&self.physical_storage,
-			&self.dirty_list,
-			&self.indices,
-			&self.locks,
-			&self.buf,
-		)
-	}
+	#[test]
+	fn shrink_cache_around_locked_and_pinned_pages() {
+		// given
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 4 * PageBuffer::stride(PageSize::DEFAULT),
+				..Default::default()
+			},
+			PageSize::DEFAULT,
+			Arc::new(MockPhysicalStorageApi::new()),
+			runtime(),
+		);
+		let guard = cache.store(page_address!(1, 1));
+		for page_num in 2..=4 {
+			cache.store(page_address!(1, page_num));
+		}
+		cache.pin(page_address!(1, 2));
 
-	fn scrap(&self, page_address: PageAddress) {
-		let mut indices = self.indices.write();
-		let Some(index) = indices.remove(&page_address) else {
-			return;
-		};
-		mem::drop(indices);
+		// when
+		cache
+			.resize(2 * PageBuffer::stride(PageSize::DEFAULT))
+			.unwrap();
+		mem::drop(guard);
 
-		self.has_scrap.store(true, Ordering::Relaxed);
-		self.scrap.lock().push(index);
+		// then
+		assert!(cache.load(page_address!(1, 1)).is_some());
+		assert!(cache.load(page_address!(1, 2)).is_some());
+		assert!(cache.load(page_address!(1, 3)).is_none());
+		assert!(cache.load(page_address!(1, 4)).is_none());
+		let stats = cache.stats();
+		assert_eq!(stats.num_pages, 2);
+		assert_eq!(stats.blocked_evictions, 1);
 	}
 
-	fn downgrade_guard<'a>(&'a self, guard: PageWriteGuard<'a>) -> PageReadGuard<'a> {
-		let lock = guard.lock;
-		// Safety: the existance of the PageWriteGuard guarantees that the lock is owned
-		// in the current context
-		unsafe { lock.downgrade() };
+	#[test]
+	fn keep_pages_if_write_back_fails() {
+		// given
+		let sim = Simulator::new(69);
+		let mut physical_storage = MockPhysicalStorageApi::new();
+		physical_storage
+			.expect_batch()
+			.returning(|_| Err(FileError::Io(std::io::ErrorKind::Other.into()).into()));
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 4 * PageBuffer::stride(PageSize::DEFAULT),
+				// Flushes only run when the simulator steps.
+				max_write_stall: Duration::ZERO,
+				..Default::default()
+			},
+			PageSize::DEFAULT,
+			Arc::new(physical_storage),
+			sim.runtime(),
+		);
+		for page_num in 1..=4 {
+			cache
+				.store(page_address!(1, page_num))
+				.write(0, &[1, 2, 3], wal_index!(1, 2));
+		}
 
-		// Safety: we have the shared lock for this page
-		let page = unsafe { self.buf.get_page(guard.index) }
-			.expect("Got out of bounds buffer index while upgrading guard");
+		// when
+		let result = cache.resize(2 * PageBuffer::stride(PageSize::DEFAULT));
 
-		mem::forget(guard);
-		PageReadGuard {
-			page,
-			lock,
-			_marker: PhantomData,
+		// then
+		assert!(result.is_err());
+		for page_num in 1..=4 {
+			assert!(cache
+				.load(page_address!(1, page_num))
+				.unwrap()
+				.header()
+				.dirty());
 		}
+		let stats = cache.stats();
+		assert_eq!(stats.num_pages, 4);
+		assert_eq!(stats.evictions, 0);
 	}
-}
 
-#[cfg(test)]
-mod tests {
-	use pretty_assertions::assert_buf_eq;
+	#[test]
+	fn resize_while_loading_and_storing() {
+		// given
+		let page_size = PageSize::new(MIN_PAGE_SIZE).unwrap();
+		let mut physical_storage = MockPhysicalStorageApi::new();
+		physical_storage.expect_batch().returning(|_| Ok(()));
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 2 * MIN_PAGES_PER_CACHE_SHARD * PageBuffer::stride(page_size),
+				max_write_stall: Duration::ZERO,
+				..Default::default()
+			},
+			page_size,
+			Arc::new(physical_storage),
+			runtime(),
+		);
 
-	use crate::{
-		page_store::{
-			physical::MockPhysicalStorageApi,
-			test_helpers::{page_address, wal_index},
-		},
-		utils::units::MIB,
-	};
+		// when
+		std::thread::scope(|scope| {
+			let cache = &cache;
+			for thread_num in 0..3 {
+				scope.spawn(move || {
+					for page_num in 1..=2 * MIN_PAGES_PER_CACHE_SHARD as u16 {
+						let page_address = page_address!(thread_num + 1, page_num);
+						let marker = page_num.to_le_bytes();
+						cache
+							.store(page_address)
+							.write(0, &marker, wal_index!(1, 2));
+
+						// then
+						let mut received = [0; 2];
+						if let Some(guard) = cache.load(page_address) {
+							guard.read(0, &mut received);
+							assert_eq!(received, marker);
+						}
+					}
+				});
+			}
+			scope.spawn(move || {
+				for num_pages in [1, 3, 2, 4, 1, 2].map(|n| n * MIN_PAGES_PER_CACHE_SHARD) {
+					cache
+						.resize(num_pages * PageBuffer::stride(page_size))
+						.unwrap();
+				}
+			});
+		});
+		// A background flush holds the pages it writes, so a shrink may have
+		// left a shard larger. Once nothing is locked, the size is reached.
+		cache.flush_sync().unwrap();
+		cache
+			.resize(2 * MIN_PAGES_PER_CACHE_SHARD * PageBuffer::stride(page_size))
+			.unwrap();
 
-	use super::*;
+		// then
+		assert_eq!(cache.stats().num_pages, 2 * MIN_PAGES_PER_CACHE_SHARD);
+	}
 
 	#[test]
-	fn load_and_store() {
+	fn track_access_stats() {
 		// given
 		let cache = PageCache::new(
 			&PageCacheConfig {
-				page_cache_size: 2 * MIB,
+				page_cache_size: 4 * PageBuffer::stride(PageSize::DEFAULT),
 				..Default::default()
 			},
+			PageSize::DEFAULT,
 			Arc::new(MockPhysicalStorageApi::new()),
-			Arc::new(ThreadPool::new().unwrap()),
+			runtime(),
 		);
+		cache.store(page_address!(1, 1));
+		cache.store(page_address!(2, 2));
+		let guard = cache.store(page_address!(3, 3));
+		cache.store(page_address!(4, 4));
+		cache.load(page_address!(1, 1));
+		cache.load(page_address!(2, 2));
+		cache.load(page_address!(1, 1));
 
 		// when
-		let expected_page = [69; PAGE_BODY_SIZE];
-		cache
-			.store(page_address!(69, 420))
-			.write(0, &expected_page, wal_index!(1, 2));
-
-		let mut received_page = [0; PAGE_BODY_SIZE];
-		cache
-			.load(page_address!(69, 420))
-			.unwrap()
-			.read(0, &mut received_page);
+		cache.store(page_address!(5, 5));
+		mem::drop(guard);
+		cache.load(page_address!(4, 4));
+		cache.scrap(page_address!(5, 5));
+		cache.store(page_address!(6, 6));
 
 		// then
-		assert_buf_eq!(expected_page, received_page);
+		let stats = cache.stats();
+		assert_eq!(stats.hits, 3);
+		assert_eq!(stats.misses, 1);
+		assert_eq!(stats.evictions, 1);
+		assert_eq!(stats.blocked_evictions, 1);
+		assert_eq!(stats.scrapped_pages, 1);
+		assert_eq!(stats.reused_scrap_pages, 1);
+		assert_eq!(stats.free_scrap_pages, 0);
+		assert_eq!(stats.hit_ratio(), 0.75);
 	}
 
 	#[test]
-	fn load_cache_miss() {
+	fn track_flush_stats() {
 		// given
+		let mut physical_storage = MockPhysicalStorageApi::new();
+		physical_storage.expect_batch().returning(|_| Ok(()));
 		let cache = PageCache::new(
 			&PageCacheConfig {
 				page_cache_size: 2 * MIB,
+				flush_period: Duration::from_secs(3600),
 				..Default::default()
 			},
+			PageSize::DEFAULT,
+			Arc::new(physical_storage),
+			runtime(),
+		);
+		for page_num in 1..=2 {
+			cache
+				.store(page_address!(1, page_num))
+				.write(0, &[1, 2, 3], wal_index!(1, 2));
+		}
+		cache
+			.load_mut(page_address!(1, 1))
+			.unwrap()
+			.write(0, &[4, 5, 6], wal_index!(1, 3));
+		assert_eq!(cache.stats().dirty_pages, 2);
+
+		// when
+		cache.flush_sync().unwrap();
+		cache.flush_sync().unwrap();
+
+		// then
+		let stats = cache.stats();
+		assert_eq!(stats.dirty_pages, 0);
+		assert_eq!(stats.flushes, 1);
+		assert_eq!(stats.failed_flushes, 0);
+		assert_eq!(stats.flushed_bytes, 2 * PAGE_BODY_SIZE as u64);
+	}
+
+	#[test]
+	fn dont_track_missed_page_as_dirty() {
+		// given
+		let cache = PageCache::new(
+			&Default::default(),
+			PageSize::DEFAULT,
 			Arc::new(MockPhysicalStorageApi::new()),
-			Arc::new(ThreadPool::new().unwrap()),
+			runtime(),
 		);
 
 		// when
-		let guard = cache.load(page_address!(69, 420));
+		let guard = cache.load_mut(page_address!(1, 1));
 
 		// then
-		assert!(guard.is_none())
+		assert!(guard.is_none());
+		assert_eq!(cache.stats().dirty_pages, 0);
 	}
 
 	#[test]
-	fn evict_correct_page() {
+	fn stall_writers_above_dirty_pages_ceiling() {
 		// given
+		let sim = Simulator::new(69);
+		let mut physical_storage = MockPhysicalStorageApi::new();
+		physical_storage
+			.expect_batch()
+			.once()
+			.withf(|ops| ops.len() == 4)
+			.returning(|_| Ok(()));
 		let cache = PageCache::new(
 			&PageCacheConfig {
-				page_cache_size: 4 * BUFFERED_PAGE_SIZE,
+				page_cache_size: 8 * PageBuffer::stride(PageSize::DEFAULT),
+				max_dirty_pages: 0.25,
+				dirty_pages_ceiling: 0.5,
+				max_write_stall: Duration::from_millis(10),
 				..Default::default()
 			},
-			Arc::new(MockPhysicalStorageApi::new()),
-			Arc::new(ThreadPool::new().unwrap()),
+			PageSize::DEFAULT,
+			Arc::new(physical_storage),
+			sim.runtime(),
 		);
+		for page_num in 1..=4 {
+			cache
+				.store(page_address!(1, page_num))
+				.write(0, &[1, 2, 3], wal_index!(1, 2));
+		}
 
 		// when
-		cache.store(page_address!(1, 1)); // add 1, 1 to recent
-		cache.store(page_address!(2, 2)); // add 2, 2 to recent
-		cache.store(page_address!(3, 3)); // add 3, 3 to recent
-		cache.store(page_address!(4, 4)); // add 4, 4 to recent
-		cache.load(page_address!(1, 1)); // 1, 1 was referenced in recent
-		cache.load(page_address!(2, 2)); // 2, 2 was referenced in recent
-		cache.load(page_address!(1, 1)); // 1, 1 is promoted to frequent
-
-		// recent is large, therefore 3, 3 is evicted as it is the first
-		// non-referenced item in frequent
-		cache.store(page_address!(5, 5));
+		std::thread::scope(|scope| {
+			let cache = &cache;
+			let writer = scope.spawn(move || cache.wait_for_flush());
+			// The clock never advances, so the writer can only continue once
+			// the flush is done.
+			while !writer.is_finished() {
+				sim.step();
+			}
+		});
 
 		// then
-		assert!(cache.load(page_address!(1, 1)).is_some());
-		assert!(cache.load(page_address!(2, 2)).is_some());
-		assert!(cache.load(page_address!(3, 3)).is_none());
-		assert!(cache.load(page_address!(4, 4)).is_some());
-		assert!(cache.load(page_address!(5, 5)).is_some());
+		let stats = cache.stats();
+		assert_eq!(stats.write_stall_duration, Duration::ZERO);
+		assert_eq!(stats.flushes, 1);
+		assert_eq!(stats.dirty_pages, 0);
 	}
 
 	#[test]
-	fn doesnt_evict_locked_page() {
+	fn stop_stalling_writers_after_max_write_stall() {
 		// given
+		let sim = Simulator::new(69);
+		let mut physical_storage = MockPhysicalStorageApi::new();
+		physical_storage
+			.expect_batch()
+			.returning(|_| Err(FileError::Io(std::io::ErrorKind::Other.into()).into()));
 		let cache = PageCache::new(
 			&PageCacheConfig {
-				page_cache_size: 4 * BUFFERED_PAGE_SIZE,
+				page_cache_size: 8 * PageBuffer::stride(PageSize::DEFAULT),
+				max_dirty_pages: 0.25,
+				dirty_pages_ceiling: 0.5,
+				max_write_stall: Duration::from_millis(10),
+				persist_working_set: false,
 				..Default::default()
 			},
-			Arc::new(MockPhysicalStorageApi::new()),
-			Arc::new(ThreadPool::new().unwrap()),
+			PageSize::DEFAULT,
+			Arc::new(physical_storage),
+			sim.runtime(),
 		);
+		for page_num in 1..=4 {
+			cache
+				.store(page_address!(1, page_num))
+				.write(0, &[1, 2, 3], wal_index!(1, 2));
+		}
 
 		// when
-		cache.store(page_address!(1, 1)); // add 1, 1 to recent
-		cache.store(page_address!(2, 2)); // add 2, 2 to recent
-		let guard = cache.store(page_address!(3, 3)); // add 3, 3 to recent
-		cache.store(page_address!(4, 4)); // add 4, 4 to recent
-		cache.load(page_address!(1, 1)); // 1, 1 was referenced in recent
-		cache.load(page_address!(2, 2)); // 2, 2 was referenced in recent
-		cache.load(page_address!(1, 1)); // 1, 1 is promoted to frequent
-
-		// recent is large, therefore 3, 3 would be evicted, but it is locked, so 4, 4
-		// is evicted instead
-		cache.store(page_address!(5, 5));
-
-		mem::drop(guard);
+		std::thread::scope(|scope| {
+			let cache = &cache;
+			let writer = scope.spawn(move || cache.wait_for_flush());
+			while !writer.is_finished() {
+				sim.step();
+				sim.advance();
+			}
+		});
 
 		// then
-		assert!(cache.load(page_address!(1, 1)).is_some());
-		assert!(cache.load(page_address!(2, 2)).is_some());
-		assert!(cache.load(page_address!(3, 3)).is_some());
-		assert!(cache.load(page_address!(4, 4)).is_none());
-		assert!(cache.load(page_address!(5, 5)).is_some());
+		let stats = cache.stats();
+		assert_eq!(stats.write_stalls, 1);
+		assert!(stats.write_stall_duration >= Duration::from_millis(10));
+		assert_eq!(stats.dirty_pages, 4);
 	}
 }
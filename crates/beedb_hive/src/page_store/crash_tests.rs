@@ -0,0 +1,265 @@
+use std::{collections::HashMap, mem, num::NonZeroU16, sync::Arc};
+
+use crate::{
+	files::{faulty::FaultyFolder, memory::MemoryFolder, PageAddress},
+	tasks::sim::Simulator,
+	utils::units::MIB,
+};
+
+use super::{
+	cache::{PageCache, PageCacheConfig},
+	physical::{PhysicalBackend, PhysicalStorageConfig},
+	wal::Wal,
+	PageStorage, PageStorageApi, PageStorageConfig, ReadPage, StorageError, TransactionApi,
+	WritePage,
+};
+
+const NUM_SEEDS: u64 = 16;
+const NUM_CRASHES: usize = 4;
+const MAX_TRANSACTIONS: usize = 16;
+const MAX_WRITES: usize = 6;
+const NUM_SEGMENTS: u32 = 2;
+const NUM_PAGES: u16 = 4;
+
+/// Large enough that no page of the workload is ever evicted.
+const PAGE_CACHE_SIZE: usize = 4 * MIB;
+
+/// The region at the start of each page that the workload writes to. It
+/// spans several sectors, so that torn pages mix old and new contents.
+const REGION_SIZE: usize = 2048;
+const MAX_WRITE_SIZE: usize = 64;
+
+type Model = HashMap<PageAddress, [u8; REGION_SIZE]>;
+
+type Folder = FaultyFolder<MemoryFolder>;
+type Storage =
+	PageStorage<PhysicalBackend<Folder>, PageCache<PhysicalBackend<Folder>>, Wal<Folder>>;
+
+struct Write {
+	page_address: PageAddress,
+	offset: usize,
+	data: Vec<u8>,
+}
+
+fn random_write(rng: &mut fastrand::Rng) -> Write {
+	let page_num = NonZeroU16::new(rng.u16(1..=NUM_PAGES)).unwrap();
+	let offset = rng.usize(0..REGION_SIZE);
+	let len = rng.usize(1..=usize::min(MAX_WRITE_SIZE, REGION_SIZE - offset));
+	Write {
+		page_address: PageAddress::new(rng.u32(0..NUM_SEGMENTS), page_num),
+		offset,
+		data: (0..len).map(|_| rng.u8(..)).collect(),
+	}
+}
+
+fn apply(model: &mut Model, writes: &[Write]) {
+	for write in writes {
+		let region = model.entry(write.page_address).or_insert([0; REGION_SIZE]);
+		region[write.offset..write.offset + write.data.len()].copy_from_slice(&write.data);
+	}
+}
+
+fn read_state(storage: &Storage) -> Result<Model, StorageError> {
+	let mut state = Model::new();
+	for segment_num in 0..NUM_SEGMENTS {
+		for page_num in 1..=NUM_PAGES {
+			let page_address = PageAddress::new(segment_num, NonZeroU16::new(page_num).unwrap());
+			let mut region = [0; REGION_SIZE];
+			storage.get_page(page_address)?.read(0, &mut region)?;
+			if region != [0; REGION_SIZE] {
+				state.insert(page_address, region);
+			}
+		}
+	}
+	Ok(state)
+}
+
+fn normalize(model: &Model) -> Model {
+	model
+		.iter()
+		.filter(|(_, region)| **region != [0; REGION_SIZE])
+		.map(|(page_address, region)| (*page_address, *region))
+		.collect()
+}
+
+/// Runs a single transaction, and applies it to `committed` if it commits.
+/// If the process dies, returns the states the storage may legally be in
+/// after the crash: the committed state, and possibly the state with the
+/// transaction applied, if it was unknown whether its commit went through.
+fn run_transaction(
+	storage: &Storage,
+	rng: &mut fastrand::Rng,
+	committed: &mut Model,
+) -> Option<Vec<Model>> {
+	let mut t = storage.transaction().unwrap();
+	let writes: Vec<Write> = (0..rng.usize(1..=MAX_WRITES))
+		.map(|_| random_write(rng))
+		.collect();
+
+	for write in writes.iter() {
+		let result = t
+			.get_page_mut(write.page_address)
+			.and_then(|mut page| page.write(write.offset, &write.data));
+		if result.is_err() {
+			// The process dies with the transaction still running.
+			mem::forget(t);
+			return Some(vec![committed.clone()]);
+		}
+	}
+
+	match rng.u8(0..10) {
+		0 => {
+			// Crash in the middle of a transaction
+			mem::forget(t);
+			return Some(vec![committed.clone()]);
+		}
+		1 => {
+			if t.undo().is_err() {
+				return Some(vec![committed.clone()]);
+			}
+		}
+		_ => {
+			if t.commit().is_err() {
+				let mut with_transaction = committed.clone();
+				apply(&mut with_transaction, &writes);
+				return Some(vec![committed.clone(), with_transaction]);
+			}
+			apply(committed, &writes);
+		}
+	}
+
+	if rng.u8(0..4) == 0 && storage.flush_sync().is_err() {
+		return Some(vec![committed.clone()]);
+	}
+	None
+}
+
+/// Runs transactions until a write fails or the workload ends, and may close
+/// the storage afterwards. The transactions are interleaved with the
+/// background flushes and checkpoints of the storage, and with time passing.
+/// Returns the states the storage may legally be in after a crash.
+fn run_workload(
+	sim: &Simulator,
+	storage: &Storage,
+	rng: &mut fastrand::Rng,
+	model: &Model,
+) -> Vec<Model> {
+	let mut committed = model.clone();
+	let mut num_transactions = rng.usize(1..=MAX_TRANSACTIONS);
+	let mut crashed: Option<Vec<Model>> = None;
+	sim.run(|| {
+		if num_transactions == 0 {
+			return false;
+		}
+		num_transactions -= 1;
+		crashed = run_transaction(storage, rng, &mut committed);
+		crashed.is_none()
+	});
+	if let Some(candidates) = crashed {
+		return candidates;
+	}
+
+	// Whether or not closing succeeds, the committed state must survive, and
+	// if it does, recovery is skipped on the next open.
+	if rng.u8(0..4) == 0 {
+		let _ = storage.close();
+	}
+	vec![committed]
+}
+
+/// The faults that are injected besides failing writes and power cuts.
+#[derive(Default)]
+struct Faults {
+	torn_page_probability: f64,
+	bit_flip_probability: f64,
+}
+
+/// Runs random transaction workloads against a [`FaultyFolder`], cuts the
+/// power at random points, and checks that recovery restores exactly the
+/// committed state.
+fn run_crash_test(seed: u64, config: &PageStorageConfig, faults: &Faults) {
+	let sim = Simulator::new(seed);
+	let mut rng = fastrand::Rng::with_seed(seed);
+	let mut folder = FaultyFolder::new(MemoryFolder::new(), seed);
+	let mut storage = PageStorage::create(Arc::new(folder.clone()), sim.runtime(), config).unwrap();
+	let mut model = Model::new();
+
+	for crash in 0..NUM_CRASHES {
+		folder.fail_after_writes(rng.u64(0..64));
+		folder.set_torn_page_probability(faults.torn_page_probability);
+		// Reads that hit a flipped bit fail, which ends the workload like a
+		// failed write.
+		folder.set_bit_flip_probability(faults.bit_flip_probability);
+		let candidates = run_workload(&sim, &storage, &mut rng, &model);
+
+		folder = folder.power_cut().unwrap();
+		mem::drop(storage);
+		storage = PageStorage::open(Arc::new(folder.clone()), sim.runtime(), config).unwrap();
+		storage.recover().unwrap();
+
+		let state = read_state(&storage).unwrap();
+		model =
+			candidates
+				.iter()
+				.map(normalize)
+				.find(|candidate| *candidate == state)
+				.unwrap_or_else(|| {
+					panic!("Seed {seed}, crash {crash}: recovered state doesn't match any committed state")
+				});
+	}
+}
+
+fn config(use_double_write: bool) -> PageStorageConfig {
+	PageStorageConfig {
+		physical_storage: PhysicalStorageConfig {
+			use_double_write,
+			..Default::default()
+		},
+		page_cache: PageCacheConfig {
+			page_cache_size: PAGE_CACHE_SIZE,
+			..Default::default()
+		},
+		..Default::default()
+	}
+}
+
+#[test]
+fn crash_recovery() {
+	let config = config(false);
+	for seed in 0..NUM_SEEDS {
+		run_crash_test(seed, &config, &Faults::default());
+	}
+}
+
+#[test]
+fn crash_recovery_with_double_write() {
+	let config = config(true);
+	for seed in 0..NUM_SEEDS {
+		run_crash_test(seed, &config, &Faults::default());
+	}
+}
+
+#[test]
+fn crash_recovery_with_torn_pages() {
+	// Torn pages can only be restored from the double-write buffer.
+	let config = config(true);
+	let faults = Faults {
+		torn_page_probability: 0.5,
+		..Default::default()
+	};
+	for seed in 0..NUM_SEEDS {
+		run_crash_test(seed, &config, &faults);
+	}
+}
+
+#[test]
+fn crash_recovery_with_bit_flips() {
+	let config = config(false);
+	let faults = Faults {
+		bit_flip_probability: 0.05,
+		..Default::default()
+	};
+	for seed in 0..NUM_SEEDS {
+		run_crash_test(seed, &config, &faults);
+	}
+}
@@ -0,0 +1,155 @@
+use std::{ptr::NonNull, sync::Arc};
+
+use static_assertions::assert_impl_all;
+
+use crate::files::{
+	segment::{PageSize, SegmentFileApi},
+	working_set::WorkingSet,
+	DatabaseFolder, DatabaseFolderApi,
+};
+
+use super::{
+	physical::{Op, PhysicalStorage, PhysicalStorageApi, PhysicalStorageConfig, ReadOp, WriteOp},
+	PageAddress, StorageError,
+};
+
+/// A physical storage that reads pages from memory-mapped segment files,
+/// instead of copying them in with a system call for every read.
+///
+/// Checksums of mapped pages are only verified the first time a page is
+/// read after it was written. Writes still go through the regular file IO
+/// of [`PhysicalStorage`].
+///
+/// Like any physical storage, it sits below the page cache, so pages are
+/// still copied out of the mapping into cache slots. Serving pages straight
+/// from the mapping would need the page cache's locking and dirty tracking
+/// for them, which pages outside of the cache don't have.
+pub(crate) struct MappedStorage<DF = DatabaseFolder>
+where
+	DF: DatabaseFolderApi,
+{
+	storage: PhysicalStorage<DF>,
+}
+
+assert_impl_all!(MappedStorage: Send, Sync);
+
+impl<DF> MappedStorage<DF>
+where
+	DF: DatabaseFolderApi,
+{
+	pub fn new(folder: Arc<DF>, config: &PhysicalStorageConfig, page_size: PageSize) -> Self {
+		Self {
+			storage: PhysicalStorage::new(folder, config, page_size),
+		}
+	}
+
+	pub fn restore_torn_pages(&self) -> Result<(), StorageError> {
+		self.storage.restore_torn_pages()
+	}
+}
+
+impl<DF: DatabaseFolderApi> PhysicalStorageApi for MappedStorage<DF> {
+	fn read(&self, op: ReadOp) -> Result<(), StorageError> {
+		self.storage
+			.use_segment(op.page_address.segment_num, |segment| {
+				segment.read_mapped(op.into())?;
+				Ok(())
+			})
+	}
+
+	fn write(&self, op: WriteOp) -> Result<(), StorageError> {
+		self.storage.write(op)
+	}
+
+	fn batch(&self, ops: Box<[Op]>) -> Result<(), StorageError> {
+		let mut write_ops: Vec<Op> = Vec::with_capacity(ops.len());
+		for op in ops {
+			match op {
+				Op::Read(read_op) => self.read(read_op)?,
+				Op::Write(..) => write_ops.push(op),
+			}
+		}
+		if write_ops.is_empty() {
+			return Ok(());
+		}
+		self.storage.batch(write_ops.into_boxed_slice())
+	}
+
+	fn truncate(&self, end: PageAddress) -> Result<(), StorageError> {
+		self.storage.truncate(end)
+	}
+
+	fn sync(&self) -> Result<(), StorageError> {
+		self.storage.sync()
+	}
+
+	fn read_working_set(&self) -> Result<Option<WorkingSet>, StorageError> {
+		self.storage.read_working_set()
+	}
+
+	fn write_working_set(&self, working_set: &WorkingSet) -> Result<(), StorageError> {
+		self.storage.write_working_set(working_set)
+	}
+
+	/// Pages are copied out of the mapping, so there is nothing to gain from
+	/// registering memory.
+	unsafe fn register_buffer(&self, _buf: NonNull<u8>, _len: usize) -> bool {
+		false
+	}
+
+	fn unregister_buffer(&self, _buf: NonNull<u8>, _len: usize) {}
+}
+
+#[cfg(test)]
+mod tests {
+	use mockall::predicate::*;
+
+	use crate::files::{
+		segment::{MockSegmentFileApi, ReadBuf, SegmentConfig, SegmentReadOp, PAGE_BODY_SIZE},
+		test_helpers::{page_address, wal_index},
+		MockDatabaseFolderApi,
+	};
+
+	use super::*;
+
+	#[test]
+	fn read_through_mapping() {
+		// expect
+		let mut folder = MockDatabaseFolderApi::new();
+		folder
+			.expect_open_segment_file()
+			.once()
+			.with(eq(69), eq(SegmentConfig::default()))
+			.returning(|_, _| {
+				let mut segment = MockSegmentFileApi::new();
+				segment
+					.expect_read_mapped()
+					.once()
+					.withf(|op| op.page_num.get() == 420)
+					.returning(|mut op: SegmentReadOp| {
+						*op.wal_index = Some(wal_index!(1, 2));
+						op.buf.fill(25);
+						Ok(())
+					});
+				Ok(segment)
+			});
+
+		// given
+		let storage = MappedStorage::new(Arc::new(folder), &Default::default(), PageSize::DEFAULT);
+
+		// when
+		let mut wal_index = None;
+		let mut buf = vec![0; PAGE_BODY_SIZE];
+		storage
+			.read(ReadOp {
+				page_address: page_address!(69, 420),
+				wal_index: &mut wal_index,
+				buf: ReadBuf::Body(&mut buf),
+			})
+			.unwrap();
+
+		// then
+		assert_eq!(wal_index, Some(wal_index!(1, 2)));
+		assert_eq!(buf, vec![25; PAGE_BODY_SIZE]);
+	}
+}
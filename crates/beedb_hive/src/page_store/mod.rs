@@ -1,11 +1,10 @@
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
-use std::sync::atomic::AtomicU64;
-use std::sync::atomic::Ordering;
+use std::ops::Range;
 use std::sync::Arc;
 
-use futures::executor::ThreadPool;
-use log::warn;
+use log::{info, warn};
+use parking_lot::{Condvar, Mutex};
 use thiserror::Error;
 
 #[cfg(test)]
@@ -14,16 +13,20 @@ use mockall::{automock, mock};
 #[cfg(test)]
 use crate::page_store::cache::{MockPageReadGuardApi, MockPageWriteGuardApi};
 
-use crate::files::DatabaseFolder;
+use crate::files::manifest::{DatabaseId, Manifest};
+use crate::files::segment::{PageSize, ReadBuf};
+use crate::files::wal::WalFileApi;
+use crate::files::DatabaseFolderApi;
 use crate::files::FileError;
 use crate::page_store::cache::PageWriteGuardApi;
+use crate::tasks::Runtime;
 
 pub(crate) use crate::files::PageAddress;
 use crate::files::TransactionState;
 use crate::files::WalIndex;
 
-use cache::{PageCache, PageCacheApi, PageCacheConfig};
-use physical::{PhysicalStorage, PhysicalStorageApi, PhysicalStorageConfig};
+use cache::{AccessHint, PageCache, PageCacheApi, PageCacheConfig, PageCacheStats};
+use physical::{PhysicalBackend, PhysicalBackendKind, PhysicalStorageApi, PhysicalStorageConfig};
 
 use wal::{Wal, WalApi, WalConfig};
 
@@ -32,6 +35,9 @@ use self::physical::ReadOp;
 use self::physical::WriteOp;
 
 mod cache;
+#[cfg(test)]
+mod crash_tests;
+mod mapped;
 mod physical;
 mod wal;
 
@@ -43,31 +49,49 @@ pub(crate) enum StorageError {
 	#[error("The maximum number of in-flight transactions has been reached")]
 	TransactionLimitReached,
 
+	#[error("The page storage has been closed")]
+	Closed,
+
 	#[error(transparent)]
 	File(#[from] FileError),
 }
 
 #[derive(Debug, Default, Clone, PartialEq)]
 pub(crate) struct PageStorageConfig {
+	/// The page size of newly created databases. Existing databases keep the
+	/// page size they were created with.
+	pub page_size: PageSize,
 	pub physical_storage: PhysicalStorageConfig,
+	pub physical_backend: PhysicalBackendKind,
 	pub page_cache: PageCacheConfig,
 	pub wal: WalConfig,
 }
 
 pub(crate) trait ReadPage {
 	fn read(&self, offset: usize, buf: &mut [u8]) -> Result<(), StorageError>;
+
+	/// The number of bytes that can be stored in the page.
+	fn size(&self) -> usize;
 }
 
 impl<T: ReadPage> ReadPage for &T {
 	fn read(&self, offset: usize, buf: &mut [u8]) -> Result<(), StorageError> {
 		(**self).read(offset, buf)
 	}
+
+	fn size(&self) -> usize {
+		(**self).size()
+	}
 }
 
 impl<T: ReadPage> ReadPage for &mut T {
 	fn read(&self, offset: usize, buf: &mut [u8]) -> Result<(), StorageError> {
 		(**self).read(offset, buf)
 	}
+
+	fn size(&self) -> usize {
+		(**self).size()
+	}
 }
 
 pub(crate) trait WritePage {
@@ -106,6 +130,13 @@ where
 		}
 		Ok(())
 	}
+
+	fn size(&self) -> usize {
+		match &self.guard {
+			WriteablePageGuard::Shared(guard) => guard.body().len(),
+			WriteablePageGuard::Exclusive(guard) => guard.body().len(),
+		}
+	}
 }
 
 pub(crate) struct PageMut<'t, 'a, PC, W>
@@ -126,6 +157,10 @@ where
 		self.guard.read(offset, buf);
 		Ok(())
 	}
+
+	fn size(&self) -> usize {
+		self.guard.body().len()
+	}
 }
 
 impl<'t, 'a, PC, W> WritePage for PageMut<'t, 'a, PC, W>
@@ -155,6 +190,7 @@ mock! {
 
 	impl ReadPage for Page {
 		fn read(&self, offset: usize, buf: &mut [u8]) -> Result<(), StorageError>;
+		fn size(&self) -> usize;
 	}
 }
 
@@ -164,6 +200,7 @@ mock! {
 
 	impl ReadPage for PageMut {
 		fn read(&self, offset: usize, buf: &mut [u8]) -> Result<(), StorageError>;
+		fn size(&self) -> usize;
 	}
 
 	impl WritePage for PageMut {
@@ -171,7 +208,7 @@ mock! {
 	}
 }
 
-pub(crate) struct Transaction<'t, PS = PhysicalStorage, PC = PageCache, W = Wal>
+pub(crate) struct Transaction<'t, PS = PhysicalBackend, PC = PageCache, W = Wal>
 where
 	PS: PhysicalStorageApi,
 	PC: PageCacheApi,
@@ -311,83 +348,167 @@ where
 	}
 }
 
+#[derive(Debug, Default)]
+struct TransactionCounts {
+	next_id: u64,
+	num_transactions: u64,
+	closed: bool,
+}
+
 #[derive(Debug)]
 struct TransactionEnumerator {
-	next_id: AtomicU64,
-	num_transactions: AtomicU64,
+	counts: Mutex<TransactionCounts>,
+	all_ended: Condvar,
 }
 
 impl TransactionEnumerator {
 	fn new() -> Self {
 		Self {
-			next_id: AtomicU64::new(0),
-			num_transactions: AtomicU64::new(0),
+			counts: Mutex::new(TransactionCounts::default()),
+			all_ended: Condvar::new(),
 		}
 	}
 
-	fn begin(&self) -> Option<u64> {
-		let num_transactions = self.num_transactions.load(Ordering::Acquire);
-		let num_transactions = num_transactions.checked_add(1)?;
-		self.num_transactions
-			.store(num_transactions, Ordering::Release);
-		let id = self.next_id.load(Ordering::Acquire);
-		self.next_id.store(id.wrapping_add(1), Ordering::Release);
-		Some(id)
+	fn begin(&self) -> Result<u64, StorageError> {
+		let mut counts = self.counts.lock();
+		if counts.closed {
+			return Err(StorageError::Closed);
+		}
+		let Some(num_transactions) = counts.num_transactions.checked_add(1) else {
+			return Err(StorageError::TransactionLimitReached);
+		};
+		counts.num_transactions = num_transactions;
+		let id = counts.next_id;
+		counts.next_id = id.wrapping_add(1);
+		Ok(id)
 	}
 
 	fn end(&self) {
-		let num_transactions = self.num_transactions.load(Ordering::Acquire);
-		self.num_transactions
-			.store(num_transactions.saturating_sub(1), Ordering::Release);
+		let mut counts = self.counts.lock();
+		counts.num_transactions = counts.num_transactions.saturating_sub(1);
+		if counts.num_transactions == 0 {
+			self.all_ended.notify_all();
+		}
+	}
+
+	/// Prevents new transactions from starting, and waits until all running
+	/// ones have ended.
+	fn close(&self) {
+		let mut counts = self.counts.lock();
+		counts.closed = true;
+		while counts.num_transactions != 0 {
+			self.all_ended.wait(&mut counts);
+		}
+	}
+
+	fn is_closed(&self) -> bool {
+		self.counts.lock().closed
 	}
 }
 
-pub(crate) struct PageStorage<PS = PhysicalStorage, PC = PageCache, W = Wal> {
+pub(crate) struct PageStorage<PS = PhysicalBackend, PC = PageCache, W = Wal> {
 	physical: Arc<PS>,
 	cache: PC,
 	wal: W,
 	transaction_enumerator: TransactionEnumerator,
+	// Set if the database was closed properly the last time, so that there
+	// is nothing to recover.
+	clean_shutdown: bool,
 }
 
-impl PageStorage {
+impl<DF> PageStorage<PhysicalBackend<DF>, PageCache<PhysicalBackend<DF>>, Wal<DF>>
+where
+	DF: DatabaseFolderApi + Send + Sync + 'static,
+{
 	pub fn create(
-		folder: Arc<DatabaseFolder>,
-		thread_pool: Arc<ThreadPool>,
+		folder: Arc<DF>,
+		runtime: Runtime,
 		config: &PageStorageConfig,
 	) -> Result<Self, StorageError> {
-		let physical_storage = Arc::new(PhysicalStorage::new(
+		folder.write_manifest(&Manifest::create(config.page_size)?)?;
+		let physical_storage = Arc::new(PhysicalBackend::new(
 			Arc::clone(&folder),
+			config.physical_backend,
 			&config.physical_storage,
+			config.page_size,
 		));
 		Ok(Self::new(
 			Arc::clone(&physical_storage),
 			PageCache::new(
 				&config.page_cache,
+				config.page_size,
 				Arc::clone(&physical_storage),
-				Arc::clone(&thread_pool),
+				runtime.clone(),
 			),
-			Wal::create(Arc::clone(&folder), thread_pool, &config.wal)?,
+			Wal::create(Arc::clone(&folder), runtime, &config.wal)?,
 		))
 	}
 
 	pub fn open(
-		folder: Arc<DatabaseFolder>,
-		thread_pool: Arc<ThreadPool>,
+		folder: Arc<DF>,
+		runtime: Runtime,
 		config: &PageStorageConfig,
 	) -> Result<Self, StorageError> {
-		let physical_storage = Arc::new(PhysicalStorage::new(
+		let manifest = Self::open_manifest(&*folder, config.page_size)?;
+		let physical_storage = Arc::new(PhysicalBackend::new(
 			Arc::clone(&folder),
+			config.physical_backend,
 			&config.physical_storage,
+			manifest.page_size,
 		));
-		Ok(Self::new(
+		if !manifest.clean_shutdown {
+			physical_storage.restore_torn_pages()?;
+		}
+		let mut storage = Self::new(
 			Arc::clone(&physical_storage),
 			PageCache::new(
 				&config.page_cache,
+				manifest.page_size,
 				Arc::clone(&physical_storage),
-				Arc::clone(&thread_pool),
+				runtime.clone(),
 			),
-			Wal::open(Arc::clone(&folder), thread_pool, &config.wal)?,
-		))
+			Wal::open(Arc::clone(&folder), runtime, &config.wal)?,
+		);
+		storage.clean_shutdown = manifest.clean_shutdown;
+		Ok(storage)
+	}
+
+	/// Reads the manifest, and makes sure that the WAL files belong to the
+	/// database it describes. Segments are verified by the folder once they
+	/// are opened. Returns the manifest as it was found, or a new one with
+	/// `page_size` if the folder is empty.
+	fn open_manifest(folder: &DF, page_size: PageSize) -> Result<Manifest, StorageError> {
+		let manifest = match folder.read_manifest()? {
+			Some(manifest) => {
+				manifest.check_versions()?;
+				manifest
+			}
+			None => {
+				// Without the manifest, there is no way of telling whether
+				// the files belong together.
+				if !folder.segment_nums()?.is_empty() || folder.iter_wal_files()?.next().is_some() {
+					return Err(FileError::MissingManifest.into());
+				}
+				warn!("The database has no manifest yet; creating a new one");
+				Manifest::create(page_size)?
+			}
+		};
+		Self::verify_wal_files(folder, manifest.database_id)?;
+
+		// The flag is set again once the database is closed properly.
+		folder.write_manifest(&Manifest {
+			clean_shutdown: false,
+			..manifest.clone()
+		})?;
+		Ok(manifest)
+	}
+
+	fn verify_wal_files(folder: &DF, database_id: DatabaseId) -> Result<(), StorageError> {
+		for wal_file in folder.iter_wal_files()? {
+			let (_, wal_file) = wal_file?;
+			database_id.verify(wal_file.database_id())?;
+		}
+		Ok(())
 	}
 }
 
@@ -402,22 +523,26 @@ where
 			cache,
 			wal,
 			transaction_enumerator: TransactionEnumerator::new(),
+			clean_shutdown: false,
 		}
 	}
 
-	fn load_into_cache(
-		&self,
+	fn load_into_cache<'a>(
+		&'a self,
 		page_address: PageAddress,
-	) -> Result<PC::WriteGuard<'_>, StorageError> {
-		let mut guard = self.cache.store(page_address);
-		if let Err(error) = self.physical.read(ReadOp {
+		mut guard: PC::WriteGuard<'a>,
+	) -> Result<PC::WriteGuard<'a>, StorageError> {
+		let mut wal_index = None;
+		let result = self.physical.read(ReadOp {
 			page_address,
-			wal_index: &mut None,
-			buf: guard.body_mut(),
-		}) {
+			wal_index: &mut wal_index,
+			buf: ReadBuf::Page(guard.slot_mut()),
+		});
+		if let Err(error) = result {
 			self.cache.scrap(page_address);
 			return Err(error);
 		}
+		guard.reset_header(wal_index);
 		Ok(guard)
 	}
 
@@ -425,7 +550,16 @@ where
 		if let Some(guard) = self.cache.load(page_address) {
 			return Ok(guard);
 		}
-		let guard = self.load_into_cache(page_address)?;
+		let guard = self.load_into_cache(page_address, self.cache.store(page_address))?;
+		Ok(self.cache.downgrade_guard(guard))
+	}
+
+	fn scan_guard(&self, page_address: PageAddress) -> Result<PC::ReadGuard<'_>, StorageError> {
+		if let Some(guard) = self.cache.load_with_hint(page_address, AccessHint::Scan) {
+			return Ok(guard);
+		}
+		let guard = self.cache.store_with_hint(page_address, AccessHint::Scan);
+		let guard = self.load_into_cache(page_address, guard)?;
 		Ok(self.cache.downgrade_guard(guard))
 	}
 
@@ -433,7 +567,7 @@ where
 		if let Some(guard) = self.cache.load_mut(page_address) {
 			return Ok(guard);
 		}
-		self.load_into_cache(page_address)
+		self.load_into_cache(page_address, self.cache.store(page_address))
 	}
 }
 
@@ -449,11 +583,56 @@ pub(crate) trait PageStorageApi {
 	where
 		Self: 'a;
 
+	/// Replays the WAL if the storage wasn't closed properly, and then starts
+	/// prewarming the page cache with the pages it held before.
 	fn recover(&self) -> Result<(), StorageError>;
 	fn get_page(&self, page_address: PageAddress) -> Result<Self::Page<'_>, StorageError>;
+
+	/// Like `get_page`, but for pages that are read by a bulk scan. Pages that
+	/// aren't cached yet don't push other pages out of the cache, unless they
+	/// are accessed again normally.
+	fn scan_page(&self, page_address: PageAddress) -> Result<Self::Page<'_>, StorageError>;
 	fn transaction(&self) -> Result<Self::Transaction<'_>, StorageError>;
 	fn flush(&self);
 	fn flush_sync(&self) -> Result<(), StorageError>;
+
+	/// Starts reading the pages in the range into the cache in the
+	/// background, e.g. before scanning them. Errors are only logged, and the
+	/// pages are read again when they are accessed.
+	fn prefetch(&self, pages: Range<PageAddress>);
+
+	/// Loads the page into the cache, and keeps it there until it is unpinned
+	/// as often as it was pinned. Returns false if too many pages are pinned
+	/// already, in which case the page is cached normally.
+	fn pin(&self, page_address: PageAddress) -> Result<bool, StorageError>;
+
+	/// Returns false if the page wasn't pinned.
+	fn unpin(&self, page_address: PageAddress) -> bool;
+
+	/// Grows or shrinks the page cache to `page_cache_size` bytes, without
+	/// closing the storage.
+	fn resize_cache(&self, page_cache_size: usize) -> Result<(), StorageError>;
+
+	/// Returns the space of all pages starting at `end` to the file system.
+	///
+	/// The caller has to make sure that none of these pages are in use, and
+	/// that they were flushed if other pages depend on that. Cached pages
+	/// past `end` are discarded without being written back. The truncation is
+	/// logged, so that recovery doesn't grow the segments again by replaying
+	/// earlier writes to these pages.
+	fn truncate(&self, end: PageAddress) -> Result<(), StorageError>;
+
+	/// Shuts the storage down cleanly, so that the next open doesn't have to
+	/// recover.
+	///
+	/// New transactions are rejected, and running ones are waited for. Then
+	/// all dirty pages are flushed and synced, the working set of the cache is
+	/// saved, and a final checkpoint is written. If this fails, the storage is
+	/// still closed, and recovery will happen on the next open.
+	fn close(&self) -> Result<(), StorageError>;
+
+	/// Returns a snapshot of the page cache's counters.
+	fn stats(&self) -> PageCacheStats;
 }
 
 impl<PS, PC, W> PageStorageApi for PageStorage<PS, PC, W>
@@ -466,16 +645,30 @@ where
 	type Transaction<'a> = Transaction<'a, PS, PC, W> where Self: 'a;
 
 	fn recover(&self) -> Result<(), StorageError> {
-		self.wal.recover(&mut |write_op| {
-			let mut guard = self.write_guard(write_op.page_address)?;
-			guard.write(write_op.offset.into(), write_op.buf, write_op.index);
-			self.physical.write(WriteOp {
-				wal_index: write_op.index,
-				page_address: write_op.page_address,
-				buf: guard.body(),
-			})?;
-			Ok(())
-		})
+		if self.clean_shutdown {
+			info!("The database was closed properly; skipping recovery");
+		} else {
+			self.wal.recover(
+				&mut |write_op| {
+					let mut guard = self.write_guard(write_op.page_address)?;
+					guard.write(write_op.offset.into(), write_op.buf, write_op.index);
+					self.physical.write(WriteOp {
+						wal_index: write_op.index,
+						page_address: write_op.page_address,
+						buf: guard.body(),
+					})?;
+					Ok(())
+				},
+				&mut |end| {
+					self.cache.scrap_from(end);
+					self.physical.truncate(end)
+				},
+			)?;
+		}
+		// Pages are only read from disk, so the replayed writes have to be
+		// there first.
+		self.cache.prewarm();
+		Ok(())
 	}
 
 	fn get_page(&self, page_address: PageAddress) -> Result<Self::Page<'_>, StorageError> {
@@ -484,10 +677,16 @@ where
 		})
 	}
 
+	fn scan_page(&self, page_address: PageAddress) -> Result<Self::Page<'_>, StorageError> {
+		Ok(Page {
+			guard: WriteablePageGuard::Shared(self.scan_guard(page_address)?),
+		})
+	}
+
 	fn transaction(&self) -> Result<Transaction<'_, PS, PC, W>, StorageError> {
-		let Some(transaction_id) = self.transaction_enumerator.begin() else {
-			return Err(StorageError::TransactionLimitReached);
-		};
+		// Writers are throttled before the transaction holds any pages.
+		self.cache.wait_for_flush();
+		let transaction_id = self.transaction_enumerator.begin()?;
 		Ok(Transaction::new(transaction_id, self))
 	}
 
@@ -498,22 +697,86 @@ where
 	fn flush_sync(&self) -> Result<(), StorageError> {
 		self.cache.flush_sync()
 	}
+
+	fn prefetch(&self, pages: Range<PageAddress>) {
+		self.cache.prefetch(pages);
+	}
+
+	fn pin(&self, page_address: PageAddress) -> Result<bool, StorageError> {
+		// The page can't be evicted while the guard is held.
+		let _guard = self.read_guard(page_address)?;
+		Ok(self.cache.pin(page_address))
+	}
+
+	fn unpin(&self, page_address: PageAddress) -> bool {
+		self.cache.unpin(page_address)
+	}
+
+	fn resize_cache(&self, page_cache_size: usize) -> Result<(), StorageError> {
+		self.cache.resize(page_cache_size)
+	}
+
+	fn truncate(&self, end: PageAddress) -> Result<(), StorageError> {
+		self.cache.scrap_from(end);
+		self.wal.log_truncate(end)?;
+		self.physical.truncate(end)
+	}
+
+	fn close(&self) -> Result<(), StorageError> {
+		self.transaction_enumerator.close();
+		self.cache.flush_sync()?;
+		if let Err(err) = self.cache.save_working_set() {
+			warn!("Saving the working set of the page cache failed: {err}");
+		}
+		self.physical.sync()?;
+		// A background flush must not write anything after the shutdown is
+		// marked as clean.
+		self.cache.close();
+		self.wal.close()
+	}
+
+	fn stats(&self) -> PageCacheStats {
+		self.cache.stats()
+	}
+}
+
+impl<PS, PC, W> Drop for PageStorage<PS, PC, W> {
+	fn drop(&mut self) {
+		if !self.transaction_enumerator.is_closed() {
+			warn!("The page storage was dropped without being closed; it will be recovered on the next open");
+		}
+	}
 }
 
 #[cfg(test)]
 mod tests {
 	use std::{
-		fs::File,
-		io::{Read, Seek, SeekFrom},
+		borrow::Cow,
+		fs::{File, OpenOptions},
+		io::{Read, Seek, SeekFrom, Write},
+		mem,
+		sync::atomic::{AtomicBool, Ordering},
+		time::Duration,
 	};
 
+	use futures::executor::ThreadPool;
 	use mockall::{predicate::*, Sequence};
 	use pretty_assertions::assert_buf_eq;
 	use tempfile::tempdir;
 	use test::Bencher;
 	use tests::wal::{CommitLog, WriteLog};
 
-	use crate::{consts::PAGE_SIZE, files::segment::PAGE_BODY_SIZE, utils::units::KIB};
+	use crate::{
+		consts::DEFAULT_PAGE_SIZE as PAGE_SIZE,
+		files::{
+			double_write::{DoubleWriteFileApi, DoubleWritePage},
+			memory::MemoryFolder,
+			segment::PAGE_BODY_SIZE,
+			DatabaseFolder,
+		},
+		tasks::sim::Simulator,
+		utils::units::{KIB, MIB},
+	};
 
 	use self::{
 		cache::MockPageCacheApi,
@@ -531,7 +794,7 @@ mod tests {
 		let mut cache = MockPageCacheApi::new();
 		let mut wal = MockWalApi::new();
 
-		wal.expect_recover().returning(|handler| {
+		wal.expect_recover().returning(|handler, _truncate| {
 			handler(wal::PartialWriteOp {
 				index: wal_index!(69, 420),
 				page_address: page_address!(1, 2),
@@ -563,9 +826,8 @@ mod tests {
 			.with(eq(page_address!(1, 2)))
 			.returning(|_| {
 				let mut guard = MockPageWriteGuardApi::new();
-				guard
-					.expect_body_mut()
-					.returning(|| vec![0; PAGE_BODY_SIZE]);
+				guard.expect_slot_mut().returning(|| vec![0; PAGE_SIZE]);
+				guard.expect_reset_header().return_const(());
 				guard.expect_body().return_const(vec![10; PAGE_BODY_SIZE]);
 				guard
 					.expect_write()
@@ -577,7 +839,7 @@ mod tests {
 			.once()
 			.in_sequence(&mut seq)
 			.withf(|read_op| read_op.page_address == page_address!(1, 2))
-			.returning(|read_op| {
+			.returning(|mut read_op| {
 				read_op.buf.fill(0);
 				*read_op.wal_index = Some(wal_index!(69, 420));
 				Ok(())
@@ -618,6 +880,11 @@ mod tests {
 					&& write_op.buf == [20; PAGE_BODY_SIZE]
 			})
 			.returning(|_| Ok(()));
+		cache
+			.expect_prewarm()
+			.once()
+			.in_sequence(&mut seq)
+			.return_const(());
 		// given
 		let page_storage = PageStorage::new(Arc::new(physical), cache, wal);
 
@@ -645,9 +912,8 @@ mod tests {
 			.with(eq(page_address!(69, 420)))
 			.returning(|_| {
 				let mut guard = MockPageWriteGuardApi::new();
-				guard
-					.expect_body_mut()
-					.returning(|| vec![0; PAGE_BODY_SIZE]);
+				guard.expect_slot_mut().returning(|| vec![0; PAGE_SIZE]);
+				guard.expect_reset_header().return_const(());
 				guard
 			});
 		physical
@@ -655,7 +921,7 @@ mod tests {
 			.once()
 			.in_sequence(&mut seq)
 			.withf(|read_op| read_op.page_address == page_address!(69, 420))
-			.returning(|read_op| {
+			.returning(|mut read_op| {
 				read_op
 					.buf
 					.iter_mut()
@@ -702,6 +968,11 @@ mod tests {
 		let mut wal = MockWalApi::new();
 
 		let mut seq = Sequence::new();
+		cache
+			.expect_wait_for_flush()
+			.once()
+			.in_sequence(&mut seq)
+			.return_const(());
 		cache
 			.expect_load_mut()
 			.once()
@@ -717,10 +988,15 @@ mod tests {
 				let mut guard = MockPageWriteGuardApi::new();
 				let mut seq = Sequence::new();
 				guard
-					.expect_body_mut()
+					.expect_slot_mut()
 					.once()
 					.in_sequence(&mut seq)
-					.returning(|| vec![0; PAGE_BODY_SIZE]);
+					.returning(|| vec![0; PAGE_SIZE]);
+				guard
+					.expect_reset_header()
+					.once()
+					.in_sequence(&mut seq)
+					.return_const(());
 				guard
 					.expect_read()
 					.once()
@@ -745,7 +1021,7 @@ mod tests {
 			.once()
 			.in_sequence(&mut seq)
 			.withf(|read_op| read_op.page_address == page_address!(1, 2))
-			.returning(|read_op| {
+			.returning(|mut read_op| {
 				read_op.buf.fill(0);
 				*read_op.wal_index = Some(wal_index!(69, 420));
 				Ok(())
@@ -794,9 +1070,9 @@ mod tests {
 	fn integration_transaction() {
 		let tempdir = tempdir().unwrap();
 
-		let folder = Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()));
-		let thread_pool = Arc::new(ThreadPool::new().unwrap());
-		let page_storage = PageStorage::create(folder, thread_pool, &Default::default()).unwrap();
+		let folder = Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()).unwrap());
+		let runtime = Runtime::from(Arc::new(ThreadPool::new().unwrap()));
+		let page_storage = PageStorage::create(folder, runtime, &Default::default()).unwrap();
 
 		let mut t = page_storage.transaction().unwrap();
 
@@ -828,13 +1104,534 @@ mod tests {
 		assert_buf_eq!(buf, expected);
 	}
 
+	#[test]
+	fn integration_memory_folder() {
+		let folder = Arc::new(MemoryFolder::new());
+		let runtime = Runtime::from(Arc::new(ThreadPool::new().unwrap()));
+		let page_storage =
+			PageStorage::create(Arc::clone(&folder), runtime.clone(), &Default::default()).unwrap();
+
+		let mut t = page_storage.transaction().unwrap();
+		t.get_page_mut(page_address!(69, 420))
+			.unwrap()
+			.write(25, &[1, 2, 3, 4])
+			.unwrap();
+		t.commit().unwrap();
+		page_storage.flush_sync().unwrap();
+		mem::drop(page_storage);
+
+		let page_storage = PageStorage::open(folder, runtime, &Default::default()).unwrap();
+		let mut data = [0; 4];
+		page_storage
+			.get_page(page_address!(69, 420))
+			.unwrap()
+			.read(25, &mut data)
+			.unwrap();
+		assert_buf_eq!(data, [1, 2, 3, 4]);
+	}
+
+	#[test]
+	fn integration_mapped_storage() {
+		let tempdir = tempdir().unwrap();
+		let config = PageStorageConfig {
+			physical_backend: PhysicalBackendKind::Mapped,
+			..Default::default()
+		};
+
+		let folder = Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()).unwrap());
+		let runtime = Runtime::from(Arc::new(ThreadPool::new().unwrap()));
+		let page_storage =
+			PageStorage::create(Arc::clone(&folder), runtime.clone(), &config).unwrap();
+
+		let mut t = page_storage.transaction().unwrap();
+		t.get_page_mut(page_address!(69, 420))
+			.unwrap()
+			.write(25, &[1, 2, 3, 4])
+			.unwrap();
+		t.commit().unwrap();
+		page_storage.flush_sync().unwrap();
+		mem::drop(page_storage);
+
+		let page_storage = PageStorage::open(folder, runtime, &config).unwrap();
+		let mut data = [0; 4];
+		page_storage
+			.get_page(page_address!(69, 420))
+			.unwrap()
+			.read(25, &mut data)
+			.unwrap();
+		assert_buf_eq!(data, [1, 2, 3, 4]);
+	}
+
+	#[test]
+	fn integration_manifest() {
+		let tempdir = tempdir().unwrap();
+		let folder = Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()).unwrap());
+		let runtime = Runtime::from(Arc::new(ThreadPool::new().unwrap()));
+		let page_storage =
+			PageStorage::create(Arc::clone(&folder), runtime.clone(), &Default::default()).unwrap();
+		let manifest = folder.read_manifest().unwrap().unwrap();
+		assert_eq!(
+			manifest.last_checkpoint.map(|index| index.generation),
+			Some(0)
+		);
+		mem::drop(page_storage);
+
+		PageStorage::open(Arc::clone(&folder), runtime, &Default::default()).unwrap();
+		let reopened_manifest = folder.read_manifest().unwrap().unwrap();
+		assert_eq!(reopened_manifest.database_id, manifest.database_id);
+		assert!(!reopened_manifest.clean_shutdown);
+	}
+
+	#[test]
+	fn integration_small_pages() {
+		let tempdir = tempdir().unwrap();
+		let config = PageStorageConfig {
+			page_size: PageSize::new(4096).unwrap(),
+			..Default::default()
+		};
+
+		let folder = Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()).unwrap());
+		let runtime = Runtime::from(Arc::new(ThreadPool::new().unwrap()));
+		let page_storage =
+			PageStorage::create(Arc::clone(&folder), runtime.clone(), &config).unwrap();
+
+		let mut t = page_storage.transaction().unwrap();
+		t.get_page_mut(page_address!(69, 420))
+			.unwrap()
+			.write(4000, &[1, 2, 3, 4])
+			.unwrap();
+		t.commit().unwrap();
+		page_storage.flush_sync().unwrap();
+		mem::drop(page_storage);
+
+		// The page size of the database wins over the configured one
+		let page_storage = PageStorage::open(folder, runtime, &Default::default()).unwrap();
+		let page = page_storage.get_page(page_address!(69, 420)).unwrap();
+		let mut data = [0; 4];
+		page.read(4000, &mut data).unwrap();
+		assert_buf_eq!(data, [1, 2, 3, 4]);
+		assert_eq!(page.size(), config.page_size.body_size());
+	}
+
+	#[test]
+	fn integration_open_empty_folder_with_small_pages() {
+		let tempdir = tempdir().unwrap();
+		let config = PageStorageConfig {
+			page_size: PageSize::new(4096).unwrap(),
+			..Default::default()
+		};
+		let folder = Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()).unwrap());
+		let runtime = Runtime::from(Arc::new(ThreadPool::new().unwrap()));
+
+		let page_storage = PageStorage::open(Arc::clone(&folder), runtime, &config).unwrap();
+
+		let page = page_storage.get_page(page_address!(69, 420)).unwrap();
+		assert_eq!(page.size(), config.page_size.body_size());
+		assert_eq!(
+			folder.read_manifest().unwrap().unwrap().page_size,
+			config.page_size
+		);
+	}
+
+	#[test]
+	fn integration_clean_shutdown() {
+		let folder = Arc::new(MemoryFolder::new());
+		let runtime = Runtime::from(Arc::new(ThreadPool::new().unwrap()));
+		let page_storage =
+			PageStorage::create(Arc::clone(&folder), runtime.clone(), &Default::default()).unwrap();
+
+		let mut t = page_storage.transaction().unwrap();
+		t.get_page_mut(page_address!(69, 420))
+			.unwrap()
+			.write(25, &[1, 2, 3, 4])
+			.unwrap();
+		t.commit().unwrap();
+		page_storage.close().unwrap();
+		assert!(matches!(
+			page_storage.transaction(),
+			Err(StorageError::Closed)
+		));
+		mem::drop(page_storage);
+		assert!(folder.read_manifest().unwrap().unwrap().clean_shutdown);
+
+		let page_storage =
+			PageStorage::open(Arc::clone(&folder), runtime, &Default::default()).unwrap();
+		assert!(page_storage.clean_shutdown);
+		assert!(!folder.read_manifest().unwrap().unwrap().clean_shutdown);
+		page_storage.recover().unwrap();
+		let mut data = [0; 4];
+		page_storage
+			.get_page(page_address!(69, 420))
+			.unwrap()
+			.read(25, &mut data)
+			.unwrap();
+		assert_buf_eq!(data, [1, 2, 3, 4]);
+	}
+
+	#[test]
+	fn integration_prefetch() {
+		// given
+		let sim = Simulator::new(69);
+		let folder = Arc::new(MemoryFolder::new());
+		let page_storage =
+			PageStorage::create(Arc::clone(&folder), sim.runtime(), &Default::default()).unwrap();
+		let mut t = page_storage.transaction().unwrap();
+		for page_num in 1..=8 {
+			t.get_page_mut(page_address!(1, page_num))
+				.unwrap()
+				.write(0, &[page_num as u8; 4])
+				.unwrap();
+		}
+		t.commit().unwrap();
+		page_storage.close().unwrap();
+		mem::drop(page_storage);
+		let page_storage = PageStorage::open(folder, sim.runtime(), &Default::default()).unwrap();
+
+		// when
+		page_storage.prefetch(page_address!(1, 1)..page_address!(1, 9));
+		sim.run_until_idle();
+
+		// then
+		assert_eq!(page_storage.stats().prefetched_pages, 8);
+		for page_num in 1..=8 {
+			let mut data = [0; 4];
+			page_storage
+				.get_page(page_address!(1, page_num))
+				.unwrap()
+				.read(0, &mut data)
+				.unwrap();
+			assert_buf_eq!(data, [page_num as u8; 4]);
+		}
+		let stats = page_storage.stats();
+		assert_eq!(stats.hits, 8);
+		assert_eq!(stats.misses, 0);
+	}
+
+	#[test]
+	fn integration_prewarm_after_reopen() {
+		// given
+		let sim = Simulator::new(69);
+		let folder = Arc::new(MemoryFolder::new());
+		let page_storage =
+			PageStorage::create(Arc::clone(&folder), sim.runtime(), &Default::default()).unwrap();
+		let mut t = page_storage.transaction().unwrap();
+		for page_num in 1..=8 {
+			t.get_page_mut(page_address!(1, page_num))
+				.unwrap()
+				.write(0, &[page_num as u8; 4])
+				.unwrap();
+		}
+		t.commit().unwrap();
+		page_storage.close().unwrap();
+		mem::drop(page_storage);
+		let page_storage = PageStorage::open(folder, sim.runtime(), &Default::default()).unwrap();
+
+		// when
+		page_storage.recover().unwrap();
+		sim.run_until_idle();
+
+		// then
+		assert_eq!(page_storage.stats().prewarmed_pages, 8);
+		for page_num in 1..=8 {
+			let mut data = [0; 4];
+			page_storage
+				.get_page(page_address!(1, page_num))
+				.unwrap()
+				.read(0, &mut data)
+				.unwrap();
+			assert_buf_eq!(data, [page_num as u8; 4]);
+		}
+		let stats = page_storage.stats();
+		assert_eq!(stats.hits, 8);
+		assert_eq!(stats.misses, 0);
+	}
+
+	#[test]
+	fn integration_pin_and_scan() {
+		// given
+		let sim = Simulator::new(69);
+		let folder = Arc::new(MemoryFolder::new());
+		let page_storage =
+			PageStorage::create(Arc::clone(&folder), sim.runtime(), &Default::default()).unwrap();
+		let mut t = page_storage.transaction().unwrap();
+		for page_num in 1..=2 {
+			t.get_page_mut(page_address!(1, page_num))
+				.unwrap()
+				.write(0, &[page_num as u8; 4])
+				.unwrap();
+		}
+		t.commit().unwrap();
+		page_storage.close().unwrap();
+		mem::drop(page_storage);
+		let page_storage = PageStorage::open(folder, sim.runtime(), &Default::default()).unwrap();
+
+		// when
+		let pinned = page_storage.pin(page_address!(1, 1)).unwrap();
+		let mut data = [0; 4];
+		page_storage
+			.scan_page(page_address!(1, 2))
+			.unwrap()
+			.read(0, &mut data)
+			.unwrap();
+
+		// then
+		assert!(pinned);
+		assert_buf_eq!(data, [2; 4]);
+		assert_eq!(page_storage.stats().pinned_pages, 1);
+		assert!(page_storage.unpin(page_address!(1, 1)));
+		assert_eq!(page_storage.stats().pinned_pages, 0);
+	}
+
+	#[test]
+	fn close_waits_for_transactions() {
+		let folder = Arc::new(MemoryFolder::new());
+		let runtime = Runtime::from(Arc::new(ThreadPool::new().unwrap()));
+		let page_storage = PageStorage::create(folder, runtime, &Default::default()).unwrap();
+		let committed = AtomicBool::new(false);
+
+		let (started_tx, started_rx) = std::sync::mpsc::channel();
+
+		std::thread::scope(|scope| {
+			scope.spawn(|| {
+				let mut t = page_storage.transaction().unwrap();
+				started_tx.send(()).unwrap();
+				std::thread::sleep(Duration::from_millis(50));
+				t.get_page_mut(page_address!(69, 420))
+					.unwrap()
+					.write(25, &[1, 2, 3, 4])
+					.unwrap();
+				t.commit().unwrap();
+				committed.store(true, Ordering::Release);
+			});
+
+			started_rx.recv().unwrap();
+			page_storage.close().unwrap();
+			assert!(committed.load(Ordering::Acquire));
+		});
+	}
+
+	#[test]
+	fn integration_reject_foreign_segment() {
+		let tempdirs = [tempdir().unwrap(), tempdir().unwrap()];
+		let folders = tempdirs
+			.each_ref()
+			.map(|tempdir| Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()).unwrap()));
+		let runtime = Runtime::from(Arc::new(ThreadPool::new().unwrap()));
+		for folder in &folders {
+			let page_storage =
+				PageStorage::create(Arc::clone(folder), runtime.clone(), &Default::default())
+					.unwrap();
+			let mut t = page_storage.transaction().unwrap();
+			t.get_page_mut(page_address!(69, 420))
+				.unwrap()
+				.write(25, &[1, 2, 3, 4])
+				.unwrap();
+			t.commit().unwrap();
+			page_storage.flush_sync().unwrap();
+		}
+
+		std::fs::copy(
+			tempdirs[1].path().join("segments/69"),
+			tempdirs[0].path().join("segments/69"),
+		)
+		.unwrap();
+
+		let page_storage =
+			PageStorage::open(Arc::clone(&folders[0]), runtime, &Default::default()).unwrap();
+		let result = page_storage.get_page(page_address!(69, 420));
+		assert!(matches!(
+			result,
+			Err(StorageError::File(FileError::DatabaseMismatch { .. }))
+		));
+	}
+
+	#[test]
+	fn integration_reject_foreign_wal_file() {
+		let tempdirs = [tempdir().unwrap(), tempdir().unwrap()];
+		let folders = tempdirs
+			.each_ref()
+			.map(|tempdir| Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()).unwrap()));
+		let runtime = Runtime::from(Arc::new(ThreadPool::new().unwrap()));
+		for folder in &folders {
+			PageStorage::create(Arc::clone(folder), runtime.clone(), &Default::default()).unwrap();
+		}
+
+		let foreign_wal_file = std::fs::read_dir(tempdirs[1].path().join("wal"))
+			.unwrap()
+			.next()
+			.unwrap()
+			.unwrap();
+		std::fs::copy(
+			foreign_wal_file.path(),
+			tempdirs[0].path().join("wal/69420"),
+		)
+		.unwrap();
+
+		assert!(matches!(
+			folders[0].open_wal_file(69420),
+			Err(FileError::DatabaseMismatch { .. })
+		));
+		let result = PageStorage::open(Arc::clone(&folders[0]), runtime, &Default::default());
+		assert!(matches!(
+			result,
+			Err(StorageError::File(FileError::DatabaseMismatch { .. }))
+		));
+	}
+
+	#[test]
+	fn integration_reject_missing_manifest() {
+		let tempdir = tempdir().unwrap();
+		let folder = Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()).unwrap());
+		let runtime = Runtime::from(Arc::new(ThreadPool::new().unwrap()));
+		let page_storage =
+			PageStorage::create(Arc::clone(&folder), runtime.clone(), &Default::default()).unwrap();
+		let mut t = page_storage.transaction().unwrap();
+		t.get_page_mut(page_address!(69, 420))
+			.unwrap()
+			.write(25, &[1, 2, 3, 4])
+			.unwrap();
+		t.commit().unwrap();
+		page_storage.flush_sync().unwrap();
+		mem::drop(page_storage);
+
+		std::fs::remove_file(tempdir.path().join("MANIFEST")).unwrap();
+
+		let result = PageStorage::open(folder, runtime, &Default::default());
+		assert!(matches!(
+			result,
+			Err(StorageError::File(FileError::MissingManifest))
+		));
+	}
+
+	#[test]
+	fn integration_restore_torn_page() {
+		let tempdir = tempdir().unwrap();
+		let config = PageStorageConfig {
+			physical_storage: PhysicalStorageConfig {
+				use_double_write: true,
+				..Default::default()
+			},
+			..Default::default()
+		};
+
+		let folder = Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()).unwrap());
+		let runtime = Runtime::from(Arc::new(ThreadPool::new().unwrap()));
+		let page_storage =
+			PageStorage::create(Arc::clone(&folder), runtime.clone(), &config).unwrap();
+
+		let mut t = page_storage.transaction().unwrap();
+		t.get_page_mut(page_address!(69, 420))
+			.unwrap()
+			.write(25, &[1, 2, 3, 4])
+			.unwrap();
+		t.commit().unwrap();
+		page_storage.flush_sync().unwrap();
+		let mut body = vec![0; PAGE_BODY_SIZE];
+		page_storage
+			.get_page(page_address!(69, 420))
+			.unwrap()
+			.read(0, &mut body)
+			.unwrap();
+		mem::drop(page_storage);
+
+		// Simulate a write that was interrupted halfway through the page,
+		// after its copy was persisted in the double-write buffer
+		folder
+			.open_double_write_file(PageSize::DEFAULT)
+			.unwrap()
+			.write_pages(&[DoubleWritePage {
+				page_address: page_address!(69, 420),
+				wal_index: wal_index!(0, 1),
+				buf: Cow::Owned(body),
+			}])
+			.unwrap();
+		let mut segment_file = OpenOptions::new()
+			.write(true)
+			.open(tempdir.path().join("segments/69"))
+			.unwrap();
+		segment_file
+			.seek(SeekFrom::Start((420 * PAGE_SIZE + PAGE_SIZE / 2) as u64))
+			.unwrap();
+		segment_file.write_all(&[0xff; PAGE_SIZE / 2]).unwrap();
+		mem::drop(segment_file);
+
+		let page_storage = PageStorage::open(folder, runtime, &config).unwrap();
+		let mut data = [0; 4];
+		page_storage
+			.get_page(page_address!(69, 420))
+			.unwrap()
+			.read(25, &mut data)
+			.unwrap();
+
+		assert_buf_eq!(data, [1, 2, 3, 4]);
+	}
+
+	fn run_simulated_workload(seed: u64) -> Vec<(Duration, u64)> {
+		let config = PageStorageConfig {
+			page_cache: PageCacheConfig {
+				page_cache_size: 4 * MIB,
+				flush_period: Duration::from_secs(3),
+				..Default::default()
+			},
+			wal: WalConfig {
+				max_generation_size: 16 * KIB,
+				checkpoint_period: Duration::from_secs(2),
+			},
+			..Default::default()
+		};
+		let sim = Simulator::new(seed);
+		let mut rng = fastrand::Rng::with_seed(seed);
+		let folder = Arc::new(MemoryFolder::new());
+		let page_storage =
+			PageStorage::create(Arc::clone(&folder), sim.runtime(), &config).unwrap();
+
+		let mut expected: HashMap<PageAddress, u8> = HashMap::new();
+		sim.run(|| {
+			let page_address = page_address!(rng.u32(0..2), rng.u16(1..=8));
+			let value = rng.u8(1..);
+			let mut t = page_storage.transaction().unwrap();
+			t.get_page_mut(page_address)
+				.unwrap()
+				.write(0, &[value; 64])
+				.unwrap();
+			t.commit().unwrap();
+			expected.insert(page_address, value);
+			rng.u8(..) != 0
+		});
+		sim.run_until_idle();
+		page_storage.flush_sync().unwrap();
+		mem::drop(page_storage);
+
+		let page_storage = PageStorage::open(folder, sim.runtime(), &config).unwrap();
+		page_storage.recover().unwrap();
+		for (page_address, value) in expected {
+			let mut data = [0; 64];
+			page_storage
+				.get_page(page_address)
+				.unwrap()
+				.read(0, &mut data)
+				.unwrap();
+			assert_buf_eq!(data, [value; 64]);
+		}
+		sim.trace()
+	}
+
+	#[test]
+	fn integration_simulated_background_tasks() {
+		let trace = run_simulated_workload(69);
+
+		// The same seed has to result in the same interleaving
+		assert!(trace.len() > 2);
+		assert_eq!(run_simulated_workload(69), trace);
+	}
+
 	#[bench]
 	fn bench_write_and_commit(b: &mut Bencher) {
 		let tempdir = tempdir().unwrap();
 
-		let folder = Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()));
-		let thread_pool = Arc::new(ThreadPool::new().unwrap());
-		let page_storage = PageStorage::create(folder, thread_pool, &Default::default()).unwrap();
+		let folder = Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()).unwrap());
+		let runtime = Runtime::from(Arc::new(ThreadPool::new().unwrap()));
+		let page_storage = PageStorage::create(folder, runtime, &Default::default()).unwrap();
 
 		const DATA: &[u8] = &[69; 16 * KIB];
 
@@ -852,9 +1649,9 @@ mod tests {
 	fn bench_flush(b: &mut Bencher) {
 		let tempdir = tempdir().unwrap();
 
-		let folder = Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()));
-		let thread_pool = Arc::new(ThreadPool::new().unwrap());
-		let page_storage = PageStorage::create(folder, thread_pool, &Default::default()).unwrap();
+		let folder = Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()).unwrap());
+		let runtime = Runtime::from(Arc::new(ThreadPool::new().unwrap()));
+		let page_storage = PageStorage::create(folder, runtime, &Default::default()).unwrap();
 
 		const DATA: &[u8] = &[69; 16 * KIB];
 
@@ -874,9 +1671,9 @@ mod tests {
 	fn bench_multi_page_write_and_commit(b: &mut Bencher) {
 		let tempdir = tempdir().unwrap();
 
-		let folder = Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()));
-		let thread_pool = Arc::new(ThreadPool::new().unwrap());
-		let page_storage = PageStorage::create(folder, thread_pool, &Default::default()).unwrap();
+		let folder = Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()).unwrap());
+		let runtime = Runtime::from(Arc::new(ThreadPool::new().unwrap()));
+		let page_storage = PageStorage::create(folder, runtime, &Default::default()).unwrap();
 
 		const DATA: &[u8] = &[69; 16 * KIB];
 
@@ -926,9 +1723,9 @@ mod tests {
 	fn bench_multi_page_flush(b: &mut Bencher) {
 		let tempdir = tempdir().unwrap();
 
-		let folder = Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()));
-		let thread_pool = Arc::new(ThreadPool::new().unwrap());
-		let page_storage = PageStorage::create(folder, thread_pool, &Default::default()).unwrap();
+		let folder = Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()).unwrap());
+		let runtime = Runtime::from(Arc::new(ThreadPool::new().unwrap()));
+		let page_storage = PageStorage::create(folder, runtime, &Default::default()).unwrap();
 
 		const DATA: &[u8] = &[69; 16 * KIB];
 
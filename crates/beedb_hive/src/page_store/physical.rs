@@ -1,28 +1,54 @@
-use std::{collections::HashMap, mem, sync::Arc};
+use std::{
+	borrow::Cow,
+	collections::{BTreeMap, HashMap, HashSet},
+	mem,
+	ptr::NonNull,
+	sync::Arc,
+};
 
+use log::warn;
 #[cfg(test)]
 use mockall::automock;
 
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use static_assertions::assert_impl_all;
 
 use crate::{
-	consts::DEFAULT_MAX_NUM_OPEN_SEGMENTS,
+	consts::{
+		DEFAULT_MAX_NUM_OPEN_SEGMENTS, DEFAULT_SEGMENT_EXTENT_SIZE, DEFAULT_USE_DIRECT_IO,
+		DEFAULT_USE_DOUBLE_WRITE,
+	},
 	files::{
-		segment::{SegmentFileApi, SegmentOp, SegmentReadOp, SegmentWriteOp},
-		DatabaseFolder, DatabaseFolderApi,
+		double_write::{DoubleWriteFileApi, DoubleWritePage},
+		segment::{
+			PageSize, ReadBuf, SegmentConfig, SegmentFileApi, SegmentOp, SegmentReadOp,
+			SegmentWriteOp,
+		},
+		working_set::WorkingSet,
+		DatabaseFolder, DatabaseFolderApi, FileError,
 	},
-	utils::cache::CacheReplacer,
+	utils::cache::{CacheReplacer, ReplacementPolicy, ReplacementPolicyKind},
+};
+
+#[cfg(feature = "io_uring")]
+use crate::{
+	consts::{DEFAULT_IO_QUEUE_DEPTH, DEFAULT_NUM_IO_RINGS},
+	files::io_ring::IoRing,
 };
 
-use super::{PageAddress, StorageError, WalIndex};
+use super::{mapped::MappedStorage, PageAddress, StorageError, WalIndex};
 
 pub(crate) struct PhysicalStorage<DF = DatabaseFolder>
 where
 	DF: DatabaseFolderApi,
 {
 	folder: Arc<DF>,
+	segment_config: SegmentConfig,
 	descriptor_cache: RwLock<DescriptorCache<DF>>,
+	double_write: Option<Mutex<Option<DF::DoubleWriteFile>>>,
+	// The segments that were written since the last sync. They may have been
+	// evicted from the descriptor cache since.
+	unsynced_segments: Mutex<HashSet<u32>>,
 }
 
 assert_impl_all!(PhysicalStorage: Send, Sync);
@@ -30,12 +56,32 @@ assert_impl_all!(PhysicalStorage: Send, Sync);
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct PhysicalStorageConfig {
 	pub max_num_open_segments: usize,
+	pub segment_extent_size: usize,
+	pub use_double_write: bool,
+	/// Whether segment files are accessed with direct IO, bypassing the kernel
+	/// page cache. Falls back to buffered IO if the file system doesn't
+	/// support it.
+	pub use_direct_io: bool,
+	/// The number of IO operations that can be in flight at once on a ring.
+	#[cfg(feature = "io_uring")]
+	pub io_queue_depth: u32,
+	/// The number of rings, which is how many batches can be executed
+	/// concurrently.
+	#[cfg(feature = "io_uring")]
+	pub num_io_rings: usize,
 }
 
 impl Default for PhysicalStorageConfig {
 	fn default() -> Self {
 		Self {
 			max_num_open_segments: DEFAULT_MAX_NUM_OPEN_SEGMENTS,
+			segment_extent_size: DEFAULT_SEGMENT_EXTENT_SIZE,
+			use_double_write: DEFAULT_USE_DOUBLE_WRITE,
+			use_direct_io: DEFAULT_USE_DIRECT_IO,
+			#[cfg(feature = "io_uring")]
+			io_queue_depth: DEFAULT_IO_QUEUE_DEPTH,
+			#[cfg(feature = "io_uring")]
+			num_io_rings: DEFAULT_NUM_IO_RINGS,
 		}
 	}
 }
@@ -44,15 +90,213 @@ impl<DF> PhysicalStorage<DF>
 where
 	DF: DatabaseFolderApi,
 {
-	pub fn new(folder: Arc<DF>, config: &PhysicalStorageConfig) -> Self {
+	pub fn new(folder: Arc<DF>, config: &PhysicalStorageConfig, page_size: PageSize) -> Self {
 		let descriptor_cache = RwLock::new(DescriptorCache::new(config));
+		let double_write = config.use_double_write.then(|| Mutex::new(None));
+		let segment_config = SegmentConfig {
+			extent_size: config.segment_extent_size,
+			direct_io: config.use_direct_io,
+			page_size,
+			#[cfg(feature = "io_uring")]
+			io_ring: Self::create_io_ring(config, page_size),
+		};
 		Self {
 			folder,
+			segment_config,
 			descriptor_cache,
+			double_write,
+			unsynced_segments: Mutex::new(HashSet::new()),
+		}
+	}
+
+	#[cfg(feature = "io_uring")]
+	fn create_io_ring(config: &PhysicalStorageConfig, page_size: PageSize) -> Option<Arc<IoRing>> {
+		let max_num_files = u32::try_from(config.max_num_open_segments).unwrap_or(u32::MAX);
+		let result = IoRing::new(
+			config.num_io_rings,
+			config.io_queue_depth,
+			max_num_files,
+			page_size.get(),
+		);
+		match result {
+			Ok(io_ring) => Some(Arc::new(io_ring)),
+			Err(err) => {
+				warn!("Failed to set up io_uring, falling back to synchronous IO: {err}");
+				None
+			}
+		}
+	}
+
+	/// Restores pages that were torn by a crash during a write from the
+	/// double-write buffer. Copies of pages that were truncated since they
+	/// were written are skipped.
+	///
+	/// This has to happen before any pages are read from the segment files.
+	pub fn restore_torn_pages(&self) -> Result<(), StorageError> {
+		let Some(double_write) = &self.double_write else {
+			return Ok(());
+		};
+		let mut double_write_file = double_write.lock();
+		let pages = self
+			.double_write_file(&mut double_write_file)?
+			.read_pages()?;
+		mem::drop(double_write_file);
+
+		// Opening missing segments would create them.
+		let existing: HashSet<u32> = self.folder.segment_nums()?.into_iter().collect();
+		for page in pages {
+			if !existing.contains(&page.page_address.segment_num) {
+				continue;
+			}
+			let mut high_water_mark = 0;
+			self.use_segment(page.page_address.segment_num, |segment| {
+				high_water_mark = segment.high_water_mark();
+				Ok(())
+			})?;
+			if page.page_address.page_num.get() > high_water_mark {
+				continue;
+			}
+
+			let mut buf = vec![0; self.segment_config.page_size.body_size()];
+			let mut wal_index: Option<WalIndex> = None;
+			let result = self.use_segment(page.page_address.segment_num, |segment| {
+				segment.read(SegmentReadOp {
+					page_num: page.page_address.page_num,
+					wal_index: &mut wal_index,
+					buf: ReadBuf::Body(&mut buf),
+				})?;
+				Ok(())
+			});
+			match result {
+				// If the page header wasn't written yet, the page will appear uninitialized.
+				Ok(()) if wal_index.is_some() => continue,
+				Ok(()) | Err(StorageError::File(FileError::ChecksumMismatch)) => (),
+				Err(err) => return Err(err),
+			}
+
+			warn!(
+				"Restoring torn page {} from the double-write buffer",
+				page.page_address
+			);
+			self.use_segment(page.page_address.segment_num, |segment| {
+				segment.write(SegmentWriteOp {
+					page_num: page.page_address.page_num,
+					wal_index: page.wal_index,
+					buf: &page.buf,
+				})?;
+				segment.sync()?;
+				Ok(())
+			})?;
+		}
+
+		Ok(())
+	}
+
+	fn double_write_file<'a>(
+		&self,
+		double_write_file: &'a mut Option<DF::DoubleWriteFile>,
+	) -> Result<&'a mut DF::DoubleWriteFile, StorageError> {
+		if double_write_file.is_none() {
+			*double_write_file = Some(
+				self.folder
+					.open_double_write_file(self.segment_config.page_size)?,
+			);
+		}
+		Ok(double_write_file.as_mut().unwrap())
+	}
+
+	fn exec_batch(&self, ops: Box<[Op]>) -> Result<(), StorageError> {
+		// Segments are written in order, so that the whole batch goes through
+		// the storage from front to back.
+		let mut segment_batches: BTreeMap<u32, Vec<SegmentOp>> = BTreeMap::new();
+		for op in ops {
+			let segment_num: u32;
+			let segment_op: SegmentOp;
+			match op {
+				Op::Read(read_op) => {
+					segment_num = read_op.page_address.segment_num;
+					segment_op = SegmentOp::Read(read_op.into());
+				}
+				Op::Write(write_op) => {
+					segment_num = write_op.page_address.segment_num;
+					segment_op = SegmentOp::Write(write_op.into());
+				}
+			}
+			segment_batches
+				.entry(segment_num)
+				.or_default()
+				.push(segment_op);
+		}
+
+		for (segment_num, mut ops) in segment_batches.into_iter() {
+			let has_writes = ops.iter().any(|op| matches!(op, SegmentOp::Write(..)));
+			self.use_segment(segment_num, |segment| {
+				segment.batch(&mut ops)?;
+				Ok(())
+			})?;
+			if has_writes {
+				self.mark_unsynced(segment_num);
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Remembers that the segment has to be synced. This happens after the
+	/// write, so that a sync that runs in between can't forget it.
+	fn mark_unsynced(&self, segment_num: u32) {
+		self.unsynced_segments.lock().insert(segment_num);
+	}
+
+	/// Executes a batch while protecting all of its writes against torn
+	/// pages.
+	///
+	/// The written pages are first persisted in the double-write buffer, and
+	/// the affected segments are synced afterwards, so that the buffer always
+	/// holds an intact copy of any page that may have been torn.
+	fn exec_batch_double_write(
+		&self,
+		double_write: &Mutex<Option<DF::DoubleWriteFile>>,
+		ops: Box<[Op]>,
+	) -> Result<(), StorageError> {
+		let pages: Vec<DoubleWritePage> = ops
+			.iter()
+			.filter_map(|op| match op {
+				Op::Write(write_op) => Some(DoubleWritePage {
+					page_address: write_op.page_address,
+					wal_index: write_op.wal_index,
+					buf: Cow::Borrowed(write_op.buf),
+				}),
+				Op::Read(..) => None,
+			})
+			.collect();
+		if pages.is_empty() {
+			return self.exec_batch(ops);
 		}
+		let written_segments: HashSet<u32> = pages
+			.iter()
+			.map(|page| page.page_address.segment_num)
+			.collect();
+
+		let mut double_write_file = double_write.lock();
+		self.double_write_file(&mut double_write_file)?
+			.write_pages(&pages)?;
+		mem::drop(pages);
+
+		self.exec_batch(ops)?;
+		for segment_num in written_segments {
+			self.use_segment(segment_num, |segment| {
+				segment.sync()?;
+				Ok(())
+			})?;
+		}
+		// The segments hold intact copies of all pages now.
+		double_write_file.as_mut().unwrap().clear()?;
+
+		Ok(())
 	}
 
-	fn use_segment(
+	pub(super) fn use_segment(
 		&self,
 		segment_num: u32,
 		handler: impl FnOnce(&DF::SegmentFile) -> Result<(), StorageError>,
@@ -63,7 +307,9 @@ where
 		}
 		mem::drop(cache);
 
-		let segment_file = self.folder.open_segment_file(segment_num)?;
+		let segment_file = self
+			.folder
+			.open_segment_file(segment_num, &self.segment_config)?;
 		let mut cache_mut = self.descriptor_cache.write();
 		let segment_file = cache_mut.store_descriptor(segment_num, segment_file);
 		handler(segment_file)
@@ -74,7 +320,7 @@ where
 pub(crate) struct ReadOp<'a> {
 	pub page_address: PageAddress,
 	pub wal_index: &'a mut Option<WalIndex>,
-	pub buf: &'a mut [u8],
+	pub buf: ReadBuf<'a>,
 }
 
 impl<'a> From<ReadOp<'a>> for SegmentReadOp<'a> {
@@ -118,6 +364,31 @@ pub(crate) trait PhysicalStorageApi {
 	fn write<'a>(&self, op: WriteOp<'a>) -> Result<(), StorageError>;
 
 	fn batch<'a>(&self, ops: Box<[Op<'a>]>) -> Result<(), StorageError>;
+
+	/// Discards all pages starting at `end`, shrinking or deleting the
+	/// affected segment files.
+	fn truncate(&self, end: PageAddress) -> Result<(), StorageError>;
+
+	/// Makes all writes so far durable.
+	fn sync(&self) -> Result<(), StorageError>;
+
+	/// Returns the working set that was saved for the page cache, if any.
+	/// Pages that don't exist anymore are left out.
+	fn read_working_set(&self) -> Result<Option<WorkingSet>, StorageError>;
+
+	fn write_working_set(&self, working_set: &WorkingSet) -> Result<(), StorageError>;
+
+	/// Registers memory that pages are read into and written from with the
+	/// IO backend, if it benefits from that. Returns whether the memory was
+	/// registered.
+	///
+	/// # Safety
+	///
+	/// The memory has to stay allocated and mapped to the same pages until
+	/// it is unregistered via [`PhysicalStorageApi::unregister_buffer`].
+	unsafe fn register_buffer(&self, buf: NonNull<u8>, len: usize) -> bool;
+
+	fn unregister_buffer(&self, buf: NonNull<u8>, len: usize);
 }
 
 impl<DF: DatabaseFolderApi> PhysicalStorageApi for PhysicalStorage<DF> {
@@ -129,42 +400,252 @@ impl<DF: DatabaseFolderApi> PhysicalStorageApi for PhysicalStorage<DF> {
 	}
 
 	fn write(&self, op: WriteOp) -> Result<(), StorageError> {
-		self.use_segment(op.page_address.segment_num, |segment| {
+		if let Some(double_write) = &self.double_write {
+			return self.exec_batch_double_write(double_write, Box::new([Op::Write(op)]));
+		}
+		let segment_num = op.page_address.segment_num;
+		self.use_segment(segment_num, |segment| {
 			segment.write(op.into())?;
 			Ok(())
-		})
+		})?;
+		self.mark_unsynced(segment_num);
+		Ok(())
 	}
 
 	fn batch(&self, ops: Box<[Op]>) -> Result<(), StorageError> {
-		let mut segment_batches: HashMap<u32, Vec<SegmentOp>> = HashMap::new();
-		for op in ops {
-			let segment_num: u32;
-			let segment_op: SegmentOp;
-			match op {
-				Op::Read(read_op) => {
-					segment_num = read_op.page_address.segment_num;
-					segment_op = SegmentOp::Read(read_op.into());
-				}
-				Op::Write(write_op) => {
-					segment_num = write_op.page_address.segment_num;
-					segment_op = SegmentOp::Write(write_op.into());
-				}
+		if let Some(double_write) = &self.double_write {
+			return self.exec_batch_double_write(double_write, ops);
+		}
+		self.exec_batch(ops)
+	}
+
+	fn truncate(&self, end: PageAddress) -> Result<(), StorageError> {
+		// A segment is empty if the end is at its first page, but segment 0
+		// contains the meta page and is always kept.
+		let last_segment_num = if end.page_num.get() == 1 {
+			end.segment_num.saturating_sub(1)
+		} else {
+			end.segment_num
+		};
+
+		let mut cache = self.descriptor_cache.write();
+		for segment_num in self.folder.segment_nums()? {
+			if segment_num > last_segment_num {
+				cache.remove_descriptor(segment_num);
+				// Syncing a deleted segment would create it again.
+				self.unsynced_segments.lock().remove(&segment_num);
+				self.folder.delete_segment_file(segment_num)?;
 			}
-			segment_batches
-				.entry(segment_num)
-				.or_default()
-				.push(segment_op);
 		}
+		mem::drop(cache);
 
-		for (segment_num, mut ops) in segment_batches.into_iter() {
+		let high_water_mark = if end.segment_num == last_segment_num {
+			end.page_num.get() - 1
+		} else {
+			u16::MAX
+		};
+		self.use_segment(last_segment_num, |segment| {
+			segment.truncate(high_water_mark)?;
+			Ok(())
+		})?;
+		self.mark_unsynced(last_segment_num);
+		Ok(())
+	}
+
+	fn sync(&self) -> Result<(), StorageError> {
+		let unsynced_segments = mem::take(&mut *self.unsynced_segments.lock());
+		let mut segment_nums = unsynced_segments.iter();
+		for &segment_num in &mut segment_nums {
+			let result = self.use_segment(segment_num, |segment| {
+				segment.sync()?;
+				Ok(())
+			});
+			if let Err(error) = result {
+				// The segments that weren't synced are synced by the next try.
+				let mut unsynced = self.unsynced_segments.lock();
+				unsynced.insert(segment_num);
+				unsynced.extend(segment_nums);
+				return Err(error);
+			}
+		}
+		Ok(())
+	}
+
+	fn read_working_set(&self) -> Result<Option<WorkingSet>, StorageError> {
+		let Some(mut working_set) = self.folder.read_working_set()? else {
+			return Ok(None);
+		};
+
+		// The storage may have been truncated since the working set was
+		// saved. Pages past the end of their segment would only take up slots
+		// as empty pages, and opening missing segments would create them.
+		let existing: HashSet<u32> = self.folder.segment_nums()?.into_iter().collect();
+		let mut high_water_marks: BTreeMap<u32, u16> = BTreeMap::new();
+		for page in &working_set.pages {
+			let segment_num = page.page_address.segment_num;
+			if !existing.contains(&segment_num) || high_water_marks.contains_key(&segment_num) {
+				continue;
+			}
 			self.use_segment(segment_num, |segment| {
-				segment.batch(&mut ops)?;
+				high_water_marks.insert(segment_num, segment.high_water_mark());
 				Ok(())
-			})?
+			})?;
 		}
+		working_set.pages.retain(|page| {
+			high_water_marks
+				.get(&page.page_address.segment_num)
+				.is_some_and(|high_water_mark| page.page_address.page_num.get() <= *high_water_mark)
+		});
+		Ok(Some(working_set))
+	}
 
+	fn write_working_set(&self, working_set: &WorkingSet) -> Result<(), StorageError> {
+		self.folder.write_working_set(working_set)?;
 		Ok(())
 	}
+
+	#[cfg(feature = "io_uring")]
+	unsafe fn register_buffer(&self, buf: NonNull<u8>, len: usize) -> bool {
+		// Safety: upheld by the caller.
+		self.segment_config
+			.io_ring
+			.as_ref()
+			.is_some_and(|io_ring| unsafe { io_ring.register_memory(buf, len) })
+	}
+
+	#[cfg(not(feature = "io_uring"))]
+	unsafe fn register_buffer(&self, _buf: NonNull<u8>, _len: usize) -> bool {
+		false
+	}
+
+	#[cfg(feature = "io_uring")]
+	fn unregister_buffer(&self, buf: NonNull<u8>, len: usize) {
+		if let Some(io_ring) = &self.segment_config.io_ring {
+			io_ring.unregister_memory(buf, len);
+		}
+	}
+
+	#[cfg(not(feature = "io_uring"))]
+	fn unregister_buffer(&self, _buf: NonNull<u8>, _len: usize) {}
+}
+
+/// Selects the implementation of [`PhysicalStorageApi`] used by a page
+/// storage.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PhysicalBackendKind {
+	/// Pages are read and written with regular file IO.
+	#[default]
+	FileIo,
+
+	/// Pages are read from memory-mapped segment files; see
+	/// [`MappedStorage`]. This only replaces the read system calls: pages are
+	/// still copied into the page cache, and every access goes through it,
+	/// so it doesn't save the memory of the cache.
+	Mapped,
+}
+
+pub(crate) enum PhysicalBackend<DF = DatabaseFolder>
+where
+	DF: DatabaseFolderApi,
+{
+	FileIo(PhysicalStorage<DF>),
+	Mapped(MappedStorage<DF>),
+}
+
+assert_impl_all!(PhysicalBackend: Send, Sync);
+
+impl<DF> PhysicalBackend<DF>
+where
+	DF: DatabaseFolderApi,
+{
+	pub fn new(
+		folder: Arc<DF>,
+		kind: PhysicalBackendKind,
+		config: &PhysicalStorageConfig,
+		page_size: PageSize,
+	) -> Self {
+		match kind {
+			PhysicalBackendKind::FileIo => {
+				Self::FileIo(PhysicalStorage::new(folder, config, page_size))
+			}
+			PhysicalBackendKind::Mapped => {
+				Self::Mapped(MappedStorage::new(folder, config, page_size))
+			}
+		}
+	}
+
+	pub fn restore_torn_pages(&self) -> Result<(), StorageError> {
+		match self {
+			Self::FileIo(storage) => storage.restore_torn_pages(),
+			Self::Mapped(storage) => storage.restore_torn_pages(),
+		}
+	}
+}
+
+impl<DF: DatabaseFolderApi> PhysicalStorageApi for PhysicalBackend<DF> {
+	fn read(&self, op: ReadOp) -> Result<(), StorageError> {
+		match self {
+			Self::FileIo(storage) => storage.read(op),
+			Self::Mapped(storage) => storage.read(op),
+		}
+	}
+
+	fn write(&self, op: WriteOp) -> Result<(), StorageError> {
+		match self {
+			Self::FileIo(storage) => storage.write(op),
+			Self::Mapped(storage) => storage.write(op),
+		}
+	}
+
+	fn batch(&self, ops: Box<[Op]>) -> Result<(), StorageError> {
+		match self {
+			Self::FileIo(storage) => storage.batch(ops),
+			Self::Mapped(storage) => storage.batch(ops),
+		}
+	}
+
+	fn truncate(&self, end: PageAddress) -> Result<(), StorageError> {
+		match self {
+			Self::FileIo(storage) => storage.truncate(end),
+			Self::Mapped(storage) => storage.truncate(end),
+		}
+	}
+
+	fn sync(&self) -> Result<(), StorageError> {
+		match self {
+			Self::FileIo(storage) => storage.sync(),
+			Self::Mapped(storage) => storage.sync(),
+		}
+	}
+
+	fn read_working_set(&self) -> Result<Option<WorkingSet>, StorageError> {
+		match self {
+			Self::FileIo(storage) => storage.read_working_set(),
+			Self::Mapped(storage) => storage.read_working_set(),
+		}
+	}
+
+	fn write_working_set(&self, working_set: &WorkingSet) -> Result<(), StorageError> {
+		match self {
+			Self::FileIo(storage) => storage.write_working_set(working_set),
+			Self::Mapped(storage) => storage.write_working_set(working_set),
+		}
+	}
+
+	unsafe fn register_buffer(&self, buf: NonNull<u8>, len: usize) -> bool {
+		// Safety: upheld by the caller.
+		match self {
+			Self::FileIo(storage) => unsafe { storage.register_buffer(buf, len) },
+			Self::Mapped(storage) => unsafe { storage.register_buffer(buf, len) },
+		}
+	}
+
+	fn unregister_buffer(&self, buf: NonNull<u8>, len: usize) {
+		match self {
+			Self::FileIo(storage) => storage.unregister_buffer(buf, len),
+			Self::Mapped(storage) => storage.unregister_buffer(buf, len),
+		}
+	}
 }
 
 struct DescriptorCache<DF: DatabaseFolderApi> {
@@ -176,7 +657,10 @@ struct DescriptorCache<DF: DatabaseFolderApi> {
 impl<DF: DatabaseFolderApi> DescriptorCache<DF> {
 	fn new(config: &PhysicalStorageConfig) -> Self {
 		let descriptors = HashMap::with_capacity(config.max_num_open_segments);
-		let replacer = CacheReplacer::new(config.max_num_open_segments);
+		let replacer = CacheReplacer::new(
+			ReplacementPolicyKind::default(),
+			config.max_num_open_segments,
+		);
 		Self {
 			descriptors,
 			replacer,
@@ -210,19 +694,27 @@ impl<DF: DatabaseFolderApi> DescriptorCache<DF> {
 		self.descriptors.insert(segment_num, segment_file);
 		self.descriptors.get(&segment_num).unwrap()
 	}
+
+	pub fn remove_descriptor(&mut self, segment_num: u32) {
+		if self.descriptors.remove(&segment_num).is_some() {
+			self.replacer.remove(&segment_num);
+		}
+	}
 }
 
 #[cfg(test)]
 mod tests {
 	use crate::{
 		files::{
+			double_write::MockDoubleWriteFileApi,
 			segment::{MockSegmentFileApi, PAGE_BODY_SIZE},
 			test_helpers::{page_address, wal_index},
+			working_set::WorkingSetPage,
 			MockDatabaseFolderApi,
 		},
-		utils::test_helpers::non_zero,
+		utils::{cache::CacheList, test_helpers::non_zero},
 	};
-	use mockall::predicate::*;
+	use mockall::{predicate::*, Sequence};
 
 	use super::*;
 
@@ -233,8 +725,8 @@ mod tests {
 		folder
 			.expect_open_segment_file()
 			.once()
-			.with(eq(69))
-			.returning(|_| {
+			.with(eq(69), eq(SegmentConfig::default()))
+			.returning(|_, _| {
 				let mut segment = MockSegmentFileApi::new();
 				segment
 					.expect_write()
@@ -251,7 +743,46 @@ mod tests {
 			});
 
 		// given
-		let storage = PhysicalStorage::new(Arc::new(folder), &Default::default());
+		let storage =
+			PhysicalStorage::new(Arc::new(folder), &Default::default(), PageSize::DEFAULT);
+
+		// when
+		storage
+			.write(WriteOp {
+				page_address: page_address!(69, 420),
+				buf: &[1; PAGE_BODY_SIZE],
+				wal_index: wal_index!(69, 420),
+			})
+			.unwrap();
+	}
+
+	#[test]
+	fn sync_written_segments() {
+		// expect
+		let mut folder = MockDatabaseFolderApi::new();
+		folder
+			.expect_open_segment_file()
+			.once()
+			.with(eq(69), eq(SegmentConfig::default()))
+			.returning(|_, _| {
+				let mut segment = MockSegmentFileApi::new();
+				let mut seq = Sequence::new();
+				segment
+					.expect_write()
+					.once()
+					.in_sequence(&mut seq)
+					.returning(|_| Ok(()));
+				segment
+					.expect_sync()
+					.once()
+					.in_sequence(&mut seq)
+					.returning(|| Ok(()));
+				Ok(segment)
+			});
+
+		// given
+		let storage =
+			PhysicalStorage::new(Arc::new(folder), &Default::default(), PageSize::DEFAULT);
 
 		// when
 		storage
@@ -261,6 +792,8 @@ mod tests {
 				wal_index: wal_index!(69, 420),
 			})
 			.unwrap();
+		storage.sync().unwrap();
+		storage.sync().unwrap();
 	}
 
 	#[test]
@@ -270,14 +803,14 @@ mod tests {
 		folder
 			.expect_open_segment_file()
 			.once()
-			.with(eq(69))
-			.returning(|_| {
+			.with(eq(69), eq(SegmentConfig::default()))
+			.returning(|_, _| {
 				let mut segment = MockSegmentFileApi::new();
 				segment
 					.expect_read()
 					.once()
 					.withf(|op| op.page_num == non_zero!(420))
-					.returning(|op| {
+					.returning(|mut op| {
 						op.buf[0..3].copy_from_slice(&[1, 2, 3]);
 						*op.wal_index = Some(wal_index!(69, 420));
 						Ok(())
@@ -286,7 +819,8 @@ mod tests {
 			});
 
 		// given
-		let storage = PhysicalStorage::new(Arc::new(folder), &Default::default());
+		let storage =
+			PhysicalStorage::new(Arc::new(folder), &Default::default(), PageSize::DEFAULT);
 
 		// when
 		let mut buf = [0; 3];
@@ -295,7 +829,7 @@ mod tests {
 			.read(ReadOp {
 				page_address: page_address!(69, 420),
 				wal_index: &mut wal_index,
-				buf: &mut buf,
+				buf: ReadBuf::Body(&mut buf),
 			})
 			.unwrap();
 
@@ -303,4 +837,271 @@ mod tests {
 		assert_eq!(wal_index, Some(wal_index!(69, 420)));
 		assert_eq!(buf[0..3], [1, 2, 3]);
 	}
+
+	#[test]
+	fn batch_with_double_write() {
+		// expect
+		let mut folder = MockDatabaseFolderApi::new();
+		let mut seq = Sequence::new();
+		folder
+			.expect_open_double_write_file()
+			.once()
+			.in_sequence(&mut seq)
+			.with(eq(PageSize::DEFAULT))
+			.returning(|_| {
+				let mut double_write_file = MockDoubleWriteFileApi::new();
+				double_write_file
+					.expect_write_pages()
+					.once()
+					.withf(|pages| {
+						pages
+							== [DoubleWritePage {
+								page_address: page_address!(69, 420),
+								wal_index: wal_index!(1, 2),
+								buf: Cow::Owned(vec![1; PAGE_BODY_SIZE]),
+							}]
+					})
+					.returning(|_| Ok(()));
+				double_write_file.expect_clear().once().returning(|| Ok(()));
+				Ok(double_write_file)
+			});
+		folder
+			.expect_open_segment_file()
+			.once()
+			.in_sequence(&mut seq)
+			.with(eq(69), eq(SegmentConfig::default()))
+			.returning(|_, _| {
+				let mut segment = MockSegmentFileApi::new();
+				let mut seq = Sequence::new();
+				segment
+					.expect_batch()
+					.once()
+					.in_sequence(&mut seq)
+					.withf(|ops| {
+						ops == [SegmentOp::Write(SegmentWriteOp {
+							page_num: non_zero!(420),
+							wal_index: wal_index!(1, 2),
+							buf: &[1; PAGE_BODY_SIZE],
+						})]
+					})
+					.returning(|_| Ok(()));
+				segment
+					.expect_sync()
+					.once()
+					.in_sequence(&mut seq)
+					.returning(|| Ok(()));
+				Ok(segment)
+			});
+
+		// given
+		let storage = PhysicalStorage::new(
+			Arc::new(folder),
+			&PhysicalStorageConfig {
+				use_double_write: true,
+				..Default::default()
+			},
+			PageSize::DEFAULT,
+		);
+
+		// when
+		storage
+			.batch(Box::new([Op::Write(WriteOp {
+				wal_index: wal_index!(1, 2),
+				page_address: page_address!(69, 420),
+				buf: &[1; PAGE_BODY_SIZE],
+			})]))
+			.unwrap();
+	}
+
+	#[test]
+	fn restore_torn_page() {
+		// expect
+		let mut folder = MockDatabaseFolderApi::new();
+		folder
+			.expect_open_double_write_file()
+			.once()
+			.with(eq(PageSize::DEFAULT))
+			.returning(|_| {
+				let mut double_write_file = MockDoubleWriteFileApi::new();
+				double_write_file.expect_read_pages().once().returning(|| {
+					Ok(vec![
+						DoubleWritePage {
+							page_address: page_address!(69, 420),
+							wal_index: wal_index!(1, 2),
+							buf: Cow::Owned(vec![1; PAGE_BODY_SIZE]),
+						},
+						DoubleWritePage {
+							page_address: page_address!(69, 421),
+							wal_index: wal_index!(1, 3),
+							buf: Cow::Owned(vec![2; PAGE_BODY_SIZE]),
+						},
+						// Pages that were truncated since are skipped
+						DoubleWritePage {
+							page_address: page_address!(69, 422),
+							wal_index: wal_index!(1, 4),
+							buf: Cow::Owned(vec![3; PAGE_BODY_SIZE]),
+						},
+						DoubleWritePage {
+							page_address: page_address!(70, 1),
+							wal_index: wal_index!(1, 5),
+							buf: Cow::Owned(vec![4; PAGE_BODY_SIZE]),
+						},
+					])
+				});
+				Ok(double_write_file)
+			});
+		folder
+			.expect_segment_nums()
+			.once()
+			.returning(|| Ok(vec![69]));
+		folder
+			.expect_open_segment_file()
+			.once()
+			.with(eq(69), eq(SegmentConfig::default()))
+			.returning(|_, _| {
+				let mut segment = MockSegmentFileApi::new();
+				segment.expect_high_water_mark().return_const(421_u16);
+				let mut seq = Sequence::new();
+
+				// Page 420 was torn, and is restored
+				segment
+					.expect_read()
+					.once()
+					.in_sequence(&mut seq)
+					.withf(|op| op.page_num == non_zero!(420))
+					.returning(|_| Err(FileError::ChecksumMismatch));
+				segment
+					.expect_write()
+					.once()
+					.in_sequence(&mut seq)
+					.withf(|op| {
+						*op == SegmentWriteOp {
+							page_num: non_zero!(420),
+							wal_index: wal_index!(1, 2),
+							buf: &[1; PAGE_BODY_SIZE],
+						}
+					})
+					.returning(|_| Ok(()));
+				segment
+					.expect_sync()
+					.once()
+					.in_sequence(&mut seq)
+					.returning(|| Ok(()));
+
+				// Page 421 is intact, and is left alone
+				segment
+					.expect_read()
+					.once()
+					.in_sequence(&mut seq)
+					.withf(|op| op.page_num == non_zero!(421))
+					.returning(|op| {
+						*op.wal_index = Some(wal_index!(1, 3));
+						Ok(())
+					});
+				Ok(segment)
+			});
+
+		// given
+		let storage = PhysicalStorage::new(
+			Arc::new(folder),
+			&PhysicalStorageConfig {
+				use_double_write: true,
+				..Default::default()
+			},
+			PageSize::DEFAULT,
+		);
+
+		// when
+		storage.restore_torn_pages().unwrap();
+	}
+
+	#[test]
+	fn leave_truncated_pages_out_of_working_set() {
+		// expect
+		let mut folder = MockDatabaseFolderApi::new();
+		folder.expect_read_working_set().once().returning(|| {
+			Ok(Some(WorkingSet {
+				pages: [
+					page_address!(1, 1),
+					page_address!(1, 3),
+					page_address!(2, 1),
+				]
+				.into_iter()
+				.map(|page_address| WorkingSetPage {
+					page_address,
+					list: CacheList::Recent,
+				})
+				.collect(),
+			}))
+		});
+		folder
+			.expect_segment_nums()
+			.once()
+			.returning(|| Ok(vec![0, 1]));
+		folder
+			.expect_open_segment_file()
+			.once()
+			.with(eq(1), eq(SegmentConfig::default()))
+			.returning(|_, _| {
+				let mut segment = MockSegmentFileApi::new();
+				segment.expect_high_water_mark().return_const(2_u16);
+				Ok(segment)
+			});
+
+		// given
+		let storage =
+			PhysicalStorage::new(Arc::new(folder), &Default::default(), PageSize::DEFAULT);
+
+		// when
+		let working_set = storage.read_working_set().unwrap().unwrap();
+
+		// then
+		assert_eq!(
+			working_set.pages,
+			[WorkingSetPage {
+				page_address: page_address!(1, 1),
+				list: CacheList::Recent,
+			}]
+		);
+	}
+
+	#[test]
+	fn truncate_storage() {
+		// expect
+		let mut folder = MockDatabaseFolderApi::new();
+		folder
+			.expect_segment_nums()
+			.once()
+			.returning(|| Ok(vec![0, 1, 2, 3]));
+		folder
+			.expect_delete_segment_file()
+			.once()
+			.with(eq(2))
+			.returning(|_| Ok(()));
+		folder
+			.expect_delete_segment_file()
+			.once()
+			.with(eq(3))
+			.returning(|_| Ok(()));
+		folder
+			.expect_open_segment_file()
+			.once()
+			.with(eq(1), eq(SegmentConfig::default()))
+			.returning(|_, _| {
+				let mut segment = MockSegmentFileApi::new();
+				segment
+					.expect_truncate()
+					.once()
+					.with(eq(419))
+					.returning(|_| Ok(()));
+				Ok(segment)
+			});
+
+		// given
+		let storage =
+			PhysicalStorage::new(Arc::new(folder), &Default::default(), PageSize::DEFAULT);
+
+		// when
+		storage.truncate(page_address!(1, 420)).unwrap();
+	}
 }
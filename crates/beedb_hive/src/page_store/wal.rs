@@ -6,7 +6,6 @@ use std::{
 	time::Duration,
 };
 
-use futures::executor::ThreadPool;
 use log::error;
 #[cfg(test)]
 use mockall::{automock, concretize};
@@ -17,10 +16,11 @@ use static_assertions::assert_impl_all;
 use crate::{
 	consts::{DEFAULT_CHECKPOINT_PERIOD, DEFAULT_MAX_WAL_GENERATION_SIZE},
 	files::{
+		manifest::Manifest,
 		wal::{self, CheckpointData, WalFileApi},
-		DatabaseFolder, DatabaseFolderApi,
+		DatabaseFolder, DatabaseFolderApi, FileError,
 	},
-	tasks::{Timer, TimerHandle},
+	tasks::{Runtime, Timer, TimerHandle},
 };
 
 use super::{PageAddress, StorageError, TransactionState, WalIndex};
@@ -72,33 +72,38 @@ pub(crate) struct CommitLog {
 
 pub(crate) struct Wal<DF: DatabaseFolderApi = DatabaseFolder> {
 	folder: Arc<DF>,
-	thread_pool: Arc<ThreadPool>,
+	runtime: Runtime,
 	generations: Arc<RwLock<GenerationQueue<DF>>>,
 	state: Arc<Mutex<State>>,
 	max_generation_size: usize,
-	checkpoint_timer_handle: TimerHandle,
+	checkpoint_timer_handle: Mutex<Option<TimerHandle>>,
+	/// Held while a checkpoint is taken and recorded in the manifest, so that
+	/// checkpoints and closing don't overwrite each other's updates. Set once
+	/// the WAL is closed, after which no checkpoint is taken anymore.
+	closed: Arc<Mutex<bool>>,
 }
 assert_impl_all!(Wal: Send, Sync);
 
 impl<DF: DatabaseFolderApi + Send + Sync + 'static> Wal<DF> {
 	pub fn create(
 		folder: Arc<DF>,
-		thread_pool: Arc<ThreadPool>,
+		runtime: Runtime,
 		config: &WalConfig,
 	) -> Result<Self, StorageError> {
 		folder.clear_wal_files()?;
 		let mut gens: GenerationQueue<DF> = GenerationQueue::new();
 		gens.push_generation(0, folder.open_wal_file(0)?);
 
-		let wal = Self::new(folder, thread_pool, config, gens, State::default());
-		Self::log_checkpoint(&wal.generations, &wal.state)?;
+		let wal = Self::new(folder, runtime, config, gens, State::default());
+		let index = Self::log_checkpoint(&wal.generations, &wal.state)?;
+		Self::record_checkpoint(&wal.folder, index)?;
 
 		Ok(wal)
 	}
 
 	pub fn open(
 		folder: Arc<DF>,
-		thread_pool: Arc<ThreadPool>,
+		runtime: Runtime,
 		config: &WalConfig,
 	) -> Result<Self, StorageError> {
 		let mut wal_files: Vec<(u64, DF::WalFile)> = Result::from_iter(folder.iter_wal_files()?)?;
@@ -109,58 +114,73 @@ impl<DF: DatabaseFolderApi + Send + Sync + 'static> Wal<DF> {
 			gens.push_generation(gen, file);
 		}
 
-		Ok(Self::new(
-			folder,
-			thread_pool,
-			config,
-			gens,
-			State::default(),
-		))
+		Ok(Self::new(folder, runtime, config, gens, State::default()))
 	}
 
 	fn new(
 		folder: Arc<DF>,
-		thread_pool: Arc<ThreadPool>,
+		runtime: Runtime,
 		config: &WalConfig,
 		generations: GenerationQueue<DF>,
 		state: State,
 	) -> Self {
 		let generations = Arc::new(RwLock::new(generations));
 		let state = Arc::new(Mutex::new(state));
+		let closed = Arc::new(Mutex::new(false));
 
-		let (checkpoint_timer, checkpoint_timer_handle) = Timer::new(config.checkpoint_period);
-		thread_pool.spawn_ok(Self::periodic_checkpoint_task(
+		let (checkpoint_timer, checkpoint_timer_handle) = runtime.timer(config.checkpoint_period);
+		runtime.spawn(Self::periodic_checkpoint_task(
 			checkpoint_timer,
 			Arc::clone(&generations),
 			Arc::clone(&state),
 			Arc::clone(&folder),
+			Arc::clone(&closed),
 		));
 
 		Self {
 			folder,
-			thread_pool,
+			runtime,
 			generations,
 			state,
 			max_generation_size: config.max_generation_size,
-			checkpoint_timer_handle,
+			checkpoint_timer_handle: Mutex::new(Some(checkpoint_timer_handle)),
+			closed,
 		}
 	}
 
 	fn log_checkpoint(
 		generations: &RwLock<GenerationQueue<DF>>,
 		state: &Mutex<State>,
-	) -> Result<(), StorageError> {
+	) -> Result<WalIndex, StorageError> {
 		let generations = generations.read();
 		let Some(mut wal_file) = generations.current_generation() else {
 			return Err(StorageError::WalNotInitialized);
 		};
 
 		let state = state.lock();
-		wal_file.push_item(wal::Item::Checkpoint(CheckpointData {
+		let offset = wal_file.push_item(wal::Item::Checkpoint(CheckpointData {
 			dirty_pages: Cow::Borrowed(&state.dirty_pages),
 			transactions: Cow::Borrowed(&state.transactions),
 		}))?;
 
+		Ok(WalIndex::new(generations.current_gen_num, offset))
+	}
+
+	/// Stores the index of the latest checkpoint in the manifest, if the
+	/// folder has one.
+	fn record_checkpoint(folder: &DF, index: WalIndex) -> Result<(), StorageError> {
+		Self::update_manifest(folder, |manifest| manifest.last_checkpoint = Some(index))
+	}
+
+	fn update_manifest(
+		folder: &DF,
+		update: impl FnOnce(&mut Manifest),
+	) -> Result<(), StorageError> {
+		let Some(mut manifest) = folder.read_manifest()? else {
+			return Ok(());
+		};
+		update(&mut manifest);
+		folder.write_manifest(&manifest)?;
 		Ok(())
 	}
 
@@ -248,13 +268,18 @@ impl<DF: DatabaseFolderApi + Send + Sync + 'static> Wal<DF> {
 		file: &mut DF::WalFile,
 		gen_num: u64,
 		mut handle: impl FnMut(PartialWriteOp) -> Result<(), StorageError>,
+		mut truncate: impl FnMut(PageAddress) -> Result<(), StorageError>,
 	) -> Result<(), StorageError> {
 		for item_result in file.iter_items()? {
 			let (offset, item) = item_result?;
 			let index = WalIndex::new(gen_num, offset);
 
-			if let wal::Item::Write(data) = item {
-				self.redo_write(index, data, &mut handle)?;
+			match item {
+				wal::Item::Write(data) => self.redo_write(index, data, &mut handle)?,
+				// Replayed in order, so that writes to the truncated pages
+				// from before the truncation don't grow the segments again.
+				wal::Item::Truncate(end) => truncate(end)?,
+				wal::Item::Commit(..) | wal::Item::Checkpoint(..) => (),
 			}
 		}
 		Ok(())
@@ -299,33 +324,37 @@ impl<DF: DatabaseFolderApi + Send + Sync + 'static> Wal<DF> {
 			.iter()
 			.filter_map(|tid| state.transactions.get(tid).map(|ts| ts.last_index))
 			.collect();
-
-		if last_indices.is_empty() {
-			return Ok(());
-		}
-
-		let lowest_index: WalIndex = *last_indices.iter().min().unwrap();
 		mem::drop(state);
 
+		// Every item of a transaction links to the previous one, so the items
+		// to revert can be found by following that chain from the last item.
 		let mut compensation_items: Vec<UndoLog> = Vec::new();
-
-		'gen_loop: for generation in gens.generations.iter().rev() {
-			let mut wal_file = generation.file.lock();
-			'item_loop: for item_result in wal_file.iter_items_reverse()? {
-				let (offset, item) = item_result?;
-				let index = WalIndex::new(generation.gen_num, offset);
-				if index < lowest_index {
-					break 'gen_loop;
-				}
-
-				if let wal::Item::Write(data) = item {
-					if !transaction_ids.contains(&data.transaction_data.transaction_id) {
-						continue 'item_loop;
-					}
-					if let Some(compensation_item) = Self::create_undo_log(data) {
-						compensation_items.push(compensation_item);
+		for last_index in last_indices {
+			let mut next_index = Some(last_index);
+			while let Some(index) = next_index {
+				let Some(generation) = gens
+					.generations
+					.iter()
+					.find(|generation| generation.gen_num == index.generation)
+				else {
+					return Err(FileError::Corrupted(format!(
+						"A transaction refers to the missing WAL generation {}",
+						index.generation
+					))
+					.into());
+				};
+				let item = generation.file.lock().read_item_at(index.offset)?;
+				next_index = match item {
+					wal::Item::Write(data) => {
+						let prev_index = data.transaction_data.prev_transaction_item;
+						if let Some(compensation_item) = Self::create_undo_log(data) {
+							compensation_items.push(compensation_item);
+						}
+						prev_index
 					}
-				}
+					wal::Item::Commit(data) => data.prev_transaction_item,
+					wal::Item::Checkpoint(..) | wal::Item::Truncate(..) => None,
+				};
 			}
 		}
 
@@ -354,18 +383,23 @@ impl<DF: DatabaseFolderApi + Send + Sync + 'static> Wal<DF> {
 		};
 		let index = WalIndex::new(gens.current_gen_num, wal_file.next_offset());
 
+		// The state may only reflect the item once it was actually written
 		let mut state = self.state.lock();
+		wal_file.push_item(item.clone())?;
 		state.handle_item(index, &item);
 		mem::drop(state);
 
-		wal_file.push_item(item)?;
-
 		if wal_file.size() >= self.max_generation_size {
 			let generations = Arc::clone(&self.generations);
 			let state = Arc::clone(&self.state);
 			let folder = Arc::clone(&self.folder);
-			self.thread_pool
-				.spawn_ok(Self::single_checkpoint_task(generations, state, folder))
+			let closed = Arc::clone(&self.closed);
+			self.runtime.spawn(Self::single_checkpoint_task(
+				generations,
+				state,
+				folder,
+				closed,
+			))
 		}
 
 		Ok(index)
@@ -420,11 +454,11 @@ impl<DF: DatabaseFolderApi + Send + Sync + 'static> Wal<DF> {
 		Ok(())
 	}
 
-	async fn checkpoint(
+	fn checkpoint(
 		generations: &RwLock<GenerationQueue<DF>>,
 		state: &Mutex<State>,
 		folder: &DF,
-	) -> Result<(), StorageError> {
+	) -> Result<WalIndex, StorageError> {
 		let mut gens_mut = generations.write();
 		Self::flush_impl(&gens_mut)?;
 		let gen_num = gens_mut.current_gen_num + 1;
@@ -432,16 +466,24 @@ impl<DF: DatabaseFolderApi + Send + Sync + 'static> Wal<DF> {
 		gens_mut.push_generation(gen_num, file);
 		Self::cleanup_generations(&mut gens_mut, state, folder)?;
 		mem::drop(gens_mut);
-		Self::log_checkpoint(generations, state)?;
-		Ok(())
+		let index = Self::log_checkpoint(generations, state)?;
+		Self::flush_impl(&generations.read())?;
+		Ok(index)
 	}
 
 	async fn checkpoint_ok(
 		generations: &RwLock<GenerationQueue<DF>>,
 		state: &Mutex<State>,
 		folder: &DF,
+		closed: &Mutex<bool>,
 	) {
-		if let Err(err) = Self::checkpoint(generations, state, folder).await {
+		let closed = closed.lock();
+		if *closed {
+			return;
+		}
+		let result = Self::checkpoint(generations, state, folder)
+			.and_then(|index| Self::record_checkpoint(folder, index));
+		if let Err(err) = result {
 			error!("A WAL checkpoint failed: {err}");
 		}
 	}
@@ -450,18 +492,20 @@ impl<DF: DatabaseFolderApi + Send + Sync + 'static> Wal<DF> {
 		generations: Arc<RwLock<GenerationQueue<DF>>>,
 		state: Arc<Mutex<State>>,
 		folder: Arc<DF>,
+		closed: Arc<Mutex<bool>>,
 	) {
-		Self::checkpoint_ok(&generations, &state, &folder).await;
+		Self::checkpoint_ok(&generations, &state, &folder, &closed).await;
 	}
 
 	async fn periodic_checkpoint_task(
-		timer: Timer,
+		mut timer: Timer,
 		generations: Arc<RwLock<GenerationQueue<DF>>>,
 		state: Arc<Mutex<State>>,
 		folder: Arc<DF>,
+		closed: Arc<Mutex<bool>>,
 	) {
-		while timer.wait() {
-			Self::checkpoint_ok(&generations, &state, &folder).await;
+		while timer.wait().await {
+			Self::checkpoint_ok(&generations, &state, &folder, &closed).await;
 		}
 	}
 }
@@ -478,12 +522,25 @@ pub(crate) trait WalApi {
 	where
 		HFn: FnMut(PartialWriteOp) -> Result<(), StorageError>;
 
+	/// Logs that all pages starting at `end` were given back to the file
+	/// system, and flushes the WAL.
+	fn log_truncate(&self, end: PageAddress) -> Result<WalIndex, StorageError>;
+
+	/// Replays the current generation, passing writes to `handle` and
+	/// truncations to `truncate` in the order they were logged, and then
+	/// reverts all transactions that didn't commit.
 	#[cfg_attr(test, concretize)]
-	fn recover<HFn>(&self, handle: &mut HFn) -> Result<(), StorageError>
+	fn recover<HFn, TFn>(&self, handle: &mut HFn, truncate: &mut TFn) -> Result<(), StorageError>
 	where
-		HFn: FnMut(PartialWriteOp) -> Result<(), StorageError>;
+		HFn: FnMut(PartialWriteOp) -> Result<(), StorageError>,
+		TFn: FnMut(PageAddress) -> Result<(), StorageError>;
 
 	fn cache_did_flush(&self);
+
+	/// Stops periodic checkpoints, and writes a final checkpoint that marks
+	/// the shutdown as clean. All pages have to be flushed and synced, and
+	/// no transaction may be running.
+	fn close(&self) -> Result<(), StorageError>;
 }
 
 impl<DF: DatabaseFolderApi + Send + Sync + 'static> WalApi for Wal<DF> {
@@ -501,6 +558,13 @@ impl<DF: DatabaseFolderApi + Send + Sync + 'static> WalApi for Wal<DF> {
 		Ok(index)
 	}
 
+	fn log_truncate(&self, end: PageAddress) -> Result<WalIndex, StorageError> {
+		let gens = self.generations.read();
+		let index = self.push_raw_item(wal::Item::Truncate(end), &gens)?;
+		Self::flush_impl(&gens)?;
+		Ok(index)
+	}
+
 	fn undo<HFn>(&self, transaction_id: u64, handle: HFn) -> Result<(), StorageError>
 	where
 		HFn: FnMut(PartialWriteOp) -> Result<(), StorageError>,
@@ -511,9 +575,14 @@ impl<DF: DatabaseFolderApi + Send + Sync + 'static> WalApi for Wal<DF> {
 		Ok(())
 	}
 
-	fn recover<HFn>(&self, mut handle: &mut HFn) -> Result<(), StorageError>
+	fn recover<HFn, TFn>(
+		&self,
+		mut handle: &mut HFn,
+		truncate: &mut TFn,
+	) -> Result<(), StorageError>
 	where
 		HFn: FnMut(PartialWriteOp) -> Result<(), StorageError>,
+		TFn: FnMut(PageAddress) -> Result<(), StorageError>,
 	{
 		// acquire exclusive gen lock to prevent conflicts
 		let mut gens = self.generations.write();
@@ -524,10 +593,38 @@ impl<DF: DatabaseFolderApi + Send + Sync + 'static> WalApi for Wal<DF> {
 
 		self.read_initial_state(&mut file)?;
 		self.recover_state(&mut file, gens.current_gen_num)?;
-		#[allow(clippy::needless_borrows_for_generic_args)]
-		self.redo(&mut file, gens.current_gen_num, &mut handle)?;
 		mem::drop(file);
 
+		// Writes from earlier generations may not have reached the disk
+		// either, so the redo starts with the oldest write to a dirty page.
+		let first_dirty_gen = self
+			.state
+			.lock()
+			.first_dirty_generation()
+			.unwrap_or(gens.current_gen_num);
+		if gens
+			.generations
+			.front()
+			.is_some_and(|generation| generation.gen_num > first_dirty_gen)
+		{
+			return Err(FileError::Corrupted(format!(
+				"A dirty page refers to the missing WAL generation {first_dirty_gen}"
+			))
+			.into());
+		}
+		for generation in gens.generations.iter() {
+			if generation.gen_num < first_dirty_gen {
+				continue;
+			}
+			#[allow(clippy::needless_borrows_for_generic_args)]
+			self.redo(
+				&mut generation.file.lock(),
+				generation.gen_num,
+				&mut handle,
+				&mut *truncate,
+			)?;
+		}
+
 		let state = self.state.lock();
 		let all_tids = state.transactions.keys().copied().collect::<Vec<_>>();
 		mem::drop(state);
@@ -541,6 +638,20 @@ impl<DF: DatabaseFolderApi + Send + Sync + 'static> WalApi for Wal<DF> {
 		let mut state = self.state.lock();
 		state.cache_did_flush();
 	}
+
+	fn close(&self) -> Result<(), StorageError> {
+		// Waits for a checkpoint that is in flight.
+		let mut closed = self.closed.lock();
+		self.checkpoint_timer_handle.lock().take();
+		self.cache_did_flush();
+		let index = Self::checkpoint(&self.generations, &self.state, &self.folder)?;
+		Self::update_manifest(&self.folder, |manifest| {
+			manifest.last_checkpoint = Some(index);
+			manifest.clean_shutdown = true;
+		})?;
+		*closed = true;
+		Ok(())
+	}
 }
 
 struct WalGeneration<DF: DatabaseFolderApi> {
@@ -576,7 +687,7 @@ impl<DF: DatabaseFolderApi> GenerationQueue<DF> {
 			.push_back(WalGeneration::new(gen_num, file))
 	}
 
-	fn current_generation(&self) -> Option<MutexGuard<DF::WalFile>> {
+	fn current_generation(&self) -> Option<MutexGuard<'_, DF::WalFile>> {
 		let generation = self.generations.back()?;
 		assert_eq!(generation.gen_num, self.current_gen_num);
 		Some(generation.file.lock())
@@ -627,33 +738,52 @@ impl State {
 		self.dirty_pages.clear();
 	}
 
+	/// Returns the oldest generation that is still needed, either to revert
+	/// a running transaction, or to redo the writes to a page that may not
+	/// have reached the disk yet.
 	fn first_needed_generation(&self) -> u64 {
-		self.transactions
+		let first_transaction_gen = self.transactions.values().map(|ts| ts.first_gen).min();
+		let first_dirty_gen = self.first_dirty_generation();
+		Option::min(
+			first_transaction_gen.or(first_dirty_gen),
+			first_dirty_gen.or(first_transaction_gen),
+		)
+		.unwrap_or(u64::MAX)
+	}
+
+	/// Returns the generation of the oldest write that recovery has to redo.
+	fn first_dirty_generation(&self) -> Option<u64> {
+		self.dirty_pages
 			.values()
-			.map(|ts| ts.first_gen)
+			.map(|index| index.generation)
 			.min()
-			.unwrap_or(u64::MAX)
 	}
 
 	fn handle_item(&mut self, index: WalIndex, item: &wal::Item) {
 		match item {
 			wal::Item::Write(data) => self.track_write(index, data),
 			wal::Item::Commit(data) => self.complete_transaction(data.transaction_id),
-			wal::Item::Checkpoint(..) => (),
+			wal::Item::Checkpoint(..) | wal::Item::Truncate(..) => (),
 		}
 	}
 }
 
 #[cfg(test)]
 mod tests {
+	use std::cell::Cell;
+
+	use futures::executor::ThreadPool;
 	use mockall::{predicate::*, Sequence};
 
 	use crate::{
-		files::MockDatabaseFolderApi,
+		files::{
+			manifest::DatabaseId, memory::MemoryFolder, segment::PageSize, MockDatabaseFolderApi,
+		},
 		page_store::{
 			test_helpers::{page_address, wal_index},
 			wal::tests::wal::test_helpers::mock_wal_file,
 		},
+		tasks::sim::Simulator,
 		utils::test_helpers::{map, non_zero},
 	};
 
@@ -661,6 +791,18 @@ mod tests {
 
 	use super::*;
 
+	fn test_manifest(last_checkpoint: Option<WalIndex>) -> Manifest {
+		Manifest {
+			database_id: DatabaseId::from_bytes([69; 16]),
+			created_at: 420,
+			segment_version: 3,
+			wal_version: 2,
+			clean_shutdown: false,
+			last_checkpoint,
+			page_size: PageSize::DEFAULT,
+		}
+	}
+
 	#[test]
 	fn create_wal() {
 		// expect
@@ -689,16 +831,73 @@ mod tests {
 					.returning(|_| Ok(non_zero!(69)));
 				Ok(file)
 			});
+		folder
+			.expect_read_manifest()
+			.once()
+			.in_sequence(&mut seq)
+			.returning(|| Ok(Some(test_manifest(None))));
+		folder
+			.expect_write_manifest()
+			.once()
+			.in_sequence(&mut seq)
+			.with(eq(test_manifest(Some(wal_index!(0, 69)))))
+			.returning(|_| Ok(()));
 
 		// when
 		Wal::create(
 			Arc::new(folder),
-			Arc::new(ThreadPool::new().unwrap()),
+			Runtime::from(Arc::new(ThreadPool::new().unwrap())),
 			&WalConfig::default(),
 		)
 		.unwrap();
 	}
 
+	#[test]
+	fn keep_clean_shutdown_after_late_checkpoint() {
+		// given
+		let sim = Simulator::new(69);
+		let folder = Arc::new(MemoryFolder::new());
+		folder.write_manifest(&test_manifest(None)).unwrap();
+		let wal = Wal::create(
+			Arc::clone(&folder),
+			sim.runtime(),
+			&WalConfig {
+				max_generation_size: 1,
+				..Default::default()
+			},
+		)
+		.unwrap();
+		// Fills the generation, which spawns a checkpoint
+		wal.log_write(WriteLog {
+			transaction_id: 1,
+			page_address: page_address!(1, 2),
+			offset: 0,
+			from: &[0, 0],
+			to: &[1, 2],
+		})
+		.unwrap();
+		wal.log_commit(CommitLog { transaction_id: 1 }).unwrap();
+
+		let generations = || -> Vec<u64> {
+			folder
+				.iter_wal_files()
+				.unwrap()
+				.map(|file| file.unwrap().0)
+				.collect()
+		};
+
+		// when
+		wal.close().unwrap();
+		let closed_manifest = folder.read_manifest().unwrap();
+		let closed_generations = generations();
+		sim.run_until_idle();
+
+		// then
+		assert!(closed_manifest.as_ref().unwrap().clean_shutdown);
+		assert_eq!(folder.read_manifest().unwrap(), closed_manifest);
+		assert_eq!(generations(), closed_generations);
+	}
+
 	#[test]
 	fn open_and_recover_wal() {
 		// expect
@@ -761,7 +960,10 @@ mod tests {
 				30 => wal::Item::Commit(wal::TransactionData {
 					transaction_id: 2,
 					prev_transaction_item: Some(wal_index!(2, 30))
-				})
+				}),
+
+				// A truncation after the reapplied write; it should be replayed after it.
+				35 => wal::Item::Truncate(page_address!(30, 1))
 			};
 
 			let mut seq = Sequence::new();
@@ -835,6 +1037,13 @@ mod tests {
 
 		// when
 		let mut expected_ops = vec![
+			// This reapplies write (2, 20), since its page is still dirty.
+			PartialWriteOp {
+				index: wal_index!(2, 20),
+				page_address: page_address!(100, 200),
+				offset: 25,
+				buf: &[1, 2, 3, 4],
+			},
 			// This reapplies write (3, 10).
 			PartialWriteOp {
 				index: wal_index!(3, 10),
@@ -854,15 +1063,27 @@ mod tests {
 
 		let wal = Wal::open(
 			Arc::new(folder),
-			Arc::new(ThreadPool::new().unwrap()),
+			Runtime::from(Arc::new(ThreadPool::new().unwrap())),
 			&WalConfig::default(),
 		)
 		.unwrap();
-		wal.recover(&mut |op| {
-			// Write operations should appear in the order of expected_ops.
-			assert_eq!(Some(op), expected_ops.next());
-			Ok(())
-		})
+		let num_ops = Cell::new(0);
+		let mut truncations = Vec::new();
+		wal.recover(
+			&mut |op| {
+				// Write operations should appear in the order of expected_ops.
+				assert_eq!(Some(op), expected_ops.next());
+				num_ops.set(num_ops.get() + 1);
+				Ok(())
+			},
+			&mut |end| {
+				truncations.push((num_ops.get(), end));
+				Ok(())
+			},
+		)
 		.unwrap();
+
+		// then
+		assert_eq!(truncations, vec![(2, page_address!(30, 1))]);
 	}
 }
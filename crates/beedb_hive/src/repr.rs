@@ -3,7 +3,7 @@ use std::{
 	mem,
 };
 
-use zerocopy::{FromBytes, Immutable, IntoBytes};
+use zerocopy::{little_endian::U16, FromBytes, Immutable, IntoBytes};
 
 pub(crate) trait Repr<T>: Sized + FromBytes + IntoBytes
 where
@@ -18,6 +18,15 @@ where
 		repr.as_mut_bytes().copy_from_slice(bytes);
 		Ok(T::try_from(repr)?)
 	}
+
+	fn from_bytes_in(bytes: &[u8], byte_order: ByteOrder) -> Result<T, Self::Error>
+	where
+		Self: SwapBytes,
+	{
+		let mut repr = Self::new_zeroed();
+		repr.as_mut_bytes().copy_from_slice(bytes);
+		Ok(T::try_from(repr.convert(byte_order))?)
+	}
 }
 
 pub(crate) trait IoRepr<T>: Repr<T>
@@ -37,6 +46,28 @@ where
 		reader.read_exact(repr.as_mut_bytes())?;
 		Ok(T::try_from(repr)?)
 	}
+
+	fn serialize_in(
+		value: T,
+		mut writer: impl Write,
+		byte_order: ByteOrder,
+	) -> Result<(), Self::Error>
+	where
+		Self: SwapBytes,
+	{
+		let repr: Self = value.into();
+		writer.write_all(repr.convert(byte_order).as_bytes())?;
+		Ok(())
+	}
+
+	fn deserialize_in(mut reader: impl Read, byte_order: ByteOrder) -> Result<T, Self::Error>
+	where
+		Self: SwapBytes,
+	{
+		let mut repr = Self::new_zeroed();
+		reader.read_exact(repr.as_mut_bytes())?;
+		Ok(T::try_from(repr.convert(byte_order))?)
+	}
 }
 
 impl<T, R> IoRepr<T> for R
@@ -47,3 +78,48 @@ where
 	Self::Error: From<io::Error>,
 {
 }
+
+/// The byte order of the integers in a file. All files are written in
+/// little-endian byte order now, but files from before that used the native
+/// byte order of the machine that wrote them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum ByteOrder {
+	Big = 0,
+	Little = 1,
+}
+
+/// Converts a repr with little-endian fields from or to another byte order.
+pub(crate) trait SwapBytes: Sized {
+	/// Reverses the bytes of every integer field.
+	fn swap_bytes(&mut self);
+
+	/// Converts between little-endian and the given byte order. Since the
+	/// conversion just swaps bytes, it works in both directions.
+	fn convert(mut self, byte_order: ByteOrder) -> Self {
+		if byte_order == ByteOrder::Big {
+			self.swap_bytes();
+		}
+		self
+	}
+}
+
+/// Implements [`SwapBytes`] for a repr by swapping the given fields, which
+/// must be zerocopy's little-endian integer types.
+macro_rules! impl_swap_bytes {
+	($repr:ty { $($field:ident),* $(,)? }) => {
+		impl $crate::repr::SwapBytes for $repr {
+			fn swap_bytes(&mut self) {
+				$(self.$field = self.$field.get().swap_bytes().into();)*
+			}
+		}
+	};
+}
+
+pub(crate) use impl_swap_bytes;
+
+impl SwapBytes for U16 {
+	fn swap_bytes(&mut self) {
+		*self = self.get().swap_bytes().into();
+	}
+}
@@ -0,0 +1,311 @@
+use std::{
+	collections::BTreeMap,
+	future::Future,
+	mem,
+	pin::Pin,
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc,
+	},
+	task::{Context, Poll, Waker},
+	thread,
+	time::{Duration, Instant},
+};
+
+use futures::{executor::ThreadPool, future::BoxFuture};
+use parking_lot::{Condvar, Mutex};
+
+#[cfg(test)]
+pub(crate) mod sim;
+
+#[derive(Clone)]
+pub(crate) struct FailureStrategy {
+	pub fatal: bool,
+	pub retries: usize,
+}
+
+impl Default for FailureStrategy {
+	fn default() -> Self {
+		Self {
+			fatal: false,
+			retries: 3,
+		}
+	}
+}
+
+/// Runs background tasks.
+pub(crate) trait Executor: Send + Sync {
+	fn spawn(&self, task: BoxFuture<'static, ()>);
+}
+
+impl Executor for ThreadPool {
+	fn spawn(&self, task: BoxFuture<'static, ()>) {
+		self.spawn_ok(task);
+	}
+}
+
+/// The time source for background tasks.
+pub(crate) trait Clock: Send + Sync {
+	/// Returns the time that passed since the clock was started.
+	fn now(&self) -> Duration;
+
+	fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+/// A clock that follows the wall-clock time.
+///
+/// Sleeping tasks don't block their executor thread. They are woken up by a
+/// single timer thread, which is started with the first sleep, and stops
+/// when the clock is dropped.
+pub(crate) struct SystemClock {
+	start: Instant,
+	timers: Arc<TimerQueue>,
+}
+
+impl SystemClock {
+	pub fn new() -> Self {
+		Self {
+			start: Instant::now(),
+			timers: Arc::new(TimerQueue::default()),
+		}
+	}
+}
+
+impl Default for SystemClock {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Drop for SystemClock {
+	fn drop(&mut self) {
+		self.timers.state.lock().stopped = true;
+		self.timers.changed.notify_all();
+	}
+}
+
+impl Clock for SystemClock {
+	fn now(&self) -> Duration {
+		self.start.elapsed()
+	}
+
+	fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+		Box::pin(Sleep {
+			deadline: Instant::now() + duration,
+			timers: Arc::clone(&self.timers),
+			waker: None,
+		})
+	}
+}
+
+#[derive(Default)]
+struct TimerQueue {
+	state: Mutex<TimerState>,
+	/// Notified when a timer is added that is due before all others, or
+	/// when the clock is dropped.
+	changed: Condvar,
+}
+
+#[derive(Default)]
+struct TimerState {
+	next_timer_id: u64,
+	timers: BTreeMap<(Instant, u64), Arc<Mutex<Option<Waker>>>>,
+	thread_started: bool,
+	stopped: bool,
+}
+
+impl TimerQueue {
+	/// Registers a timer that wakes up the task with the waker in `waker`
+	/// once the deadline has passed.
+	fn add(self: &Arc<Self>, deadline: Instant, waker: Arc<Mutex<Option<Waker>>>) {
+		let mut state = self.state.lock();
+		let timer_id = state.next_timer_id;
+		state.next_timer_id += 1;
+		let is_first = state
+			.timers
+			.first_key_value()
+			.is_none_or(|((first_deadline, _), _)| deadline < *first_deadline);
+		state.timers.insert((deadline, timer_id), waker);
+		if !state.thread_started {
+			state.thread_started = true;
+			let timers = Arc::clone(self);
+			thread::Builder::new()
+				.name("beedb-timers".to_string())
+				.spawn(move || timers.run())
+				.expect("Failed to start the timer thread");
+		}
+		mem::drop(state);
+		if is_first {
+			self.changed.notify_all();
+		}
+	}
+
+	fn run(&self) {
+		let mut state = self.state.lock();
+		while !state.stopped {
+			let now = Instant::now();
+			while let Some(entry) = state.timers.first_entry() {
+				if entry.key().0 > now {
+					break;
+				}
+				if let Some(waker) = entry.remove().lock().take() {
+					waker.wake();
+				}
+			}
+			match state.timers.first_key_value() {
+				Some(((deadline, _), _)) => {
+					let deadline = *deadline;
+					self.changed.wait_until(&mut state, deadline);
+				}
+				None => self.changed.wait(&mut state),
+			}
+		}
+	}
+}
+
+/// Completes once its deadline has passed.
+struct Sleep {
+	deadline: Instant,
+	timers: Arc<TimerQueue>,
+	/// The waker of the task that waits for the timer, once it is registered.
+	waker: Option<Arc<Mutex<Option<Waker>>>>,
+}
+
+impl Future for Sleep {
+	type Output = ();
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+		if Instant::now() >= self.deadline {
+			return Poll::Ready(());
+		}
+		match &self.waker {
+			Some(waker) => *waker.lock() = Some(cx.waker().clone()),
+			None => {
+				let waker = Arc::new(Mutex::new(Some(cx.waker().clone())));
+				self.timers.add(self.deadline, Arc::clone(&waker));
+				self.waker = Some(waker);
+			}
+		}
+		Poll::Pending
+	}
+}
+
+/// The executor and clock that background tasks run on.
+#[derive(Clone)]
+pub(crate) struct Runtime {
+	executor: Arc<dyn Executor>,
+	clock: Arc<dyn Clock>,
+}
+
+impl Runtime {
+	pub fn new(executor: Arc<dyn Executor>, clock: Arc<dyn Clock>) -> Self {
+		Self { executor, clock }
+	}
+
+	pub fn spawn(&self, task: impl Future<Output = ()> + Send + 'static) {
+		self.executor.spawn(Box::pin(task));
+	}
+
+	pub fn now(&self) -> Duration {
+		self.clock.now()
+	}
+
+	pub fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+		self.clock.sleep(duration)
+	}
+
+	pub fn timer(&self, period: Duration) -> (Timer, TimerHandle) {
+		Timer::new(Arc::clone(&self.clock), period)
+	}
+}
+
+impl From<Arc<ThreadPool>> for Runtime {
+	fn from(thread_pool: Arc<ThreadPool>) -> Self {
+		Self::new(thread_pool, Arc::new(SystemClock::new()))
+	}
+}
+
+pub(crate) struct Timer {
+	clock: Arc<dyn Clock>,
+	next_run: Duration,
+	period: Duration,
+	active: Arc<AtomicBool>,
+}
+
+impl Timer {
+	pub fn new(clock: Arc<dyn Clock>, period: Duration) -> (Self, TimerHandle) {
+		let active = Arc::new(AtomicBool::new(true));
+		let timer = Self {
+			next_run: clock.now() + period,
+			clock,
+			period,
+			active: Arc::clone(&active),
+		};
+		(timer, TimerHandle { active })
+	}
+
+	/// Waits until the next period starts. Returns `false` once the timer
+	/// was stopped.
+	pub async fn wait(&mut self) -> bool {
+		if !self.active.load(Ordering::Relaxed) {
+			return false;
+		}
+		let duration = self.next_run.saturating_sub(self.clock.now());
+		self.clock.sleep(duration).await;
+		self.next_run = self.clock.now() + self.period;
+		self.active.load(Ordering::Relaxed)
+	}
+}
+
+pub(crate) struct TimerHandle {
+	active: Arc<AtomicBool>,
+}
+
+impl TimerHandle {
+	pub fn stop(self) {}
+}
+
+impl Drop for TimerHandle {
+	fn drop(&mut self) {
+		self.active.store(false, Ordering::Relaxed);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::mpsc;
+
+	use super::*;
+
+	#[test]
+	fn sleeps_dont_block_the_executor() {
+		// given
+		let thread_pool = ThreadPool::builder().pool_size(1).create().unwrap();
+		let runtime = Runtime::from(Arc::new(thread_pool));
+		let (sender, receiver) = mpsc::channel();
+		let long_sleep = runtime.sleep(Duration::from_secs(3600));
+		let long_sender = sender.clone();
+		runtime.spawn(async move {
+			long_sender.send("long sleep started").unwrap();
+			long_sleep.await;
+			long_sender.send("long sleep finished").unwrap();
+		});
+		let first_event = receiver.recv().unwrap();
+
+		// when
+		let short_sleep = runtime.sleep(Duration::from_millis(10));
+		runtime.spawn(async move {
+			short_sleep.await;
+			sender.send("short sleep finished").unwrap();
+		});
+
+		// then
+		assert_eq!(first_event, "long sleep started");
+		// The timeout only keeps the test from hanging if the executor is
+		// blocked by the long sleep.
+		assert_eq!(
+			receiver.recv_timeout(Duration::from_secs(60)),
+			Ok("short sleep finished")
+		);
+	}
+}
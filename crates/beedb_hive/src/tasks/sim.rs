@@ -0,0 +1,281 @@
+use std::{
+	collections::{BTreeMap, BTreeSet, HashMap},
+	future::Future,
+	pin::Pin,
+	sync::{Arc, Weak},
+	task::{Context, Poll, Waker},
+	time::Duration,
+};
+
+use futures::{
+	future::BoxFuture,
+	task::{self, ArcWake},
+};
+use parking_lot::Mutex;
+
+use super::{Clock, Executor, Runtime};
+
+/// A seeded, single-threaded executor and clock for background tasks.
+///
+/// Spawned tasks only make progress when the simulator is stepped, and time
+/// only passes when it is advanced, so that a test can interleave its own
+/// operations with background tasks in an order that only depends on the
+/// seed.
+#[derive(Clone)]
+pub(crate) struct Simulator {
+	shared: Arc<Shared>,
+}
+
+struct Shared {
+	state: Mutex<State>,
+	ready: Mutex<BTreeSet<u64>>,
+}
+
+struct State {
+	rng: fastrand::Rng,
+	now: Duration,
+	next_task_id: u64,
+	tasks: HashMap<u64, BoxFuture<'static, ()>>,
+	next_timer_id: u64,
+	timers: BTreeMap<(Duration, u64), Waker>,
+	trace: Vec<(Duration, u64)>,
+}
+
+impl Simulator {
+	pub fn new(seed: u64) -> Self {
+		Self {
+			shared: Arc::new(Shared {
+				state: Mutex::new(State {
+					rng: fastrand::Rng::with_seed(seed),
+					now: Duration::ZERO,
+					next_task_id: 0,
+					tasks: HashMap::new(),
+					next_timer_id: 0,
+					timers: BTreeMap::new(),
+					trace: Vec::new(),
+				}),
+				ready: Mutex::new(BTreeSet::new()),
+			}),
+		}
+	}
+
+	pub fn runtime(&self) -> Runtime {
+		Runtime::new(Arc::new(self.clone()), Arc::new(self.clone()))
+	}
+
+	/// Polls one randomly chosen task that is ready to make progress.
+	/// Returns `false` if no task was ready.
+	pub fn step(&self) -> bool {
+		let ready: Vec<u64> = self.shared.ready.lock().iter().copied().collect();
+		if ready.is_empty() {
+			return false;
+		}
+
+		let mut state = self.shared.state.lock();
+		let task_id = ready[state.rng.usize(..ready.len())];
+		self.shared.ready.lock().remove(&task_id);
+		let Some(mut task) = state.tasks.remove(&task_id) else {
+			return true;
+		};
+		let now = state.now;
+		state.trace.push((now, task_id));
+		std::mem::drop(state);
+
+		let waker = task::waker(Arc::new(TaskWaker {
+			task_id,
+			shared: Arc::downgrade(&self.shared),
+		}));
+		if task
+			.as_mut()
+			.poll(&mut Context::from_waker(&waker))
+			.is_pending()
+		{
+			self.shared.state.lock().tasks.insert(task_id, task);
+		}
+		true
+	}
+
+	/// Advances the clock to the next timer, and wakes up all tasks waiting
+	/// for it. Returns `false` if no task was waiting for a timer.
+	pub fn advance(&self) -> bool {
+		let mut state = self.shared.state.lock();
+		let Some(((deadline, _), _)) = state.timers.first_key_value() else {
+			return false;
+		};
+		let deadline = *deadline;
+		state.now = Duration::max(state.now, deadline);
+		let mut wakers: Vec<Waker> = Vec::new();
+		while let Some(entry) = state.timers.first_entry() {
+			if entry.key().0 > deadline {
+				break;
+			}
+			wakers.push(entry.remove());
+		}
+		std::mem::drop(state);
+
+		for waker in wakers {
+			waker.wake();
+		}
+		true
+	}
+
+	/// Runs all tasks until none of them can make progress without the
+	/// clock advancing.
+	pub fn run_until_idle(&self) {
+		while self.step() {}
+	}
+
+	/// Randomly interleaves calls to `foreground` with steps of background
+	/// tasks and advances of the clock, until `foreground` returns `false`.
+	pub fn run(&self, mut foreground: impl FnMut() -> bool) {
+		loop {
+			let choice = self.shared.state.lock().rng.u8(..3);
+			match choice {
+				0 => {
+					if !foreground() {
+						return;
+					}
+				}
+				1 => {
+					self.step();
+				}
+				_ => {
+					self.advance();
+				}
+			}
+		}
+	}
+
+	/// Returns the time and task of every poll so far.
+	pub fn trace(&self) -> Vec<(Duration, u64)> {
+		self.shared.state.lock().trace.clone()
+	}
+}
+
+impl Executor for Simulator {
+	fn spawn(&self, task: BoxFuture<'static, ()>) {
+		let mut state = self.shared.state.lock();
+		let task_id = state.next_task_id;
+		state.next_task_id += 1;
+		state.tasks.insert(task_id, task);
+		std::mem::drop(state);
+		self.shared.ready.lock().insert(task_id);
+	}
+}
+
+impl Clock for Simulator {
+	fn now(&self) -> Duration {
+		self.shared.state.lock().now
+	}
+
+	fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+		Box::pin(Sleep {
+			deadline: self.now() + duration,
+			shared: Arc::clone(&self.shared),
+		})
+	}
+}
+
+struct TaskWaker {
+	task_id: u64,
+	shared: Weak<Shared>,
+}
+
+impl ArcWake for TaskWaker {
+	fn wake_by_ref(arc_self: &Arc<Self>) {
+		if let Some(shared) = arc_self.shared.upgrade() {
+			shared.ready.lock().insert(arc_self.task_id);
+		}
+	}
+}
+
+struct Sleep {
+	deadline: Duration,
+	shared: Arc<Shared>,
+}
+
+impl Future for Sleep {
+	type Output = ();
+
+	fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+		let mut state = self.shared.state.lock();
+		if state.now >= self.deadline {
+			return Poll::Ready(());
+		}
+		let timer_id = state.next_timer_id;
+		state.next_timer_id += 1;
+		state
+			.timers
+			.insert((self.deadline, timer_id), cx.waker().clone());
+		Poll::Pending
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn spawn_periodic(sim: &Simulator, period: u64, log: &Arc<Mutex<Vec<(u64, Duration)>>>) {
+		let runtime = sim.runtime();
+		let (mut timer, handle) = runtime.timer(Duration::from_secs(period));
+		std::mem::forget(handle);
+		let log = Arc::clone(log);
+		let clock = sim.clone();
+		runtime.spawn(async move {
+			while timer.wait().await {
+				log.lock().push((period, clock.now()));
+			}
+		});
+	}
+
+	#[test]
+	fn timers_fire_in_order() {
+		// given
+		let sim = Simulator::new(69);
+		let log = Arc::new(Mutex::new(Vec::new()));
+		spawn_periodic(&sim, 2, &log);
+		spawn_periodic(&sim, 3, &log);
+
+		// when
+		sim.run_until_idle();
+		for _ in 0..4 {
+			sim.advance();
+			sim.run_until_idle();
+		}
+
+		// then
+		let mut log = log.lock().clone();
+		log.sort_by_key(|(period, time)| (*time, *period));
+		assert_eq!(
+			log,
+			vec![
+				(2, Duration::from_secs(2)),
+				(3, Duration::from_secs(3)),
+				(2, Duration::from_secs(4)),
+				(2, Duration::from_secs(6)),
+				(3, Duration::from_secs(6)),
+			]
+		);
+	}
+
+	#[test]
+	fn same_seed_same_interleaving() {
+		let run = |seed: u64| {
+			let sim = Simulator::new(seed);
+			let log = Arc::new(Mutex::new(Vec::new()));
+			for period in 1..=4 {
+				spawn_periodic(&sim, period, &log);
+			}
+			let mut num_calls = 0;
+			sim.run(|| {
+				num_calls += 1;
+				num_calls < 32
+			});
+			let log = log.lock().clone();
+			(sim.trace(), log)
+		};
+
+		assert_eq!(run(25), run(25));
+		assert_ne!(run(25).0, run(420).0);
+	}
+}
@@ -0,0 +1,422 @@
+use std::hash::Hash;
+
+use super::{CacheList, ClockList, LruList, ReplacementPolicy};
+
+/// This is an impelementation of the CAR algorithm.
+/// See [Bansal et. al. 2012](https://theory.stanford.edu/~sbansal/pubs/fast04.pdf).
+pub(crate) struct CarReplacer<T> {
+	/// A clock containing recently added values
+	recent: ClockList<T>,
+
+	/// A LRU list containing values recently dropped from `recent`
+	recent_history: LruList<T>,
+
+	/// A clock containing values that are considered frequently used
+	frequent: ClockList<T>,
+
+	/// A LRU list containing values recently dropped from `frequent`
+	frequent_history: LruList<T>,
+
+	/// The target size for `recent`.
+	recent_target_size: usize,
+
+	/// The total cache size
+	size: usize,
+}
+
+impl<T: Clone + Hash + Eq> CarReplacer<T> {
+	pub fn new(size: usize) -> Self {
+		Self {
+			recent: ClockList::new(),
+			recent_history: LruList::new(),
+			frequent: ClockList::new(),
+			frequent_history: LruList::new(),
+			recent_target_size: 0,
+			size,
+		}
+	}
+
+	/// Checks wether one of the history lists contains the value.
+	fn value_in_history(&self, value: &T) -> bool {
+		self.recent_history.contains(value) || self.frequent_history.contains(value)
+	}
+
+	/// Checks whether the active cache clocks can be extended without violating
+	/// their size requirement: `|recent| + |frequent| <= size`
+	fn cache_is_full(&self) -> bool {
+		self.recent.size() + self.frequent.size() >= self.size
+	}
+
+	/// Checks whether the recent cache clock can be extended without exceeding
+	/// `recent_target_size`.
+	fn recent_cache_is_full(&self) -> bool {
+		!self.recent.is_empty() && self.recent.size() >= self.recent_target_size
+	}
+
+	/// Checks whether the recent history LRU can be extended without violating
+	/// its size requirement: `|recent| + |recent_history| <= size`
+	fn recent_history_is_full(&self) -> bool {
+		self.recent.size() + self.recent_history.len() >= self.size
+	}
+
+	/// Checks whether the frequent history LRU can be extended without
+	/// violating its size requirement: `|recent| + |frequent| +
+	/// |recent_history| + |frequent_history| <= 2 * size`
+	fn frequent_history_is_full(&self) -> bool {
+		self.recent.size()
+			+ self.frequent.size()
+			+ self.recent_history.len()
+			+ self.frequent_history.len()
+			>= self.size * 2
+	}
+
+	/// Evicts a value from the cache, unless the cache is empty. This is
+	/// called `REPLACE` in the paper.
+	fn replace(&mut self) -> Option<T> {
+		// We loop until we find a suitable item. Every referenced item that is
+		// passed over has its reference bit cleared, so this terminates after
+		// at most one round through both clocks.
+		loop {
+			if self.recent_cache_is_full() {
+				// If the recent clock is full, we want to look at its head.
+
+				let (recent_head, referenced) = self.recent.remove().unwrap();
+				if !referenced {
+					// The recent head item was not recently referenced! We evict it, and add it to
+					// the history.
+					self.recent_history.enqueue(recent_head.clone());
+					return Some(recent_head);
+				} else {
+					// The recent head item was recently referenced. We promote it to the frequent
+					// clock, where it can get a second chance.
+					self.frequent.insert(recent_head);
+				}
+			} else {
+				// Otherwise, we look at the frequent clock so that `recent_target_size` is
+				// maintained. If its head item was recently referenced, it gets a second
+				// chance, and we try again.
+
+				if self.frequent.is_empty() {
+					return None;
+				}
+				if let Some(frequent_head) = self.frequent.sweep() {
+					// The frequent head item was not recently referenced! We evict it, and add it
+					// to the history.
+					self.frequent_history.enqueue(frequent_head.clone());
+					return Some(frequent_head);
+				}
+			}
+		}
+	}
+
+	/// Evicts an item from one of the history LRUs if they are full
+	fn maybe_evict_history(&mut self) {
+		if self.recent_history_is_full() {
+			self.recent_history.dequeue();
+		} else if self.frequent_history_is_full() {
+			self.frequent_history.dequeue();
+		}
+	}
+
+	/// Increases `recent_target_size`.
+	fn increase_recent_target(&mut self) {
+		// We want to change it by at least one, but if there is a lot more traffic on
+		// the frequent history than on the recent history, we should increase it by
+		// more. The goal is to get the two roughly equal.
+		let delta = usize::max(
+			1,
+			self.frequent_history.len() / self.recent_history.len().max(1),
+		);
+		self.recent_target_size = usize::min(self.recent_target_size + delta, self.size);
+	}
+
+	/// Decreases `recent_target_size`.
+	fn decrease_recent_target(&mut self) {
+		let delta = usize::max(
+			1,
+			self.recent_history.len() / self.frequent_history.len().max(1),
+		);
+		self.recent_target_size = self.recent_target_size.saturating_sub(delta);
+	}
+
+	/// Inserts a value into the cache.
+	fn insert(&mut self, value: T) {
+		if self.recent_history.contains(&value) {
+			// The value was only recently evicted from `recent`, so we might want `recent`
+			// to be bigger. We increase the `recent` target size, and move it to
+			// `frequent`.
+			self.increase_recent_target();
+			self.recent_history.remove(&value);
+			self.frequent.insert(value);
+		} else if self.frequent_history.contains(&value) {
+			// The value was only recently evicted from `frequent`, so we might want
+			// `frequent` to be bigger. We decrease the `recent` target size, and move it to
+			// `frequent`.
+			self.decrease_recent_target();
+			self.frequent_history.remove(&value);
+			self.frequent.insert(value);
+		} else {
+			// The value is not known to have been recently evicted. We add it to `recent`.
+			self.recent.insert(value);
+		}
+	}
+}
+
+impl<T: Clone + Hash + Eq> ReplacementPolicy<T> for CarReplacer<T> {
+	fn access(&self, value: &T) -> bool {
+		// Mark the corresponding page as referenced.
+		self.recent.access(value) || self.frequent.access(value)
+	}
+
+	fn evict_replace(&mut self, value: T) -> Option<T> {
+		debug_assert!(!self.recent.contains(&value) && !self.frequent.contains(&value));
+
+		let mut evicted: Option<T> = None;
+
+		if self.cache_is_full() {
+			// If the cache is full, we have to evict a value.
+			evicted = self.replace();
+
+			if !self.value_in_history(&value) {
+				// If the value doesn't appear in the history lists, it will have to be added,
+				// meaning that we may have to make space for it.
+				self.maybe_evict_history();
+			}
+		}
+
+		self.insert(value);
+
+		evicted
+	}
+
+	fn remove(&mut self, value: &T) -> bool {
+		self.recent.remove_value(value) || self.frequent.remove_value(value)
+	}
+
+	fn evict(&mut self) -> Option<T> {
+		// Below its target size, `recent` is only evicted from once `frequent` is
+		// empty.
+		self.replace().or_else(|| {
+			let (recent_head, _) = self.recent.remove()?;
+			self.recent_history.enqueue(recent_head.clone());
+			Some(recent_head)
+		})
+	}
+
+	fn len(&self) -> usize {
+		self.recent.size() + self.frequent.size()
+	}
+
+	fn resize(&mut self, size: usize) {
+		self.size = size;
+		self.recent_target_size = usize::min(self.recent_target_size, size);
+		while self.recent.size() + self.recent_history.len() > size {
+			if self.recent_history.dequeue().is_none() {
+				break;
+			}
+		}
+		while self.len() + self.recent_history.len() + self.frequent_history.len() > 2 * size {
+			if self.frequent_history.dequeue().is_none() {
+				break;
+			}
+		}
+	}
+
+	fn entries(&self) -> Vec<(T, CacheList)> {
+		let recent = self
+			.recent
+			.values()
+			.map(|value| (value.clone(), CacheList::Recent));
+		let frequent = self
+			.frequent
+			.values()
+			.map(|value| (value.clone(), CacheList::Frequent));
+		recent.chain(frequent).collect()
+	}
+
+	fn restore(&mut self, value: T, list: CacheList) -> bool {
+		debug_assert!(!self.recent.contains(&value) && !self.frequent.contains(&value));
+
+		if self.cache_is_full() {
+			return false;
+		}
+		match list {
+			CacheList::Recent => self.recent.insert(value),
+			CacheList::Frequent => self.frequent.insert(value),
+		}
+		true
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use test::Bencher;
+
+	use super::{super::linear::LinearCacheReplacer, *};
+
+	const BENCH_CACHE_SIZE: u32 = 65536;
+
+	#[test]
+	fn evict_least_recently_inserted() {
+		// given
+		let mut replacer = CarReplacer::new(3);
+		for value in 1..=3 {
+			assert_eq!(replacer.evict_replace(value), None);
+		}
+
+		// when
+		let evicted = replacer.evict_replace(4);
+
+		// then
+		assert_eq!(evicted, Some(1));
+	}
+
+	#[test]
+	fn referenced_value_gets_second_chance() {
+		// given
+		let mut replacer = CarReplacer::new(3);
+		for value in 1..=3 {
+			replacer.evict_replace(value);
+		}
+
+		// when
+		assert!(replacer.access(&1));
+		let evicted = replacer.evict_replace(4);
+
+		// then
+		assert_eq!(evicted, Some(2));
+		assert!(replacer.frequent.contains(&1));
+	}
+
+	#[test]
+	fn history_hit_moves_to_frequent() {
+		// given
+		let mut replacer = CarReplacer::new(2);
+		replacer.evict_replace(1);
+		replacer.access(&1);
+		replacer.evict_replace(2);
+		assert_eq!(replacer.evict_replace(3), Some(2));
+		assert!(replacer.recent_history.contains(&2));
+
+		// when
+		let evicted = replacer.evict_replace(2);
+
+		// then
+		assert_eq!(evicted, Some(3));
+		assert!(replacer.frequent.contains(&1));
+		assert!(replacer.frequent.contains(&2));
+		assert!(!replacer.recent_history.contains(&2));
+		assert_eq!(replacer.recent_target_size, 1);
+	}
+
+	#[test]
+	fn remove_value() {
+		// given
+		let mut replacer = CarReplacer::new(2);
+		replacer.evict_replace(1);
+		replacer.evict_replace(2);
+
+		// when
+		let removed = replacer.remove(&1);
+
+		// then
+		assert!(removed);
+		assert!(!replacer.access(&1));
+		assert_eq!(replacer.evict_replace(3), None);
+		assert!(!replacer.remove(&1));
+	}
+
+	#[test]
+	fn random_accesses_keep_invariants() {
+		let mut rng = fastrand::Rng::with_seed(69);
+		let size = 16;
+		let mut replacer = CarReplacer::new(size);
+
+		for _ in 0..10_000 {
+			let value = rng.u32(0..100);
+			if !replacer.access(&value) {
+				if let Some(evicted) = replacer.evict_replace(value) {
+					assert_ne!(evicted, value);
+					assert!(!replacer.access(&evicted));
+				}
+			}
+
+			let recent = replacer.recent.size();
+			let frequent = replacer.frequent.size();
+			let recent_history = replacer.recent_history.len();
+			let frequent_history = replacer.frequent_history.len();
+			assert!(recent + frequent <= size);
+			assert!(recent + recent_history <= size);
+			assert!(recent + frequent + recent_history + frequent_history <= 2 * size);
+			assert!(replacer.recent_target_size <= size);
+			for value in 0..100 {
+				let num_lists = [
+					replacer.recent.contains(&value),
+					replacer.frequent.contains(&value),
+					replacer.recent_history.contains(&value),
+					replacer.frequent_history.contains(&value),
+				]
+				.into_iter()
+				.filter(|contained| *contained)
+				.count();
+				assert!(num_lists <= 1);
+			}
+		}
+	}
+
+	#[bench]
+	fn bench_access(b: &mut Bencher) {
+		let mut replacer = CarReplacer::new(BENCH_CACHE_SIZE as usize);
+		for value in 0..BENCH_CACHE_SIZE {
+			replacer.evict_replace(value);
+		}
+
+		let mut value = 0;
+		b.iter(|| {
+			value = (value + 7919) % BENCH_CACHE_SIZE;
+			replacer.access(&value)
+		});
+	}
+
+	#[bench]
+	fn bench_access_linear(b: &mut Bencher) {
+		let mut replacer = LinearCacheReplacer::new(BENCH_CACHE_SIZE as usize);
+		for value in 0..BENCH_CACHE_SIZE {
+			replacer.evict_replace(value);
+		}
+
+		let mut value = 0;
+		b.iter(|| {
+			value = (value + 7919) % BENCH_CACHE_SIZE;
+			replacer.access(&value)
+		});
+	}
+
+	#[bench]
+	fn bench_evict_replace(b: &mut Bencher) {
+		let mut replacer = CarReplacer::new(BENCH_CACHE_SIZE as usize);
+		for value in 0..BENCH_CACHE_SIZE {
+			replacer.evict_replace(value);
+		}
+
+		let mut value = BENCH_CACHE_SIZE;
+		b.iter(|| {
+			value += 1;
+			replacer.evict_replace(value)
+		});
+	}
+
+	#[bench]
+	fn bench_evict_replace_linear(b: &mut Bencher) {
+		let mut replacer = LinearCacheReplacer::new(BENCH_CACHE_SIZE as usize);
+		for value in 0..BENCH_CACHE_SIZE {
+			replacer.evict_replace(value);
+		}
+
+		let mut value = BENCH_CACHE_SIZE;
+		b.iter(|| {
+			value += 1;
+			replacer.evict_replace(value)
+		});
+	}
+}
@@ -0,0 +1,112 @@
+use std::hash::Hash;
+
+use super::{CacheList, ClockList, ReplacementPolicy};
+
+/// The classic CLOCK algorithm, which approximates LRU with a single clock of
+/// reference bits. It is cheap, but a large scan can wipe the whole cache.
+pub(crate) struct ClockReplacer<T> {
+	clock: ClockList<T>,
+	size: usize,
+}
+
+impl<T: Clone + Hash + Eq> ClockReplacer<T> {
+	pub fn new(size: usize) -> Self {
+		Self {
+			clock: ClockList::new(),
+			size,
+		}
+	}
+}
+
+impl<T: Clone + Hash + Eq> ReplacementPolicy<T> for ClockReplacer<T> {
+	fn access(&self, value: &T) -> bool {
+		self.clock.access(value)
+	}
+
+	fn evict_replace(&mut self, value: T) -> Option<T> {
+		debug_assert!(!self.clock.contains(&value));
+
+		let mut evicted = None;
+		if self.clock.size() >= self.size {
+			// Every referenced value that is passed over has its reference bit
+			// cleared, so this terminates after at most one round.
+			while !self.clock.is_empty() && evicted.is_none() {
+				evicted = self.clock.sweep();
+			}
+		}
+		self.clock.insert(value);
+		evicted
+	}
+
+	fn remove(&mut self, value: &T) -> bool {
+		self.clock.remove_value(value)
+	}
+
+	fn evict(&mut self) -> Option<T> {
+		let mut evicted = None;
+		while !self.clock.is_empty() && evicted.is_none() {
+			evicted = self.clock.sweep();
+		}
+		evicted
+	}
+
+	fn len(&self) -> usize {
+		self.clock.size()
+	}
+
+	fn resize(&mut self, size: usize) {
+		self.size = size;
+	}
+
+	/// There is only one clock, so referenced values count as frequently
+	/// used.
+	fn entries(&self) -> Vec<(T, CacheList)> {
+		self.clock
+			.values()
+			.map(|value| {
+				let list = if self.clock.is_referenced(value) {
+					CacheList::Frequent
+				} else {
+					CacheList::Recent
+				};
+				(value.clone(), list)
+			})
+			.collect()
+	}
+
+	fn restore(&mut self, value: T, list: CacheList) -> bool {
+		debug_assert!(!self.clock.contains(&value));
+
+		if self.clock.size() >= self.size {
+			return false;
+		}
+		self.clock.insert(value.clone());
+		if list == CacheList::Frequent {
+			self.clock.access(&value);
+		}
+		true
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn evict_unreferenced_value() {
+		// given
+		let mut replacer = ClockReplacer::new(3);
+		for value in 1..=3 {
+			assert_eq!(replacer.evict_replace(value), None);
+		}
+		replacer.access(&1);
+		replacer.access(&2);
+
+		// when
+		let evicted = replacer.evict_replace(4);
+
+		// then
+		assert_eq!(evicted, Some(3));
+		assert_eq!(replacer.evict_replace(5), Some(1));
+	}
+}
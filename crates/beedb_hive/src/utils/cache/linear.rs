@@ -52,6 +52,14 @@ impl<T: PartialEq> ClockList<T> {
 		Some(item)
 	}
 
+	fn remove_value(&mut self, value: &T) -> bool {
+		let Some(position) = self.items.iter().position(|item| item.value == *value) else {
+			return false;
+		};
+		self.items.remove(position);
+		true
+	}
+
 	fn current(&self) -> Option<&ClockItem<T>> {
 		self.items.front()
 	}
@@ -110,9 +118,9 @@ impl<T: Clone + Hash + Eq> LruList<T> {
 	}
 }
 
-/// This is an impelementation of the CAR algorithm.
-/// See [Bansal et. al. 2012](https://theory.stanford.edu/~sbansal/pubs/fast04.pdf).
-pub(crate) struct CacheReplacer<T> {
+/// The previous implementation of [`CarReplacer`](super::car::CarReplacer),
+/// which searches its clocks linearly. It is only kept to benchmark against.
+pub(super) struct LinearCacheReplacer<T> {
 	/// A clock containing recently added values
 	recent: ClockList<T>,
 
@@ -132,7 +140,7 @@ pub(crate) struct CacheReplacer<T> {
 	size: usize,
 }
 
-impl<T: Clone + Hash + Eq> CacheReplacer<T> {
+impl<T: Clone + Hash + Eq> LinearCacheReplacer<T> {
 	pub fn new(size: usize) -> Self {
 		Self {
 			recent: ClockList::new(),
@@ -153,8 +161,6 @@ impl<T: Clone + Hash + Eq> CacheReplacer<T> {
 	/// Insert a value into the cache, potentially evicting a value to make
 	/// space.
 	pub fn evict_replace(&mut self, value: T) -> Option<T> {
-		debug_assert!(!self.recent.contains(&value) && !self.frequent.contains(&value));
-
 		let mut evicted: Option<T> = None;
 
 		if self.cache_is_full() {
@@ -173,6 +179,12 @@ impl<T: Clone + Hash + Eq> CacheReplacer<T> {
 		evicted
 	}
 
+	/// Stops tracking the given value, if it is in the cache. Returns whether
+	/// the value was found.
+	pub fn remove(&mut self, value: &T) -> bool {
+		self.recent.remove_value(value) || self.frequent.remove_value(value)
+	}
+
 	/// Checks wether one of the history lists contains the value.
 	fn value_in_history(&self, value: &T) -> bool {
 		self.recent_history.contains(value) || self.frequent_history.contains(value)
@@ -0,0 +1,337 @@
+use std::{collections::HashMap, hash::Hash};
+
+use parking_lot::Mutex;
+
+use super::{CacheList, IndexedList, ReplacementPolicy};
+
+/// An implementation of the LIRS algorithm.
+/// See [Jiang and Zhang 2002](https://dl.acm.org/doi/10.1145/511334.511340).
+///
+/// Values are ranked by their reuse distance rather than their recency.
+/// Most of the cache holds values with a short reuse distance (LIR), while a
+/// small part holds the values that were seen once or rarely (HIR), which
+/// are evicted first.
+///
+/// Like 2Q, an access moves the value in the recency stack, so every cache
+/// hit takes the replacer's lock; see [`super::two_queue::TwoQueueReplacer`].
+pub(crate) struct LirsReplacer<T>(Mutex<LirsState<T>>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+	Lir,
+	ResidentHir,
+	/// The value is no longer in the cache, but its recency is still tracked
+	/// in the stack.
+	NonResidentHir,
+}
+
+struct LirsState<T> {
+	statuses: HashMap<T, Status>,
+
+	/// The recency stack `S`, with the most recently used value at the back.
+	/// Its bottom is always a LIR value.
+	stack: IndexedList<T, ()>,
+
+	/// The resident HIR values, in the order in which they are evicted
+	/// (`Q`).
+	queue: IndexedList<T, ()>,
+
+	/// The non-resident HIR values in the stack, oldest first. They are
+	/// limited to the cache size, so that the stack can't grow without
+	/// bounds.
+	non_resident: IndexedList<T, ()>,
+
+	num_lir: usize,
+	max_lir: usize,
+
+	/// The total cache size
+	size: usize,
+}
+
+impl<T: Clone + Hash + Eq> LirsReplacer<T> {
+	pub fn new(size: usize) -> Self {
+		let mut state = LirsState {
+			statuses: HashMap::new(),
+			stack: IndexedList::new(),
+			queue: IndexedList::new(),
+			non_resident: IndexedList::new(),
+			num_lir: 0,
+			max_lir: 0,
+			size: 0,
+		};
+		state.resize(size);
+		Self(Mutex::new(state))
+	}
+}
+
+impl<T: Clone + Hash + Eq> LirsState<T> {
+	fn resize(&mut self, size: usize) {
+		// The paper suggests reserving 1% of the cache for HIR values.
+		let max_hir = usize::max(size / 100, 1);
+		self.max_lir = size.saturating_sub(max_hir);
+		self.size = size;
+		while self.num_lir > self.max_lir {
+			self.demote_bottom();
+		}
+		while self.non_resident.len() > size {
+			let (oldest, ()) = self.non_resident.pop_front().unwrap();
+			self.stack.remove(&oldest);
+			self.statuses.remove(&oldest);
+		}
+		self.prune();
+	}
+
+	/// Removes HIR values from the bottom of the stack, until a LIR value is
+	/// at the bottom.
+	fn prune(&mut self) {
+		while let Some((bottom, ())) = self.stack.front() {
+			match self.statuses[bottom] {
+				Status::Lir => break,
+				Status::ResidentHir => {
+					self.stack.pop_front();
+				}
+				Status::NonResidentHir => {
+					let (bottom, ()) = self.stack.pop_front().unwrap();
+					self.non_resident.remove(&bottom);
+					self.statuses.remove(&bottom);
+				}
+			}
+		}
+	}
+
+	/// Turns the LIR value at the bottom of the stack into a resident HIR
+	/// value.
+	fn demote_bottom(&mut self) {
+		if let Some((bottom, ())) = self.stack.pop_front() {
+			debug_assert_eq!(self.statuses[&bottom], Status::Lir);
+			self.statuses.insert(bottom.clone(), Status::ResidentHir);
+			self.queue.push_back(bottom, ());
+			self.num_lir -= 1;
+		}
+		self.prune();
+	}
+
+	/// Turns a value that is in the stack into a LIR value.
+	fn promote(&mut self, value: &T) {
+		self.stack.touch(value);
+		self.statuses.insert(value.clone(), Status::Lir);
+		self.num_lir += 1;
+		if self.num_lir > self.max_lir {
+			self.demote_bottom();
+		}
+	}
+
+	fn access(&mut self, value: &T) -> bool {
+		match self.statuses.get(value) {
+			Some(Status::Lir) => {
+				self.stack.touch(value);
+				self.prune();
+				true
+			}
+			Some(Status::ResidentHir) => {
+				if self.stack.contains(value) {
+					// The value's reuse distance is shorter than that of the
+					// bottom LIR value, so they switch places.
+					self.queue.remove(value);
+					self.promote(value);
+				} else {
+					self.stack.push_back(value.clone(), ());
+					self.queue.touch(value);
+				}
+				true
+			}
+			Some(Status::NonResidentHir) | None => false,
+		}
+	}
+
+	fn evict(&mut self) -> Option<T> {
+		let (value, ()) = self.queue.pop_front()?;
+		if self.stack.contains(&value) {
+			self.statuses.insert(value.clone(), Status::NonResidentHir);
+			self.non_resident.push_back(value.clone(), ());
+			if self.non_resident.len() > self.size {
+				let (oldest, ()) = self.non_resident.pop_front().unwrap();
+				self.stack.remove(&oldest);
+				self.statuses.remove(&oldest);
+			}
+		} else {
+			self.statuses.remove(&value);
+		}
+		Some(value)
+	}
+
+	fn insert(&mut self, value: T) {
+		match self.statuses.get(&value) {
+			Some(Status::NonResidentHir) => {
+				self.non_resident.remove(&value);
+				self.promote(&value);
+			}
+			_ if self.num_lir < self.max_lir => {
+				// While the cache is warming up, all values become LIR values.
+				self.stack.push_back(value.clone(), ());
+				self.statuses.insert(value, Status::Lir);
+				self.num_lir += 1;
+			}
+			_ => {
+				self.stack.push_back(value.clone(), ());
+				self.queue.push_back(value.clone(), ());
+				self.statuses.insert(value, Status::ResidentHir);
+			}
+		}
+	}
+}
+
+impl<T: Clone + Hash + Eq> ReplacementPolicy<T> for LirsReplacer<T> {
+	fn access(&self, value: &T) -> bool {
+		self.0.lock().access(value)
+	}
+
+	fn evict_replace(&mut self, value: T) -> Option<T> {
+		let state = self.0.get_mut();
+		debug_assert!(!matches!(
+			state.statuses.get(&value),
+			Some(Status::Lir | Status::ResidentHir)
+		));
+
+		let mut evicted = None;
+		if state.num_lir + state.queue.len() >= state.size {
+			evicted = state.evict();
+		}
+		state.insert(value);
+		evicted
+	}
+
+	fn remove(&mut self, value: &T) -> bool {
+		let state = self.0.get_mut();
+		match state.statuses.get(value) {
+			Some(Status::Lir) => state.num_lir -= 1,
+			Some(Status::ResidentHir) => {
+				state.queue.remove(value);
+			}
+			Some(Status::NonResidentHir) | None => return false,
+		}
+		state.statuses.remove(value);
+		state.stack.remove(value);
+		state.prune();
+		true
+	}
+
+	fn evict(&mut self) -> Option<T> {
+		let state = self.0.get_mut();
+		if state.queue.is_empty() {
+			state.demote_bottom();
+		}
+		state.evict()
+	}
+
+	fn len(&self) -> usize {
+		let state = self.0.lock();
+		state.num_lir + state.queue.len()
+	}
+
+	fn resize(&mut self, size: usize) {
+		self.0.get_mut().resize(size);
+	}
+
+	/// LIR values count as frequently used, and resident HIR values as
+	/// recently used.
+	fn entries(&self) -> Vec<(T, CacheList)> {
+		let state = self.0.lock();
+		let lir = state
+			.stack
+			.iter()
+			.filter(|(value, ())| state.statuses[*value] == Status::Lir)
+			.map(|(value, ())| (value.clone(), CacheList::Frequent));
+		let hir = state
+			.queue
+			.iter()
+			.map(|(value, ())| (value.clone(), CacheList::Recent));
+		lir.chain(hir).collect()
+	}
+
+	fn restore(&mut self, value: T, list: CacheList) -> bool {
+		let state = self.0.get_mut();
+		if state.num_lir + state.queue.len() >= state.size {
+			return false;
+		}
+		if state.statuses.get(&value) == Some(&Status::NonResidentHir) {
+			state.non_resident.remove(&value);
+			state.stack.remove(&value);
+		}
+		if list == CacheList::Frequent && state.num_lir < state.max_lir {
+			state.stack.push_back(value.clone(), ());
+			state.statuses.insert(value, Status::Lir);
+			state.num_lir += 1;
+		} else {
+			// Resident HIR values don't have to be in the stack.
+			state.queue.push_back(value.clone(), ());
+			state.statuses.insert(value, Status::ResidentHir);
+		}
+		true
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn promote_hir_value_with_short_reuse_distance() {
+		// given
+		let mut replacer = LirsReplacer::new(3);
+		for value in 1..=3 {
+			assert_eq!(replacer.evict_replace(value), None);
+		}
+
+		// when
+		assert!(replacer.access(&3));
+		assert!(replacer.access(&1));
+
+		// then
+		let state = replacer.0.get_mut();
+		assert_eq!(state.statuses[&1], Status::ResidentHir);
+		assert_eq!(state.statuses[&2], Status::Lir);
+		assert_eq!(state.statuses[&3], Status::Lir);
+		assert_eq!(state.num_lir, 2);
+	}
+
+	#[test]
+	fn keep_lir_values_during_scan() {
+		// given
+		let mut replacer = LirsReplacer::new(4);
+		for value in 1..=3 {
+			replacer.evict_replace(value);
+		}
+
+		// when
+		let evicted: Vec<_> = (10..20)
+			.filter_map(|value| replacer.evict_replace(value))
+			.collect();
+
+		// then
+		assert_eq!(evicted, (10..19).collect::<Vec<_>>());
+		for value in 1..=3 {
+			assert!(replacer.access(&value));
+		}
+		assert!(replacer.access(&19));
+	}
+
+	#[test]
+	fn remove_values() {
+		// given
+		let mut replacer = LirsReplacer::new(2);
+		replacer.evict_replace(1);
+		replacer.evict_replace(2);
+
+		// when
+		let removed = replacer.remove(&1);
+
+		// then
+		assert!(removed);
+		assert!(!replacer.remove(&1));
+		assert!(!replacer.access(&1));
+		assert_eq!(replacer.evict_replace(3), None);
+		assert!(replacer.access(&2));
+		assert!(replacer.access(&3));
+	}
+}
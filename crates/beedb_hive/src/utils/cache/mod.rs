@@ -0,0 +1,552 @@
+use std::{
+	collections::HashMap,
+	fmt,
+	hash::Hash,
+	sync::atomic::{AtomicBool, Ordering},
+};
+
+use self::{
+	car::CarReplacer, clock::ClockReplacer, lirs::LirsReplacer, two_queue::TwoQueueReplacer,
+};
+
+mod car;
+mod clock;
+#[cfg(test)]
+mod linear;
+mod lirs;
+pub(crate) mod replay;
+mod two_queue;
+
+/// Decides which values to evict from a cache that holds a fixed number of
+/// them.
+pub(crate) trait ReplacementPolicy<T> {
+	/// Tracks an access to a value. Returns `false` if the value is not in
+	/// the cache.
+	///
+	/// This is called on every cache hit, so it only needs shared access;
+	/// policies that reorder values on access synchronize internally.
+	fn access(&self, value: &T) -> bool;
+
+	/// Inserts a value that is not in the cache, evicting another one if the
+	/// cache is full.
+	fn evict_replace(&mut self, value: T) -> Option<T>;
+
+	/// Stops tracking the given value, if it is in the cache. Returns whether
+	/// the value was found.
+	fn remove(&mut self, value: &T) -> bool;
+
+	/// Evicts a value without inserting another one, unless the cache is
+	/// empty.
+	fn evict(&mut self) -> Option<T>;
+
+	/// Returns the number of values in the cache.
+	fn len(&self) -> usize;
+
+	/// Changes the number of values that the cache holds. If it shrinks below
+	/// [`Self::len`], the excess values have to be evicted with
+	/// [`Self::evict`].
+	fn resize(&mut self, size: usize);
+
+	/// Returns the values in the cache with the lists they are on, in the
+	/// order in which they would be restored.
+	fn entries(&self) -> Vec<(T, CacheList)>;
+
+	/// Inserts a value that is not in the cache into the given list, to
+	/// restore a previous state of the cache. Nothing is evicted for it;
+	/// returns `false` if the cache is full.
+	fn restore(&mut self, value: T, list: CacheList) -> bool;
+}
+
+/// The part of a cache that a value is on. Policies keep values that were
+/// only used once apart from values that were used again, so that a scan
+/// can't push out the latter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CacheList {
+	Recent,
+	Frequent,
+}
+
+/// Selects the [`ReplacementPolicy`] of a cache.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ReplacementPolicyKind {
+	/// Clock with adaptive replacement; see [`CarReplacer`].
+	#[default]
+	Car,
+
+	/// A single clock; see [`ClockReplacer`].
+	Clock,
+
+	/// The 2Q algorithm; see [`TwoQueueReplacer`]. Cache hits take a lock.
+	TwoQueue,
+
+	/// Low inter-reference recency set; see [`LirsReplacer`]. Cache hits
+	/// take a lock.
+	Lirs,
+}
+
+impl ReplacementPolicyKind {
+	pub const ALL: [Self; 4] = [Self::Car, Self::Clock, Self::TwoQueue, Self::Lirs];
+}
+
+pub(crate) enum CacheReplacer<T> {
+	Car(CarReplacer<T>),
+	Clock(ClockReplacer<T>),
+	TwoQueue(TwoQueueReplacer<T>),
+	Lirs(LirsReplacer<T>),
+}
+
+impl<T: Clone + Hash + Eq> CacheReplacer<T> {
+	pub fn new(kind: ReplacementPolicyKind, size: usize) -> Self {
+		match kind {
+			ReplacementPolicyKind::Car => Self::Car(CarReplacer::new(size)),
+			ReplacementPolicyKind::Clock => Self::Clock(ClockReplacer::new(size)),
+			ReplacementPolicyKind::TwoQueue => Self::TwoQueue(TwoQueueReplacer::new(size)),
+			ReplacementPolicyKind::Lirs => Self::Lirs(LirsReplacer::new(size)),
+		}
+	}
+}
+
+impl<T: Clone + Hash + Eq> ReplacementPolicy<T> for CacheReplacer<T> {
+	fn access(&self, value: &T) -> bool {
+		match self {
+			Self::Car(replacer) => replacer.access(value),
+			Self::Clock(replacer) => replacer.access(value),
+			Self::TwoQueue(replacer) => replacer.access(value),
+			Self::Lirs(replacer) => replacer.access(value),
+		}
+	}
+
+	fn evict_replace(&mut self, value: T) -> Option<T> {
+		match self {
+			Self::Car(replacer) => replacer.evict_replace(value),
+			Self::Clock(replacer) => replacer.evict_replace(value),
+			Self::TwoQueue(replacer) => replacer.evict_replace(value),
+			Self::Lirs(replacer) => replacer.evict_replace(value),
+		}
+	}
+
+	fn remove(&mut self, value: &T) -> bool {
+		match self {
+			Self::Car(replacer) => replacer.remove(value),
+			Self::Clock(replacer) => replacer.remove(value),
+			Self::TwoQueue(replacer) => replacer.remove(value),
+			Self::Lirs(replacer) => replacer.remove(value),
+		}
+	}
+
+	fn evict(&mut self) -> Option<T> {
+		match self {
+			Self::Car(replacer) => replacer.evict(),
+			Self::Clock(replacer) => replacer.evict(),
+			Self::TwoQueue(replacer) => replacer.evict(),
+			Self::Lirs(replacer) => replacer.evict(),
+		}
+	}
+
+	fn len(&self) -> usize {
+		match self {
+			Self::Car(replacer) => replacer.len(),
+			Self::Clock(replacer) => replacer.len(),
+			Self::TwoQueue(replacer) => replacer.len(),
+			Self::Lirs(replacer) => replacer.len(),
+		}
+	}
+
+	fn resize(&mut self, size: usize) {
+		match self {
+			Self::Car(replacer) => replacer.resize(size),
+			Self::Clock(replacer) => replacer.resize(size),
+			Self::TwoQueue(replacer) => replacer.resize(size),
+			Self::Lirs(replacer) => replacer.resize(size),
+		}
+	}
+
+	fn entries(&self) -> Vec<(T, CacheList)> {
+		match self {
+			Self::Car(replacer) => replacer.entries(),
+			Self::Clock(replacer) => replacer.entries(),
+			Self::TwoQueue(replacer) => replacer.entries(),
+			Self::Lirs(replacer) => replacer.entries(),
+		}
+	}
+
+	fn restore(&mut self, value: T, list: CacheList) -> bool {
+		match self {
+			Self::Car(replacer) => replacer.restore(value, list),
+			Self::Clock(replacer) => replacer.restore(value, list),
+			Self::TwoQueue(replacer) => replacer.restore(value, list),
+			Self::Lirs(replacer) => replacer.restore(value, list),
+		}
+	}
+}
+
+const NIL: usize = usize::MAX;
+
+struct Node<T, M> {
+	value: T,
+	meta: M,
+	prev: usize,
+	next: usize,
+}
+
+/// A doubly linked list with a hash index, so that values can be found and
+/// removed in constant time. The nodes are kept in a slab and linked by their
+/// indices.
+pub(crate) struct IndexedList<T, M> {
+	nodes: Vec<Option<Node<T, M>>>,
+	free: Vec<usize>,
+	index: HashMap<T, usize>,
+	head: usize,
+	tail: usize,
+}
+
+impl<T: Clone + Hash + Eq, M> IndexedList<T, M> {
+	pub fn new() -> Self {
+		Self {
+			nodes: Vec::new(),
+			free: Vec::new(),
+			index: HashMap::new(),
+			head: NIL,
+			tail: NIL,
+		}
+	}
+
+	#[inline]
+	pub fn len(&self) -> usize {
+		self.index.len()
+	}
+
+	#[inline]
+	fn is_empty(&self) -> bool {
+		self.index.is_empty()
+	}
+
+	#[inline]
+	fn contains(&self, value: &T) -> bool {
+		self.index.contains_key(value)
+	}
+
+	fn get(&self, value: &T) -> Option<&M> {
+		let slot = *self.index.get(value)?;
+		Some(&self.node(slot).meta)
+	}
+
+	#[inline]
+	fn node(&self, slot: usize) -> &Node<T, M> {
+		self.nodes[slot]
+			.as_ref()
+			.expect("Linked a free slot of an indexed list!")
+	}
+
+	#[inline]
+	fn node_mut(&mut self, slot: usize) -> &mut Node<T, M> {
+		self.nodes[slot]
+			.as_mut()
+			.expect("Linked a free slot of an indexed list!")
+	}
+
+	pub fn push_back(&mut self, value: T, meta: M) {
+		debug_assert!(!self.contains(&value));
+
+		let node = Node {
+			value: value.clone(),
+			meta,
+			prev: NIL,
+			next: NIL,
+		};
+		let slot = if let Some(slot) = self.free.pop() {
+			self.nodes[slot] = Some(node);
+			slot
+		} else {
+			self.nodes.push(Some(node));
+			self.nodes.len() - 1
+		};
+		self.index.insert(value, slot);
+		self.link_back(slot);
+	}
+
+	pub fn pop_front(&mut self) -> Option<(T, M)> {
+		if self.head == NIL {
+			return None;
+		}
+		let value = self.node(self.head).value.clone();
+		let meta = self.remove(&value)?;
+		Some((value, meta))
+	}
+
+	pub fn remove(&mut self, value: &T) -> Option<M> {
+		let slot = self.index.remove(value)?;
+		self.unlink(slot);
+		self.free.push(slot);
+		self.nodes[slot].take().map(|node| node.meta)
+	}
+
+	/// Moves the value to the back. Returns `false` if it isn't in the list.
+	fn touch(&mut self, value: &T) -> bool {
+		let Some(&slot) = self.index.get(value) else {
+			return false;
+		};
+		if slot != self.tail {
+			self.unlink(slot);
+			self.link_back(slot);
+		}
+		true
+	}
+
+	/// Moves the value at the front to the back, without touching the index.
+	fn rotate(&mut self) {
+		let slot = self.head;
+		if slot != NIL && slot != self.tail {
+			self.unlink(slot);
+			self.link_back(slot);
+		}
+	}
+
+	/// Iterates over the values from front to back.
+	pub fn iter(&self) -> impl Iterator<Item = (&T, &M)> {
+		let mut slot = self.head;
+		std::iter::from_fn(move || {
+			if slot == NIL {
+				return None;
+			}
+			let node = self.node(slot);
+			slot = node.next;
+			Some((&node.value, &node.meta))
+		})
+	}
+
+	fn front(&self) -> Option<(&T, &M)> {
+		(self.head != NIL).then(|| {
+			let node = self.node(self.head);
+			(&node.value, &node.meta)
+		})
+	}
+
+	fn link_back(&mut self, slot: usize) {
+		let tail = self.tail;
+		let node = self.node_mut(slot);
+		node.prev = tail;
+		node.next = NIL;
+		if tail == NIL {
+			self.head = slot;
+		} else {
+			self.node_mut(tail).next = slot;
+		}
+		self.tail = slot;
+	}
+
+	fn unlink(&mut self, slot: usize) {
+		let Node { prev, next, .. } = *self.node(slot);
+		if prev == NIL {
+			self.head = next;
+		} else {
+			self.node_mut(prev).next = next;
+		}
+		if next == NIL {
+			self.tail = prev;
+		} else {
+			self.node_mut(next).prev = prev;
+		}
+	}
+}
+
+impl<T: Clone + Hash + Eq, M> Default for IndexedList<T, M> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<T: Clone + Hash + Eq + fmt::Debug, M> fmt::Debug for IndexedList<T, M> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_list()
+			.entries(self.iter().map(|(value, _)| value))
+			.finish()
+	}
+}
+
+/// A clock whose hand always points at the front of the list. Each value has
+/// a reference bit, which can be set through a shared reference.
+struct ClockList<T>(IndexedList<T, AtomicBool>);
+
+impl<T: Clone + Hash + Eq> ClockList<T> {
+	fn new() -> Self {
+		Self(IndexedList::new())
+	}
+
+	#[inline]
+	fn size(&self) -> usize {
+		self.0.len()
+	}
+
+	#[inline]
+	fn is_empty(&self) -> bool {
+		self.0.is_empty()
+	}
+
+	/// Inserts the value right behind the hand, with its reference bit
+	/// cleared.
+	fn insert(&mut self, value: T) {
+		self.0.push_back(value, AtomicBool::new(false));
+	}
+
+	/// Removes the value under the hand, and returns whether it was
+	/// referenced.
+	fn remove(&mut self) -> Option<(T, bool)> {
+		let (value, referenced) = self.0.pop_front()?;
+		Some((value, referenced.into_inner()))
+	}
+
+	fn remove_value(&mut self, value: &T) -> bool {
+		self.0.remove(value).is_some()
+	}
+
+	/// If the value under the hand was referenced, clears its reference bit
+	/// and moves the hand past it. Otherwise, the value is removed and
+	/// returned.
+	fn sweep(&mut self) -> Option<T> {
+		let (_, referenced) = self.0.front()?;
+		let referenced = referenced.swap(false, Ordering::Relaxed);
+		if referenced {
+			self.0.rotate();
+			return None;
+		}
+		self.0.pop_front().map(|(value, _)| value)
+	}
+
+	fn access(&self, value: &T) -> bool {
+		let Some(referenced) = self.0.get(value) else {
+			return false;
+		};
+		referenced.store(true, Ordering::Relaxed);
+		true
+	}
+
+	#[inline]
+	fn contains(&self, value: &T) -> bool {
+		self.0.contains(value)
+	}
+
+	fn is_referenced(&self, value: &T) -> bool {
+		self.0
+			.get(value)
+			.is_some_and(|referenced| referenced.load(Ordering::Relaxed))
+	}
+
+	/// Iterates over the values, starting at the hand.
+	fn values(&self) -> impl Iterator<Item = &T> {
+		self.0.iter().map(|(value, _)| value)
+	}
+}
+
+struct LruList<T>(IndexedList<T, ()>);
+
+impl<T: Clone + Hash + Eq> LruList<T> {
+	fn new() -> Self {
+		Self(IndexedList::new())
+	}
+
+	#[inline]
+	fn len(&self) -> usize {
+		self.0.len()
+	}
+
+	fn enqueue(&mut self, value: T) {
+		self.0.push_back(value, ());
+	}
+
+	fn dequeue(&mut self) -> Option<T> {
+		self.0.pop_front().map(|(value, ())| value)
+	}
+
+	fn remove(&mut self, value: &T) -> bool {
+		self.0.remove(value).is_some()
+	}
+
+	#[inline]
+	fn contains(&self, value: &T) -> bool {
+		self.0.contains(value)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashSet;
+
+	use super::*;
+
+	#[test]
+	fn shrink_and_grow() {
+		for kind in ReplacementPolicyKind::ALL {
+			// given
+			let mut replacer = CacheReplacer::new(kind, 8);
+			for value in 0..8 {
+				assert_eq!(replacer.evict_replace(value), None);
+			}
+
+			// when
+			replacer.resize(4);
+			let evicted: HashSet<_> = (0..4).map(|_| replacer.evict().unwrap()).collect();
+
+			// then
+			assert_eq!(replacer.len(), 4, "{kind:?}");
+			assert_eq!(evicted.len(), 4, "{kind:?}");
+			for value in 0..8 {
+				assert_eq!(
+					replacer.access(&value),
+					!evicted.contains(&value),
+					"{kind:?}"
+				);
+			}
+			assert!(replacer.evict_replace(8).is_some(), "{kind:?}");
+
+			replacer.resize(5);
+			assert_eq!(replacer.evict_replace(9), None, "{kind:?}");
+			assert_eq!(replacer.len(), 5, "{kind:?}");
+		}
+	}
+
+	#[test]
+	fn evict_until_empty() {
+		for kind in ReplacementPolicyKind::ALL {
+			// given
+			let mut replacer = CacheReplacer::new(kind, 4);
+			for value in 0..3 {
+				replacer.evict_replace(value);
+			}
+			replacer.access(&1);
+
+			// when
+			let mut evicted: Vec<_> = std::iter::from_fn(|| replacer.evict()).collect();
+
+			// then
+			evicted.sort();
+			assert_eq!(evicted, [0, 1, 2], "{kind:?}");
+			assert_eq!(replacer.len(), 0, "{kind:?}");
+		}
+	}
+
+	#[test]
+	fn restore_entries() {
+		for kind in ReplacementPolicyKind::ALL {
+			// given
+			let mut replacer = CacheReplacer::new(kind, 4);
+			for value in 0..4 {
+				replacer.evict_replace(value);
+			}
+			replacer.access(&1);
+			replacer.evict_replace(4);
+			let entries = replacer.entries();
+
+			// when
+			let mut restored = CacheReplacer::new(kind, 4);
+			for (value, list) in entries.iter().cloned() {
+				assert!(restored.restore(value, list), "{kind:?}");
+			}
+
+			// then
+			assert_eq!(restored.entries(), entries, "{kind:?}");
+			assert!(!restored.restore(5, CacheList::Recent), "{kind:?}");
+			assert_eq!(restored.len(), 4, "{kind:?}");
+		}
+	}
+}
@@ -0,0 +1,270 @@
+use std::{
+	fmt::{self, Display},
+	fs::File,
+	hash::Hash,
+	io::{self, BufRead, BufReader, BufWriter, Write},
+	path::Path,
+	str::FromStr,
+};
+
+use log::warn;
+use parking_lot::Mutex;
+
+use crate::files::PageAddress;
+
+use super::{CacheReplacer, ReplacementPolicy, ReplacementPolicyKind};
+
+/// The outcome of replaying an access trace against a replacement policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ReplayResult {
+	pub policy: ReplacementPolicyKind,
+	pub accesses: usize,
+	pub hits: usize,
+}
+
+impl ReplayResult {
+	pub fn hit_ratio(&self) -> f64 {
+		if self.accesses == 0 {
+			return 0.0;
+		}
+		self.hits as f64 / self.accesses as f64
+	}
+}
+
+impl fmt::Display for ReplayResult {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"{:?}: {} of {} accesses hit ({:.2}%)",
+			self.policy,
+			self.hits,
+			self.accesses,
+			self.hit_ratio() * 100.0
+		)
+	}
+}
+
+/// Reads a recorded access trace with one value per line, e.g. page
+/// addresses in the form they are displayed in. Empty lines are skipped.
+pub(crate) fn read_trace<T: FromStr>(reader: impl BufRead) -> io::Result<Vec<T>> {
+	let mut trace = Vec::new();
+	for line in reader.lines() {
+		let line = line?;
+		let line = line.trim();
+		if line.is_empty() {
+			continue;
+		}
+		let Ok(value) = line.parse() else {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidData,
+				format!("Invalid trace entry '{line}'"),
+			));
+		};
+		trace.push(value);
+	}
+	Ok(trace)
+}
+
+/// Records accesses in the format that [`read_trace`] reads. Failed writes
+/// are only logged, since the trace is a diagnostic aid.
+pub(crate) struct TraceRecorder<W: Write = BufWriter<File>>(Mutex<W>);
+
+impl TraceRecorder {
+	/// Creates the trace file at `path`, replacing an existing one.
+	pub fn create(path: &Path) -> io::Result<Self> {
+		Ok(Self::new(BufWriter::new(File::create(path)?)))
+	}
+}
+
+impl<W: Write> TraceRecorder<W> {
+	pub fn new(writer: W) -> Self {
+		Self(Mutex::new(writer))
+	}
+
+	pub fn record(&self, value: &impl Display) {
+		if let Err(err) = writeln!(self.0.lock(), "{value}") {
+			warn!("Failed to record an access: {err}");
+		}
+	}
+
+	pub fn into_inner(self) -> W {
+		self.0.into_inner()
+	}
+}
+
+/// Simulates a cache of `cache_size` values that uses the given policy, and
+/// counts how many of the accesses in `trace` would have been hits.
+pub(crate) fn replay<T: Clone + Hash + Eq>(
+	policy: ReplacementPolicyKind,
+	cache_size: usize,
+	trace: &[T],
+) -> ReplayResult {
+	let mut replacer = CacheReplacer::new(policy, cache_size);
+	let mut hits = 0;
+	for value in trace {
+		if replacer.access(value) {
+			hits += 1;
+		} else {
+			replacer.evict_replace(value.clone());
+		}
+	}
+	ReplayResult {
+		policy,
+		accesses: trace.len(),
+		hits,
+	}
+}
+
+/// Replays the trace against every replacement policy.
+pub(crate) fn replay_all<T: Clone + Hash + Eq>(
+	cache_size: usize,
+	trace: &[T],
+) -> Vec<ReplayResult> {
+	ReplacementPolicyKind::ALL
+		.into_iter()
+		.map(|policy| replay(policy, cache_size, trace))
+		.collect()
+}
+
+/// Replays the page access trace that was recorded at `path` against every
+/// policy, with a cache of `cache_size` pages, and writes one line with the
+/// hit ratio of each policy to `out`. This is what the `replay_trace`
+/// example runs.
+pub fn replay_trace_file(path: &Path, cache_size: usize, mut out: impl Write) -> io::Result<()> {
+	let trace: Vec<PageAddress> = read_trace(BufReader::new(File::open(path)?))?;
+	for result in replay_all(cache_size, &trace) {
+		writeln!(out, "{result}")?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::mem;
+
+	use test::Bencher;
+
+	use crate::files::test_helpers::page_address;
+
+	use super::*;
+
+	/// A small working set that is accessed over and over, and interrupted
+	/// by long scans over pages that are only read once.
+	fn scan_trace() -> Vec<u32> {
+		let mut trace = Vec::new();
+		for round in 0..20 {
+			for _ in 0..10 {
+				trace.extend(0..50);
+			}
+			trace.extend(1000 + round * 200..1200 + round * 200);
+		}
+		trace
+	}
+
+	#[bench]
+	fn bench_replay(b: &mut Bencher) {
+		let trace = scan_trace();
+		b.iter(|| replay(ReplacementPolicyKind::default(), 100, &trace));
+	}
+
+	fn hit_ratio(results: &[ReplayResult], policy: ReplacementPolicyKind) -> f64 {
+		results
+			.iter()
+			.find(|result| result.policy == policy)
+			.unwrap()
+			.hit_ratio()
+	}
+
+	#[test]
+	fn replay_scans() {
+		// given
+		let trace = scan_trace();
+
+		// when
+		let results = replay_all(100, &trace);
+
+		// then
+		assert!(results.iter().all(|result| result.accesses == trace.len()));
+		// The scans wipe out the working set under CLOCK, but not under CAR and
+		// LIRS.
+		let clock_hit_ratio = hit_ratio(&results, ReplacementPolicyKind::Clock);
+		assert!(hit_ratio(&results, ReplacementPolicyKind::Car) > clock_hit_ratio);
+		assert!(hit_ratio(&results, ReplacementPolicyKind::Lirs) > clock_hit_ratio);
+	}
+
+	#[test]
+	fn replay_loop_larger_than_cache() {
+		// given
+		// A loop over a working set that is slightly larger than the cache,
+		// interleaved with a scan.
+		let mut trace = Vec::new();
+		for round in 0..100 {
+			trace.extend(0..60);
+			trace.extend(1000 + round * 60..1060 + round * 60);
+		}
+
+		// when
+		let results = replay_all(100, &trace);
+
+		// then
+		// Recency based policies always evict the value that is needed next.
+		assert_eq!(hit_ratio(&results, ReplacementPolicyKind::Clock), 0.0);
+		assert_eq!(hit_ratio(&results, ReplacementPolicyKind::Car), 0.0);
+		assert!(hit_ratio(&results, ReplacementPolicyKind::TwoQueue) > 0.4);
+		assert!(hit_ratio(&results, ReplacementPolicyKind::Lirs) > 0.4);
+	}
+
+	#[test]
+	fn read_page_address_trace() {
+		// given
+		let recorded = "00000001:0002\n\n00000045:01a4\n";
+
+		// when
+		let trace: Vec<PageAddress> = read_trace(recorded.as_bytes()).unwrap();
+
+		// then
+		assert_eq!(trace, vec![page_address!(1, 2), page_address!(0x45, 0x1a4)]);
+		assert!(read_trace::<PageAddress>("00000001:0000".as_bytes()).is_err());
+	}
+
+	#[test]
+	fn record_and_read_trace() {
+		// given
+		let recorder = TraceRecorder::new(Vec::new());
+		let accesses = [page_address!(1, 2), page_address!(0x45, 0x1a4)];
+
+		// when
+		for page_address in &accesses {
+			recorder.record(page_address);
+		}
+
+		// then
+		let recorded = recorder.into_inner();
+		let trace: Vec<PageAddress> = read_trace(&recorded[..]).unwrap();
+		assert_eq!(trace, accesses);
+	}
+
+	#[test]
+	fn replay_recorded_trace_file() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let trace_path = tempdir.path().join("trace");
+		let recorder = TraceRecorder::create(&trace_path).unwrap();
+		for page_num in [1, 2, 1, 3, 1] {
+			recorder.record(&page_address!(1, page_num));
+		}
+		mem::drop(recorder);
+
+		// when
+		let mut out = Vec::new();
+		replay_trace_file(&trace_path, 2, &mut out).unwrap();
+
+		// then
+		let report = String::from_utf8(out).unwrap();
+		let expected: Vec<String> = ReplacementPolicyKind::ALL
+			.into_iter()
+			.map(|policy| replay(policy, 2, &[1, 2, 1, 3, 1]).to_string())
+			.collect();
+		assert_eq!(report.lines().collect::<Vec<_>>(), expected);
+	}
+}
@@ -0,0 +1,181 @@
+use std::hash::Hash;
+
+use parking_lot::Mutex;
+
+use super::{CacheList, IndexedList, ReplacementPolicy};
+
+/// An implementation of the full version of the 2Q algorithm, which
+/// approximates LRU-2 in constant time.
+/// See [Johnson and Shasha 1994](https://www.vldb.org/conf/1994/P439.PDF).
+///
+/// Values that are seen for the first time only enter a small FIFO queue,
+/// so scans don't push frequently used values out of the cache.
+///
+/// Unlike CAR and CLOCK, which only set a reference bit, an access moves the
+/// value in its list, so even a cache hit takes the replacer's lock. The page
+/// cache has a replacer per shard, so hits only contend with other hits on
+/// the same shard, but CAR scales better under many concurrent readers.
+pub(crate) struct TwoQueueReplacer<T>(Mutex<TwoQueueState<T>>);
+
+struct TwoQueueState<T> {
+	/// A FIFO queue of values that were inserted once (`A1in`).
+	recent: IndexedList<T, ()>,
+
+	/// A FIFO queue of values that were recently evicted from `recent`
+	/// (`A1out`). They are no longer in the cache.
+	recent_history: IndexedList<T, ()>,
+
+	/// An LRU list of values that were inserted again while they were in
+	/// `recent_history` (`Am`).
+	frequent: IndexedList<T, ()>,
+
+	/// The size above which values are evicted from `recent` rather than
+	/// `frequent`.
+	max_recent: usize,
+
+	max_recent_history: usize,
+
+	/// The total cache size
+	size: usize,
+}
+
+impl<T: Clone + Hash + Eq> TwoQueueReplacer<T> {
+	pub fn new(size: usize) -> Self {
+		let mut state = TwoQueueState {
+			recent: IndexedList::new(),
+			recent_history: IndexedList::new(),
+			frequent: IndexedList::new(),
+			max_recent: 0,
+			max_recent_history: 0,
+			size: 0,
+		};
+		state.resize(size);
+		Self(Mutex::new(state))
+	}
+}
+
+impl<T: Clone + Hash + Eq> TwoQueueState<T> {
+	fn resize(&mut self, size: usize) {
+		// These are the sizes recommended in the paper.
+		self.max_recent = usize::max(size / 4, 1);
+		self.max_recent_history = usize::max(size / 2, 1);
+		self.size = size;
+		while self.recent_history.len() > self.max_recent_history {
+			self.recent_history.pop_front();
+		}
+	}
+
+	fn evict(&mut self) -> Option<T> {
+		if self.recent.len() > self.max_recent || self.frequent.is_empty() {
+			let (value, ()) = self.recent.pop_front()?;
+			self.recent_history.push_back(value.clone(), ());
+			if self.recent_history.len() > self.max_recent_history {
+				self.recent_history.pop_front();
+			}
+			Some(value)
+		} else {
+			self.frequent.pop_front().map(|(value, ())| value)
+		}
+	}
+}
+
+impl<T: Clone + Hash + Eq> ReplacementPolicy<T> for TwoQueueReplacer<T> {
+	fn access(&self, value: &T) -> bool {
+		let mut state = self.0.lock();
+		// Accesses to values in `recent` are not counted, since they are
+		// usually correlated with the access that inserted them.
+		state.frequent.touch(value) || state.recent.contains(value)
+	}
+
+	fn evict_replace(&mut self, value: T) -> Option<T> {
+		let state = self.0.get_mut();
+		debug_assert!(!state.recent.contains(&value) && !state.frequent.contains(&value));
+
+		let mut evicted = None;
+		if state.recent.len() + state.frequent.len() >= state.size {
+			evicted = state.evict();
+		}
+
+		if state.recent_history.remove(&value).is_some() {
+			state.frequent.push_back(value, ());
+		} else {
+			state.recent.push_back(value, ());
+		}
+		evicted
+	}
+
+	fn remove(&mut self, value: &T) -> bool {
+		let state = self.0.get_mut();
+		state.recent.remove(value).is_some() || state.frequent.remove(value).is_some()
+	}
+
+	fn evict(&mut self) -> Option<T> {
+		self.0.get_mut().evict()
+	}
+
+	fn len(&self) -> usize {
+		let state = self.0.lock();
+		state.recent.len() + state.frequent.len()
+	}
+
+	fn resize(&mut self, size: usize) {
+		self.0.get_mut().resize(size);
+	}
+
+	fn entries(&self) -> Vec<(T, CacheList)> {
+		let state = self.0.lock();
+		let recent = state
+			.recent
+			.iter()
+			.map(|(value, ())| (value.clone(), CacheList::Recent));
+		let frequent = state
+			.frequent
+			.iter()
+			.map(|(value, ())| (value.clone(), CacheList::Frequent));
+		recent.chain(frequent).collect()
+	}
+
+	fn restore(&mut self, value: T, list: CacheList) -> bool {
+		let state = self.0.get_mut();
+		debug_assert!(!state.recent.contains(&value) && !state.frequent.contains(&value));
+
+		if state.recent.len() + state.frequent.len() >= state.size {
+			return false;
+		}
+		state.recent_history.remove(&value);
+		match list {
+			CacheList::Recent => state.recent.push_back(value, ()),
+			CacheList::Frequent => state.frequent.push_back(value, ()),
+		}
+		true
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn keep_frequent_values_during_scan() {
+		// given
+		let mut replacer = TwoQueueReplacer::new(4);
+		for value in 1..=4 {
+			replacer.evict_replace(value);
+		}
+		// 1 and 2 are evicted from `recent`, so they become frequent when they
+		// are inserted again.
+		assert_eq!(replacer.evict_replace(5), Some(1));
+		assert_eq!(replacer.evict_replace(1), Some(2));
+		assert_eq!(replacer.evict_replace(2), Some(3));
+
+		// when
+		let evicted: Vec<_> = (10..20)
+			.filter_map(|value| replacer.evict_replace(value))
+			.collect();
+
+		// then
+		assert!(!evicted.contains(&1) && !evicted.contains(&2));
+		assert!(replacer.access(&1));
+		assert!(replacer.access(&2));
+	}
+}
@@ -10,6 +10,7 @@ pub trait Write {
 	fn write(&mut self, offset: usize, buf: &[u8]) -> Result<(), Self::Error>;
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 enum LayoutSize {
 	Fixed(isize),
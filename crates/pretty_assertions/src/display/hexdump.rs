@@ -102,10 +102,7 @@ impl fmt::Display for HexdumpDiff<'_> {
 		let line_length = (terminal_width - index_width - DELIMITER_WIDTH) / 2;
 
 		let bytes_per_line = get_bytes_per_line(line_length);
-		let mut num_lines = diff_len / bytes_per_line;
-		if diff_len % bytes_per_line != 0 {
-			num_lines += 1;
-		}
+		let num_lines = diff_len.div_ceil(bytes_per_line);
 
 		let pad_to = if num_lines == 1 { 0 } else { bytes_per_line };
 